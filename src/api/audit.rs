@@ -0,0 +1,71 @@
+use axum::{
+    Json,
+    extract::Path,
+    response::{IntoResponse, Response},
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    services::{
+        audit::{self, AuditEntry},
+        redaction,
+    },
+};
+
+/// Options of a replay
+#[derive(Deserialize, Default)]
+pub struct ReplayOptions {
+    /// Path to send the request to instead of the original one
+    #[serde(default)]
+    pub path: Option<String>,
+}
+
+/// API endpoint to retrieve a recorded request
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `id` - Request ID, as returned in `x-request-id`
+pub async fn api_get_audit_entry(
+    AuthBearer(t): AuthBearer,
+    Path(id): Path<String>,
+) -> Result<Json<AuditEntry>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    match audit::get(&id) {
+        // replays send the stored body, only what is shown is redacted
+        Some(entry) => {
+            let mut entry = entry.as_ref().to_owned();
+            entry.body = redaction::redact(&entry.body).into_owned();
+            Ok(Json(entry))
+        }
+        None => Err(ClewdrError::PathNotFound {
+            msg: format!("Request {id} is not in the audit log"),
+        }),
+    }
+}
+
+/// API endpoint to replay a recorded request against the current pool
+/// The response is streamed back as the API route produced it, with the
+/// original request ID in `x-replay-of`
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `id` - Request ID of the recorded request
+/// * `options` - Optional replay options
+pub async fn api_replay_request(
+    AuthBearer(t): AuthBearer,
+    Path(id): Path<String>,
+    options: Option<Json<ReplayOptions>>,
+) -> Result<Response, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let options = options.map(|Json(o)| o).unwrap_or_default();
+    Ok(audit::replay(&id, options.path.as_deref())
+        .await?
+        .into_response())
+}
@@ -0,0 +1,112 @@
+use axum::{
+    Extension, Json,
+    extract::{FromRequest, Path, Request, State},
+    response::IntoResponse,
+};
+use http::header::CONTENT_TYPE;
+use serde_json::{Value, json};
+
+use crate::{
+    error::ClewdrError,
+    middleware::{
+        Caller,
+        multipart::{Form, is_multipart},
+    },
+    services::batch::{Batch, BatchManager, BatchRequest, CreateBatchParams},
+};
+
+/// Batch creation body, either JSON or an OpenAI style JSONL upload
+///
+/// The multipart form carries the requests as JSON lines in `file`, and
+/// optionally `endpoint` and `metadata` as a JSON object.
+pub struct BatchUpload(CreateBatchParams);
+
+impl<S> FromRequest<S> for BatchUpload
+where
+    S: Send + Sync,
+{
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
+        if !is_multipart(&req) {
+            let Json(params) = Json::<CreateBatchParams>::from_request(req, &()).await?;
+            return Ok(Self(params));
+        }
+        let Form(fields) = Form::from_request(req, &()).await?;
+        let mut params = CreateBatchParams {
+            endpoint: None,
+            requests: vec![],
+            metadata: None,
+        };
+        for field in fields {
+            match field.name.as_str() {
+                "file" => {
+                    for line in field.text().lines().filter(|l| !l.trim().is_empty()) {
+                        params
+                            .requests
+                            .push(serde_json::from_str::<BatchRequest>(line)?);
+                    }
+                }
+                "endpoint" => params.endpoint = Some(field.text()),
+                "metadata" => params.metadata = serde_json::from_str(&field.text())?,
+                _ => {}
+            }
+        }
+        Ok(Self(params))
+    }
+}
+
+/// Creates a batch and starts executing it in the background, with the
+/// scopes of the caller
+pub async fn api_create_batch(
+    State(s): State<BatchManager>,
+    Extension(caller): Extension<Caller>,
+    BatchUpload(params): BatchUpload,
+) -> Result<Json<Batch>, ClewdrError> {
+    s.create(caller, params).await.map(Json)
+}
+
+/// Lists the batches of the caller in OpenAI list format
+pub async fn api_list_batches(
+    State(s): State<BatchManager>,
+    Extension(caller): Extension<Caller>,
+) -> Json<Value> {
+    Json(json!({
+        "object": "list",
+        "data": s.list(&caller).await,
+        "has_more": false,
+    }))
+}
+
+/// Gets the status of a batch
+pub async fn api_get_batch(
+    State(s): State<BatchManager>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<Json<Batch>, ClewdrError> {
+    s.get(&caller, &id).await.map(Json)
+}
+
+/// Cancels a batch
+pub async fn api_cancel_batch(
+    State(s): State<BatchManager>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<Json<Batch>, ClewdrError> {
+    s.cancel(&caller, &id).await.map(Json)
+}
+
+/// Gets the results of a batch as JSON lines, like an OpenAI output file
+pub async fn api_get_batch_results(
+    State(s): State<BatchManager>,
+    Extension(caller): Extension<Caller>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, ClewdrError> {
+    let results = s.results(&caller, &id).await?;
+    let mut body = String::new();
+    for result in results {
+        body.push_str(&serde_json::to_string(&result)?);
+        body.push('\n');
+    }
+    Ok(([(CONTENT_TYPE, "application/jsonl")], body))
+}
@@ -1,13 +1,21 @@
 use std::time::Instant;
 
-use axum::{Extension, extract::State, response::Response};
+use axum::{
+    Extension, Json,
+    extract::{Path, State},
+    response::Response,
+};
 use colored::Colorize;
+use http::Method;
+use serde_json::Value;
 use tracing::info;
 
 use crate::{
     claude_code_state::ClaudeCodeState,
     error::ClewdrError,
     middleware::claude::{ClaudeApiFormat, ClaudeCodePreprocess, ClaudeContext},
+    services::daily_report,
+    streaming::{ResponseStream, StreamDialect},
     utils::{enabled, print_out_json},
 };
 
@@ -15,10 +23,11 @@ pub async fn api_claude_code(
     State(mut state): State<ClaudeCodeState>,
     ClaudeCodePreprocess(p, f): ClaudeCodePreprocess,
 ) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
-    state.system_prompt_hash = f.system_prompt_hash();
+    state.session_hash = f.session_hash();
     state.stream = p.stream.unwrap_or_default();
     state.api_format = f.api_format();
     state.usage = f.usage().to_owned();
+    daily_report::add_tokens(state.usage.input_tokens as u64);
     print_out_json(&p, "claude_code_client_req.json");
     let format_display = match f.api_format() {
         ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
@@ -41,5 +50,47 @@ pub async fn api_claude_code(
         format!("{}", elapsed.as_secs_f32()).green()
     );
 
-    res.map(|r| (Extension(f), r))
+    res.map(|r| {
+        let r = if f.is_stream() {
+            ResponseStream::from_response(StreamDialect::Claude, r)
+                .with_keep_alive()
+                .cancel_on_shutdown("Claude Code stream")
+                .into_response()
+        } else {
+            r
+        };
+        (Extension(f), r)
+    })
+}
+
+/// Creates an Anthropic message batch through the cookie pool
+pub async fn api_claude_code_create_batch(
+    State(mut state): State<ClaudeCodeState>,
+    Json(body): Json<Value>,
+) -> Result<Response, ClewdrError> {
+    state.create_batch(body).await
+}
+
+/// Retrieves a message batch with the cookie that created it
+pub async fn api_claude_code_get_batch(
+    State(mut state): State<ClaudeCodeState>,
+    Path(id): Path<String>,
+) -> Result<Response, ClewdrError> {
+    state.forward_batch(Method::GET, &id, "").await
+}
+
+/// Streams the JSONL results of a message batch
+pub async fn api_claude_code_batch_results(
+    State(mut state): State<ClaudeCodeState>,
+    Path(id): Path<String>,
+) -> Result<Response, ClewdrError> {
+    state.forward_batch(Method::GET, &id, "/results").await
+}
+
+/// Cancels a message batch
+pub async fn api_claude_code_cancel_batch(
+    State(mut state): State<ClaudeCodeState>,
+    Path(id): Path<String>,
+) -> Result<Response, ClewdrError> {
+    state.forward_batch(Method::POST, &id, "/cancel").await
 }
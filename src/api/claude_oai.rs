@@ -0,0 +1,43 @@
+use axum::{
+    Extension,
+    extract::{FromRequest, Request, State},
+    response::Response,
+};
+
+use crate::{
+    api::{api_claude_code, api_claude_web},
+    claude_code_state::ClaudeCodeState,
+    claude_web_state::ClaudeWebState,
+    config::{CLEWDR_CONFIG, ClaudeBackend},
+    error::ClewdrError,
+    middleware::claude::{ClaudeCodePreprocess, ClaudeContext, ClaudeWebPreprocess},
+};
+
+/// Both Claude backends, the one serving a request is picked by the routing
+/// script or `oai_backend`
+#[derive(Clone)]
+pub struct ClaudeOaiState {
+    pub web: ClaudeWebState,
+    pub code: ClaudeCodeState,
+}
+
+/// Axum handler for the OpenAI compatible chat completions endpoint
+///
+/// Hands the request to the Claude web or Claude code handler depending on
+/// the configured backend, both translate the request and response.
+pub async fn api_claude_oai(
+    State(state): State<ClaudeOaiState>,
+    req: Request,
+) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
+    let backend = req.extensions().get::<ClaudeBackend>().copied();
+    match backend.unwrap_or(CLEWDR_CONFIG.load().oai_backend) {
+        ClaudeBackend::Web => {
+            let p = ClaudeWebPreprocess::from_request(req, &()).await?;
+            api_claude_web(State(state.web), p).await
+        }
+        ClaudeBackend::Code => {
+            let p = ClaudeCodePreprocess::from_request(req, &()).await?;
+            api_claude_code(State(state.code), p).await
+        }
+    }
+}
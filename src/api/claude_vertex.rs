@@ -0,0 +1,79 @@
+use std::time::Instant;
+
+use axum::{Extension, extract::State, response::Response};
+use colored::Colorize;
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    claude_vertex_state::ClaudeVertexState,
+    error::ClewdrError,
+    middleware::claude::{ClaudeCodePreprocess, ClaudeContext},
+    provider,
+    services::{continuation, daily_report, quality_gate},
+    streaming::{ResponseStream, StreamDialect},
+    types::claude::CreateMessageParams,
+    utils::{enabled, print_out_json},
+};
+
+/// Continues a non-streaming completion cut off by the output limit and
+/// retries one failing the quality gate, where configured
+async fn complete(
+    state: &ClaudeVertexState,
+    res: Response,
+    p: CreateMessageParams,
+    body: &Value,
+) -> Result<Response, ClewdrError> {
+    let res = continuation::complete(res, body, StreamDialect::Claude, |body| {
+        let state = state.to_owned();
+        async move { provider::chat_with_retries(&state, serde_json::from_value(body)?).await }
+    })
+    .await?;
+    quality_gate::enforce(res, StreamDialect::Claude, || {
+        let state = state.to_owned();
+        let p = p.to_owned();
+        async move { provider::chat_with_retries(&state, p).await }
+    })
+    .await
+}
+
+/// Serves Claude messages, or OpenAI chat completions, through Vertex AI
+/// with the configured service account
+pub async fn api_claude_vertex(
+    State(mut state): State<ClaudeVertexState>,
+    ClaudeCodePreprocess(p, f): ClaudeCodePreprocess,
+) -> Result<(Extension<ClaudeContext>, Response), ClewdrError> {
+    state.stream = p.stream.unwrap_or_default();
+    daily_report::add_tokens(f.usage().input_tokens as u64);
+    print_out_json(&p, "claude_vertex_client_req.json");
+    info!(
+        "[REQ] stream: {}, msgs: {}, model: {}, think: {}, vertex: {}",
+        enabled(state.stream),
+        p.messages.len().to_string().green(),
+        p.model.green(),
+        enabled(p.thinking.is_some()),
+        enabled(true)
+    );
+    let stopwatch = Instant::now();
+    let body = serde_json::to_value(&p)?;
+    let res = match provider::chat_with_retries(&state, p.to_owned()).await {
+        Ok(res) if !state.stream => complete(&state, res, p, &body).await,
+        res => res,
+    };
+    info!(
+        "[FIN] elapsed: {}s",
+        format!("{}", stopwatch.elapsed().as_secs_f32()).green()
+    );
+
+    res.map(|r| {
+        let r = if f.is_stream() {
+            ResponseStream::from_response(StreamDialect::Claude, r)
+                .with_keep_alive()
+                .cancel_on_shutdown("Claude Vertex stream")
+                .into_response()
+        } else {
+            r
+        };
+        (Extension(f), r)
+    })
+}
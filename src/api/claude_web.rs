@@ -8,6 +8,8 @@ use crate::{
     claude_web_state::ClaudeWebState,
     error::ClewdrError,
     middleware::claude::{ClaudeApiFormat, ClaudeContext, ClaudeWebPreprocess},
+    services::daily_report,
+    streaming::{ResponseStream, StreamDialect},
     utils::{enabled, print_out_json},
 };
 /// Axum handler for the API messages
@@ -30,6 +32,8 @@ pub async fn api_claude_web(
     state.api_format = f.api_format();
     state.stream = stream;
     state.usage = f.usage().to_owned();
+    daily_report::add_tokens(state.usage.input_tokens as u64);
+    state.session_hash = f.session_hash();
     let format_display = match f.api_format() {
         ClaudeApiFormat::Claude => ClaudeApiFormat::Claude.to_string().green(),
         ClaudeApiFormat::OpenAI => ClaudeApiFormat::OpenAI.to_string().yellow(),
@@ -51,5 +55,15 @@ pub async fn api_claude_web(
         format!("{}", elapsed.as_secs_f32()).green()
     );
 
-    res.map(|r| (Extension(f), r))
+    res.map(|r| {
+        let r = if f.is_stream() {
+            ResponseStream::from_response(StreamDialect::Claude, r)
+                .with_keep_alive()
+                .cancel_on_shutdown("Claude web stream")
+                .into_response()
+        } else {
+            r
+        };
+        (Extension(f), r)
+    })
 }
@@ -1,9 +1,15 @@
+use std::time::Duration;
+
 use axum::Json;
 use axum_auth::AuthBearer;
+use serde::Deserialize;
 use serde_json::json;
 use wreq::StatusCode;
 
-use crate::config::{CLEWDR_CONFIG, ClewdrConfig};
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrConfig},
+    services::log_filter,
+};
 
 /// API endpoint to retrieve the application configuration
 /// Returns the config as JSON with sensitive fields removed
@@ -32,6 +38,16 @@ pub async fn api_get_config(
         obj.remove("wasted_cookie");
         obj.remove("gemini_keys");
         obj["vertex"]["credential"] = "placeholder".into();
+        for upstream in obj
+            .get_mut("openai_upstreams")
+            .and_then(|u| u.as_array_mut())
+            .into_iter()
+            .flatten()
+        {
+            if let Some(upstream) = upstream.as_object_mut() {
+                upstream.remove("keys");
+            }
+        }
     }
 
     Ok(Json(config_json))
@@ -69,9 +85,23 @@ pub async fn api_post_config(
         if new_c.vertex.credential.is_none() {
             new_c.vertex.credential = old_c.vertex.credential.to_owned();
         }
+        // hooks run programs on this host, so the dashboard cannot change them
+        new_c.hooks = old_c.hooks.to_owned();
+        new_c.routing_script = old_c.routing_script.to_owned();
+        // keys are hidden from the dashboard, keep them for upstreams sent back without
+        for upstream in new_c.openai_upstreams.iter_mut() {
+            if upstream.keys.is_empty()
+                && let Some(old) = old_c
+                    .openai_upstreams
+                    .iter()
+                    .find(|u| u.name == upstream.name)
+            {
+                upstream.keys = old.keys.to_owned();
+            }
+        }
         new_c
     });
-    if let Err(e) = CLEWDR_CONFIG.load().save().await {
+    if let Err(e) = ClewdrConfig::save().await {
         return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(serde_json::json!({
@@ -85,3 +115,74 @@ pub async fn api_post_config(
         "config": c
     })))
 }
+
+/// Body of a log filter change
+#[derive(Deserialize)]
+pub struct LogFilterUpdate {
+    /// Directives in `RUST_LOG` syntax, e.g. `info,clewdr::gemini_state=debug`
+    pub filter: String,
+    /// Restore the previous filter after this many seconds
+    #[serde(default)]
+    pub duration_secs: Option<u64>,
+}
+
+/// API endpoint to retrieve the active log filter
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+///
+/// # Returns
+/// * `Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)>` - Active filter on success, error response on failure
+pub async fn api_get_log_filter(
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_read_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    match log_filter::current() {
+        Ok(filter) => Ok(Json(json!({ "filter": filter }))),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({ "error": e })),
+        )),
+    }
+}
+
+/// API endpoint to change the log filter without a restart
+/// The change is not saved, a restart goes back to `RUST_LOG`
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `u` - New filter and optional duration
+///
+/// # Returns
+/// * `Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)>` - Previous filter on success, error response on failure
+pub async fn api_put_log_filter(
+    AuthBearer(t): AuthBearer,
+    Json(u): Json<LogFilterUpdate>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+    let revert_after = u.duration_secs.map(Duration::from_secs);
+    match log_filter::set(&u.filter, revert_after) {
+        Ok(previous) => Ok(Json(json!({
+            "filter": u.filter,
+            "previous": previous,
+            "revert_at": u
+                .duration_secs
+                .map(|s| chrono::Utc::now().timestamp() + s as i64),
+        }))),
+        Err(e) => Err((StatusCode::BAD_REQUEST, Json(json!({ "error": e })))),
+    }
+}
@@ -0,0 +1,285 @@
+use std::{io::Write, sync::LazyLock};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
+        header::{
+            ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, ETAG, IF_NONE_MATCH,
+            VARY,
+        },
+    },
+    response::{IntoResponse, Response},
+};
+use moka::sync::Cache;
+
+use crate::middleware::API_PREFIXES;
+
+const INDEX: &str = "index.html";
+
+/// Bundler output named after its content, it never changes under its name
+const HASHED_DIR: &str = "assets/";
+
+const CACHE_HASHED: &str = "public, max-age=31536000, immutable";
+
+/// The index is revalidated on every load, so a new build shows up at once
+const CACHE_INDEX: &str = "no-cache";
+
+const CACHE_OTHER: &str = "public, max-age=3600";
+
+/// Smaller files are not worth compressing
+const MIN_COMPRESS: usize = 1024;
+
+/// Compressed assets by ETag and encoding, so each is compressed only once
+static COMPRESSED: LazyLock<Cache<(String, Encoding), Bytes>> = LazyLock::new(|| Cache::new(512));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Encoding {
+    Br,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Extension of precompressed files shipped next to an asset
+    fn extension(self) -> &'static str {
+        match self {
+            Encoding::Br => "br",
+            Encoding::Gzip => "gz",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Option<Bytes> {
+        let out = match self {
+            Encoding::Br => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+                writer.write_all(data).ok()?;
+                writer.into_inner()
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data).ok()?;
+                encoder.finish().ok()?
+            }
+        };
+        (out.len() < data.len()).then(|| Bytes::from(out))
+    }
+}
+
+/// Asset body and its ETag
+struct Asset {
+    body: Bytes,
+    etag: String,
+}
+
+/// Quoted ETag from the first half of a SHA-256 hash
+fn to_etag(hash: &[u8]) -> String {
+    let hex = hash[..16]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+    format!("\"{hex}\"")
+}
+
+#[cfg(feature = "embed-resource")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "static/"]
+struct Static;
+
+/// ETags of the embedded assets, from the hashes computed at build time
+#[cfg(feature = "embed-resource")]
+static ETAGS: LazyLock<std::collections::HashMap<String, String>> = LazyLock::new(|| {
+    use rust_embed::RustEmbed;
+    Static::iter()
+        .filter_map(|path| {
+            let file = Static::get(&path)?;
+            Some((path.into_owned(), to_etag(&file.metadata.sha256_hash())))
+        })
+        .collect()
+});
+
+#[cfg(feature = "embed-resource")]
+async fn asset(path: &str) -> Option<Asset> {
+    use std::borrow::Cow;
+
+    use rust_embed::RustEmbed;
+    let file = Static::get(path)?;
+    let body = match file.data {
+        Cow::Borrowed(data) => Bytes::from_static(data),
+        Cow::Owned(data) => Bytes::from(data),
+    };
+    let etag = ETAGS.get(path)?.to_owned();
+    Some(Asset { body, etag })
+}
+
+/// ETags of files read from `static`, by path, with the modification time
+/// they were hashed at
+#[cfg(all(feature = "external-resource", not(feature = "embed-resource")))]
+static ETAGS: LazyLock<Cache<String, (std::time::SystemTime, String)>> =
+    LazyLock::new(|| Cache::new(512));
+
+#[cfg(all(feature = "external-resource", not(feature = "embed-resource")))]
+async fn asset(path: &str) -> Option<Asset> {
+    let file = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("static")
+        .join(path);
+    let modified = tokio::fs::metadata(&file).await.ok()?.modified().ok()?;
+    let body = Bytes::from(tokio::fs::read(&file).await.ok()?);
+    let etag = match ETAGS.get(path) {
+        Some((at, etag)) if at == modified => etag,
+        _ => {
+            let etag = to_etag(ring::digest::digest(&ring::digest::SHA256, &body).as_ref());
+            ETAGS.insert(path.to_owned(), (modified, etag.to_owned()));
+            etag
+        }
+    };
+    Some(Asset { body, etag })
+}
+
+#[cfg(not(any(feature = "embed-resource", feature = "external-resource")))]
+async fn asset(_: &str) -> Option<Asset> {
+    None
+}
+
+/// Whether the client accepts the encoding, one refused with `q=0` excluded
+fn accepts(headers: &HeaderMap, encoding: Encoding) -> bool {
+    headers
+        .get_all(ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|e| {
+            let mut params = e.split(';');
+            params
+                .next()
+                .is_some_and(|n| n.trim().eq_ignore_ascii_case(encoding.name()))
+                && !params.any(|p| {
+                    p.trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        == Some(0.0)
+                })
+        })
+}
+
+fn compressible(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || ["javascript", "json", "xml", "wasm"]
+            .iter()
+            .any(|t| mime.contains(t))
+}
+
+/// Body of the asset in an encoding the client accepts: a `.br` or `.gz`
+/// file shipped next to it, else the asset compressed once and cached
+async fn encode(
+    path: &str,
+    etag: &str,
+    body: &Bytes,
+    headers: &HeaderMap,
+) -> Option<(Encoding, Bytes)> {
+    for encoding in [Encoding::Br, Encoding::Gzip] {
+        if !accepts(headers, encoding) {
+            continue;
+        }
+        if let Some(pre) = asset(&format!("{path}.{}", encoding.extension())).await {
+            return Some((encoding, pre.body));
+        }
+        let key = (etag.to_owned(), encoding);
+        if let Some(cached) = COMPRESSED.get(&key) {
+            return Some((encoding, cached));
+        }
+        let data = body.to_owned();
+        let compressed = tokio::task::spawn_blocking(move || encoding.compress(&data))
+            .await
+            .ok()
+            .flatten();
+        if let Some(compressed) = compressed {
+            COMPRESSED.insert(key, compressed.to_owned());
+            return Some((encoding, compressed));
+        }
+    }
+    None
+}
+
+/// Serves the dashboard, embedded in the binary with the `embed-resource`
+/// feature or read from `static` with `external-resource`
+///
+/// Paths that are no file and look like client side routes get `index.html`,
+/// so reloading a page of the dashboard works. Hashed bundles under
+/// `assets/` are cached for good, the index is revalidated with its ETag.
+///
+/// # Arguments
+/// * `method` - Only GET and HEAD are served
+/// * `uri` - Path of the asset
+/// * `headers` - Accepted encodings and the ETag the client has
+pub async fn serve_frontend(method: Method, uri: Uri, headers: HeaderMap) -> Response {
+    if method != Method::GET && method != Method::HEAD {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let path = match uri.path().trim_start_matches('/') {
+        "" => INDEX,
+        path => path,
+    };
+    if path.split('/').any(|s| s == ".." || s.contains('\\')) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let is_route = !path.rsplit('/').next().unwrap_or_default().contains('.')
+        && !API_PREFIXES
+            .iter()
+            .chain(&["/api/"])
+            .any(|p| uri.path().starts_with(p));
+    let (path, Asset { body, etag }) = match asset(path).await {
+        Some(asset) => (path, asset),
+        None if is_route => match asset(INDEX).await {
+            Some(asset) => (INDEX, asset),
+            None => return StatusCode::NOT_FOUND.into_response(),
+        },
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let cache_control = if path == INDEX {
+        CACHE_INDEX
+    } else if path.starts_with(HASHED_DIR) {
+        CACHE_HASHED
+    } else {
+        CACHE_OTHER
+    };
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    let mut res = Response::builder()
+        .header(CACHE_CONTROL, cache_control)
+        .header(ETAG, &etag)
+        .header(VARY, ACCEPT_ENCODING.as_str());
+    let fresh = headers
+        .get(IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.split(',').any(|t| t.trim() == etag || t.trim() == "*"));
+    if fresh {
+        return res
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .unwrap_or_default();
+    }
+    res = res.header(
+        CONTENT_TYPE,
+        HeaderValue::from_str(mime.as_ref())
+            .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+    );
+    let encoded = if compressible(mime.essence_str()) && body.len() >= MIN_COMPRESS {
+        encode(path, &etag, &body, &headers).await
+    } else {
+        None
+    };
+    match encoded {
+        Some((encoding, compressed)) => res
+            .header(CONTENT_ENCODING, encoding.name())
+            .body(Body::from(compressed)),
+        None => res.body(Body::from(body)),
+    }
+    .unwrap_or_default()
+}
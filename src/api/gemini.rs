@@ -1,21 +1,30 @@
 use async_stream::stream;
 use axum::{
+    Json,
     body::Body,
     extract::State,
     response::{IntoResponse, Response},
 };
+use base64::{Engine, prelude::BASE64_STANDARD};
 use bytes::Bytes;
 use colored::Colorize;
-use futures::{FutureExt, Stream, StreamExt, pin_mut};
+use eventsource_stream::Eventsource;
+use futures::{StreamExt, pin_mut};
 use http::header::CONTENT_TYPE;
 use serde::Serialize;
-use tokio::select;
+use serde_json::Value;
 use tracing::info;
 
 use crate::{
+    config::{Backend, CLEWDR_CONFIG},
     error::ClewdrError,
     gemini_state::{GeminiApiFormat, GeminiState},
-    middleware::gemini::{GeminiContext, GeminiOaiPreprocess, GeminiPreprocess},
+    middleware::gemini::{
+        GeminiContext, GeminiImagePreprocess, GeminiOaiPreprocess, GeminiPreprocess,
+        GeminiSpeechPreprocess, GeminiTranscriptionPreprocess,
+    },
+    services::request_queue::{QueuePermit, REQUEST_QUEUE},
+    streaming::{ResponseStream, StreamDialect, gemini::GeminiFraming, json_keep_alive},
     utils::enabled,
 };
 
@@ -26,6 +35,7 @@ async fn handle_gemini_request<T: Serialize + Clone + Send + 'static>(
     ctx: GeminiContext,
 ) -> Result<Response, ClewdrError> {
     state.update_from_ctx(&ctx);
+    let permit = REQUEST_QUEUE.acquire(ctx.priority).await?;
     let GeminiContext {
         model,
         stream,
@@ -44,66 +54,85 @@ async fn handle_gemini_request<T: Serialize + Clone + Send + 'static>(
         model.green(),
     );
 
+    let dialect = match ctx.api_format {
+        GeminiApiFormat::Gemini => StreamDialect::Gemini,
+        GeminiApiFormat::OpenAI => StreamDialect::OpenAI,
+    };
     // For non-streaming requests, we need to handle keep-alive differently
     if !stream {
-        let stream = keep_alive_stream(state, body);
-        let res = Response::builder()
-            .header(CONTENT_TYPE, "application/json")
-            .body(Body::from_stream(stream))?;
-        return Ok(res);
+        let backend = if vertex {
+            Backend::Vertex
+        } else {
+            Backend::Gemini
+        };
+        let timeout = CLEWDR_CONFIG.load().timeouts.request(backend, Some(&model));
+        let res = json_keep_alive(
+            async move {
+                state
+                    .try_chat(body)
+                    .await
+                    .unwrap_or_else(|e| e.into_response())
+            },
+            dialect,
+            timeout,
+        );
+        return Ok(hold_permit(res, permit));
     }
 
-    // For streaming requests, proceed as before
+    let label = format!("{} stream", ctx.api_format);
+    let events = ctx.api_format == GeminiApiFormat::OpenAI
+        || GeminiFraming::from_alt(state.query.alt.as_deref()) == GeminiFraming::Sse;
+    if events && state.must_wait_for_key().await {
+        let res = ResponseStream::new(dialect, wait_for_key(state, body, dialect))
+            .with_keep_alive()
+            .cancel_on_shutdown(label)
+            .into_response();
+        return Ok(hold_permit(res, permit));
+    }
     let res = state.try_chat(body).await?;
-    Ok(res)
+    let res = ResponseStream::from_response(dialect, res)
+        .with_keep_alive()
+        .cancel_on_shutdown(label)
+        .into_response();
+    Ok(hold_permit(res, permit))
 }
 
-fn keep_alive_stream<T>(
+/// Starts the event stream before a key is available, so keep-alives go out
+/// while the request waits
+///
+/// The status is sent before the completion is known, so errors arrive as an
+/// error event.
+fn wait_for_key<T: Serialize + Clone + Send + 'static>(
     mut state: GeminiState,
     body: T,
-) -> impl Stream<Item = Result<Bytes, axum::Error>>
-where
-    T: Serialize + Clone + Send + 'static,
-{
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(15));
-    let time_out = std::time::Duration::from_secs(360);
+    dialect: StreamDialect,
+) -> impl futures::Stream<Item = Result<Bytes, axum::Error>> + Send + 'static {
     stream! {
-        let future = async move {
-            state
-                .try_chat(body.clone())
-                .await
-                .unwrap_or_else(|e| e.into_response())
-                .into_body()
-                .into_data_stream()
-        };
-        let stream = future.into_stream().flatten();
-        pin_mut!(stream);
-        let start = std::time::Instant::now();
-        loop {
-            select! {
-                biased;
-                data = stream.next() => {
-                    match data {
-                        Some(Ok(d)) => yield Ok(d),
-                        Some(Err(e)) => {
-                            yield Err(e);
-                            break;
-                        }
-                        None => break
-                    }
-                }
-                _ = interval.tick() => {
-                    if start.elapsed() > time_out {
-                        break;
-                    }
-                    yield Ok(Bytes::from("\n"));
+        match state.try_chat(body).await {
+            Ok(res) => {
+                let mut body = res.into_body().into_data_stream();
+                while let Some(chunk) = body.next().await {
+                    yield chunk;
                 }
-                else => break
             }
+            Err(e) => yield Ok(dialect.error_event(&e.to_string())),
         }
     }
 }
 
+/// Keeps the queue slot taken until the response body is fully sent
+fn hold_permit(res: Response, permit: Option<QueuePermit>) -> Response {
+    let Some(permit) = permit else {
+        return res;
+    };
+    let (parts, body) = res.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
 pub async fn api_post_gemini(
     State(state): State<GeminiState>,
     GeminiPreprocess(body, ctx): GeminiPreprocess,
@@ -112,8 +141,136 @@ pub async fn api_post_gemini(
 }
 
 pub async fn api_post_gemini_oai(
-    State(state): State<GeminiState>,
+    State(mut state): State<GeminiState>,
     GeminiOaiPreprocess(body, ctx): GeminiOaiPreprocess,
 ) -> Result<Response, ClewdrError> {
+    state.response_format = body.response_format.to_owned();
+    if ctx.stream && !ctx.vertex && CLEWDR_CONFIG.load().gemini_native_oai_stream {
+        state.native_stream = true;
+        return handle_gemini_request(state, body.to_gemini(), ctx).await;
+    }
     handle_gemini_request(state, body, ctx).await
 }
+
+/// OpenAI compatible image generation backed by Imagen or Gemini image models
+pub async fn api_post_gemini_images(
+    State(mut state): State<GeminiState>,
+    GeminiImagePreprocess(params, ctx): GeminiImagePreprocess,
+) -> Result<Response, ClewdrError> {
+    state.update_from_ctx(&ctx);
+    let _permit = REQUEST_QUEUE.acquire(ctx.priority).await?;
+    info!(
+        "[REQ] images: {}, model: {}",
+        params.n.unwrap_or(1).to_string().green(),
+        ctx.model.green(),
+    );
+    let res = state.try_chat(params.to_gemini()).await?;
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+    let res = serde_json::from_slice::<serde_json::Value>(&bytes)?;
+    Ok(Json(params.images_response(&res)).into_response())
+}
+
+/// Streaming WAV header for 16-bit mono PCM, sizes are left unknown
+fn wav_header(sample_rate: u32) -> Bytes {
+    let mut header = Vec::with_capacity(44);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    header.extend_from_slice(&1u16.to_le_bytes()); // mono
+    header.extend_from_slice(&sample_rate.to_le_bytes());
+    header.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&u32::MAX.to_le_bytes());
+    Bytes::from(header)
+}
+
+/// Audio chunk and its sample rate from a Gemini stream event
+fn audio_chunk(data: &str) -> Option<(Bytes, u32)> {
+    let event = serde_json::from_str::<Value>(data).ok()?;
+    let inline = event["candidates"][0]["content"]["parts"]
+        .as_array()?
+        .iter()
+        .find_map(|p| p.get("inlineData"))?;
+    let audio = BASE64_STANDARD.decode(inline["data"].as_str()?).ok()?;
+    // e.g. `audio/L16;codec=pcm;rate=24000`
+    let rate = inline["mimeType"]
+        .as_str()
+        .and_then(|m| m.split(';').find_map(|p| p.trim().strip_prefix("rate=")))
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(24000);
+    Some((Bytes::from(audio), rate))
+}
+
+/// OpenAI compatible text to speech backed by Gemini TTS models
+///
+/// Audio is streamed as it is generated, as `wav` unless `pcm` is requested
+pub async fn api_post_gemini_speech(
+    State(mut state): State<GeminiState>,
+    GeminiSpeechPreprocess(params, ctx): GeminiSpeechPreprocess,
+) -> Result<Response, ClewdrError> {
+    state.update_from_ctx(&ctx);
+    let permit = REQUEST_QUEUE.acquire(ctx.priority).await?;
+    info!(
+        "[REQ] speech: {} chars, model: {}",
+        params.input.chars().count().to_string().green(),
+        ctx.model.green(),
+    );
+    let res = state.try_chat(params.to_gemini()).await?;
+    let pcm = params.is_pcm();
+    let events = res.into_body().into_data_stream().eventsource();
+    let body = stream! {
+        let mut header_sent = pcm;
+        pin_mut!(events);
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    yield Err(axum::Error::new(e));
+                    return;
+                }
+            };
+            let Some((audio, rate)) = audio_chunk(&event.data) else {
+                continue;
+            };
+            if !header_sent {
+                header_sent = true;
+                yield Ok(wav_header(rate));
+            }
+            yield Ok(audio);
+        }
+    };
+    let res = Response::builder()
+        .header(CONTENT_TYPE, if pcm { "audio/pcm" } else { "audio/wav" })
+        .body(Body::from_stream(body))?;
+    Ok(hold_permit(res, permit))
+}
+
+/// OpenAI compatible transcription backed by Gemini audio understanding
+pub async fn api_post_gemini_transcriptions(
+    State(mut state): State<GeminiState>,
+    GeminiTranscriptionPreprocess(params, ctx): GeminiTranscriptionPreprocess,
+) -> Result<Response, ClewdrError> {
+    state.update_from_ctx(&ctx);
+    let _permit = REQUEST_QUEUE.acquire(ctx.priority).await?;
+    info!(
+        "[REQ] transcription: {}, model: {}",
+        params.mime_type.green(),
+        ctx.model.green(),
+    );
+    let res = state.try_chat(params.to_gemini()).await?;
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+    let res = serde_json::from_slice::<Value>(&bytes)?;
+    let (body, is_json) = params.transcription_response(&res);
+    let content_type = if is_json {
+        "application/json"
+    } else {
+        "text/plain; charset=utf-8"
+    };
+    Ok(Response::builder()
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))?)
+}
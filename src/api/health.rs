@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use axum::{
+    Json,
+    extract::State,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde_json::json;
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    services::{
+        connection_registry::CONNECTION_REGISTRY, cookie_actor::CookieActorHandle,
+        key_actor::KeyActorHandle,
+    },
+};
+
+/// How long an actor may take to answer before it is considered dead
+const ACTOR_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Handles needed to probe the actors
+#[derive(Clone)]
+pub struct HealthState {
+    pub cookie_actor_handle: CookieActorHandle,
+    pub key_actor_handle: KeyActorHandle,
+}
+
+/// Liveness probe, answers as long as the process serves requests
+pub async fn api_healthz() -> Json<serde_json::Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness probe
+///
+/// Ready when both actors answer and at least one cookie, key or Vertex
+/// credential can serve a request. Responds with 503 otherwise, the body
+/// details every check either way.
+pub async fn api_readyz(State(s): State<HealthState>) -> Response {
+    let cookies = tokio::time::timeout(ACTOR_TIMEOUT, s.cookie_actor_handle.get_status())
+        .await
+        .ok()
+        .and_then(Result::ok);
+    let keys = tokio::time::timeout(ACTOR_TIMEOUT, s.key_actor_handle.get_status())
+        .await
+        .ok()
+        .and_then(Result::ok);
+    let now = chrono::Utc::now().timestamp();
+    let valid_cookies = cookies.as_ref().map_or(0, |c| c.valid.len());
+    let usable_keys = keys
+        .as_ref()
+        .map_or(0, |k| k.valid.iter().filter(|k| k.usable(now)).count());
+    let vertex = CLEWDR_CONFIG.load().vertex.validate();
+
+    let ready =
+        cookies.is_some() && keys.is_some() && (valid_cookies > 0 || usable_keys > 0 || vertex);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": {
+            // the router is only built once the config is loaded
+            "config": true,
+            "cookie_actor": cookies.is_some(),
+            "key_actor": keys.is_some(),
+        },
+        "cookies": {
+            "valid": valid_cookies,
+            "exhausted": cookies.as_ref().map_or(0, |c| c.exhausted.len()),
+        },
+        "keys": {
+            "usable": usable_keys,
+            "total": keys.as_ref().map_or(0, |k| k.valid.len()),
+        },
+        "vertex": vertex,
+        "connections": CONNECTION_REGISTRY.stats(),
+    });
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,112 @@
+use axum::{
+    Json,
+    extract::{Path, State},
+};
+use axum_auth::AuthBearer;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tracing::info;
+
+use crate::{
+    config::{CLEWDR_CONFIG, GeminiKey},
+    error::ClewdrError,
+    services::key_actor::KeyActorHandle,
+};
+
+/// Body of a forced cooldown
+#[derive(Deserialize)]
+pub struct CooldownRequest {
+    /// Seconds every key stays out of rotation
+    pub secs: u64,
+}
+
+/// Body of a rotation reorder
+#[derive(Deserialize)]
+pub struct ReorderRequest {
+    /// Keys dispatched next, in order
+    pub keys: Vec<GeminiKey>,
+}
+
+/// Body of a key pin
+#[derive(Deserialize)]
+pub struct PinRequest {
+    /// Requests the key is dispatched first for, 0 unpins it
+    pub requests: u32,
+}
+
+/// API endpoint to lift the cooldowns of every key
+///
+/// # Arguments
+/// * `s` - Key actor handle
+/// * `t` - Auth bearer token for admin authentication
+pub async fn api_clear_key_cooldowns(
+    State(s): State<KeyActorHandle>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Value>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let cleared = s.clear_cooldowns().await?;
+    info!("Cleared the cooldowns of {} keys", cleared);
+    Ok(Json(json!({ "cleared": cleared })))
+}
+
+/// API endpoint to take every key out of rotation for a while, e.g. during
+/// maintenance
+///
+/// # Arguments
+/// * `s` - Key actor handle
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - How long the keys cool down
+pub async fn api_cooldown_keys(
+    State(s): State<KeyActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<CooldownRequest>,
+) -> Result<Json<Value>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let until = chrono::Utc::now().timestamp() + c.secs as i64;
+    let suspended = s.cooldown_all(until).await?;
+    info!("Suspended {} keys for {}s", suspended, c.secs);
+    Ok(Json(json!({ "suspended": suspended, "until": until })))
+}
+
+/// API endpoint to move keys to the front of the rotation
+///
+/// # Arguments
+/// * `s` - Key actor handle
+/// * `t` - Auth bearer token for admin authentication
+/// * `c` - Keys dispatched next, in order
+pub async fn api_reorder_keys(
+    State(s): State<KeyActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Json(c): Json<ReorderRequest>,
+) -> Result<Json<Value>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let moved = c.keys.len();
+    s.reorder(c.keys).await?;
+    Ok(Json(json!({ "moved": moved })))
+}
+
+/// API endpoint to dispatch a key before any other for the next requests
+///
+/// # Arguments
+/// * `s` - Key actor handle
+/// * `t` - Auth bearer token for admin authentication
+/// * `id` - The key, as configured
+/// * `c` - Requests the key is pinned for
+pub async fn api_pin_key(
+    State(s): State<KeyActorHandle>,
+    AuthBearer(t): AuthBearer,
+    Path(id): Path<String>,
+    Json(c): Json<PinRequest>,
+) -> Result<Json<Value>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    s.pin(GeminiKey::from(id), c.requests).await?;
+    Ok(Json(json!({ "requests": c.requests })))
+}
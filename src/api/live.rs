@@ -0,0 +1,350 @@
+use axum::{
+    Extension,
+    extract::{
+        Path, Query, State, WebSocketUpgrade,
+        ws::{CloseFrame, Message, WebSocket, close_code},
+    },
+    response::Response,
+};
+use colored::Colorize;
+use eventsource_stream::Eventsource;
+use futures::{SinkExt, StreamExt, pin_mut};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{select, spawn, sync::mpsc, task::JoinHandle};
+use tracing::{debug, info, warn};
+
+use crate::{
+    error::ClewdrError,
+    gemini_state::{GeminiApiFormat, GeminiState},
+    middleware::{
+        Caller,
+        gemini::{GeminiArgs, GeminiContext},
+    },
+    services::{
+        connection_registry::CONNECTION_REGISTRY,
+        jwt::TokenScopes,
+        request_queue::{Priority, REQUEST_QUEUE},
+    },
+    types::realtime::{ClientEvent, RealtimeSession, ResponseEvents, error_event, realtime_model},
+};
+
+/// Only Live API services are proxied, not arbitrary paths on the endpoint
+const LIVE_SERVICE_PREFIX: &str = "google.ai.generativelanguage.";
+
+/// Maps a client frame to the upstream socket, pings are answered by each
+/// side's own socket and not forwarded
+fn to_upstream(msg: Message) -> Option<wreq::Message> {
+    match msg {
+        Message::Text(text) => Some(wreq::Message::Text(text.as_str().into())),
+        Message::Binary(data) => Some(wreq::Message::Binary(data)),
+        Message::Close(frame) => Some(wreq::Message::Close(frame.map(|f| wreq::CloseFrame {
+            code: f.code.into(),
+            reason: f.reason.as_str().into(),
+        }))),
+        Message::Ping(_) | Message::Pong(_) => None,
+    }
+}
+
+/// Maps an upstream frame to the client socket
+fn to_client(msg: wreq::Message) -> Option<Message> {
+    match msg {
+        wreq::Message::Text(text) => Some(Message::Text(text.as_str().into())),
+        wreq::Message::Binary(data) => Some(Message::Binary(data)),
+        wreq::Message::Close(frame) => Some(Message::Close(frame.map(|f| CloseFrame {
+            code: f.code.into(),
+            reason: f.reason.as_str().into(),
+        }))),
+        wreq::Message::Ping(_) | wreq::Message::Pong(_) => None,
+    }
+}
+
+/// Model of a Live `setup` message, the first frame a client sends
+fn setup_model(msg: &Message) -> Option<String> {
+    let bytes = match msg {
+        Message::Text(text) => text.as_str().as_bytes(),
+        Message::Binary(data) => &data[..],
+        _ => return None,
+    };
+    let setup = serde_json::from_slice::<Value>(bytes).ok()?;
+    setup["setup"]["model"].as_str().map(str::to_string)
+}
+
+/// Forwards frames both ways until either side closes or the connection is
+/// cancelled through the registry
+///
+/// The model of each `setup` message must be covered by the token's scopes,
+/// the connection is closed otherwise.
+async fn relay(
+    client: WebSocket,
+    upstream: wreq::WebSocket,
+    label: String,
+    scopes: Option<TokenScopes>,
+) {
+    let mut handle = CONNECTION_REGISTRY.register(label);
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+    loop {
+        select! {
+            msg = client_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                if let Some(scopes) = scopes.as_ref().filter(|s| s.limits_models())
+                    && let Some(model) = setup_model(&msg)
+                    && !scopes.allows_model(&model)
+                {
+                    warn!(
+                        "Token of {} denied model {}",
+                        scopes.subject.as_deref().unwrap_or("unknown subject"),
+                        model
+                    );
+                    let frame = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: format!("Token scope does not cover model {model}").into(),
+                    };
+                    let _ = client_tx.send(Message::Close(Some(frame))).await;
+                    break;
+                }
+                let close = matches!(msg, Message::Close(_));
+                let sent = match to_upstream(msg) {
+                    Some(msg) => upstream_tx.send(msg).await.is_ok(),
+                    None => true,
+                };
+                if !sent || close {
+                    break;
+                }
+            }
+            msg = upstream_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                let close = matches!(msg, wreq::Message::Close(_));
+                let sent = match to_client(msg) {
+                    Some(msg) => client_tx.send(msg).await.is_ok(),
+                    None => true,
+                };
+                if !sent || close {
+                    break;
+                }
+            }
+            _ = handle.cancelled() => {
+                let frame = CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "Server is shutting down".into(),
+                };
+                let _ = client_tx.send(Message::Close(Some(frame))).await;
+                break;
+            }
+        }
+    }
+    let _ = upstream_tx.close().await;
+    let _ = client_tx.close().await;
+    debug!("Live connection {} closed", handle.id);
+}
+
+/// Proxies the Gemini Live API (`BidiGenerateContent`) over WebSocket
+///
+/// The upstream session is opened with a key from the pool before the client
+/// is upgraded, so key and handshake errors are reported as plain HTTP errors.
+/// The session holds a queue slot for its whole lifetime.
+pub async fn api_gemini_live(
+    State(mut state): State<GeminiState>,
+    Path(service): Path<String>,
+    caller: Option<Extension<Caller>>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ClewdrError> {
+    if !service.starts_with(LIVE_SERVICE_PREFIX) {
+        return Err(ClewdrError::BadRequest {
+            msg: "Unknown Live API service",
+        });
+    }
+    let permit = REQUEST_QUEUE.acquire(Priority::Interactive).await?;
+    let upstream = state.connect_live(&service).await?;
+    info!("[LIVE] {}", service.green());
+    let scopes = caller.and_then(|Extension(c)| c.scopes);
+    Ok(ws.on_upgrade(move |socket| async move {
+        relay(socket, upstream, service, scopes).await;
+        drop(permit);
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct RealtimeQuery {
+    #[serde(default)]
+    model: Option<String>,
+}
+
+/// Text parts and usage of a Gemini stream event, thoughts are left out
+fn stream_chunk(data: &str) -> Option<(String, Option<Value>)> {
+    let mut event = serde_json::from_str::<Value>(data).ok()?;
+    let text = event["candidates"][0]["content"]["parts"]
+        .as_array()
+        .map(|parts| {
+            parts
+                .iter()
+                .filter(|p| p["thought"] != true)
+                .filter_map(|p| p["text"].as_str())
+                .collect::<String>()
+        })
+        .unwrap_or_default();
+    Some((text, event.get_mut("usageMetadata").map(Value::take)))
+}
+
+/// Drives one streaming `generateContent` call and reports it as realtime
+/// response events
+async fn run_response(
+    mut state: GeminiState,
+    body: Value,
+    events: ResponseEvents,
+    tx: mpsc::UnboundedSender<Value>,
+) {
+    let result = async {
+        let _permit = REQUEST_QUEUE.acquire(Priority::Interactive).await?;
+        let res = state.try_chat(body).await?;
+        for event in events.started() {
+            let _ = tx.send(event);
+        }
+        let stream = res.into_body().into_data_stream().eventsource();
+        pin_mut!(stream);
+        let mut text = String::new();
+        let mut usage = None;
+        while let Some(event) = stream.next().await {
+            let event = event.map_err(axum::Error::new)?;
+            let Some((delta, chunk_usage)) = stream_chunk(&event.data) else {
+                continue;
+            };
+            if !delta.is_empty() {
+                let _ = tx.send(events.delta(&delta));
+                text.push_str(&delta);
+            }
+            usage = chunk_usage.or(usage);
+        }
+        Ok::<_, ClewdrError>((text, usage))
+    };
+    match result.await {
+        Ok((text, usage)) => {
+            for event in events.completed(&text, usage.as_ref()) {
+                let _ = tx.send(event);
+            }
+        }
+        Err(e) => {
+            let _ = tx.send(error_event("upstream_error", e.to_string()));
+            let _ = tx.send(events.ended("failed", Some(e.to_string())));
+        }
+    }
+}
+
+/// Serves an OpenAI Realtime session, each `response.create` becomes a
+/// streaming Gemini call over the conversation so far
+async fn realtime(socket: WebSocket, mut state: GeminiState, model: String) {
+    let mut handle = CONNECTION_REGISTRY.register(format!("realtime {model}"));
+    state.update_from_ctx(&GeminiContext {
+        vertex: false,
+        model: model.to_owned(),
+        stream: true,
+        path: format!("models/{model}:streamGenerateContent"),
+        query: GeminiArgs {
+            alt: Some("sse".to_string()),
+            ..Default::default()
+        },
+        api_format: GeminiApiFormat::Gemini,
+        session_hash: None,
+        priority: Priority::Interactive,
+        key_tiers: None,
+    });
+    let (mut client_tx, mut client_rx) = socket.split();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut session = RealtimeSession::new(model);
+    let mut running: Option<(ResponseEvents, JoinHandle<()>)> = None;
+    let _ = tx.send(session.created_event());
+    loop {
+        let event = select! {
+            msg = client_rx.next() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Binary(_))) => continue,
+                    _ => break,
+                };
+                let event = match serde_json::from_str::<ClientEvent>(text.as_str()) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        let _ = tx.send(error_event("unsupported_event", e.to_string()));
+                        continue;
+                    }
+                };
+                match event {
+                    ClientEvent::SessionUpdate { session: params } => session.update(params),
+                    ClientEvent::ItemCreate { item } => session.add_item(item),
+                    ClientEvent::ItemDelete { item_id } => session.delete_item(&item_id),
+                    ClientEvent::ResponseCreate { response } => {
+                        if running.as_ref().is_some_and(|(_, task)| !task.is_finished()) {
+                            error_event(
+                                "conversation_already_has_active_response",
+                                "A response is already in progress",
+                            )
+                        } else {
+                            let events = ResponseEvents::default();
+                            let body = session.to_gemini(&response);
+                            let task = spawn(run_response(
+                                state.to_owned(),
+                                body,
+                                events.to_owned(),
+                                tx.to_owned(),
+                            ));
+                            running = Some((events, task));
+                            continue;
+                        }
+                    }
+                    ClientEvent::ResponseCancel => {
+                        let active = running.take().filter(|(_, t)| !t.is_finished());
+                        let Some((events, task)) = active else {
+                            continue;
+                        };
+                        task.abort();
+                        events.ended("cancelled", None)
+                    }
+                }
+            }
+            Some(event) = rx.recv() => {
+                if event["type"] == "response.done"
+                    && let Some(item) = event["response"]["output"].get(0)
+                {
+                    session.push_output(item.to_owned());
+                }
+                event
+            }
+            _ = handle.cancelled() => {
+                let frame = CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "Server is shutting down".into(),
+                };
+                let _ = client_tx.send(Message::Close(Some(frame))).await;
+                break;
+            }
+        };
+        if client_tx
+            .send(Message::Text(event.to_string().into()))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    if let Some((_, task)) = running {
+        task.abort();
+    }
+    let _ = client_tx.close().await;
+    debug!("Realtime connection {} closed", handle.id);
+}
+
+/// OpenAI Realtime compatible endpoint backed by streaming Gemini
+///
+/// Keys are taken from the pool per response, so realtime clients share the
+/// pool with every other route.
+pub async fn api_oai_realtime(
+    State(state): State<GeminiState>,
+    Query(query): Query<RealtimeQuery>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let model = realtime_model(query.model.as_deref());
+    info!("[REALTIME] {}", model.green());
+    ws.protocols(["realtime"])
+        .on_upgrade(move |socket| realtime(socket, state, model))
+}
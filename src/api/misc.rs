@@ -7,9 +7,14 @@ use wreq::StatusCode;
 use crate::{
     VERSION_INFO,
     config::{CLEWDR_CONFIG, CookieStatus, KeyStatus},
+    error::ClewdrError,
     services::{
-        cookie_actor::{CookieActorHandle, CookieStatusInfo},
+        adaptive_limit::{ADAPTIVE_LIMIT, LimitStatus},
+        auth_guard::{AUTH_GUARD, AuthEvent},
+        cookie_actor::{CookieActorHandle, CookieStatusInfo, CookieUsageInfo},
         key_actor::{KeyActorHandle, KeyStatusInfo},
+        latency_stats::{LATENCY_STATS, LatencyStatus},
+        token_actor::{TokenActorHandle, TokenStatusInfo},
     },
 };
 
@@ -103,6 +108,58 @@ pub async fn api_get_cookies(
     }
 }
 
+/// API endpoint to retrieve the OAuth tokens of Claude code credentials
+/// Reports each token's expiry and its recent refreshes
+pub async fn api_get_tokens(
+    State(s): State<TokenActorHandle>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<TokenStatusInfo>>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+
+    match s.get_status().await {
+        Ok(status) => Ok(Json(status)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to get token status: {}", e)
+            })),
+        )),
+    }
+}
+
+/// API endpoint to retrieve per-cookie usage analytics
+/// Reports quota window usage, projected resets and rate limit history
+pub async fn api_get_cookie_usage(
+    State(s): State<CookieActorHandle>,
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<CookieUsageInfo>>, (StatusCode, Json<serde_json::Value>)> {
+    if !CLEWDR_CONFIG.load().admin_read_auth(&t) {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "error": "Unauthorized"
+            })),
+        ));
+    }
+
+    match s.get_usage().await {
+        Ok(usage) => Ok(Json(usage)),
+        Err(e) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "error": format!("Failed to get cookie usage: {}", e)
+            })),
+        )),
+    }
+}
+
 pub async fn api_get_keys(
     State(s): State<KeyActorHandle>,
     AuthBearer(t): AuthBearer,
@@ -208,6 +265,47 @@ pub async fn api_delete_key(
     }
 }
 
+/// API endpoint to retrieve the adaptive concurrency limit of each backend
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+pub async fn api_get_concurrency(
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<LimitStatus>>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_read_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    Ok(Json(ADAPTIVE_LIMIT.status()))
+}
+
+/// API endpoint to retrieve the time to first byte and generation speed of
+/// each cookie and key
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+pub async fn api_get_latency(
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<LatencyStatus>>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_read_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    Ok(Json(LATENCY_STATS.status()))
+}
+
+/// API endpoint to retrieve the recent admin logins and denied attempts,
+/// newest first
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+pub async fn api_get_auth_log(
+    AuthBearer(t): AuthBearer,
+) -> Result<Json<Vec<AuthEvent>>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    Ok(Json(AUTH_GUARD.events()))
+}
+
 /// API endpoint to get the application version information
 ///
 /// # Returns
@@ -225,7 +323,7 @@ pub async fn api_version() -> String {
 /// # Returns
 /// * `StatusCode` - OK if authorized, UNAUTHORIZED otherwise
 pub async fn api_auth(AuthBearer(t): AuthBearer) -> StatusCode {
-    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+    if !CLEWDR_CONFIG.load().admin_read_auth(&t) {
         return StatusCode::UNAUTHORIZED;
     }
     info!("Auth token accepted,");
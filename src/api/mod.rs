@@ -3,19 +3,59 @@
 /// This module serves as the main entry point for all API requests, providing endpoints
 /// for configuration management, message handling, authentication, and OpenAI-compatible
 /// interfaces. It also implements response transformation between different API formats.
+mod audit;
+mod batch;
 mod claude_code;
+mod claude_oai;
+mod claude_vertex;
 mod claude_web;
 mod config;
+mod frontend;
 mod gemini;
+mod health;
+mod key_pool;
+mod live;
 mod misc;
-pub use claude_code::api_claude_code;
+mod openai;
+mod probe;
+/// Recorded requests and their replay
+pub use audit::{api_get_audit_entry, api_replay_request};
+/// OpenAI style batch endpoints
+pub use batch::{
+    api_cancel_batch, api_create_batch, api_get_batch, api_get_batch_results, api_list_batches,
+};
+/// Claude code messages and Anthropic message batches
+pub use claude_code::{
+    api_claude_code, api_claude_code_batch_results, api_claude_code_cancel_batch,
+    api_claude_code_create_batch, api_claude_code_get_batch,
+};
+/// OpenAI compatible chat completions served by either Claude backend
+pub use claude_oai::{ClaudeOaiState, api_claude_oai};
+/// Claude served by Vertex AI's anthropic publisher
+pub use claude_vertex::api_claude_vertex;
 /// Message handling endpoints for creating and managing chat conversations
 pub use claude_web::api_claude_web;
 /// Configuration related endpoints for retrieving and updating Clewdr settings
-pub use config::{api_get_config, api_post_config};
-pub use gemini::{api_post_gemini, api_post_gemini_oai};
+pub use config::{api_get_config, api_get_log_filter, api_post_config, api_put_log_filter};
+/// The dashboard, with client side routes falling back to its index
+pub use frontend::serve_frontend;
+pub use gemini::{
+    api_post_gemini, api_post_gemini_images, api_post_gemini_oai, api_post_gemini_speech,
+    api_post_gemini_transcriptions,
+};
+/// Liveness and readiness probes
+pub use health::{HealthState, api_healthz, api_readyz};
+/// Bulk cooldown and rotation order operations on the key pool
+pub use key_pool::{api_clear_key_cooldowns, api_cooldown_keys, api_pin_key, api_reorder_keys};
+/// Realtime WebSocket endpoints, the Gemini Live API proxy and its OpenAI emulation
+pub use live::{api_gemini_live, api_oai_realtime};
 /// Miscellaneous endpoints for authentication, cookies, and version information
 pub use misc::{
-    api_auth, api_delete_cookie, api_delete_key, api_get_cookies, api_get_keys, api_get_models,
-    api_post_cookie, api_post_key, api_version,
+    api_auth, api_delete_cookie, api_delete_key, api_get_auth_log, api_get_concurrency,
+    api_get_cookie_usage, api_get_cookies, api_get_keys, api_get_latency, api_get_models,
+    api_get_tokens, api_post_cookie, api_post_key, api_version,
 };
+/// Chat completions forwarded to the configured OpenAI compatible upstreams
+pub use openai::{api_openai_chat, api_openai_models};
+/// Live probes of a single key or cookie
+pub use probe::{api_test_cookie, api_test_key};
@@ -0,0 +1,58 @@
+use axum::{Json, extract::State, response::Response};
+use serde_json::{Value, json};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    openai_state::{OpenAIState, resolve},
+    provider,
+    services::quality_gate,
+    streaming::StreamDialect,
+};
+
+/// Forwards an OpenAI chat completion to the upstream serving the model,
+/// requested as `<upstream>/<model>` or by a model the upstream lists
+pub async fn api_openai_chat(
+    State(mut state): State<OpenAIState>,
+    Json(mut body): Json<Value>,
+) -> Result<Response, ClewdrError> {
+    let model = body["model"].as_str().ok_or(ClewdrError::BadRequest {
+        msg: "model is required",
+    })?;
+    let (upstream, model) = resolve(model)?;
+    body["model"] = model.to_owned().into();
+    state.stream = body["stream"].as_bool().unwrap_or_default();
+    state.upstream = Some(upstream);
+    state.model = model;
+    let res = provider::chat_with_retries(&state, body.to_owned()).await?;
+    if state.stream {
+        return Ok(res);
+    }
+    quality_gate::enforce(res, StreamDialect::OpenAI, || {
+        let state = state.to_owned();
+        let body = body.to_owned();
+        async move { provider::chat_with_retries(&state, body).await }
+    })
+    .await
+}
+
+/// Lists the models of the OpenAI compatible upstreams, with their upstream
+/// prefix
+pub async fn api_openai_models() -> Json<Value> {
+    let data = CLEWDR_CONFIG
+        .load()
+        .openai_upstreams
+        .iter()
+        .flat_map(|u| {
+            u.models.iter().map(|m| {
+                json!({
+                    "id": format!("{}/{m}", u.name),
+                    "object": "model",
+                    "created": 0,
+                    "owned_by": u.name,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    Json(json!({ "object": "list", "data": data }))
+}
@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+use axum::{Json, extract::Path};
+use axum_auth::AuthBearer;
+use tracing::info;
+
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrCookie},
+    error::ClewdrError,
+    services::{
+        doctor::{ProbeReport, test_cookie, test_key},
+        proxy_pool::PROXY_POOL,
+    },
+};
+
+/// API endpoint to probe a single Gemini key through its proxy
+/// Lists the models the key can use, which costs no quota
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `id` - The key, as configured
+pub async fn api_test_key(
+    AuthBearer(t): AuthBearer,
+    Path(id): Path<String>,
+) -> Result<Json<ProbeReport>, ClewdrError> {
+    if !CLEWDR_CONFIG.load().admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let Some(key) = CLEWDR_CONFIG
+        .load()
+        .gemini_keys
+        .iter()
+        .find(|k| *k.key == *id)
+        .cloned()
+    else {
+        return Err(ClewdrError::PathNotFound {
+            msg: "The key is not configured".to_string(),
+        });
+    };
+    let proxy = PROXY_POOL.resolve(key.proxy.as_deref());
+    let report = test_key(&key.key, proxy.as_deref()).await;
+    info!(
+        "Tested key {}: {}",
+        key.key.ellipse(),
+        report.error.as_deref().unwrap_or("ok")
+    );
+    Ok(Json(report))
+}
+
+/// API endpoint to probe a single cookie through its proxy
+/// Loads the account behind the cookie, invalid cookies can be tested too
+///
+/// # Arguments
+/// * `t` - Auth bearer token for admin authentication
+/// * `id` - The cookie, with or without the `sk-ant-sid01-` prefix
+pub async fn api_test_cookie(
+    AuthBearer(t): AuthBearer,
+    Path(id): Path<String>,
+) -> Result<Json<ProbeReport>, ClewdrError> {
+    let config = CLEWDR_CONFIG.load();
+    if !config.admin_auth(&t) {
+        return Err(ClewdrError::InvalidAuth);
+    }
+    let cookie = ClewdrCookie::from_str(&id)?;
+    let assigned = match config.cookie_array.iter().find(|c| c.cookie == cookie) {
+        Some(c) => c.proxy.to_owned(),
+        None if config.wasted_cookie.iter().any(|c| c.cookie == cookie) => None,
+        None => {
+            return Err(ClewdrError::PathNotFound {
+                msg: "The cookie is not configured".to_string(),
+            });
+        }
+    };
+    let proxy = PROXY_POOL.resolve(assigned.as_deref());
+    let report = test_cookie(&cookie, proxy.as_deref()).await;
+    info!(
+        "Tested cookie {}: {}",
+        cookie.ellipse(),
+        report.error.as_deref().unwrap_or("ok")
+    );
+    Ok(Json(report))
+}
@@ -0,0 +1,118 @@
+use std::{sync::LazyLock, time::Duration};
+
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use colored::Colorize;
+use http::Method;
+use moka::sync::Cache;
+use serde_json::Value;
+use snafu::ResultExt;
+use tracing::{error, info};
+use wreq::RequestBuilder;
+
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie},
+    error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    utils::forward_response,
+};
+
+/// Anthropic keeps batch results for 29 days
+const BATCH_RETENTION: Duration = Duration::from_secs(29 * 24 * 60 * 60);
+
+/// Cookie that created each batch, the batch is only visible to that account
+static BATCH_OWNERS: LazyLock<Cache<String, ClewdrCookie>> =
+    LazyLock::new(|| Cache::builder().time_to_live(BATCH_RETENTION).build());
+
+impl ClaudeCodeState {
+    fn batch_request(&self, method: Method, path: &str, access_token: &str) -> RequestBuilder {
+        self.client
+            .request(
+                method,
+                format!("{}/v1/messages/batches{path}", self.endpoint),
+            )
+            .bearer_auth(access_token)
+            .header("anthropic-beta", "oauth-2025-04-20")
+            .header("anthropic-version", "2023-06-01")
+    }
+
+    /// Creates a message batch with a cookie from the pool, and remembers the
+    /// cookie so later calls for the batch use the same account
+    ///
+    /// # Arguments
+    /// * `body` - Anthropic batch creation body, forwarded as is
+    pub async fn create_batch(&mut self, body: Value) -> Result<Response, ClewdrError> {
+        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+            if i > 0 {
+                info!("[RETRY] attempt: {}", i.to_string().green());
+            }
+            let mut state = self.to_owned();
+            let cookie = state.request_cookie().await?;
+            let res = async {
+                let access_token = state.ensure_token().await?;
+                state
+                    .batch_request(Method::POST, "", &access_token)
+                    .json(&body)
+                    .send()
+                    .await
+                    .context(WreqSnafu {
+                        msg: "Failed to create message batch",
+                    })?
+                    .check_claude()
+                    .await
+            }
+            .await;
+            match res {
+                Ok(res) => {
+                    let batch = res.json::<Value>().await.context(WreqSnafu {
+                        msg: "Failed to parse message batch",
+                    })?;
+                    if let Some(id) = batch["id"].as_str() {
+                        info!("[BATCH] {} created", id.green());
+                        BATCH_OWNERS.insert(id.to_string(), cookie.cookie);
+                    }
+                    return Ok(Json(batch).into_response());
+                }
+                Err(ClewdrError::InvalidCookie { reason }) => {
+                    error!("[{}] {}", cookie.cookie.ellipse().green(), reason);
+                    state.return_cookie(Some(reason)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(ClewdrError::TooManyRetries)
+    }
+
+    /// Forwards a call for an existing batch with the cookie that created it
+    ///
+    /// # Arguments
+    /// * `method` - HTTP method of the call
+    /// * `id` - Batch ID
+    /// * `action` - Path after the batch ID, e.g. `/results`, empty for status
+    pub async fn forward_batch(
+        &mut self,
+        method: Method,
+        id: &str,
+        action: &str,
+    ) -> Result<Response, ClewdrError> {
+        let Some(owner) = BATCH_OWNERS.get(id) else {
+            return Err(ClewdrError::PathNotFound {
+                msg: format!("Batch {id} was not created through this instance"),
+            });
+        };
+        self.request_exact_cookie(owner).await?;
+        let access_token = self.ensure_token().await?;
+        let res = self
+            .batch_request(method, &format!("/{id}{action}"), &access_token)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to forward message batch request",
+            })?
+            .check_claude()
+            .await?;
+        forward_response(res)
+    }
+}
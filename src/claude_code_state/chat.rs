@@ -1,28 +1,25 @@
-use colored::Colorize;
 use snafu::ResultExt;
-use tracing::{Instrument, error, info};
+use tracing::{debug, info, warn};
 
 use crate::{
     claude_code_state::{ClaudeCodeState, TokenStatus},
-    config::CLEWDR_CONFIG,
+    config::{Backend, CLEWDR_CONFIG},
     error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    provider::{self, Provider, Verdict},
+    services::{continuation, mock, quality_gate},
+    streaming::{StreamDialect, fallback},
     types::claude::CreateMessageParams,
-    utils::forward_response,
+    utils::{forward_guarded, forward_response},
 };
 
 impl ClaudeCodeState {
     /// Attempts to send a chat message to Claude API with retry mechanism
     ///
-    /// This method handles the complete chat flow including:
-    /// - Request preparation and logging
-    /// - Cookie management for authentication
-    /// - Executing the chat request with automatic retries on failure
-    /// - Response transformation according to the specified API format
-    /// - Error handling and cleanup
-    ///
-    /// The method implements a sophisticated retry mechanism to handle transient failures,
-    /// and manages conversation cleanup to prevent resource leaks. It also includes
-    /// performance tracking to measure response times.
+    /// Each attempt takes a cookie from the pool and makes sure it holds an
+    /// OAuth token, see the [`Provider`] implementation. A failed stream is
+    /// retried without streaming where the error allows it. A completion cut
+    /// off by the output limit is continued, and one failing the quality gate
+    /// retried, where configured.
     ///
     /// # Arguments
     /// * `p` - The client request body containing messages and configuration
@@ -33,73 +30,82 @@ impl ClaudeCodeState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
-        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
-            if i > 0 {
-                info!("[RETRY] attempt: {}", i.to_string().green());
+        if mock::enabled() {
+            let input_tokens = mock::estimate_tokens(&p);
+            return mock::claude(&p.model, p.stream.unwrap_or_default(), input_tokens).await;
+        }
+        let stream = p.stream.unwrap_or_default();
+        match provider::chat_with_retries(self, p.to_owned()).await {
+            Err(e) if stream && fallback::applies(&e) => {
+                warn!(
+                    "[FALLBACK] streaming failed: {}, retrying without streaming",
+                    e
+                );
+                let mut state = self.to_owned();
+                state.stream = false;
+                let res = provider::chat_with_retries(&state, p.with_stream(false)).await?;
+                fallback::into_sse(res, StreamDialect::Claude).await
             }
-            let mut state = self.to_owned();
-            let p = p.to_owned();
-
-            let cookie = state.request_cookie().await?;
-            let retry = async {
-                match state.check_token() {
-                    TokenStatus::None => {
-                        info!("No token found, requesting new token");
-                        let org = state.get_organization().await?;
-                        let code_res = state.exchange_code(&org).await?;
-                        state.exchange_token(code_res).await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Expired => {
-                        info!("Token expired, refreshing token");
-                        state.refresh_token().await?;
-                        state.return_cookie(None).await;
-                    }
-                    TokenStatus::Valid => {
-                        info!("Token is valid, proceeding with request");
+            Ok(res) if !stream => {
+                let mut state = self.to_owned();
+                let body = serde_json::to_value(&p)?;
+                let res = continuation::complete(res, &body, StreamDialect::Claude, |body| {
+                    let state = state.to_owned();
+                    async move {
+                        provider::chat_with_retries(&state, serde_json::from_value(body)?).await
                     }
-                }
-                let Some(access_token) = state.cookie.as_ref().and_then(|c| c.token.to_owned())
-                else {
-                    return Err(ClewdrError::UnexpectedNone {
-                        msg: "No access token found in cookie",
-                    });
-                };
-                state
-                    .send_chat(access_token.access_token.to_owned(), p)
-                    .await
+                })
+                .await?;
+                // the sticky cookie answered poorly, a retry may take any other
+                state.session_hash = None;
+                quality_gate::enforce(res, StreamDialect::Claude, || {
+                    let state = state.to_owned();
+                    let p = p.to_owned();
+                    async move { provider::chat_with_retries(&state, p).await }
+                })
+                .await
             }
-            .instrument(tracing::info_span!(
-                "claude_code",
-                "cookie" = cookie.cookie.ellipse()
-            ));
-            match retry.await {
-                Ok(res) => {
-                    return Ok(res);
-                }
-                Err(e) => {
-                    error!(
-                        "[{}] {}",
-                        state.cookie.as_ref().unwrap().cookie.ellipse().green(),
-                        e
-                    );
-                    // 429 error
-                    if let ClewdrError::InvalidCookie { reason } = e {
-                        state.return_cookie(Some(reason.to_owned())).await;
-                        continue;
-                    }
-                    return Err(e);
-                }
+            res => res,
+        }
+    }
+
+    /// Makes sure the current cookie holds a valid OAuth token, exchanging or
+    /// refreshing it as needed
+    ///
+    /// # Returns
+    /// The access token
+    pub async fn ensure_token(&mut self) -> Result<String, ClewdrError> {
+        match self.check_token() {
+            TokenStatus::None => {
+                info!("No token found, requesting new token");
+                let org = self.get_organization().await?;
+                let code_res = self.exchange_code(&org).await?;
+                self.exchange_token(code_res).await?;
+                self.return_cookie(None).await;
+            }
+            TokenStatus::Expired => {
+                info!("Token expired, refreshing token");
+                self.refresh_token().await?;
+                self.return_cookie(None).await;
+            }
+            TokenStatus::Valid => {
+                info!("Token is valid, proceeding with request");
             }
         }
-        Err(ClewdrError::TooManyRetries)
+        let Some(token) = self.cookie.as_ref().and_then(|c| c.token.to_owned()) else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "No access token found in cookie",
+            });
+        };
+        Ok(token.access_token)
     }
 
+    /// Sends the request to the messages endpoint with the OAuth token
     pub async fn send_chat(
         &mut self,
         access_token: String,
         mut p: CreateMessageParams,
-    ) -> Result<axum::response::Response, ClewdrError> {
+    ) -> Result<wreq::Response, ClewdrError> {
         // Check if model is 1M context version and prepare for API
         let beta_header = if let Some(model) = p.model.strip_suffix("-1M") {
             // Remove -1M suffix before sending to API
@@ -109,9 +115,13 @@ impl ClaudeCodeState {
             "oauth-2025-04-20"
         };
 
-        let api_res = self
-            .client
-            .post(format!("{}/v1/messages", self.endpoint))
+        let endpoint = format!("{}/v1/messages", self.endpoint);
+        debug!("[UPSTREAM] {}", endpoint);
+        let req = self.client.post(endpoint);
+        CLEWDR_CONFIG
+            .load()
+            .timeouts
+            .chat(req, Backend::ClaudeCode, &p.model)
             .bearer_auth(access_token)
             .header("anthropic-beta", beta_header)
             .header("anthropic-version", "2023-06-01")
@@ -122,7 +132,53 @@ impl ClaudeCodeState {
                 msg: "Failed to send chat message",
             })?
             .check_claude()
-            .await?;
-        forward_response(api_res)
+            .await
+    }
+}
+
+impl Provider for ClaudeCodeState {
+    type Request = CreateMessageParams;
+
+    const NAME: &'static str = "claude_code";
+
+    async fn prepare(&mut self) -> Result<(), ClewdrError> {
+        self.request_cookie().await?;
+        Ok(())
+    }
+
+    async fn send(&mut self, p: CreateMessageParams) -> Result<wreq::Response, ClewdrError> {
+        let access_token = self.ensure_token().await?;
+        self.send_chat(access_token, p).await
+    }
+
+    async fn decode(
+        &mut self,
+        res: wreq::Response,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        if self.stream {
+            return forward_guarded(res, StreamDialect::Claude, || ()).await;
+        }
+        forward_response(res)
+    }
+
+    async fn classify_error(&mut self, e: ClewdrError) -> Verdict {
+        // 429 error
+        if let ClewdrError::InvalidCookie { ref reason } = e {
+            let reason = reason.to_owned();
+            self.return_cookie(Some(reason)).await;
+            return Verdict::Retry(e);
+        }
+        if provider::transient(&e, self.proxy.as_deref()) {
+            return Verdict::Retry(e);
+        }
+        Verdict::Fail(e)
+    }
+
+    fn credential(&self) -> Option<String> {
+        self.cookie.as_ref().map(|c| c.cookie.ellipse())
+    }
+
+    fn credential_id(&self) -> Option<String> {
+        self.cookie.as_ref().map(|c| c.cookie.to_string())
     }
 }
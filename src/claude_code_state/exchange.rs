@@ -193,7 +193,6 @@ impl ClaudeCodeState {
     }
 
     pub async fn refresh_token(&mut self) -> Result<(), ClewdrError> {
-        let wreq_client = self.get_wreq_client();
         let Some(CookieStatus {
             token: Some(ref mut token),
             ..
@@ -206,6 +205,22 @@ impl ClaudeCodeState {
         if !token.is_expired() {
             return Ok(());
         }
+        self.rotate_token().await
+    }
+
+    /// Exchanges the refresh token of the current cookie for a new token pair,
+    /// whether or not the access token expired
+    pub async fn rotate_token(&mut self) -> Result<(), ClewdrError> {
+        let wreq_client = self.get_wreq_client();
+        let Some(CookieStatus {
+            token: Some(ref mut token),
+            ..
+        }) = self.cookie
+        else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "No token found to refresh token",
+            });
+        };
 
         let cc_client_id = CLEWDR_CONFIG.load().cc_client_id();
 
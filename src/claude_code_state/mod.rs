@@ -1,3 +1,4 @@
+mod batch;
 mod chat;
 mod exchange;
 mod organization;
@@ -12,10 +13,13 @@ use wreq_util::Emulation;
 
 use crate::{
     claude_web_state::SUPER_CLIENT,
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
+    config::{Backend, CLAUDE_ENDPOINT, CLEWDR_CONFIG, ClewdrCookie, CookieStatus, Reason},
     error::{ClewdrError, WreqSnafu},
     middleware::claude::ClaudeApiFormat,
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        cookie_actor::CookieActorHandle,
+        proxy_pool::{PROXY_POOL, to_wreq_proxy},
+    },
     types::claude::Usage,
 };
 
@@ -24,12 +28,13 @@ pub struct ClaudeCodeState {
     pub cookie_actor_handle: CookieActorHandle,
     pub cookie: Option<CookieStatus>,
     pub cookie_header_value: HeaderValue,
-    pub proxy: Option<wreq::Proxy>,
+    /// Proxy used by the current client
+    pub proxy: Option<String>,
     pub endpoint: url::Url,
     pub client: wreq::Client,
     pub api_format: ClaudeApiFormat,
     pub stream: bool,
-    pub system_prompt_hash: Option<u64>,
+    pub session_hash: Option<u64>,
     pub usage: Usage,
 }
 
@@ -40,12 +45,12 @@ impl ClaudeCodeState {
             cookie_actor_handle,
             cookie: None,
             cookie_header_value: HeaderValue::from_static(""),
-            proxy: CLEWDR_CONFIG.load().wreq_proxy.to_owned(),
+            proxy: None,
             endpoint: CLEWDR_CONFIG.load().endpoint(),
             client: SUPER_CLIENT.to_owned(),
             api_format: ClaudeApiFormat::Claude,
             stream: false,
-            system_prompt_hash: None,
+            session_hash: None,
             usage: Usage::default(),
         }
     }
@@ -83,23 +88,37 @@ impl ClaudeCodeState {
     /// Requests a new cookie from the cookie manager
     /// Updates the internal state with the new cookie and proxy configuration
     pub async fn request_cookie(&mut self) -> Result<CookieStatus, ClewdrError> {
-        let res = self
-            .cookie_actor_handle
-            .request(self.system_prompt_hash)
-            .await?;
+        let res = self.cookie_actor_handle.request(self.session_hash).await?;
+        self.use_cookie(res)
+    }
+
+    /// Requests a specific cookie, e.g. the one that created a batch
+    pub async fn request_exact_cookie(
+        &mut self,
+        cookie: ClewdrCookie,
+    ) -> Result<CookieStatus, ClewdrError> {
+        let res = self.cookie_actor_handle.lookup(cookie).await?;
+        self.use_cookie(res)
+    }
+
+    /// Switches the state to the cookie and rebuilds the client for its proxy
+    pub fn use_cookie(&mut self, res: CookieStatus) -> Result<CookieStatus, ClewdrError> {
         self.cookie = Some(res.to_owned());
         self.cookie_header_value = HeaderValue::from_str(res.cookie.to_string().as_str())?;
-        let mut client = ClientBuilder::new()
+        let client = ClientBuilder::new()
             .cookie_store(true)
             .emulation(Emulation::Chrome136);
-        if let Some(ref proxy) = self.proxy {
-            client = client.proxy(proxy.to_owned());
+        let mut client = CLEWDR_CONFIG
+            .load()
+            .timeouts
+            .client(client, Backend::ClaudeCode, None);
+        self.proxy = PROXY_POOL.resolve(res.proxy.as_deref());
+        if let Some(proxy) = self.proxy.as_deref().and_then(to_wreq_proxy) {
+            client = client.proxy(proxy);
         }
         self.client = client.build().context(WreqSnafu {
             msg: "Failed to build client with new cookie",
         })?;
-        // load newest config
-        self.proxy = CLEWDR_CONFIG.load().wreq_proxy.to_owned();
         Ok(res)
     }
 
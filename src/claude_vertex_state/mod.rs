@@ -0,0 +1,174 @@
+use serde_json::json;
+use snafu::ResultExt;
+use tracing::{debug, info};
+use wreq::{Client, ClientBuilder, StatusCode};
+
+use crate::{
+    config::{Backend, CLEWDR_CONFIG, ServiceAccountKey},
+    error::{CheckGeminiErr, ClewdrError, WreqSnafu},
+    gemini_state::{vertex_region, vertex_token},
+    provider::{self, Provider, Verdict},
+    services::proxy_pool::{PROXY_POOL, to_wreq_proxy},
+    streaming::StreamDialect,
+    types::claude::CreateMessageParams,
+    utils::{forward_guarded, forward_response},
+};
+
+/// API version Vertex expects in the body instead of the header
+const VERTEX_ANTHROPIC_VERSION: &str = "vertex-2023-10-16";
+
+/// Request state of Claude served by Vertex AI's `anthropic` publisher
+///
+/// Shares the service account, its access token cache and the region rotation
+/// with the Gemini Vertex path.
+#[derive(Clone)]
+pub struct ClaudeVertexState {
+    pub stream: bool,
+    credential: Option<ServiceAccountKey>,
+    /// Region of the current attempt
+    region: String,
+    proxy: Option<String>,
+    client: Client,
+}
+
+impl Default for ClaudeVertexState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ClaudeVertexState {
+    pub fn new() -> Self {
+        ClaudeVertexState {
+            stream: false,
+            credential: None,
+            region: vertex_region::GLOBAL.to_string(),
+            proxy: None,
+            client: Client::new(),
+        }
+    }
+
+    fn build_client(&mut self, model: &str) -> Result<(), ClewdrError> {
+        let client = CLEWDR_CONFIG.load().timeouts.client(
+            ClientBuilder::new(),
+            Backend::Vertex,
+            Some(model),
+        );
+        self.proxy = PROXY_POOL.resolve(None);
+        let client = if let Some(proxy) = self.proxy.as_deref().and_then(to_wreq_proxy) {
+            client.proxy(proxy)
+        } else {
+            client
+        };
+        self.client = client.build().context(WreqSnafu {
+            msg: "Failed to build Vertex client",
+        })?;
+        Ok(())
+    }
+}
+
+impl Provider for ClaudeVertexState {
+    type Request = CreateMessageParams;
+
+    const NAME: &'static str = "claude_vertex";
+
+    async fn prepare(&mut self) -> Result<(), ClewdrError> {
+        let Some(cred) = CLEWDR_CONFIG.load().vertex.credential.to_owned() else {
+            return Err(ClewdrError::BadRequest {
+                msg: "Vertex credential not found",
+            });
+        };
+        self.credential = Some(cred);
+        self.region = vertex_region::next_region();
+        Ok(())
+    }
+
+    async fn send(&mut self, mut p: CreateMessageParams) -> Result<wreq::Response, ClewdrError> {
+        let Some(cred) = self.credential.to_owned() else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "Credential is None, did you prepare the attempt?",
+            });
+        };
+        let beta = if let Some(model) = p.model.strip_suffix("-1M") {
+            p.model = model.to_string();
+            Some("context-1m-2025-08-07")
+        } else {
+            None
+        };
+        self.build_client(&p.model)?;
+        let access_token = vertex_token::get_token(&cred, &self.client).await?;
+        info!("[VERTEX] {} {}", self.region, p.model);
+        let method = if self.stream {
+            "streamRawPredict"
+        } else {
+            "rawPredict"
+        };
+        let endpoint = format!(
+            "{}/v1/projects/{}/locations/{}/publishers/anthropic/models/{}:{method}",
+            vertex_region::base_url(&self.region),
+            cred.project_id.unwrap_or_default(),
+            self.region,
+            p.model
+        );
+        debug!("[UPSTREAM] {}", endpoint);
+        // the model is part of the URL, Vertex rejects it in the body
+        let mut body = serde_json::to_value(p)?;
+        if let Some(obj) = body.as_object_mut() {
+            obj.remove("model");
+            obj.insert("anthropic_version".into(), VERTEX_ANTHROPIC_VERSION.into());
+            if let Some(beta) = beta {
+                obj.insert("anthropic_beta".into(), json!([beta]));
+            }
+        }
+        self.client
+            .post(endpoint)
+            .bearer_auth(access_token)
+            .json(&body)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to send request to Claude Vertex API",
+            })?
+            .check_gemini()
+            .await
+            .map_err(|e| match e {
+                ClewdrError::GeminiHttpError { code, inner } => {
+                    ClewdrError::UpstreamHttpError { code, inner }
+                }
+                e => e,
+            })
+    }
+
+    async fn decode(
+        &mut self,
+        res: wreq::Response,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        if self.stream {
+            return forward_guarded(res, StreamDialect::Claude, || ()).await;
+        }
+        forward_response(res)
+    }
+
+    async fn classify_error(&mut self, e: ClewdrError) -> Verdict {
+        match e {
+            // another region may have quota or capacity left
+            ClewdrError::UpstreamHttpError { code, .. }
+                if code == StatusCode::TOO_MANY_REQUESTS || code.is_server_error() =>
+            {
+                Verdict::Retry(e)
+            }
+            e if provider::transient(&e, self.proxy.as_deref()) => Verdict::Retry(e),
+            e => Verdict::Fail(e),
+        }
+    }
+
+    /// The region, the service account is the same for every attempt
+    fn credential(&self) -> Option<String> {
+        Some(self.region.to_owned())
+    }
+
+    /// The last upstream error tells more than a bare retry count
+    fn exhausted(&self, last: ClewdrError) -> ClewdrError {
+        last
+    }
+}
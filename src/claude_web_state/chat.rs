@@ -1,14 +1,15 @@
-use colored::Colorize;
-use futures::TryFutureExt;
 use serde_json::json;
 use snafu::ResultExt;
-use tracing::{Instrument, debug, error, info, info_span, warn};
+use tracing::{debug, warn};
 use wreq::{Method, Response, header::ACCEPT};
 
-use super::ClaudeWebState;
+use super::{CHAT_NAME_PREFIX, ClaudeWebState};
 use crate::{
-    config::CLEWDR_CONFIG,
+    config::{Backend, CLEWDR_CONFIG},
     error::{CheckClaudeErr, ClewdrError, WreqSnafu},
+    provider::{self, Provider, Verdict},
+    services::{mock, quality_gate},
+    streaming::StreamDialect,
     types::claude::CreateMessageParams,
     utils::print_out_json,
 };
@@ -16,16 +17,9 @@ use crate::{
 impl ClaudeWebState {
     /// Attempts to send a chat message to Claude API with retry mechanism
     ///
-    /// This method handles the complete chat flow including:
-    /// - Request preparation and logging
-    /// - Cookie management for authentication
-    /// - Executing the chat request with automatic retries on failure
-    /// - Response transformation according to the specified API format
-    /// - Error handling and cleanup
-    ///
-    /// The method implements a sophisticated retry mechanism to handle transient failures,
-    /// and manages conversation cleanup to prevent resource leaks. It also includes
-    /// performance tracking to measure response times.
+    /// Each attempt bootstraps a cookie from the pool, creates a conversation
+    /// for the request and deletes it again afterwards, see the [`Provider`]
+    /// implementation.
     ///
     /// # Arguments
     /// * `p` - The client request body containing messages and configuration
@@ -36,44 +30,24 @@ impl ClaudeWebState {
         &mut self,
         p: CreateMessageParams,
     ) -> Result<axum::response::Response, ClewdrError> {
-        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
-            if i > 0 {
-                info!("[RETRY] attempt: {}", i.to_string().green());
-            }
-            let mut state = self.to_owned();
-            let p = p.to_owned();
-
-            let cookie = state.request_cookie().await?;
-            // check if request is successful
-            let web_res = async { state.bootstrap().await.and(state.send_chat(p).await) };
-            let transform_res = web_res
-                .and_then(async |r| self.transform_response(r).await)
-                .instrument(info_span!("claude_web", "cookie" = cookie.cookie.ellipse()));
-
-            match transform_res.await {
-                Ok(b) => {
-                    if let Err(e) = state.clean_chat().await {
-                        warn!("Failed to clean chat: {}", e);
-                    }
-                    return Ok(b);
-                }
-                Err(e) => {
-                    // delete chat after an error
-                    if let Err(e) = state.clean_chat().await {
-                        warn!("Failed to clean chat: {}", e);
-                    }
-                    error!("{e}");
-                    // 429 error
-                    if let ClewdrError::InvalidCookie { reason } = e {
-                        state.return_cookie(Some(reason.to_owned())).await;
-                        continue;
-                    }
-                    return Err(e);
-                }
-            }
+        if mock::enabled() {
+            let input_tokens = mock::estimate_tokens(&p);
+            return mock::claude(&p.model, p.stream.unwrap_or_default(), input_tokens).await;
+        }
+        let stream = p.stream.unwrap_or_default();
+        let res = provider::chat_with_retries(self, p.to_owned()).await?;
+        if stream {
+            return Ok(res);
         }
-        error!("Max retries exceeded");
-        Err(ClewdrError::TooManyRetries)
+        // the sticky cookie answered poorly, a retry may take any other
+        let mut state = self.to_owned();
+        state.session_hash = None;
+        quality_gate::enforce(res, StreamDialect::Claude, || {
+            let state = state.to_owned();
+            let p = p.to_owned();
+            async move { provider::chat_with_retries(&state, p).await }
+        })
+        .await
     }
 
     /// Sends a message to the Claude API by creating a new conversation and processing the request
@@ -110,7 +84,10 @@ impl ClaudeWebState {
         );
         let body = json!({
             "uuid": new_uuid,
-            "name": format!("ClewdR-{}", chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")),
+            "name": format!(
+                "{CHAT_NAME_PREFIX}{}",
+                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")
+            ),
         });
 
         self.build_request(Method::POST, endpoint)
@@ -142,6 +119,7 @@ impl ClaudeWebState {
             .json(&body)
             .send()
             .await;
+        let model = p.model.to_owned();
         // generate the request body
         // check if the request is empty
         let mut body = self.transform_request(p).ok_or(ClewdrError::BadRequest {
@@ -161,8 +139,13 @@ impl ClaudeWebState {
             "{}/api/organizations/{}/chat_conversations/{}/completion",
             self.endpoint, org_uuid, new_uuid
         );
+        debug!("[UPSTREAM] {}", endpoint);
 
-        self.build_request(Method::POST, endpoint)
+        let req = self.build_request(Method::POST, endpoint);
+        CLEWDR_CONFIG
+            .load()
+            .timeouts
+            .chat(req, Backend::ClaudeWeb, &model)
             .json(&body)
             .header_append(ACCEPT, "text/event-stream")
             .send()
@@ -174,3 +157,50 @@ impl ClaudeWebState {
             .await
     }
 }
+
+impl Provider for ClaudeWebState {
+    type Request = CreateMessageParams;
+
+    const NAME: &'static str = "claude_web";
+
+    async fn prepare(&mut self) -> Result<(), ClewdrError> {
+        self.request_cookie().await?;
+        self.bootstrap().await
+    }
+
+    async fn send(&mut self, p: CreateMessageParams) -> Result<Response, ClewdrError> {
+        self.send_chat(p).await
+    }
+
+    async fn decode(&mut self, res: Response) -> Result<axum::response::Response, ClewdrError> {
+        self.transform_response(res).await
+    }
+
+    async fn classify_error(&mut self, e: ClewdrError) -> Verdict {
+        // 429 error
+        if let ClewdrError::InvalidCookie { ref reason } = e {
+            let reason = reason.to_owned();
+            self.return_cookie(Some(reason)).await;
+            return Verdict::Retry(e);
+        }
+        if provider::transient(&e, self.proxy.as_deref()) {
+            return Verdict::Retry(e);
+        }
+        Verdict::Fail(e)
+    }
+
+    async fn report_outcome(&mut self, _: bool) {
+        // conversations are deleted after errors too
+        if let Err(e) = self.clean_chat().await {
+            warn!("Failed to clean chat: {}", e);
+        }
+    }
+
+    fn credential(&self) -> Option<String> {
+        self.cookie.as_ref().map(|c| c.cookie.ellipse())
+    }
+
+    fn credential_id(&self) -> Option<String> {
+        self.cookie.as_ref().map(|c| c.cookie.to_string())
+    }
+}
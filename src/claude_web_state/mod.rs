@@ -1,26 +1,36 @@
 use std::sync::LazyLock;
 
 use axum::http::HeaderValue;
+use serde_json::Value;
 use snafu::ResultExt;
-use tracing::{debug, error};
+use tracing::{Instrument, debug, error, info, warn};
 use url::Url;
 use wreq::{
-    Client, ClientBuilder, IntoUrl, Method, Proxy, RequestBuilder,
+    Client, ClientBuilder, IntoUrl, Method, RequestBuilder,
     header::{ORIGIN, REFERER},
 };
 use wreq_util::Emulation;
 
 use crate::{
-    config::{CLAUDE_ENDPOINT, CLEWDR_CONFIG, CookieStatus, Reason},
-    error::{ClewdrError, WreqSnafu},
+    config::{Backend, CLAUDE_ENDPOINT, CLEWDR_CONFIG, ChatCleanup, CookieStatus, Reason},
+    error::{CheckClaudeErr, ClewdrError, WreqSnafu},
     middleware::claude::ClaudeApiFormat,
-    services::cookie_actor::CookieActorHandle,
+    services::{
+        cookie_actor::CookieActorHandle,
+        proxy_pool::{PROXY_POOL, to_wreq_proxy},
+    },
     types::claude::Usage,
 };
 
 pub mod bootstrap;
 pub mod chat;
 mod transform;
+/// Name prefix of the conversations ClewdR creates, only those are swept
+pub const CHAT_NAME_PREFIX: &str = "ClewdR-";
+
+/// Age below which a conversation is never swept, in seconds
+const CHAT_GRACE_SECS: i64 = 600;
+
 /// Placeholder
 pub static SUPER_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
 
@@ -34,12 +44,15 @@ pub struct ClaudeWebState {
     pub conv_uuid: Option<String>,
     pub capabilities: Vec<String>,
     pub endpoint: Url,
-    pub proxy: Option<Proxy>,
+    /// Proxy used by the current client
+    pub proxy: Option<String>,
     pub api_format: ClaudeApiFormat,
     pub stream: bool,
     pub client: Client,
     pub key: Option<(u64, usize)>,
     pub usage: Usage,
+    /// Hash pinning the conversation to a cookie
+    pub session_hash: Option<u64>,
 }
 
 impl ClaudeWebState {
@@ -53,12 +66,13 @@ impl ClaudeWebState {
             cookie_header_value: HeaderValue::from_static(""),
             capabilities: Vec::new(),
             endpoint: CLEWDR_CONFIG.load().endpoint(),
-            proxy: CLEWDR_CONFIG.load().wreq_proxy.to_owned(),
+            proxy: None,
             api_format: ClaudeApiFormat::Claude,
             stream: false,
             client: SUPER_CLIENT.to_owned(),
             key: None,
             usage: Usage::default(),
+            session_hash: None,
         }
     }
 
@@ -102,22 +116,32 @@ impl ClaudeWebState {
     /// Requests a new cookie from the cookie manager
     /// Updates the internal state with the new cookie and proxy configuration
     pub async fn request_cookie(&mut self) -> Result<CookieStatus, ClewdrError> {
-        let res = self.cookie_actor_handle.request(None).await?;
+        let res = self.cookie_actor_handle.request(self.session_hash).await?;
+        self.use_cookie(res.to_owned())?;
+        Ok(res)
+    }
+
+    /// Switches the state to `res`, building a client through its proxy
+    pub fn use_cookie(&mut self, res: CookieStatus) -> Result<(), ClewdrError> {
         self.cookie = Some(res.to_owned());
-        let mut client = ClientBuilder::new()
+        let client = ClientBuilder::new()
             .cookie_store(true)
             .emulation(Emulation::Chrome136);
-        if let Some(ref proxy) = self.proxy {
-            client = client.proxy(proxy.to_owned());
+        let mut client = CLEWDR_CONFIG
+            .load()
+            .timeouts
+            .client(client, Backend::ClaudeWeb, None);
+        self.proxy = PROXY_POOL.resolve(res.proxy.as_deref());
+        if let Some(proxy) = self.proxy.as_deref().and_then(to_wreq_proxy) {
+            client = client.proxy(proxy);
         }
         self.client = client.build().context(WreqSnafu {
             msg: "Failed to build client with new cookie",
         })?;
         self.cookie_header_value = HeaderValue::from_str(res.cookie.to_string().as_str())?;
         // load newest config
-        self.proxy = CLEWDR_CONFIG.load().wreq_proxy.to_owned();
         self.endpoint = CLEWDR_CONFIG.load().endpoint();
-        Ok(res)
+        Ok(())
     }
 
     /// Returns the current cookie to the cookie manager
@@ -134,30 +158,121 @@ impl ClaudeWebState {
         }
     }
 
-    /// Deletes or renames the current chat conversation based on configuration
-    /// If preserve_chats is true, the chat is renamed rather than deleted
+    /// Cleans up the current chat conversation according to `chat_cleanup`
+    ///
+    /// The conversation is deleted right away, or the account's older
+    /// conversations are swept in the background when only the most recent
+    /// ones are kept. TTL based cleanup is left to the background sweeper.
     pub async fn clean_chat(&self) -> Result<(), ClewdrError> {
-        if CLEWDR_CONFIG.load().preserve_chats {
-            return Ok(());
-        }
         let Some(ref org_uuid) = self.org_uuid else {
             return Ok(());
         };
-        let Some(ref conv_uuid) = self.conv_uuid else {
-            return Ok(());
-        };
+        match CLEWDR_CONFIG.load().cleanup_policy() {
+            ChatCleanup::Immediate => {
+                if let Some(ref conv_uuid) = self.conv_uuid {
+                    self.delete_chat(org_uuid, conv_uuid).await;
+                }
+            }
+            ChatCleanup::KeepRecent(_) => {
+                let state = self.to_owned();
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = state.sweep_chats().await {
+                            warn!("Failed to sweep chats: {}", e);
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            ChatCleanup::Ttl(_) | ChatCleanup::Keep => {}
+        }
+        Ok(())
+    }
+
+    /// Deletes a chat conversation, failures are only logged
+    async fn delete_chat(&self, org_uuid: &str, conv_uuid: &str) {
         let endpoint = format!(
             "{}/api/organizations/{}/chat_conversations/{}",
             self.endpoint, org_uuid, conv_uuid
         );
         debug!("Deleting chat: {}", conv_uuid);
-        let _ = self
+        if let Err(e) = self
             .build_request(Method::DELETE, endpoint)
             .send()
             .await
             .context(WreqSnafu {
                 msg: "Failed to delete chat conversation",
-            });
+            })
+        {
+            warn!("{}", e);
+        }
+    }
+
+    /// Deletes the conversations ClewdR created on the account that the
+    /// cleanup policy no longer keeps
+    ///
+    /// Conversations created within [`CHAT_GRACE_SECS`] are spared, they may
+    /// belong to requests still in flight. Requires a bootstrapped state.
+    pub async fn sweep_chats(&self) -> Result<(), ClewdrError> {
+        let policy = CLEWDR_CONFIG.load().cleanup_policy();
+        if matches!(policy, ChatCleanup::Immediate | ChatCleanup::Keep) {
+            return Ok(());
+        }
+        let Some(ref org_uuid) = self.org_uuid else {
+            return Ok(());
+        };
+        let endpoint = format!(
+            "{}/api/organizations/{}/chat_conversations",
+            self.endpoint, org_uuid
+        );
+        let list = self
+            .build_request(Method::GET, endpoint)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to list chat conversations",
+            })?
+            .check_claude()
+            .await?
+            .json::<Value>()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to parse chat conversations",
+            })?;
+        let mut chats = list
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|c| {
+                c["name"]
+                    .as_str()
+                    .is_some_and(|n| n.starts_with(CHAT_NAME_PREFIX))
+            })
+            .filter_map(|c| {
+                let created =
+                    chrono::DateTime::parse_from_rfc3339(c["created_at"].as_str()?).ok()?;
+                Some((c["uuid"].as_str()?.to_string(), created.timestamp()))
+            })
+            .collect::<Vec<_>>();
+        chats.sort_by_key(|(_, created)| std::cmp::Reverse(*created));
+        let now = chrono::Utc::now().timestamp();
+        let stale = chats
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, created))| now - created >= CHAT_GRACE_SECS)
+            .filter(|(i, (_, created))| match policy {
+                ChatCleanup::KeepRecent(n) => *i >= n,
+                ChatCleanup::Ttl(secs) => now - created >= secs as i64,
+                ChatCleanup::Immediate | ChatCleanup::Keep => false,
+            })
+            .map(|(_, (uuid, _))| uuid)
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            info!("Sweeping {} chat conversations", stale.len());
+        }
+        for uuid in stale {
+            self.delete_chat(org_uuid, uuid).await;
+        }
         Ok(())
     }
 }
@@ -10,6 +10,7 @@ use wreq::multipart::{Form, Part};
 use crate::{
     claude_web_state::ClaudeWebState,
     config::CLEWDR_CONFIG,
+    services::image_fetch::parse_data_url,
     types::{
         claude::{ContentBlock, CreateMessageParams, ImageSource, Message, MessageContent, Role},
         claude_web::request::*,
@@ -159,15 +160,15 @@ fn merge_messages(msgs: Vec<Message>, system: String) -> Option<Merged> {
                 let blocks = content
                     .into_iter()
                     .filter_map(|b| match b {
-                        ContentBlock::Text { text } => Some(text.trim().to_string()),
-                        ContentBlock::Image { source } => {
+                        ContentBlock::Text { text, .. } => Some(text.trim().to_string()),
+                        ContentBlock::Image { source, .. } => {
                             // push image to the list
                             imgs.push(source);
                             None
                         }
                         ContentBlock::ImageUrl { image_url } => {
                             // oai image
-                            if let Some(source) = extract_image_from_url(&image_url.url) {
+                            if let Some(source) = parse_data_url(&image_url.url) {
                                 imgs.push(source);
                             }
                             None
@@ -249,18 +250,3 @@ fn merge_system(sys: Value) -> String {
         _ => String::new(),
     }
 }
-
-fn extract_image_from_url(url: &str) -> Option<ImageSource> {
-    if !url.starts_with("data:") {
-        return None; // only support data URI
-    }
-    let (metadata, base64_data) = url.split_once(',')?;
-
-    let (media_type, type_) = metadata.strip_prefix("data:")?.split_once(';')?;
-
-    Some(ImageSource {
-        type_: type_.to_string(),
-        media_type: media_type.to_string(),
-        data: base64_data.to_owned(),
-    })
-}
@@ -1,8 +1,11 @@
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env,
     fmt::{Debug, Display},
     net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::OnceLock,
+    time::Duration,
 };
 
 use axum::http::{Uri, uri::Scheme};
@@ -10,27 +13,133 @@ use clap::Parser;
 use colored::Colorize;
 use figment::{
     Figment,
-    providers::{Env, Format, Toml},
+    providers::{Env, Format, Serialized, Toml},
 };
 use http::uri::Authority;
 use passwords::PasswordGenerator;
 use serde::{Deserialize, Serialize};
-use tokio::spawn;
-use tracing::error;
+use tokio::{io::AsyncWriteExt, spawn, sync::Mutex};
+use tracing::{error, warn};
 use wreq::{Proxy, Url};
-use yup_oauth2::ServiceAccountKey;
 
-use super::{CONFIG_PATH, ENDPOINT_URL, key::KeyStatus};
+use super::{
+    CLEWDR_CONFIG, CONFIG_PATH, ENDPOINT_URL,
+    key::{GeminiKey, KeyShard, KeyStatus},
+};
 use crate::{
     Args,
     config::{
-        CC_CLIENT_ID, CookieStatus, UselessCookie, default_check_update, default_ip,
-        default_max_retries, default_port, default_skip_cool_down, default_use_real_roles,
+        CC_CLIENT_ID, ClewdrCookie, CookieStatus, SealedCredentials, UselessCookie,
+        default_adaptive_backoff, default_adaptive_max_concurrency,
+        default_adaptive_min_concurrency, default_batch_concurrency, default_chaos_delay_ms,
+        default_chaos_error_statuses, default_check_update, default_claude_thinking_budget,
+        default_connect_timeout, default_connection_max_age, default_context_windows,
+        default_cors_headers, default_cors_methods, default_cors_origins, default_error_policy,
+        default_gemini_merge_turns, default_gemini_version_fallback, default_hook_stages,
+        default_hook_timeout_secs, default_ip, default_jwt_leeway,
+        default_keep_alive_interval_secs, default_key_budget_rotate_at, default_max_body_size,
+        default_max_image_size, default_max_retries, default_mock_error_status,
+        default_mock_response, default_output_limits, default_port, default_quality_retries,
+        default_queue_max_depth, default_queue_timeout, default_redaction_replacement,
+        default_redis_sync_secs, default_remote_config_poll_secs, default_request_timeout,
+        default_response_cache_entries, default_response_cache_ttl, default_s3_region,
+        default_serve_stale, default_skip_cool_down, default_sticky_session,
+        default_sticky_session_ttl, default_stream_resume_events, default_token_refresh_ahead,
+        default_unix_socket_tcp, default_use_real_roles, format_issues, open_credentials,
+        persist_cookies, seal_credentials,
     },
     error::ClewdrError,
     utils::enabled,
 };
 
+/// `CLEWDR_*` variables holding credentials in their own format rather than
+/// the config format
+const ENV_CREDENTIALS: [&str; 4] = [
+    "GEMINI_KEYS",
+    "COOKIES",
+    "VERTEX_CREDENTIAL",
+    "CONFIG_PASSPHRASE",
+];
+
+/// Value of `CLEWDR_<name>`, or else the contents of the file named by
+/// `CLEWDR_<name>_FILE`
+pub(crate) fn env_or_file(name: &str) -> Option<String> {
+    if let Ok(value) = env::var(format!("CLEWDR_{name}")) {
+        return Some(value);
+    }
+    let path = env::var(format!("CLEWDR_{name}_FILE")).ok()?;
+    std::fs::read_to_string(&path)
+        .inspect_err(|e| error!("Failed to read CLEWDR_{}_FILE from {}: {}", name, path, e))
+        .ok()
+        .map(|v| v.trim_end().to_string())
+}
+
+/// Top level config keys set by `CLEWDR_*` variables or secrets files, see
+/// [`ClewdrConfig::figment`]
+fn env_keys() -> HashSet<String> {
+    env::vars()
+        .filter_map(|(name, _)| {
+            let key = name.strip_prefix("CLEWDR_")?;
+            let key = key.strip_suffix("_FILE").unwrap_or(key);
+            (!ENV_CREDENTIALS.iter().any(|c| c.eq_ignore_ascii_case(key)))
+                .then(|| key.to_ascii_lowercase())
+        })
+        .collect()
+}
+
+/// What the environment overlaid onto the config file, set once at startup
+static ENV_OVERLAY: OnceLock<EnvOverlay> = OnceLock::new();
+
+/// Settings and credentials taken from the environment rather than the
+/// config file, they are kept in memory only and left out when saving
+#[derive(Debug, Default)]
+struct EnvOverlay {
+    /// Keys set from the environment, with the value the config file has
+    keys: Vec<(String, Option<toml::Value>)>,
+    /// Cookies from the environment the config file does not hold
+    cookies: HashSet<ClewdrCookie>,
+    /// Gemini keys from the environment the config file does not hold
+    gemini_keys: HashSet<KeyStatus>,
+    /// Vertex credential of the config file, if the environment replaced it
+    vertex_credential: Option<Option<ServiceAccountKey>>,
+}
+
+impl EnvOverlay {
+    /// Removes the credentials that came from the environment
+    fn strip(&self, config: &mut ClewdrConfig) {
+        config
+            .cookie_array
+            .retain(|c| !self.cookies.contains(&c.cookie));
+        config
+            .wasted_cookie
+            .retain(|c| !self.cookies.contains(&c.cookie));
+        config.gemini_keys.retain(|k| !self.gemini_keys.contains(k));
+        if let Some(ref credential) = self.vertex_credential {
+            config.vertex.credential = credential.to_owned();
+        }
+    }
+
+    /// Puts back the values of the config file for keys set from the
+    /// environment
+    fn restore(&self, table: &mut toml::Table) {
+        for (key, value) in &self.keys {
+            match value {
+                Some(value) => table.insert(key.to_owned(), value.to_owned()),
+                None => table.remove(key),
+            };
+        }
+    }
+}
+
+/// Items of a list separated by commas, whitespace or newlines
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|s| !s.is_empty())
+}
+
+/// Serializes writes of the config file
+static SAVE_LOCK: Mutex<()> = Mutex::const_new(());
+
 /// Generates a random password for authentication
 /// Creates a secure 64-character password with mixed character types
 ///
@@ -52,11 +161,38 @@ fn generate_password() -> String {
     pg.generate_one().unwrap()
 }
 
+/// Google service account credential, as downloaded from the cloud console
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceAccountKey {
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub private_key_id: Option<String>,
+    pub private_key: String,
+    pub client_email: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_uri: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_provider_x509_cert_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_x509_cert_url: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct VertexConfig {
     #[serde(default)]
     pub credential: Option<ServiceAccountKey>,
     pub model_id: Option<String>,
+    /// Regions requests rotate over, e.g. `us-east5`, the global endpoint if
+    /// empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub regions: Vec<String>,
 }
 
 impl VertexConfig {
@@ -65,24 +201,941 @@ impl VertexConfig {
     }
 }
 
+/// Unix domain socket listener, for reverse proxies on the same host
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnixSocketConfig {
+    pub path: PathBuf,
+    /// Permissions of the socket file in octal notation, e.g. `660`
+    #[serde(default)]
+    pub mode: Option<String>,
+    /// Keep serving on TCP as well
+    #[serde(default = "default_unix_socket_tcp")]
+    pub tcp: bool,
+}
+
+impl UnixSocketConfig {
+    /// Binds the socket, replacing a stale socket file and applying permissions
+    ///
+    /// Anything but a socket at the path, e.g. a regular file from a wrong
+    /// path, is left alone and fails the bind
+    #[cfg(unix)]
+    pub fn bind(&self) -> Result<tokio::net::UnixListener, ClewdrError> {
+        use std::{
+            io::{Error, ErrorKind},
+            os::unix::fs::{FileTypeExt, PermissionsExt},
+        };
+
+        match std::fs::symlink_metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(&self.path)?,
+            Ok(_) => {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!(
+                        "{} exists and is not a socket, refusing to replace it",
+                        self.path.display()
+                    ),
+                )
+                .into());
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+            && !parent.exists()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let listener = tokio::net::UnixListener::bind(&self.path)?;
+        if let Some(ref mode) = self.mode {
+            let mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)?;
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(mode))?;
+        }
+        Ok(listener)
+    }
+}
+
+/// Cache of non-streaming completions, keyed on the normalized request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResponseCacheConfig {
+    /// Lifetime of a cached response, in seconds
+    #[serde(default = "default_response_cache_ttl")]
+    pub ttl: u64,
+    /// Maximum number of responses kept in memory
+    #[serde(default = "default_response_cache_entries")]
+    pub max_entries: u64,
+    /// Also keep responses on disk, next to the config file
+    #[serde(default)]
+    pub disk: bool,
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: default_response_cache_ttl(),
+            max_entries: default_response_cache_entries(),
+            disk: false,
+        }
+    }
+}
+
+/// Thinking settings for Gemini models, applied when the client sets none
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GeminiThinkingConfig {
+    /// Default thinking budget in tokens, `-1` for dynamic and `0` to disable
+    #[serde(default)]
+    pub budget: Option<i32>,
+    /// Ask Gemini for thought summaries
+    #[serde(default)]
+    pub include_thoughts: bool,
+    /// Return thought summaries as `reasoning_content` in OpenAI format
+    /// responses instead of stripping them
+    #[serde(default)]
+    pub expose_thoughts: bool,
+}
+
+impl GeminiThinkingConfig {
+    /// Whether the model accepts a thinking config, sending one to older
+    /// models fails the request
+    pub fn supports(model: &str) -> bool {
+        let model = model.trim_start_matches("google/");
+        model.starts_with("gemini-2.5") || model.starts_with("gemini-3")
+    }
+}
+
+/// Extended thinking settings for Claude models
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ClaudeThinkingConfig {
+    /// Thinking budget in tokens used for models requested with a `-thinking`
+    /// suffix
+    #[serde(default = "default_claude_thinking_budget")]
+    pub budget: u64,
+    /// Drop thinking from OpenAI format responses instead of returning it as
+    /// `reasoning_content`
+    #[serde(default)]
+    pub strip_for_oai: bool,
+}
+
+impl Default for ClaudeThinkingConfig {
+    fn default() -> Self {
+        Self {
+            budget: default_claude_thinking_budget(),
+            strip_for_oai: false,
+        }
+    }
+}
+
+/// Output token ceilings, enforced before a request is sent upstream
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutputLimitConfig {
+    /// Reject requests above the ceiling instead of lowering them to it
+    #[serde(default)]
+    pub reject: bool,
+    /// Ceiling per model name prefix, the longest matching prefix wins
+    #[serde(default = "default_output_limits")]
+    pub models: HashMap<String, u32>,
+}
+
+impl Default for OutputLimitConfig {
+    fn default() -> Self {
+        Self {
+            reject: false,
+            models: default_output_limits(),
+        }
+    }
+}
+
+impl OutputLimitConfig {
+    /// Output token ceiling of the model, if known
+    pub fn ceiling(&self, model: &str) -> Option<u32> {
+        let model = model
+            .trim_start_matches("models/")
+            .trim_start_matches("google/");
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limit)| *limit)
+    }
+
+    /// Checks the requested output tokens against the ceiling of the model
+    ///
+    /// # Returns
+    /// The value to send upstream, lowered to the ceiling unless `reject` is set
+    pub fn apply(&self, model: &str, requested: u32) -> Result<u32, ClewdrError> {
+        let Some(limit) = self.ceiling(model).filter(|l| requested > *l) else {
+            return Ok(requested);
+        };
+        if self.reject {
+            return Err(ClewdrError::MaxTokensExceeded {
+                model: model.to_string(),
+                requested,
+                limit,
+            });
+        }
+        warn!(
+            "Lowering max tokens of {} from {} to {}",
+            model, requested, limit
+        );
+        Ok(limit)
+    }
+}
+
+/// Surface of the Gemini API serving a model, preview models often live on
+/// one version only
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, strum::Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum GeminiApiVersion {
+    V1,
+    #[default]
+    V1beta,
+    V1alpha,
+}
+
+/// Gemini API version of each model
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GeminiApiVersionConfig {
+    /// Version per model name prefix, the longest matching prefix wins and
+    /// other models use v1beta
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub models: HashMap<String, GeminiApiVersion>,
+    /// Try the other versions when a model is not found on its own
+    #[serde(default = "default_gemini_version_fallback")]
+    pub fallback: bool,
+}
+
+impl Default for GeminiApiVersionConfig {
+    fn default() -> Self {
+        Self {
+            models: HashMap::new(),
+            fallback: default_gemini_version_fallback(),
+        }
+    }
+}
+
+impl GeminiApiVersionConfig {
+    /// Configured version of the model
+    pub fn version(&self, model: &str) -> GeminiApiVersion {
+        let model = model.trim_start_matches("models/");
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, version)| *version)
+            .unwrap_or_default()
+    }
+}
+
+/// What happens to a prompt that does not fit the context window of the model
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ContextStrategy {
+    /// Forward the prompt as is
+    #[default]
+    Off,
+    /// Fail the request with a 400
+    Reject,
+    /// Drop the oldest turns until the prompt fits
+    DropOldestTurns,
+    /// Drop turns from the middle, keeping the system prompt, the first turn
+    /// and the latest turns
+    MiddleOut,
+}
+
+/// Context window handling, applied before a request is sent upstream
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ContextLimitConfig {
+    #[serde(default)]
+    pub strategy: ContextStrategy,
+    /// Context window per model name prefix, the longest matching prefix wins
+    #[serde(default = "default_context_windows")]
+    pub models: HashMap<String, u32>,
+}
+
+impl Default for ContextLimitConfig {
+    fn default() -> Self {
+        Self {
+            strategy: Default::default(),
+            models: default_context_windows(),
+        }
+    }
+}
+
+impl ContextLimitConfig {
+    /// Context window of the model, if known
+    pub fn window(&self, model: &str) -> Option<u32> {
+        let model = model
+            .trim_start_matches("models/")
+            .trim_start_matches("google/");
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limit)| *limit)
+    }
+}
+
+/// Upstream an API request is served by
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Backend {
+    ClaudeWeb,
+    ClaudeCode,
+    Gemini,
+    Vertex,
+    /// Upstreams of `openai_upstreams`
+    #[serde(rename = "openai")]
+    OpenAI,
+}
+
+/// OpenAI compatible upstream, e.g. OpenRouter, vLLM, Ollama or LM Studio
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenAIUpstream {
+    /// Prefix selecting the upstream, clients request `<name>/<model>`
+    pub name: String,
+    /// URL the `chat/completions` path is appended to, e.g.
+    /// `https://openrouter.ai/api/v1` or `http://127.0.0.1:11434/v1`
+    pub base_url: Url,
+    /// API keys used in rotation, none for local servers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<String>,
+    /// Models also served without the name prefix, and listed by `/openai/models`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Proxy for this upstream, overrides the proxy pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+/// Routing of Gemini requests to key tiers, see [`KeyStatus::tier`]
+///
+/// Tiers are tried in order, falling back to the next one when none of the
+/// keys of a tier is usable. A client can pick its own tiers with the
+/// `x-clewdr-key-tier` header.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct KeyTierConfig {
+    /// Tiers per model name prefix, the longest matching prefix wins
+    #[serde(default)]
+    pub models: HashMap<String, Vec<String>>,
+    /// Tiers for the other models, empty to use any key
+    #[serde(default)]
+    pub default: Vec<String>,
+}
+
+impl KeyTierConfig {
+    /// Tiers serving `model` in fallback order, empty for any key
+    pub fn route(&self, model: &str) -> Vec<String> {
+        let model = model.trim_start_matches("models/");
+        self.models
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, tiers)| tiers)
+            .unwrap_or(&self.default)
+            .to_owned()
+    }
+}
+
+/// Timeouts of upstream requests, `0` waits forever
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TimeoutConfig {
+    /// Whole request including a streamed body, in seconds
+    #[serde(default = "default_request_timeout")]
+    pub request_secs: u64,
+    /// Connecting to the upstream, in seconds
+    #[serde(default = "default_connect_timeout")]
+    pub connect_secs: u64,
+    /// `request_secs` per backend
+    #[serde(default)]
+    pub backends: HashMap<Backend, u64>,
+    /// `request_secs` per model name prefix, the longest matching prefix wins
+    /// over the backend one, e.g. for long thinking models
+    #[serde(default)]
+    pub models: HashMap<String, u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            request_secs: default_request_timeout(),
+            connect_secs: default_connect_timeout(),
+            backends: HashMap::new(),
+            models: HashMap::new(),
+        }
+    }
+}
+
+impl TimeoutConfig {
+    fn duration(secs: u64) -> Option<Duration> {
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// Timeout of a request to `backend`, for `model` if known
+    pub fn request(&self, backend: Backend, model: Option<&str>) -> Option<Duration> {
+        let model = model.map(|m| {
+            m.trim_start_matches("models/")
+                .trim_start_matches("google/")
+        });
+        let by_model = model.and_then(|model| {
+            self.models
+                .iter()
+                .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+                .max_by_key(|(prefix, _)| prefix.len())
+                .map(|(_, secs)| *secs)
+        });
+        let secs = by_model
+            .or_else(|| self.backends.get(&backend).copied())
+            .unwrap_or(self.request_secs);
+        Self::duration(secs)
+    }
+
+    /// Timeout for connecting to an upstream
+    pub fn connect(&self) -> Option<Duration> {
+        Self::duration(self.connect_secs)
+    }
+
+    /// Applies the timeouts of `backend` to a client, for `model` if the
+    /// client only serves one
+    pub fn client(
+        &self,
+        builder: wreq::ClientBuilder,
+        backend: Backend,
+        model: Option<&str>,
+    ) -> wreq::ClientBuilder {
+        let builder = match self.connect() {
+            Some(t) => builder.connect_timeout(t),
+            None => builder,
+        };
+        match self.request(backend, model) {
+            Some(t) => builder.timeout(t),
+            None => builder,
+        }
+    }
+
+    /// Applies the timeout for `model` to a single request
+    pub fn chat(
+        &self,
+        builder: wreq::RequestBuilder,
+        backend: Backend,
+        model: &str,
+    ) -> wreq::RequestBuilder {
+        match self.request(backend, Some(model)) {
+            Some(t) => builder.timeout(t),
+            None => builder,
+        }
+    }
+}
+
+/// What happens to the conversations created on claude.ai, e.g.
+/// `{ keep_recent = 20 }` or `{ ttl = 86400 }`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatCleanup {
+    /// Delete each conversation once its request is done
+    #[default]
+    Immediate,
+    /// Keep this many of the most recent conversations per account
+    KeepRecent(usize),
+    /// Delete conversations older than this many seconds, swept in the
+    /// background
+    Ttl(u64),
+    /// Never delete conversations
+    Keep,
+}
+
+/// Claude backend serving the OpenAI compatible `/v1/chat/completions` route
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaudeBackend {
+    /// claude.ai conversations through the cookie pool
+    #[default]
+    Web,
+    /// The Claude Code API with the cookies' OAuth tokens
+    Code,
+}
+
+/// How the cookie pool picks a cookie for a request
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CookieDispatch {
+    /// The cookie with the fewest requests in its 5-hour window
+    #[default]
+    Headroom,
+    /// The cookie with the smallest share of its window and weekly limits
+    /// spent, spreading requests so every cookie stays under both
+    Spread,
+}
+
+/// Usage limits of the Claude accounts behind the cookies, as far as known,
+/// cookies at a limit are skipped until their window or week resets
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ClaudeQuotaConfig {
+    #[serde(default)]
+    pub dispatch: CookieDispatch,
+    /// Requests per account in a 5-hour window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_requests: Option<u32>,
+    /// Requests per account in a week
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weekly_requests: Option<u32>,
+}
+
+/// What to do when Gemini returns an error
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorAction {
+    /// Retry with another key
+    Retry,
+    /// Take the key out of rotation for this many seconds, then retry, only
+    /// for the requested model on a 429
+    Cooldown(u64),
+    /// Take the key out of rotation until it is added again, then retry
+    Quarantine,
+    /// Remove the key, then retry
+    DeleteKey,
+    /// Return the error to the client without retrying
+    FailFast,
+}
+
+/// Maps an upstream error to an action, e.g.
+/// `{ status = 429, reason = "RESOURCE_EXHAUSTED", action = { cooldown = 60 } }`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ErrorRule {
+    /// HTTP status code, any if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// Google `ErrorInfo` reason or error status, any if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub action: ErrorAction,
+}
+
+impl ErrorRule {
+    /// Whether the rule applies to the error
+    ///
+    /// # Arguments
+    /// * `status` - HTTP status code of the response
+    /// * `reasons` - Reasons and status reported in the error body
+    pub fn matches(&self, status: u16, reasons: &[String]) -> bool {
+        self.status.is_none_or(|s| s == status)
+            && self
+                .reason
+                .as_ref()
+                .is_none_or(|r| reasons.iter().any(|reason| reason == r))
+    }
+}
+
+/// Canned upstream responses, for developing and testing clients without
+/// real credentials
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MockConfig {
+    /// Answer every chat request with the mock, also set by `--mock`
+    #[serde(default)]
+    pub enabled: bool,
+    /// Response text, `{model}` is replaced with the requested model
+    #[serde(default = "default_mock_response")]
+    pub response: String,
+    /// Delay before the first byte, in milliseconds
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Delay between streamed chunks, in milliseconds
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+    /// Share of attempts failing with `error_status`, from 0 to 1
+    #[serde(default)]
+    pub error_rate: f64,
+    #[serde(default = "default_mock_error_status")]
+    pub error_status: u16,
+}
+
+impl Default for MockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            response: default_mock_response(),
+            latency_ms: 0,
+            chunk_delay_ms: 0,
+            error_rate: 0.0,
+            error_status: default_mock_error_status(),
+        }
+    }
+}
+
+/// Faults injected into API responses, to test how clients cope with a
+/// misbehaving proxy
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChaosConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Share of requests answered with an error instead of being served
+    #[serde(default)]
+    pub error_rate: f64,
+    /// Statuses of injected errors, one is picked at random
+    #[serde(default = "default_chaos_error_statuses")]
+    pub error_statuses: Vec<u16>,
+    /// Share of responses held back before the first byte
+    #[serde(default)]
+    pub delay_rate: f64,
+    #[serde(default = "default_chaos_delay_ms")]
+    pub delay_ms: u64,
+    /// Share of streamed responses cut off after a random number of chunks
+    #[serde(default)]
+    pub truncate_rate: f64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            error_rate: 0.0,
+            error_statuses: default_chaos_error_statuses(),
+            delay_rate: 0.0,
+            delay_ms: default_chaos_delay_ms(),
+            truncate_rate: 0.0,
+        }
+    }
+}
+
+/// Central config artifact polled for changes, see
+/// [`crate::services::remote_config`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RemoteConfig {
+    /// `http(s)://` URL or `s3://bucket/key` of a config file in TOML
+    pub url: String,
+    #[serde(default = "default_remote_config_poll_secs")]
+    pub poll_secs: u64,
+    /// Bearer token sent to HTTP sources
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bearer_token: Option<String>,
+    /// Endpoint of an S3 compatible service, e.g. `https://minio.local:9000`,
+    /// AWS is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_endpoint: Option<String>,
+    #[serde(default = "default_s3_region")]
+    pub s3_region: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_access_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub s3_secret_key: Option<String>,
+}
+
+/// Limit on concurrent upstream requests per backend, halved when requests
+/// fail with 429 and raised again as they succeed (AIMD)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AdaptiveConcurrencyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Limit the backends start at and never exceed
+    #[serde(default = "default_adaptive_max_concurrency")]
+    pub max_concurrency: usize,
+    /// Limit the backends never go below
+    #[serde(default = "default_adaptive_min_concurrency")]
+    pub min_concurrency: usize,
+    /// Factor the limit is multiplied by after a burst of 429s
+    #[serde(default = "default_adaptive_backoff")]
+    pub backoff: f64,
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_concurrency: default_adaptive_max_concurrency(),
+            min_concurrency: default_adaptive_min_concurrency(),
+            backoff: default_adaptive_backoff(),
+        }
+    }
+}
+
+/// Point in the life of a request a hook runs at
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, strum::Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum HookStage {
+    /// The client request body, before it is dispatched
+    Request,
+    /// The non-streaming response body, before it is sent to the client
+    Response,
+}
+
+/// External program or WASM module inspecting or rewriting JSON bodies, see
+/// [`crate::services::hooks`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HookConfig {
+    /// Program and its arguments, e.g. `["python3", "preset.py"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+    /// WASM module run in process instead of a program, see
+    /// [`crate::services::wasm_hook::call`] for what it exports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<PathBuf>,
+    #[serde(default = "default_hook_stages")]
+    pub stages: Vec<HookStage>,
+    /// Path prefixes the hook applies to, e.g. `/v1/`, every API route if
+    /// empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<String>,
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Checks on non-streaming completions, see
+/// [`crate::services::quality_gate`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QualityGate {
+    /// Characters of text a completion needs at least, 0 disables the check
+    #[serde(default)]
+    pub min_length: usize,
+    /// Regular expressions marking a refusal, e.g. `(?i)^I can't help`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub refusal_patterns: Vec<String>,
+    /// Retries of failing completions, counted apart from `max_retries`
+    #[serde(default = "default_quality_retries")]
+    pub max_retries: usize,
+}
+
+impl Default for QualityGate {
+    fn default() -> Self {
+        Self {
+            min_length: 0,
+            refusal_patterns: Vec::new(),
+            max_retries: default_quality_retries(),
+        }
+    }
+}
+
+impl QualityGate {
+    pub fn enabled(&self) -> bool {
+        (self.min_length > 0 || !self.refusal_patterns.is_empty()) && self.max_retries > 0
+    }
+}
+
+/// Downstream JWTs accepted next to `password`, see
+/// [`crate::services::jwt::TokenScopes`] for the scopes limiting them
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JwtAuth {
+    /// Shared secret of HS256 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+    /// Key set of RS256 and ES256 tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_url: Option<Url>,
+    /// Required `iss` claim, not checked when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Required `aud` claim, not checked when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    /// Clock skew tolerated for `exp` and `nbf`, in seconds
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway_secs: u64,
+}
+
+/// Time a backend has to start answering, see
+/// `x-clewdr-latency-budget` for budgets set per request
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LatencyBudget {
+    /// Milliseconds until the response head, 0 leaves requests without a
+    /// budget unless they send one
+    #[serde(default)]
+    pub budget_ms: u64,
+    /// Route taking over a request that ran out of budget, by the path it
+    /// was sent to, e.g. `/v1/messages` to `/code/v1/messages`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub fallbacks: HashMap<String, String>,
+    /// Answer from the response cache, expired entries on disk included,
+    /// when there is no fallback route or it fails as well
+    #[serde(default = "default_serve_stale")]
+    pub serve_stale: bool,
+}
+
+impl Default for LatencyBudget {
+    fn default() -> Self {
+        Self {
+            budget_ms: 0,
+            fallbacks: HashMap::new(),
+            serve_stale: default_serve_stale(),
+        }
+    }
+}
+
+/// Text removed from what is written to disk, see
+/// [`crate::services::redaction`]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RedactionRule {
+    /// Regular expression, e.g. `[\w.+-]+@[\w-]+\.[\w.]+` for emails
+    pub pattern: String,
+    #[serde(default = "default_redaction_replacement")]
+    pub replacement: String,
+}
+
+/// Script deciding how requests are routed, see [`crate::services::routing`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoutingScript {
+    /// Rhai script file, recompiled when it changes so edits apply at once
+    pub path: PathBuf,
+    #[serde(default = "default_hook_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+/// Summary of each day of traffic, written to the log directory after midnight
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DailyReportConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// URL the summary is also posted to as JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook: Option<String>,
+}
+
+/// CORS policy of the API routes, so browser frontends such as a self-hosted
+/// chat UI can call clewdr directly
+///
+/// `*` allows any origin, header or method. With `allow_credentials` browsers
+/// do not accept `*`, the origin, headers or method of the request are sent
+/// back instead.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, e.g. `https://chat.example.com`
+    #[serde(default = "default_cors_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Request headers browsers may send
+    #[serde(default = "default_cors_headers")]
+    pub allowed_headers: Vec<String>,
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Let browsers send cookies and HTTP auth along
+    #[serde(default)]
+    pub allow_credentials: bool,
+    /// Origins besides its own allowed to call the admin API, e.g. a
+    /// frontend dev server, the admin API is same-origin only if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub admin_origins: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: default_cors_origins(),
+            allowed_headers: default_cors_headers(),
+            allowed_methods: default_cors_methods(),
+            allow_credentials: false,
+            admin_origins: Vec::new(),
+        }
+    }
+}
+
+/// Format of log lines
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable, colored lines
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+/// What an admin password may do
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminRole {
+    /// View status and usage, e.g. for dashboards
+    ReadOnly,
+    /// Everything, including changing keys, cookies and the config
+    Full,
+}
+
+/// What a streamed response carries while upstream is silent
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeepAliveStyle {
+    /// Nothing, the connection stays quiet
+    #[default]
+    Off,
+    /// SSE comment lines, ignored by every SSE parser
+    Comment,
+    /// Chunks with an empty delta, for clients that only watch data events
+    EmptyDelta,
+}
+
+/// Keep-alives sent while waiting on upstream, so idle timeouts of load
+/// balancers do not cut long generations
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeepAliveConfig {
+    /// Seconds of silence before a keep-alive is sent
+    #[serde(default = "default_keep_alive_interval_secs")]
+    pub interval_secs: u64,
+    /// Style for streamed Gemini format responses
+    #[serde(default)]
+    pub gemini: KeepAliveStyle,
+    /// Style for streamed OpenAI format responses
+    #[serde(default)]
+    pub openai: KeepAliveStyle,
+    /// Style for streamed Claude format responses
+    #[serde(default)]
+    pub claude: KeepAliveStyle,
+    /// Pad non-streaming Claude responses with newlines while waiting, like
+    /// Gemini responses. Errors are then sent with a 200 status
+    #[serde(default)]
+    pub claude_non_stream: bool,
+}
+
+impl Default for KeepAliveConfig {
+    fn default() -> Self {
+        Self {
+            interval_secs: default_keep_alive_interval_secs(),
+            gemini: KeepAliveStyle::default(),
+            openai: KeepAliveStyle::default(),
+            claude: KeepAliveStyle::default(),
+            claude_non_stream: false,
+        }
+    }
+}
+
+/// How a streamed response is ended when upstream dies mid-response
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamSalvage {
+    /// Drop the connection, clients see an incomplete stream
+    #[default]
+    Off,
+    /// End the stream cleanly as if the token limit was reached
+    Length,
+    /// End the stream cleanly with an `error` finish reason
+    Error,
+}
+
 /// A struct representing the configuration of the application
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClewdrConfig {
     // key configurations
     #[serde(default)]
     pub vertex: VertexConfig,
-    #[serde(default)]
+    #[serde(default, serialize_with = "persist_cookies")]
     pub cookie_array: HashSet<CookieStatus>,
     #[serde(default)]
     pub wasted_cookie: HashSet<UselessCookie>,
     #[serde(default)]
     pub gemini_keys: HashSet<KeyStatus>,
+    /// Manage only this instance's part of `gemini_keys`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_shard: Option<KeyShard>,
 
     // Server settings, cannot hot reload
     #[serde(default = "default_ip")]
     ip: IpAddr,
     #[serde(default = "default_port")]
     port: u16,
+    /// Serve admin API and dashboard on a separate address, e.g. `127.0.0.1:9091`
+    #[serde(default)]
+    pub admin_address: Option<SocketAddr>,
+    #[serde(default)]
+    pub unix_socket: Option<UnixSocketConfig>,
+    #[serde(default)]
+    pub cors: CorsConfig,
 
     // App settings, can hot reload, but meaningless
     #[serde(default = "default_check_update")]
@@ -91,26 +1144,241 @@ pub struct ClewdrConfig {
     pub auto_update: bool,
     #[serde(default)]
     pub no_fs: bool,
+    /// Store cookies, keys and the Vertex credential encrypted in the config
+    /// file, with the passphrase from `CLEWDR_CONFIG_PASSPHRASE` or the OS
+    /// keyring
+    #[serde(default)]
+    pub encrypt_credentials: bool,
     #[serde(default)]
     pub log_to_file: bool,
+    /// Log line format, for both stdout and the log file
+    #[serde(default)]
+    pub log_format: LogFormat,
 
     // Network settings, can hot reload
     #[serde(default)]
     password: String,
     #[serde(default)]
     admin_password: String,
+    /// Admin password that can only view status and usage
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    readonly_admin_password: Option<String>,
+    /// Reverse proxies whose `X-Forwarded-For` names the client the admin
+    /// lockout applies to, the Unix socket is always trusted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Accept JWTs as well as the password on API routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt_auth: Option<JwtAuth>,
+    /// HMAC key signing API responses, see `x-clewdr-signature`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_signing_secret: Option<String>,
     #[serde(default)]
     pub proxy: Option<String>,
+    /// Proxies rotated round-robin for keys and cookies without a pinned proxy
+    #[serde(default)]
+    pub proxy_pool: Vec<String>,
     #[serde(default)]
     pub rproxy: Option<Url>,
+    /// Mirror of the Gemini API, e.g. a Cloudflare worker, used for keys
+    /// without their own while it passes health checks
+    #[serde(default)]
+    pub gemini_endpoint: Option<Url>,
 
     // Api settings, can hot reload
     #[serde(default = "default_max_retries")]
     pub max_retries: usize,
+    /// Never delete conversations, overrides `chat_cleanup`
     #[serde(default)]
     pub preserve_chats: bool,
+    /// What happens to the conversations created on claude.ai
+    #[serde(default)]
+    pub chat_cleanup: ChatCleanup,
+    /// Redis shared with other instances for key and cookie cooldowns and key
+    /// usage, e.g. `redis://:password@127.0.0.1:6379/0`, read at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redis_url: Option<String>,
+    /// Take settings from a central config file, credentials stay local, read
+    /// at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_config: Option<RemoteConfig>,
+    /// Seconds between reads of the state shared through Redis
+    #[serde(default = "default_redis_sync_secs")]
+    pub redis_sync_secs: u64,
+    /// Backend behind `/v1/chat/completions`
+    #[serde(default)]
+    pub oai_backend: ClaudeBackend,
+    /// OpenAI compatible upstreams served under `/openai/`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub openai_upstreams: Vec<OpenAIUpstream>,
+    /// Programs run on request and response bodies, in order, only read from
+    /// the local config file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookConfig>,
+    /// Script picking the backend, key tiers and parameter overrides of each
+    /// request, only read from the local config file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_script: Option<RoutingScript>,
+    /// Window and weekly limits of the cookies and how requests are spread
+    /// over them
+    #[serde(default)]
+    pub claude_quota: ClaudeQuotaConfig,
     #[serde(default)]
     pub web_search: bool,
+    /// Concurrent requests per Gemini key, excess requests are queued, 0 disables the queue
+    #[serde(default)]
+    pub key_concurrency: usize,
+    /// Maximum number of queued requests
+    #[serde(default = "default_queue_max_depth")]
+    pub queue_max_depth: usize,
+    /// Seconds a request waits in the queue before failing
+    #[serde(default = "default_queue_timeout")]
+    pub queue_timeout: u64,
+    /// Seconds a request waits for a cooling down key instead of failing right
+    /// away, the waiters count against `queue_max_depth`, 0 to fail at once
+    #[serde(default)]
+    pub key_wait_secs: u64,
+    /// Seconds a cookie may sit idle before it is checked against claude.ai,
+    /// keeping its session warm and catching expired or banned cookies before
+    /// a request does, 0 to only check cookies when they are used
+    #[serde(default)]
+    pub cookie_keepalive_secs: u64,
+    /// Seconds before expiry the OAuth tokens of Claude code credentials are
+    /// refreshed in the background, 0 to only refresh them on use
+    #[serde(default = "default_token_refresh_ahead")]
+    pub token_refresh_ahead_secs: u64,
+    /// Percentage of a key's daily budget after which keys with more left are
+    /// preferred, see `daily_request_budget` and `daily_token_budget` of keys
+    #[serde(default = "default_key_budget_rotate_at")]
+    pub key_budget_rotate_at: u64,
+    /// Pass over keys answering much slower than the fastest usable key, see
+    /// `/api/admin/latency` for the time each key takes to first byte
+    #[serde(default)]
+    pub prefer_fast_keys: bool,
+    /// Serve repeated non-streaming requests from a cache, cannot hot reload size and TTL
+    #[serde(default)]
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Number of batch requests executed at once
+    #[serde(default = "default_batch_concurrency")]
+    pub batch_concurrency: usize,
+    /// Pin requests carrying a conversation ID to the same cookie or key
+    #[serde(default = "default_sticky_session")]
+    pub sticky_session: bool,
+    /// Seconds a conversation stays pinned without requests, cannot hot reload
+    #[serde(default = "default_sticky_session_ttl")]
+    pub sticky_session_ttl_secs: u64,
+    #[serde(default)]
+    pub gemini_thinking: GeminiThinkingConfig,
+    #[serde(default)]
+    pub claude_thinking: ClaudeThinkingConfig,
+    /// Largest accepted request body, in bytes
+    #[serde(default = "default_max_body_size")]
+    pub max_body_size: usize,
+    /// Largest remote image downloaded for `image_url` parts, in bytes
+    #[serde(default = "default_max_image_size")]
+    pub max_image_size: usize,
+    /// Download remote `image_url` parts, off by default as API users would
+    /// pick the URLs this instance fetches
+    #[serde(default)]
+    pub fetch_remote_images: bool,
+    /// Hosts remote images may come from, subdomains included, any public
+    /// host if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub image_hosts: Vec<String>,
+    /// Number of recent API requests kept for replay, 0 disables recording,
+    /// read at startup
+    #[serde(default)]
+    pub audit_log_size: u64,
+    /// Abort a streamed response when upstream sends nothing for this many
+    /// seconds, 0 waits for the client timeout
+    #[serde(default)]
+    pub stream_idle_timeout_secs: u64,
+    /// Close long-lived connections, e.g. realtime sessions, after this many
+    /// seconds, so leaked registrations do not pile up, 0 keeps them forever
+    #[serde(default = "default_connection_max_age")]
+    pub connection_max_age_secs: u64,
+    /// Requests a single client address may have in flight, 0 for no limit
+    #[serde(default)]
+    pub max_requests_per_client: usize,
+    /// Retry a streaming request without streaming when it fails before any
+    /// content, and stream the result to the client. Claude web always
+    /// streams upstream, so only Claude Code and Gemini fall back
+    #[serde(default)]
+    pub stream_fallback: bool,
+    /// Continuation requests sent at most when a non-streaming Claude Code,
+    /// Claude Vertex or native Gemini completion stops at the output limit,
+    /// the parts are returned as one response. 0 disables continuation
+    #[serde(default)]
+    pub max_continuations: u32,
+    /// Short or refusing non-streaming completions are retried with another
+    /// cookie or key
+    #[serde(default)]
+    pub quality_gate: QualityGate,
+    /// Requests whose backend has not answered in time are cancelled and
+    /// served by a fallback route or the response cache
+    #[serde(default)]
+    pub latency_budget: LatencyBudget,
+    /// Patterns removed from request and response dumps and from audit
+    /// entries shown in the dashboard, what is sent upstream stays intact
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redaction: Vec<RedactionRule>,
+    #[serde(default)]
+    pub keep_alive: KeepAliveConfig,
+    /// Keep what was streamed when upstream dies mid-response, and end the
+    /// stream with a finish reason instead of dropping the connection
+    #[serde(default)]
+    pub stream_salvage: StreamSalvage,
+    /// How long a client may reconnect to a dropped stream with
+    /// `Last-Event-ID`, 0 disables resumption
+    #[serde(default)]
+    pub stream_resume_secs: u64,
+    /// Events of each stream kept for resumption
+    #[serde(default = "default_stream_resume_events")]
+    pub stream_resume_events: usize,
+    #[serde(default)]
+    pub mock: MockConfig,
+    #[serde(default)]
+    pub chaos: ChaosConfig,
+    #[serde(default)]
+    pub daily_report: DailyReportConfig,
+    /// Concurrency limit per backend adapting to rate limits, waiters count
+    /// against `queue_timeout`
+    #[serde(default)]
+    pub adaptive_concurrency: AdaptiveConcurrencyConfig,
+    /// Send system prompts to Gemini as a user turn preamble, for models such
+    /// as Gemma that reject system instructions
+    #[serde(default)]
+    pub gemini_system_as_user: bool,
+    /// Merge consecutive turns of the same role and start the conversation
+    /// with a user turn, Gemini rejects the request otherwise
+    #[serde(default = "default_gemini_merge_turns")]
+    pub gemini_merge_turns: bool,
+    /// Serve streaming OpenAI format Gemini requests from the native streaming
+    /// endpoint, translating every chunk, instead of Gemini's OpenAI endpoint
+    #[serde(default)]
+    pub gemini_native_oai_stream: bool,
+    /// API version native Gemini requests are sent to, per model
+    #[serde(default)]
+    pub gemini_api_versions: GeminiApiVersionConfig,
+    /// Retry Gemini completions that do not match the requested JSON schema
+    #[serde(default)]
+    pub structured_output_retry: bool,
+    /// Actions for Gemini errors, the first matching rule wins and unmatched
+    /// errors are retried
+    #[serde(default = "default_error_policy")]
+    pub gemini_error_policy: Vec<ErrorRule>,
+    /// Output token ceilings per model, checked before requests go upstream
+    #[serde(default)]
+    pub output_limits: OutputLimitConfig,
+    /// Context windows per model and what to do with prompts exceeding them
+    #[serde(default)]
+    pub context_limits: ContextLimitConfig,
+    /// Timeouts of upstream requests
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
+    /// Key tiers serving each model
+    #[serde(default)]
+    pub key_tiers: KeyTierConfig,
 
     // Cookie settings, can hot reload
     #[serde(default)]
@@ -141,6 +1409,9 @@ pub struct ClewdrConfig {
     pub claude_code_client_id: Option<String>,
     #[serde(default)]
     pub custom_system: Option<String>,
+    /// Add a cache breakpoint to large system prompts without one
+    #[serde(default)]
+    pub auto_cache_control: bool,
 
     // Skip field, can hot reload
     #[serde(skip)]
@@ -157,19 +1428,83 @@ impl Default for ClewdrConfig {
             cookie_array: HashSet::new(),
             wasted_cookie: HashSet::new(),
             gemini_keys: HashSet::new(),
+            key_shard: None,
             password: String::new(),
             admin_password: String::new(),
+            readonly_admin_password: None,
+            trusted_proxies: Vec::new(),
+            jwt_auth: None,
+            response_signing_secret: None,
             proxy: None,
+            proxy_pool: Vec::new(),
             ip: default_ip(),
             port: default_port(),
+            admin_address: None,
+            unix_socket: None,
+            cors: Default::default(),
             rproxy: None,
+            gemini_endpoint: None,
             use_real_roles: default_use_real_roles(),
             custom_prompt: String::new(),
             custom_h: None,
             custom_a: None,
             wreq_proxy: None,
             preserve_chats: false,
+            chat_cleanup: Default::default(),
+            redis_url: None,
+            remote_config: None,
+            redis_sync_secs: default_redis_sync_secs(),
+            oai_backend: Default::default(),
+            claude_quota: ClaudeQuotaConfig::default(),
+            openai_upstreams: Vec::new(),
+            hooks: Vec::new(),
+            routing_script: None,
             web_search: false,
+            sticky_session: default_sticky_session(),
+            sticky_session_ttl_secs: default_sticky_session_ttl(),
+            gemini_thinking: Default::default(),
+            claude_thinking: Default::default(),
+            structured_output_retry: false,
+            output_limits: Default::default(),
+            context_limits: Default::default(),
+            timeouts: Default::default(),
+            key_tiers: Default::default(),
+            gemini_error_policy: default_error_policy(),
+            gemini_system_as_user: false,
+            gemini_merge_turns: default_gemini_merge_turns(),
+            gemini_native_oai_stream: false,
+            gemini_api_versions: GeminiApiVersionConfig::default(),
+            max_image_size: default_max_image_size(),
+            fetch_remote_images: false,
+            image_hosts: Vec::new(),
+            audit_log_size: 0,
+            connection_max_age_secs: default_connection_max_age(),
+            max_requests_per_client: 0,
+            stream_idle_timeout_secs: 0,
+            stream_fallback: false,
+            max_continuations: 0,
+            quality_gate: QualityGate::default(),
+            latency_budget: LatencyBudget::default(),
+            redaction: Vec::new(),
+            keep_alive: KeepAliveConfig::default(),
+            stream_salvage: StreamSalvage::default(),
+            stream_resume_secs: 0,
+            stream_resume_events: default_stream_resume_events(),
+            mock: MockConfig::default(),
+            chaos: ChaosConfig::default(),
+            daily_report: Default::default(),
+            adaptive_concurrency: AdaptiveConcurrencyConfig::default(),
+            max_body_size: default_max_body_size(),
+            batch_concurrency: default_batch_concurrency(),
+            response_cache: None,
+            key_concurrency: 0,
+            queue_max_depth: default_queue_max_depth(),
+            queue_timeout: default_queue_timeout(),
+            key_wait_secs: 0,
+            cookie_keepalive_secs: 0,
+            token_refresh_ahead_secs: default_token_refresh_ahead(),
+            key_budget_rotate_at: default_key_budget_rotate_at(),
+            prefer_fast_keys: false,
             skip_first_warning: false,
             skip_second_warning: false,
             skip_restricted: false,
@@ -178,8 +1513,11 @@ impl Default for ClewdrConfig {
             skip_normal_pro: false,
             claude_code_client_id: None,
             custom_system: None,
+            auto_cache_control: false,
             no_fs: false,
+            encrypt_credentials: false,
             log_to_file: false,
+            log_format: LogFormat::default(),
         }
     }
 }
@@ -201,6 +1539,12 @@ impl Display for ClewdrConfig {
             .path_and_query("")
             .build()
             .map_err(|_| std::fmt::Error)?;
+        let admin_url = Uri::builder()
+            .scheme(Scheme::HTTP)
+            .authority(self.admin_address().to_string())
+            .path_and_query("")
+            .build()
+            .map_err(|_| std::fmt::Error)?;
         write!(
             f,
             "Claude(Claude and OpenAI format) / Gemini(Gemini format) Endpoint: {}\n\
@@ -217,15 +1561,25 @@ impl Display for ClewdrConfig {
             (web_url.to_string() + "gemini").green().underline(),
             (web_url.to_string() + "gemini/vertex").green().underline(),
             self.password.yellow(),
-            web_url.to_string().green().underline(),
+            admin_url.to_string().green().underline(),
             self.admin_password.yellow(),
         )?;
         if let Some(ref proxy) = self.proxy {
             writeln!(f, "Proxy: {}", proxy.to_string().blue())?;
         }
+        if !self.proxy_pool.is_empty() {
+            writeln!(
+                f,
+                "Proxy pool: {} proxies",
+                self.proxy_pool.len().to_string().blue()
+            )?;
+        }
         if let Some(ref rproxy) = self.rproxy {
             writeln!(f, "Reverse Proxy: {}", rproxy.to_string().blue())?;
         }
+        if let Some(ref endpoint) = self.gemini_endpoint {
+            writeln!(f, "Gemini Endpoint: {}", endpoint.to_string().blue())?;
+        }
         if self.vertex.validate() {
             writeln!(f, "Vertex {}", "Enabled".green().bold())?;
         }
@@ -248,12 +1602,46 @@ impl Display for ClewdrConfig {
 }
 
 impl ClewdrConfig {
+    /// Cleanup policy of claude.ai conversations, `preserve_chats` included
+    pub fn cleanup_policy(&self) -> ChatCleanup {
+        if self.preserve_chats {
+            ChatCleanup::Keep
+        } else {
+            self.chat_cleanup
+        }
+    }
+
+    /// Whether this instance manages the key, see `key_shard`
+    pub fn owns_key(&self, key: &GeminiKey) -> bool {
+        self.key_shard.is_none_or(|s| s.owns(key))
+    }
+
     pub fn user_auth(&self, key: &str) -> bool {
         key == self.password
     }
 
+    /// Role of an admin key, `None` if it is no admin password
+    pub fn admin_role(&self, key: &str) -> Option<AdminRole> {
+        if key == self.admin_password {
+            Some(AdminRole::Full)
+        } else if self
+            .readonly_admin_password
+            .as_deref()
+            .is_some_and(|p| !p.is_empty() && key == p)
+        {
+            Some(AdminRole::ReadOnly)
+        } else {
+            None
+        }
+    }
+
     pub fn admin_auth(&self, key: &str) -> bool {
-        key == self.admin_password
+        self.admin_role(key) == Some(AdminRole::Full)
+    }
+
+    /// Whether the key may view status and usage, in any role
+    pub fn admin_read_auth(&self, key: &str) -> bool {
+        self.admin_role(key).is_some()
     }
 
     pub fn cc_client_id(&self) -> String {
@@ -263,27 +1651,122 @@ impl ClewdrConfig {
             .to_string()
     }
 
+    /// Sources of the configuration: the config file, overlaid by `CLEWDR_*`
+    /// variables and by files named in `CLEWDR_*_FILE` variables, e.g. Docker
+    /// secrets
+    ///
+    /// Credential lists and the Vertex credential are read by [`Self::new`].
+    pub fn figment() -> Figment {
+        let mut figment = Figment::from(Toml::file(CONFIG_PATH.as_path())).admerge(
+            Env::prefixed("CLEWDR_")
+                .ignore(&ENV_CREDENTIALS)
+                .filter(|k| !k.as_str().to_ascii_lowercase().ends_with("_file")),
+        );
+        for (name, path) in env::vars() {
+            let Some(key) = name
+                .strip_prefix("CLEWDR_")
+                .and_then(|n| n.strip_suffix("_FILE"))
+            else {
+                continue;
+            };
+            if ENV_CREDENTIALS.iter().any(|c| c.eq_ignore_ascii_case(key)) {
+                continue;
+            }
+            match std::fs::read_to_string(&path) {
+                Ok(value) => {
+                    figment = figment.admerge(Serialized::default(
+                        &key.to_ascii_lowercase(),
+                        value.trim_end().to_string(),
+                    ));
+                }
+                Err(e) => error!("Failed to read {} from {}: {}", name, path, e),
+            }
+        }
+        figment
+    }
+
     /// Loads configuration from files and environment variables
     /// Combines settings from config.toml, clewdr.toml, and environment variables
     /// Also loads cookies from a file if specified
     ///
+    /// What the environment sets is remembered, so [`Self::save`] writes the
+    /// config file's own values instead and credentials from the environment
+    /// never end up on disk.
+    ///
     /// # Returns
     /// * Config instance
     pub fn new() -> Self {
-        let mut config: ClewdrConfig = Figment::from(Toml::file(CONFIG_PATH.as_path()))
-            .admerge(Env::prefixed("CLEWDR_"))
+        let mut config: ClewdrConfig = Self::figment()
             .extract_lossy()
             .inspect_err(|e| {
                 error!("Failed to load config: {}", e);
             })
             .unwrap_or_default();
-        if let Some(credential) = env::var("CLEWDR_VERTEX_CREDENTIAL").ok().and_then(|v| {
+        if let Ok(sealed) =
+            Figment::from(Toml::file(CONFIG_PATH.as_path())).extract_inner::<String>("sealed")
+        {
+            match open_credentials(&sealed) {
+                Ok(credentials) => {
+                    config.cookie_array.extend(credentials.cookie_array);
+                    config.wasted_cookie.extend(credentials.wasted_cookie);
+                    config.gemini_keys.extend(credentials.gemini_keys);
+                    if config.vertex.credential.is_none() {
+                        config.vertex.credential = credentials.vertex_credential;
+                    }
+                }
+                Err(e) => {
+                    // saving would drop the sealed credentials
+                    error!(
+                        "Failed to open sealed credentials, the config will not be saved: {}",
+                        e
+                    );
+                    config.no_fs = true;
+                }
+            }
+        }
+        let file = std::fs::read_to_string(CONFIG_PATH.as_path())
+            .ok()
+            .and_then(|f| toml::from_str::<toml::Table>(&f).ok())
+            .unwrap_or_default();
+        let mut overlay = EnvOverlay {
+            keys: env_keys()
+                .into_iter()
+                .map(|k| {
+                    let value = file.get(&k).cloned();
+                    (k, value)
+                })
+                .collect(),
+            ..Default::default()
+        };
+        if let Some(credential) = env_or_file("VERTEX_CREDENTIAL").and_then(|v| {
             serde_json::from_str::<ServiceAccountKey>(&v)
                 .map_err(|e| error!("Failed to parse vertex credential: {}", e))
                 .ok()
         }) {
-            config.vertex.credential = Some(credential);
+            overlay.vertex_credential = Some(config.vertex.credential.replace(credential));
         }
+        if let Some(keys) = env_or_file("GEMINI_KEYS") {
+            for key in split_list(&keys).map(|k| KeyStatus::from(GeminiKey::from(k))) {
+                if !config.gemini_keys.contains(&key) {
+                    overlay.gemini_keys.insert(key.to_owned());
+                    config.gemini_keys.insert(key);
+                }
+            }
+        }
+        if let Some(cookies) = env_or_file("COOKIES") {
+            let cookies = split_list(&cookies).filter_map(|c| {
+                CookieStatus::new(c, None)
+                    .inspect_err(|e| error!("Failed to parse cookie from environment: {}", e))
+                    .ok()
+            });
+            for cookie in cookies {
+                if !config.cookie_array.contains(&cookie) {
+                    overlay.cookies.insert(cookie.cookie.to_owned());
+                    config.cookie_array.insert(cookie);
+                }
+            }
+        }
+        ENV_OVERLAY.set(overlay).ok();
         if let Some(ref f) = Args::parse().file {
             // load cookies from file
             if f.exists() {
@@ -299,10 +1782,14 @@ impl ClewdrConfig {
                 error!("Cookie file not found: {}", f.display());
             }
         }
+        let issues = config.check();
+        if !issues.is_empty() {
+            println!("{}", format_issues(&issues));
+        }
         let config = config.validate();
-        let config_clone = config.to_owned();
+        // runs once this config is in `CLEWDR_CONFIG`
         spawn(async move {
-            config_clone.save().await.unwrap_or_else(|e| {
+            ClewdrConfig::save().await.unwrap_or_else(|e| {
                 error!("Failed to save config: {}", e);
             });
         });
@@ -326,17 +1813,78 @@ impl ClewdrConfig {
         SocketAddr::new(self.ip, self.port)
     }
 
+    /// address of admin API and dashboard, same as [`Self::address`] unless configured
+    pub fn admin_address(&self) -> SocketAddr {
+        self.admin_address.unwrap_or_else(|| self.address())
+    }
+
+    /// Serializes the configuration for the config file, with the credentials
+    /// sealed if `encrypt_credentials` is set
+    fn to_toml(&self) -> Result<String, ClewdrError> {
+        let overlay = ENV_OVERLAY.get();
+        let mut config = self.to_owned();
+        if let Some(overlay) = overlay {
+            overlay.strip(&mut config);
+        }
+        if !self.encrypt_credentials && overlay.is_none_or(|o| o.keys.is_empty()) {
+            return Ok(toml::ser::to_string_pretty(&config)?);
+        }
+        let mut sealed = None;
+        if self.encrypt_credentials {
+            let credentials = SealedCredentials {
+                cookie_array: std::mem::take(&mut config.cookie_array),
+                wasted_cookie: std::mem::take(&mut config.wasted_cookie),
+                gemini_keys: std::mem::take(&mut config.gemini_keys),
+                vertex_credential: config.vertex.credential.take(),
+            };
+            sealed = Some(seal_credentials(&credentials)?);
+        }
+        let mut value = toml::Value::try_from(&config)?;
+        if let Some(table) = value.as_table_mut() {
+            if let Some(overlay) = overlay {
+                overlay.restore(table);
+            }
+            if let Some(sealed) = sealed {
+                table.insert("sealed".to_string(), sealed.into());
+            }
+        }
+        Ok(toml::ser::to_string_pretty(&value)?)
+    }
+
     /// Save the configuration to a file
-    pub async fn save(&self) -> Result<(), ClewdrError> {
-        if self.no_fs {
+    ///
+    /// Saves are serialized and each one writes the current [`CLEWDR_CONFIG`],
+    /// read once the previous save finished, so an older config never
+    /// overwrites a newer one. The file is written to a temporary file that is
+    /// synced and renamed over the config, so readers and crashes never see a
+    /// partial file.
+    pub async fn save() -> Result<(), ClewdrError> {
+        let _guard = SAVE_LOCK.lock().await;
+        let config = CLEWDR_CONFIG.load_full();
+        if config.no_fs {
             return Ok(());
         }
+        let toml = config.to_toml()?;
         if let Some(parent) = CONFIG_PATH.parent()
             && !parent.exists()
         {
             tokio::fs::create_dir_all(parent).await?;
         }
-        Ok(tokio::fs::write(CONFIG_PATH.as_path(), toml::ser::to_string_pretty(self)?).await?)
+        let tmp = CONFIG_PATH.with_extension("toml.tmp");
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(toml.as_bytes()).await?;
+        file.sync_all().await?;
+        drop(file);
+        if let Err(e) = tokio::fs::rename(&tmp, CONFIG_PATH.as_path()).await {
+            // e.g. the config is a bind mounted file, which cannot be replaced
+            warn!(
+                "Failed to replace the config atomically, writing in place: {}",
+                e
+            );
+            tokio::fs::remove_file(&tmp).await.ok();
+            tokio::fs::write(CONFIG_PATH.as_path(), toml).await?;
+        }
+        Ok(())
     }
 
     /// Validate the configuration
@@ -347,6 +1895,10 @@ impl ClewdrConfig {
         if self.admin_password.trim().is_empty() {
             self.admin_password = generate_password();
         }
+        if self.readonly_admin_password.as_deref() == Some(self.admin_password.as_str()) {
+            warn!("readonly_admin_password equals admin_password, ignoring it");
+            self.readonly_admin_password = None;
+        }
         self.cookie_array = self.cookie_array.into_iter().map(|x| x.reset()).collect();
         self.wreq_proxy = self.proxy.to_owned().and_then(|p| {
             Proxy::all(p)
@@ -356,6 +1908,11 @@ impl ClewdrConfig {
                 })
                 .ok()
         });
+        self.proxy_pool.retain(|p| {
+            Proxy::all(p)
+                .inspect_err(|e| error!("Failed to parse pooled proxy {}: {}", p, e))
+                .is_ok()
+        });
         self
     }
 }
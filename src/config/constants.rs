@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     net::{IpAddr, Ipv4Addr},
     path::PathBuf,
     sync::LazyLock,
@@ -8,7 +9,10 @@ use arc_swap::ArcSwap;
 use clap::Parser;
 use url::Url;
 
-use crate::{Args, config::ClewdrConfig};
+use crate::{
+    Args,
+    config::{ClewdrConfig, ErrorAction, ErrorRule, HookStage},
+};
 
 pub const CONFIG_NAME: &str = "clewdr.toml";
 pub const CLAUDE_ENDPOINT: &str = "https://api.anthropic.com";
@@ -16,6 +20,16 @@ pub const GEMINI_ENDPOINT: &str = "https://generativelanguage.googleapis.com";
 pub const CC_CLIENT_ID: &str = "9d1c250a-e61b-44d9-88ed-5944d1962f5e";
 pub const CC_TOKEN_URL: &str = "https://console.anthropic.com/v1/oauth/token";
 pub const CC_REDIRECT_URI: &str = "https://console.anthropic.com/oauth/code/callback";
+/// Maximum number of prompt caching breakpoints in a Claude request
+pub const MAX_CACHE_BREAKPOINTS: usize = 4;
+/// Minimum size of a system prompt, in tokens, to be cached automatically
+pub const AUTO_CACHE_MIN_TOKENS: usize = 1024;
+/// Maximum number of requests in a single batch
+pub const MAX_BATCH_REQUESTS: usize = 10_000;
+/// Length of a Claude usage window, in seconds
+pub const COOKIE_WINDOW_SECS: i64 = 5 * 60 * 60;
+/// Length of a Claude weekly usage cap, in seconds
+pub const COOKIE_WEEK_SECS: i64 = 7 * 24 * 60 * 60;
 
 pub static ENDPOINT_URL: LazyLock<Url> = LazyLock::new(|| {
     Url::parse(CLAUDE_ENDPOINT).unwrap_or_else(|_| {
@@ -134,5 +148,381 @@ pub const fn default_skip_cool_down() -> bool {
     true
 }
 
+/// Default setting for merging consecutive same-role turns sent to Gemini
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_gemini_merge_turns() -> bool {
+    true
+}
+
+/// Default setting for trying other Gemini API versions on a 404
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_gemini_version_fallback() -> bool {
+    true
+}
+
+/// Default interval of reading the state shared through Redis, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 5
+pub const fn default_redis_sync_secs() -> u64 {
+    5
+}
+
+/// Default interval of polling the remote config source, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 60
+pub const fn default_remote_config_poll_secs() -> u64 {
+    60
+}
+
+/// Default region of S3 remote config sources
+///
+/// # Returns
+/// * `String` - The default value of `us-east-1`
+pub fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Default setting for pinning conversations to a cookie or key
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_sticky_session() -> bool {
+    true
+}
+
+/// Default idle time after which a pinned conversation is forgotten
+///
+/// # Returns
+/// * `u64` - The default value of 3600 seconds
+pub const fn default_sticky_session_ttl() -> u64 {
+    60 * 60
+}
+
+/// Default number of batch requests executed at once
+///
+/// # Returns
+/// * `usize` - The default value of 4
+pub const fn default_batch_concurrency() -> usize {
+    4
+}
+
+/// Default number of requests waiting in the request queue
+///
+/// # Returns
+/// * `usize` - The default value of 100
+pub const fn default_queue_max_depth() -> usize {
+    100
+}
+
+/// Default upper bound of the adaptive concurrency limit per backend
+///
+/// # Returns
+/// * `usize` - The default value of 64
+pub const fn default_adaptive_max_concurrency() -> usize {
+    64
+}
+
+/// Default lower bound of the adaptive concurrency limit per backend
+///
+/// # Returns
+/// * `usize` - The default value of 1
+pub const fn default_adaptive_min_concurrency() -> usize {
+    1
+}
+
+/// Default factor the adaptive concurrency limit is multiplied by after a 429
+///
+/// # Returns
+/// * `f64` - The default value of 0.5
+pub const fn default_adaptive_backoff() -> f64 {
+    0.5
+}
+
+/// Default number of retries of completions failing the quality gate
+///
+/// # Returns
+/// * `usize` - The default value of 2
+pub const fn default_quality_retries() -> usize {
+    2
+}
+
+/// Default for serving a cached response once the latency budget is spent
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_serve_stale() -> bool {
+    true
+}
+
+/// Default text replacing a redacted match
+///
+/// # Returns
+/// * `String` - `[REDACTED]`
+pub fn default_redaction_replacement() -> String {
+    "[REDACTED]".to_string()
+}
+
+/// Default stages a hook runs at
+///
+/// # Returns
+/// * `Vec<HookStage>` - Only the request stage
+pub fn default_hook_stages() -> Vec<HookStage> {
+    vec![HookStage::Request]
+}
+
+/// Default time a hook may take before the body is passed on unchanged, in
+/// seconds
+///
+/// # Returns
+/// * `u64` - The default value of 10 seconds
+pub const fn default_hook_timeout_secs() -> u64 {
+    10
+}
+
+/// Default clock skew tolerated when checking JWT expiry, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 60 seconds
+pub const fn default_jwt_leeway() -> u64 {
+    60
+}
+
+/// Default time a request waits in the request queue, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 60
+pub const fn default_queue_timeout() -> u64 {
+    60
+}
+
+/// Default thinking budget for Claude models requested with a `-thinking`
+/// suffix, in tokens
+///
+/// # Returns
+/// * `u64` - The default value of 4096
+pub const fn default_claude_thinking_budget() -> u64 {
+    4096
+}
+
+/// Default share of a daily key budget after which other keys are preferred,
+/// in percent
+///
+/// # Returns
+/// * `u64` - The default value of 90
+pub const fn default_key_budget_rotate_at() -> u64 {
+    90
+}
+
+/// Default time before expiry an OAuth token is refreshed, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 1800
+pub const fn default_token_refresh_ahead() -> u64 {
+    1800
+}
+
+/// Default lifetime of a cached response, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 3600
+pub const fn default_response_cache_ttl() -> u64 {
+    3600
+}
+
+/// Default number of responses kept in memory
+///
+/// # Returns
+/// * `u64` - The default value of 1000
+pub const fn default_response_cache_entries() -> u64 {
+    1000
+}
+
+/// Default size limit of a request body
+///
+/// # Returns
+/// * `usize` - The default value of 32 MiB
+pub const fn default_max_body_size() -> usize {
+    32 * 1024 * 1024
+}
+
+/// Default size limit of a remote image inlined into a request
+///
+/// # Returns
+/// * `usize` - The default value of 20 MiB
+pub const fn default_max_image_size() -> usize {
+    20 * 1024 * 1024
+}
+
+/// Default setting for serving TCP alongside a Unix socket
+///
+/// # Returns
+/// * `bool` - The default value of true
+pub const fn default_unix_socket_tcp() -> bool {
+    true
+}
+
+/// Default text of mock responses
+///
+/// # Returns
+/// * `String` - A short sentence naming the model
+pub fn default_mock_response() -> String {
+    "This is a mock response from ClewdR for {model}.".to_string()
+}
+
+/// Default status of errors injected by the mock
+///
+/// # Returns
+/// * `u16` - 429, the status that exercises retries and cooldowns
+pub const fn default_mock_error_status() -> u16 {
+    429
+}
+
+/// Default statuses of injected errors
+///
+/// # Returns
+/// * `Vec<u16>` - Rate limited, internal error and unavailable
+pub fn default_chaos_error_statuses() -> Vec<u16> {
+    vec![429, 500, 503]
+}
+
+/// Default delay before the first byte of a delayed response
+///
+/// # Returns
+/// * `u64` - The default value of 5000 milliseconds
+pub const fn default_chaos_delay_ms() -> u64 {
+    5000
+}
+
+/// Default silence before a keep-alive is sent
+///
+/// # Returns
+/// * `u64` - The default value of 15 seconds
+pub const fn default_keep_alive_interval_secs() -> u64 {
+    15
+}
+
+/// Default number of events kept per resumable stream
+///
+/// # Returns
+/// * `usize` - The default value of 512
+pub const fn default_stream_resume_events() -> usize {
+    512
+}
+
+/// Default timeout of an upstream request, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 300, covering the streamed body
+pub const fn default_request_timeout() -> u64 {
+    300
+}
+
+/// Default timeout for connecting to an upstream, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 30
+pub const fn default_connect_timeout() -> u64 {
+    30
+}
+
+/// Default age after which a registered connection is evicted, in seconds
+///
+/// # Returns
+/// * `u64` - The default value of 86400, a day
+pub const fn default_connection_max_age() -> u64 {
+    86400
+}
+
+/// Default output token ceilings, keyed by model name prefix
+///
+/// # Returns
+/// * `HashMap<String, u32>` - The documented output limits of current models
+pub fn default_output_limits() -> HashMap<String, u32> {
+    [
+        ("claude-opus-4", 32000),
+        ("claude-sonnet-4", 64000),
+        ("claude-3-7-sonnet", 64000),
+        ("claude-3-5", 8192),
+        ("claude-3-opus", 4096),
+        ("claude-3-haiku", 4096),
+        ("gemini-2.5", 65536),
+        ("gemini-2.0", 8192),
+        ("gemini-1.5", 8192),
+    ]
+    .into_iter()
+    .map(|(model, limit)| (model.to_string(), limit))
+    .collect()
+}
+
+/// Default origins allowed to call the API from a browser
+///
+/// # Returns
+/// * `Vec<String>` - Any origin
+pub fn default_cors_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+/// Default request headers browsers may send to the API
+///
+/// # Returns
+/// * `Vec<String>` - Authorization and Content-Type
+pub fn default_cors_headers() -> Vec<String> {
+    ["authorization", "content-type"]
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Default methods browsers may use on the API
+///
+/// # Returns
+/// * `Vec<String>` - GET, POST and DELETE
+pub fn default_cors_methods() -> Vec<String> {
+    ["GET", "POST", "DELETE"]
+        .into_iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// Default context windows, by model name prefix
+///
+/// # Returns
+/// * `HashMap<String, u32>` - The documented context windows of current models
+pub fn default_context_windows() -> HashMap<String, u32> {
+    [
+        ("claude", 200000),
+        ("gemini-1.5-pro", 2097152),
+        ("gemini", 1048576),
+    ]
+    .into_iter()
+    .map(|(model, limit)| (model.to_string(), limit))
+    .collect()
+}
+
+/// Default Gemini error policy
+///
+/// # Returns
+/// * `Vec<ErrorRule>` - Rules deleting keys Google reports as invalid and
+///   failing fast on other 400s, which are caused by the request itself
+pub fn default_error_policy() -> Vec<ErrorRule> {
+    let rule = |reason: Option<&str>, action| ErrorRule {
+        status: Some(400),
+        reason: reason.map(ToString::to_string),
+        action,
+    };
+    vec![
+        rule(Some("API_KEY_INVALID"), ErrorAction::DeleteKey),
+        rule(Some("API_KEY_EXPIRED"), ErrorAction::DeleteKey),
+        rule(Some("API_KEY_NOT_FOUND"), ErrorAction::DeleteKey),
+        rule(None, ErrorAction::FailFast),
+    ]
+}
+
 /// Default cookie value for testing purposes
 pub const PLACEHOLDER_COOKIE: &str = "sk-ant-REDACTED";
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     fmt::{Debug, Display},
     hash::Hash,
     ops::Deref,
@@ -12,7 +13,9 @@ use snafu::{GenerateImplicitData, Location};
 use tracing::info;
 
 use crate::{
-    config::{PLACEHOLDER_COOKIE, TokenInfo},
+    config::{
+        COOKIE_WEEK_SECS, COOKIE_WINDOW_SECS, ClaudeQuotaConfig, PLACEHOLDER_COOKIE, TokenInfo,
+    },
     error::ClewdrError,
 };
 
@@ -41,6 +44,138 @@ impl<'de> Deserialize<'de> for ClewdrCookie {
     }
 }
 
+/// Usage of a cookie within its current quota window
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct CookieUsage {
+    /// Start of the current quota window
+    #[serde(default)]
+    pub window_start: Option<i64>,
+    /// Requests dispatched in the current quota window
+    #[serde(default)]
+    pub window_requests: u32,
+    /// Start of the current weekly cap period
+    #[serde(default)]
+    pub week_start: Option<i64>,
+    /// Requests dispatched in the current weekly cap period
+    #[serde(default)]
+    pub week_requests: u32,
+    #[serde(default)]
+    pub total_requests: u64,
+    #[serde(default)]
+    pub last_used: Option<i64>,
+    /// Last time the cookie hit a rate limit
+    #[serde(default)]
+    pub last_429: Option<i64>,
+    /// Last time the idle cookie was checked by the keep-alive task
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_checked: Option<i64>,
+}
+
+impl CookieUsage {
+    fn window_active(&self, now: i64) -> bool {
+        self.window_start
+            .is_some_and(|start| now < start + COOKIE_WINDOW_SECS)
+    }
+
+    fn week_active(&self, now: i64) -> bool {
+        self.week_start
+            .is_some_and(|start| now < start + COOKIE_WEEK_SECS)
+    }
+
+    /// Records a dispatched request, opening a new window or week if the last
+    /// one elapsed
+    pub fn record_use(&mut self, now: i64) {
+        if !self.window_active(now) {
+            self.window_start = Some(now);
+            self.window_requests = 0;
+        }
+        if !self.week_active(now) {
+            self.week_start = Some(now);
+            self.week_requests = 0;
+        }
+        self.window_requests += 1;
+        self.week_requests += 1;
+        self.total_requests += 1;
+        self.last_used = Some(now);
+    }
+
+    /// Projected time the current quota window resets, if one is open
+    pub fn window_reset_at(&self, now: i64) -> Option<i64> {
+        self.window_active(now)
+            .then(|| self.window_start.map(|s| s + COOKIE_WINDOW_SECS))
+            .flatten()
+    }
+
+    /// Projected time the current weekly cap period resets, if one is open
+    pub fn week_reset_at(&self, now: i64) -> Option<i64> {
+        self.week_active(now)
+            .then(|| self.week_start.map(|s| s + COOKIE_WEEK_SECS))
+            .flatten()
+    }
+
+    /// Requests spent in the open window and week, 0 for elapsed ones
+    fn spent(&self, now: i64) -> (u32, u32) {
+        (
+            if self.window_active(now) {
+                self.window_requests
+            } else {
+                0
+            },
+            if self.week_active(now) {
+                self.week_requests
+            } else {
+                0
+            },
+        )
+    }
+
+    /// Estimated requests left in the window and the week, `None` where no
+    /// limit is configured
+    pub fn remaining(&self, now: i64, quota: &ClaudeQuotaConfig) -> (Option<u32>, Option<u32>) {
+        let (window, week) = self.spent(now);
+        (
+            quota.window_requests.map(|l| l.saturating_sub(window)),
+            quota.weekly_requests.map(|l| l.saturating_sub(week)),
+        )
+    }
+
+    /// Whether the cookie is below every configured limit
+    pub fn within_quota(&self, now: i64, quota: &ClaudeQuotaConfig) -> bool {
+        let (window, week) = self.remaining(now, quota);
+        window.is_none_or(|r| r > 0) && week.is_none_or(|r| r > 0)
+    }
+
+    /// Share of the tighter of the window and weekly limits already spent,
+    /// lower is better
+    ///
+    /// Without limits the request counts themselves are compared, so the
+    /// least used cookie still wins.
+    pub fn utilization(&self, now: i64, quota: &ClaudeQuotaConfig) -> f64 {
+        let (window, week) = self.spent(now);
+        let share = |spent: u32, limit: Option<u32>| match limit {
+            Some(limit) => spent as f64 / limit.max(1) as f64,
+            None => 0.0,
+        };
+        if quota.window_requests.is_none() && quota.weekly_requests.is_none() {
+            // tie-break on the week so usage evens out across windows
+            return window as f64 + week as f64 / (u32::MAX as f64 + 1.0);
+        }
+        share(window, quota.window_requests).max(share(week, quota.weekly_requests))
+    }
+
+    /// Estimated remaining headroom, higher is better
+    ///
+    /// A cookie without an open window has full headroom, otherwise the
+    /// fewer requests spent in the window the more is left
+    pub fn headroom(&self, now: i64) -> u32 {
+        if self.window_active(now) {
+            u32::MAX - self.window_requests
+        } else {
+            u32::MAX
+        }
+    }
+}
+
 /// A struct representing a cookie with its information
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct CookieStatus {
@@ -49,6 +184,43 @@ pub struct CookieStatus {
     pub token: Option<TokenInfo>,
     #[serde(default)]
     pub reset_time: Option<i64>,
+    /// Proxy pinned to this cookie, overrides the proxy pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Changes with every request, so it is kept out of the config file and
+    /// persisted by the cookie actor, see [`persist_cookies`]
+    #[serde(default)]
+    pub usage: CookieUsage,
+}
+
+/// Form of a [`CookieStatus`] written to the config file, without the usage
+/// counters
+#[derive(Serialize)]
+struct PersistedCookie<'a> {
+    cookie: &'a ClewdrCookie,
+    token: Option<&'a TokenInfo>,
+    reset_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy: Option<&'a str>,
+}
+
+impl<'a> From<&'a CookieStatus> for PersistedCookie<'a> {
+    fn from(status: &'a CookieStatus) -> Self {
+        Self {
+            cookie: &status.cookie,
+            token: status.token.as_ref(),
+            reset_time: status.reset_time,
+            proxy: status.proxy.as_deref(),
+        }
+    }
+}
+
+/// Serializes cookies for the config file, usage counters are left out
+pub fn persist_cookies<S>(cookies: &HashSet<CookieStatus>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    serializer.collect_seq(cookies.iter().map(PersistedCookie::from))
 }
 
 impl PartialEq for CookieStatus {
@@ -92,6 +264,8 @@ impl CookieStatus {
             cookie,
             token: None,
             reset_time,
+            proxy: None,
+            usage: CookieUsage::default(),
         })
     }
 
@@ -206,4 +380,40 @@ mod tests {
         let result = ClewdrCookie::from_str("invalid-cookie");
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_usage_window() {
+        let mut usage = CookieUsage::default();
+        assert_eq!(usage.headroom(0), u32::MAX);
+        usage.record_use(100);
+        usage.record_use(200);
+        assert_eq!(usage.window_requests, 2);
+        assert_eq!(usage.window_reset_at(200), Some(100 + COOKIE_WINDOW_SECS));
+        assert!(usage.headroom(200) < u32::MAX);
+        // window elapsed, a new one is opened
+        let later = 100 + COOKIE_WINDOW_SECS;
+        assert_eq!(usage.headroom(later), u32::MAX);
+        usage.record_use(later);
+        assert_eq!(usage.window_requests, 1);
+        assert_eq!(usage.total_requests, 3);
+    }
+
+    #[test]
+    fn test_usage_kept_out_of_config() {
+        #[derive(Serialize)]
+        struct Config {
+            #[serde(serialize_with = "persist_cookies")]
+            cookie_array: HashSet<CookieStatus>,
+        }
+        let mut cookie = CookieStatus::new("sk-ant-REDACTED", None).unwrap();
+        cookie.usage.record_use(100);
+        let json = serde_json::to_value(&cookie).unwrap();
+        assert_eq!(json["usage"]["total_requests"], 1);
+        let config = Config {
+            cookie_array: HashSet::from([cookie]),
+        };
+        let toml = toml::to_string(&config).unwrap();
+        assert!(toml.contains("sk-ant-sid01"));
+        assert!(!toml.contains("usage"));
+    }
 }
@@ -1,4 +1,4 @@
-use std::{fmt::Display, ops::Deref, sync::LazyLock};
+use std::{collections::HashMap, fmt::Display, ops::Deref, sync::LazyLock};
 
 use serde::{Deserialize, Serialize};
 use tracing::warn;
@@ -66,11 +66,160 @@ impl From<GeminiKey> for String {
     }
 }
 
+/// Tier of keys without one
+pub const DEFAULT_KEY_TIER: &str = "default";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct KeyStatus {
     pub key: GeminiKey,
     #[serde(default)]
     pub count_403: u32,
+    /// Proxy pinned to this key, overrides the proxy pool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    /// Mirror of the Gemini API serving this key, e.g. a Cloudflare worker,
+    /// overrides `gemini_endpoint`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    /// Key is out of rotation until this timestamp
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub suspended_until: Option<i64>,
+    /// Key is out of rotation until it is removed and added again
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub quarantined: bool,
+    /// Tier the key belongs to, e.g. `free` or `paid`, untagged keys are in
+    /// [`DEFAULT_KEY_TIER`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tier: Option<String>,
+    /// Models the key is out of rotation for until the timestamp, e.g. after
+    /// exhausting the quota of one model
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub model_cooldowns: HashMap<String, i64>,
+    /// Requests the key may serve per Pacific Time day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_request_budget: Option<u64>,
+    /// Tokens the key may consume per Pacific Time day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_token_budget: Option<u64>,
+    /// Consumption counted against the budgets
+    #[serde(default, skip_serializing_if = "KeyUsage::is_empty")]
+    pub usage: KeyUsage,
+}
+
+/// How close a key is to its daily budget
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BudgetState {
+    /// Below the rotation threshold
+    Fresh,
+    /// Past the rotation threshold, only used when no fresh key is left
+    Low,
+    /// Budget used up until the next day
+    Exhausted,
+}
+
+/// Consumption of a key on one Pacific Time day
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct KeyUsage {
+    /// Day the counts belong to, `YYYY-MM-DD`
+    #[serde(default)]
+    pub day: String,
+    #[serde(default)]
+    pub requests: u64,
+    #[serde(default)]
+    pub tokens: u64,
+}
+
+impl KeyUsage {
+    fn is_empty(&self) -> bool {
+        self.requests == 0 && self.tokens == 0
+    }
+
+    /// Counts of `today`, starting over on a new day
+    pub fn today(&mut self, today: &str) -> &mut Self {
+        if self.day != today {
+            *self = Self {
+                day: today.to_string(),
+                ..Default::default()
+            };
+        }
+        self
+    }
+}
+
+/// Part of the key list managed by this instance, for several instances
+/// sharing a key list without Redis
+///
+/// Keys are assigned by a hash of the key, so every instance given the same
+/// `count` and a distinct `index` manages a disjoint subset.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct KeyShard {
+    /// Shard of this instance, from 0 to `count - 1`
+    pub index: usize,
+    /// Number of instances sharing the key list
+    pub count: usize,
+}
+
+impl KeyShard {
+    /// Whether the shard is usable, an invalid one is ignored
+    pub fn is_valid(&self) -> bool {
+        self.index < self.count
+    }
+
+    /// Shard a key belongs to
+    pub fn of(&self, key: &GeminiKey) -> usize {
+        let hash = ring::digest::digest(&ring::digest::SHA256, key.as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash.as_ref()[..8]);
+        (u64::from_be_bytes(bytes) % self.count as u64) as usize
+    }
+
+    /// Whether this instance manages the key
+    pub fn owns(&self, key: &GeminiKey) -> bool {
+        !self.is_valid() || self.of(key) == self.index
+    }
+}
+
+/// Current day in Pacific Time, when Gemini quotas reset
+///
+/// Follows US daylight saving time, from 2 AM on the second Sunday of March
+/// to 2 AM on the first Sunday of November.
+pub fn pacific_day() -> String {
+    use chrono::{Datelike, Duration, NaiveDate, Utc, Weekday};
+    let now = Utc::now().naive_utc();
+    let sunday = |month, n| {
+        NaiveDate::from_weekday_of_month_opt(now.year(), month, Weekday::Sun, n)
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+    };
+    let dst = match (sunday(3, 2), sunday(11, 1)) {
+        // 2 AM PST and 2 AM PDT in UTC
+        (Some(start), Some(end)) => {
+            now >= start + Duration::hours(10) && now < end + Duration::hours(9)
+        }
+        _ => false,
+    };
+    let offset = if dst { 7 } else { 8 };
+    (now - Duration::hours(offset))
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+impl From<GeminiKey> for KeyStatus {
+    /// A fresh key in the default tier, without budgets
+    fn from(key: GeminiKey) -> Self {
+        Self {
+            key,
+            count_403: 0,
+            proxy: None,
+            endpoint: None,
+            suspended_until: None,
+            quarantined: false,
+            tier: None,
+            model_cooldowns: Default::default(),
+            daily_request_budget: None,
+            daily_token_budget: None,
+            usage: Default::default(),
+        }
+    }
 }
 
 impl PartialEq for KeyStatus {
@@ -89,4 +238,61 @@ impl KeyStatus {
     pub fn validate(&self) -> bool {
         self.key.validate()
     }
+
+    /// Whether the key belongs to `tier`
+    pub fn in_tier(&self, tier: &str) -> bool {
+        self.tier.as_deref().unwrap_or(DEFAULT_KEY_TIER) == tier
+    }
+
+    /// Whether the key can be dispatched at the given time
+    pub fn usable(&self, now: i64) -> bool {
+        !self.quarantined && self.suspended_until.is_none_or(|t| t <= now)
+    }
+
+    /// Whether the key can be dispatched for `model` at the given time
+    pub fn usable_for(&self, model: Option<&str>, now: i64) -> bool {
+        self.usable(now)
+            && model
+                .and_then(|m| self.model_cooldowns.get(m))
+                .is_none_or(|&t| t <= now)
+    }
+
+    /// How much of its daily budget the key has used
+    ///
+    /// # Arguments
+    /// * `today` - Current day, see [`pacific_day`]
+    /// * `rotate_at` - Percentage of a budget past which the key is low
+    pub fn budget(&self, today: &str, rotate_at: u64) -> BudgetState {
+        if self.usage.day != today {
+            return BudgetState::Fresh;
+        }
+        [
+            (self.usage.requests, self.daily_request_budget),
+            (self.usage.tokens, self.daily_token_budget),
+        ]
+        .into_iter()
+        .filter_map(|(used, budget)| {
+            let budget = budget?;
+            Some(if used >= budget {
+                BudgetState::Exhausted
+            } else if used * 100 >= budget * rotate_at {
+                BudgetState::Low
+            } else {
+                BudgetState::Fresh
+            })
+        })
+        .max()
+        .unwrap_or(BudgetState::Fresh)
+    }
+
+    /// Merges the cooldowns of another copy of the key, keeping the later
+    /// ones and dropping expired ones
+    pub fn merge_cooldowns(&mut self, other: &KeyStatus, now: i64) {
+        self.suspended_until = self.suspended_until.max(other.suspended_until);
+        for (model, &until) in &other.model_cooldowns {
+            let entry = self.model_cooldowns.entry(model.to_owned()).or_default();
+            *entry = (*entry).max(until);
+        }
+        self.model_cooldowns.retain(|_, &mut until| until > now);
+    }
 }
@@ -1,14 +1,18 @@
 // Re-export all items from submodules
+mod check;
 mod clewdr_config;
 mod constants;
 mod cookie;
 mod key;
 mod reason;
+mod sealed;
 mod token;
 
+pub use check::*;
 pub use clewdr_config::*;
 pub use constants::*;
 pub use cookie::*;
 pub use key::*;
 pub use reason::*;
+pub use sealed::*;
 pub use token::*;
@@ -0,0 +1,261 @@
+use std::{
+    collections::HashSet,
+    num::NonZeroU32,
+    process::Command,
+    sync::{LazyLock, Mutex},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use ring::{
+    aead::{AES_256_GCM, Aad, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    digest::{SHA256, SHA256_OUTPUT_LEN, digest},
+    pbkdf2::{self, PBKDF2_HMAC_SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    CookieStatus, KeyStatus, ServiceAccountKey, UselessCookie, env_or_file, persist_cookies,
+};
+use crate::error::ClewdrError;
+
+/// Environment variable holding the passphrase of the sealed credentials,
+/// also read from the file named by `CLEWDR_CONFIG_PASSPHRASE_FILE`
+pub const PASSPHRASE_ENV: &str = "CLEWDR_CONFIG_PASSPHRASE";
+/// Service and account the passphrase is stored under in the OS keyring
+const KEYRING_SERVICE: &str = "clewdr";
+const KEYRING_ACCOUNT: &str = "config";
+
+const PBKDF2_ROUNDS: NonZeroU32 = NonZeroU32::new(600_000).unwrap();
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+/// Passphrase from the environment, or else from the OS keyring
+static PASSPHRASE: LazyLock<Option<String>> = LazyLock::new(|| {
+    env_or_file("CONFIG_PASSPHRASE")
+        .filter(|p| !p.is_empty())
+        .or_else(keyring_passphrase)
+});
+
+/// Key derived from a passphrase, with the salt and a hash of the
+/// passphrase it was derived from; derivation is slow so it is done once
+static DERIVED: Mutex<Option<Derived>> = Mutex::new(None);
+
+struct Derived {
+    salt: [u8; SALT_LEN],
+    passphrase: [u8; SHA256_OUTPUT_LEN],
+    key: [u8; KEY_LEN],
+}
+
+/// Credentials stored encrypted in the `sealed` field of the config file
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct SealedCredentials {
+    #[serde(default, serialize_with = "persist_cookies")]
+    pub cookie_array: HashSet<CookieStatus>,
+    #[serde(default)]
+    pub wasted_cookie: HashSet<UselessCookie>,
+    #[serde(default)]
+    pub gemini_keys: HashSet<KeyStatus>,
+    #[serde(default)]
+    pub vertex_credential: Option<ServiceAccountKey>,
+}
+
+/// Whether a passphrase to seal credentials with is set
+pub fn passphrase_available() -> bool {
+    PASSPHRASE.is_some()
+}
+
+/// Reads the passphrase with the keyring tool of the OS, `security` on macOS
+/// and `secret-tool` of libsecret elsewhere
+fn keyring_passphrase() -> Option<String> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut c = Command::new("security");
+        c.args([
+            "find-generic-password",
+            "-s",
+            KEYRING_SERVICE,
+            "-a",
+            KEYRING_ACCOUNT,
+            "-w",
+        ]);
+        c
+    } else if cfg!(unix) {
+        let mut c = Command::new("secret-tool");
+        c.args([
+            "lookup",
+            "service",
+            KEYRING_SERVICE,
+            "account",
+            KEYRING_ACCOUNT,
+        ]);
+        c
+    } else {
+        return None;
+    };
+    let output = command.output().ok().filter(|o| o.status.success())?;
+    let passphrase = String::from_utf8(output.stdout).ok()?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+    (!passphrase.is_empty()).then(|| passphrase.to_string())
+}
+
+fn seal_error(msg: impl Into<String>) -> ClewdrError {
+    ClewdrError::SealError { msg: msg.into() }
+}
+
+fn passphrase() -> Result<&'static str, ClewdrError> {
+    PASSPHRASE.as_deref().ok_or_else(|| {
+        seal_error(format!(
+            "no passphrase, set {PASSPHRASE_ENV} or store one in the OS keyring under service `{KEYRING_SERVICE}`"
+        ))
+    })
+}
+
+/// Key for `salt`, derived from the passphrase unless it was already
+fn key(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<LessSafeKey, ClewdrError> {
+    let hash: [u8; SHA256_OUTPUT_LEN] = digest(&SHA256, passphrase.as_bytes())
+        .as_ref()
+        .try_into()
+        .expect("SHA-256 output length");
+    let mut derived = DERIVED.lock().unwrap_or_else(|e| e.into_inner());
+    let bytes = match *derived {
+        Some(ref d) if d.salt == salt && d.passphrase == hash => d.key,
+        _ => {
+            let mut key = [0; KEY_LEN];
+            pbkdf2::derive(
+                PBKDF2_HMAC_SHA256,
+                PBKDF2_ROUNDS,
+                &salt,
+                passphrase.as_bytes(),
+                &mut key,
+            );
+            *derived = Some(Derived {
+                salt,
+                passphrase: hash,
+                key,
+            });
+            key
+        }
+    };
+    let key = UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| seal_error("invalid key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypts credentials with AES-256-GCM under a key derived from the
+/// passphrase, the salt is kept across saves so the key is derived once
+///
+/// # Returns
+/// Base64 of the salt, nonce and ciphertext
+pub fn seal_credentials(credentials: &SealedCredentials) -> Result<String, ClewdrError> {
+    seal_with(passphrase()?, credentials)
+}
+
+fn seal_with(passphrase: &str, credentials: &SealedCredentials) -> Result<String, ClewdrError> {
+    let rng = SystemRandom::new();
+    let salt = match *DERIVED.lock().unwrap_or_else(|e| e.into_inner()) {
+        Some(ref d) => d.salt,
+        None => {
+            let mut salt = [0; SALT_LEN];
+            rng.fill(&mut salt)
+                .map_err(|_| seal_error("no randomness for the salt"))?;
+            salt
+        }
+    };
+    let key = key(passphrase, salt)?;
+    let mut nonce = [0; NONCE_LEN];
+    rng.fill(&mut nonce)
+        .map_err(|_| seal_error("no randomness for the nonce"))?;
+    let mut data = serde_json::to_vec(credentials)?;
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+        .map_err(|_| seal_error("encryption failed"))?;
+    let mut out = Vec::with_capacity(SALT_LEN + NONCE_LEN + data.len());
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&data);
+    Ok(BASE64_STANDARD.encode(out))
+}
+
+/// Decrypts credentials sealed by [`seal_credentials`]
+pub fn open_credentials(sealed: &str) -> Result<SealedCredentials, ClewdrError> {
+    open_with(passphrase()?, sealed)
+}
+
+fn open_with(passphrase: &str, sealed: &str) -> Result<SealedCredentials, ClewdrError> {
+    let data = BASE64_STANDARD
+        .decode(sealed.trim())
+        .map_err(|e| seal_error(format!("invalid base64: {e}")))?;
+    if data.len() < SALT_LEN + NONCE_LEN {
+        return Err(seal_error("sealed data is truncated"));
+    }
+    let (salt, rest) = data.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key = key(passphrase, salt.try_into().expect("salt length checked"))?;
+    let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| seal_error("invalid nonce"))?;
+    let mut ciphertext = ciphertext.to_vec();
+    let plain = key
+        .open_in_place(nonce, Aad::empty(), &mut ciphertext)
+        .map_err(|_| seal_error("wrong passphrase or corrupted data"))?;
+    serde_json::from_slice(plain)
+        .map_err(|e| seal_error(format!("sealed credentials are malformed: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{GeminiKey, KeyStatus};
+
+    fn credentials() -> SealedCredentials {
+        SealedCredentials {
+            gemini_keys: HashSet::from([KeyStatus::from(GeminiKey::from(
+                "AIzaSyA-test-key-0123456789abcdefghijklm",
+            ))]),
+            ..Default::default()
+        }
+    }
+
+    fn is_seal_error<T>(result: Result<T, ClewdrError>) -> bool {
+        matches!(result, Err(ClewdrError::SealError { .. }))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let sealed = seal_with("correct horse", &credentials()).unwrap();
+        let opened = open_with("correct horse", &sealed).unwrap();
+        assert_eq!(opened.gemini_keys, credentials().gemini_keys);
+        assert!(opened.cookie_array.is_empty());
+        // fresh nonce per seal, the salt is reused
+        let again = seal_with("correct horse", &credentials()).unwrap();
+        assert_ne!(sealed, again);
+        let (sealed, again) = (
+            BASE64_STANDARD.decode(sealed).unwrap(),
+            BASE64_STANDARD.decode(again).unwrap(),
+        );
+        assert_eq!(sealed[..SALT_LEN], again[..SALT_LEN]);
+        assert_ne!(
+            sealed[SALT_LEN..SALT_LEN + NONCE_LEN],
+            again[SALT_LEN..SALT_LEN + NONCE_LEN]
+        );
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = seal_with("one passphrase", &credentials()).unwrap();
+        assert!(is_seal_error(open_with("another passphrase", &sealed)));
+        // the cached key of the other passphrase is not reused
+        assert!(open_with("one passphrase", &sealed).is_ok());
+    }
+
+    #[test]
+    fn test_tampered_or_truncated() {
+        let sealed = seal_with("tamper", &credentials()).unwrap();
+        let mut bytes = BASE64_STANDARD.decode(&sealed).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        assert!(is_seal_error(open_with(
+            "tamper",
+            &BASE64_STANDARD.encode(&bytes)
+        )));
+        let short = BASE64_STANDARD.encode(&bytes[..SALT_LEN + NONCE_LEN - 1]);
+        assert!(is_seal_error(open_with("tamper", &short)));
+        assert!(is_seal_error(open_with("tamper", "not base64!")));
+    }
+}
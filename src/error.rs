@@ -16,7 +16,10 @@ use tokio::sync::oneshot;
 use tracing::{debug, error};
 use wreq::{Response, StatusCode, header::InvalidHeaderValue};
 
-use crate::{config::Reason, types::claude::Message};
+use crate::{
+    config::{HookStage, Reason},
+    types::claude::Message,
+};
 
 #[derive(Debug, IntoStaticStr, snafu::Snafu)]
 #[snafu(visibility(pub(crate)))]
@@ -63,11 +66,33 @@ pub enum ClewdrError {
         uri: String,
         source: http::uri::InvalidUri,
     },
-    #[snafu(display("YuOAuth2 error: {}", source))]
-    #[snafu(context(false))]
-    YuOAuth2Error { source: yup_oauth2::Error },
+    #[snafu(display("Vertex auth error: {}", msg))]
+    VertexAuthError { msg: String },
+    #[snafu(display("Request body exceeds {} bytes", limit))]
+    PayloadTooLarge { limit: usize },
+    #[snafu(display("Image fetch error: {}", msg))]
+    ImageFetchError { msg: String },
+    #[snafu(display(
+        "max_tokens {} exceeds the {} output tokens allowed for {}",
+        requested,
+        limit,
+        model
+    ))]
+    MaxTokensExceeded {
+        model: String,
+        requested: u32,
+        limit: u32,
+    },
     #[snafu(display("Empty choices"))]
     EmptyChoices,
+    #[snafu(display("Rejected by {} hook: {}", stage, msg))]
+    HookRejected { stage: HookStage, msg: String },
+    #[snafu(display("Fault injected for resilience testing"))]
+    InjectedFault { code: StatusCode },
+    #[snafu(display("No response within the latency budget of {}ms", budget_ms))]
+    LatencyBudgetExceeded { budget_ms: u64 },
+    #[snafu(display("Structured output does not match the schema: {}", msg))]
+    InvalidStructuredOutput { msg: String },
     #[snafu(display("JSON error: {}", source))]
     #[snafu(context(false))]
     JsonError { source: serde_json::Error },
@@ -89,8 +114,27 @@ pub enum ClewdrError {
     InvalidHeaderValue { source: InvalidHeaderValue },
     #[snafu(display("Bad request: {}", msg))]
     BadRequest { msg: &'static str },
+    #[snafu(display(
+        "prompt of about {} tokens exceeds the {} token context window of {}",
+        tokens,
+        limit,
+        model
+    ))]
+    ContextOverflow {
+        model: String,
+        tokens: usize,
+        limit: usize,
+    },
+    #[snafu(display("Redis error: {}", msg))]
+    RedisError { msg: String },
+    #[snafu(display("Sealed credentials error: {}", msg))]
+    SealError { msg: String },
+    #[snafu(display("Invalid request: {}", errors.join("; ")))]
+    InvalidRequest { errors: Vec<String> },
     #[snafu(display("Retries exceeded"))]
     TooManyRetries,
+    #[snafu(display("Upstream sent no data for {}s", secs))]
+    StreamStalled { secs: u64 },
     #[snafu(display("EventSource error: {}", source))]
     #[snafu(context(false))]
     EventSourceAxumError {
@@ -100,6 +144,9 @@ pub enum ClewdrError {
     EventSourceRquestError {
         source: eventsource_stream::EventStreamError<wreq::Error>,
     },
+    #[snafu(display("Body error: {}", source))]
+    #[snafu(context(false))]
+    BodyError { source: axum::Error },
     #[snafu(display("Zip error: {}", source))]
     #[snafu(context(false))]
     #[cfg(feature = "portable")]
@@ -118,6 +165,17 @@ pub enum ClewdrError {
     NoCookieAvailable,
     #[snafu(display("No key available"))]
     NoKeyAvailable,
+    #[snafu(display("Request queue is full"))]
+    QueueFull,
+    #[snafu(display(
+        "Too many concurrent requests from this client, the limit is {}",
+        limit
+    ))]
+    ClientLimitExceeded { limit: usize },
+    #[snafu(display("Timed out waiting in the request queue"))]
+    QueueTimeout,
+    #[snafu(display("Timed out waiting for a key to become available"))]
+    KeyWaitTimeout,
     #[snafu(display("Invalid Cookie: {}", reason))]
     #[snafu(context(false))]
     InvalidCookie {
@@ -150,6 +208,8 @@ pub enum ClewdrError {
     },
     #[snafu(display("Http error: code: {}, body: {}", code.to_string().red(), serde_json::to_string_pretty(&inner).unwrap_or_default()))]
     GeminiHttpError { code: StatusCode, inner: Value },
+    #[snafu(display("Http error: code: {}, body: {}", code.to_string().red(), serde_json::to_string_pretty(&inner).unwrap_or_default()))]
+    UpstreamHttpError { code: StatusCode, inner: Value },
     #[snafu(display("Unexpected None: {}", msg))]
     UnexpectedNone { msg: &'static str },
     #[snafu(display("IO error: {}", source))]
@@ -165,6 +225,13 @@ pub enum ClewdrError {
     TimestampError { timestamp: i64 },
     #[snafu(display("Key/Password Invalid"))]
     InvalidAuth,
+    #[snafu(display("Token does not allow {}", msg))]
+    ScopeDenied { msg: String },
+    #[snafu(display(
+        "Too many failed logins from this address, retry in {}s",
+        retry_after_secs
+    ))]
+    AuthLockedOut { retry_after_secs: u64 },
     #[snafu(whatever, display("{}: {}", message, source.as_ref().map_or_else(|| "Unknown error".into(), |e| e.to_string())))]
     Whatever {
         message: String,
@@ -173,36 +240,138 @@ pub enum ClewdrError {
     },
 }
 
-impl IntoResponse for ClewdrError {
-    fn into_response(self) -> axum::response::Response {
-        let (status, msg) = match self {
-            ClewdrError::UrlError {
-                loc,
-                source,
-                ref url,
-            } => (
-                StatusCode::BAD_REQUEST,
-                json!(format!("{}: {} (URL: {})", loc, source, url)),
-            ),
-            ClewdrError::ParseCookieError { .. } => {
-                (StatusCode::BAD_REQUEST, json!(self.to_string()))
-            }
-            ClewdrError::InvalidUri { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
-            ClewdrError::YuOAuth2Error { .. } => {
-                (StatusCode::UNAUTHORIZED, json!(self.to_string()))
+impl ClewdrError {
+    /// HTTP status code that best describes this error to the client
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ClewdrError::UrlError { .. }
+            | ClewdrError::ParseCookieError { .. }
+            | ClewdrError::InvalidUri { .. }
+            | ClewdrError::BadRequest { .. }
+            | ClewdrError::ImageFetchError { .. }
+            | ClewdrError::MaxTokensExceeded { .. }
+            | ClewdrError::ContextOverflow { .. }
+            | ClewdrError::InvalidHeaderValue { .. }
+            | ClewdrError::JsonError { .. } => StatusCode::BAD_REQUEST,
+            ClewdrError::InvalidRequest { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            ClewdrError::HookRejected {
+                stage: HookStage::Request,
+                ..
+            } => StatusCode::BAD_REQUEST,
+            ClewdrError::HookRejected {
+                stage: HookStage::Response,
+                ..
+            } => StatusCode::BAD_GATEWAY,
+            ClewdrError::PathRejection { source } => source.status(),
+            ClewdrError::QueryRejection { source } => source.status(),
+            ClewdrError::JsonRejection { source } => source.status(),
+            ClewdrError::VertexAuthError { .. } | ClewdrError::InvalidAuth => {
+                StatusCode::UNAUTHORIZED
             }
-            ClewdrError::PathRejection { ref source } => {
-                (source.status(), json!(source.body_text()))
-            }
-            ClewdrError::QueryRejection { ref source } => {
-                (source.status(), json!(source.body_text()))
-            }
-            ClewdrError::ClaudeHttpError { code, inner } => {
-                return (code, Json(ClaudeError { error: inner })).into_response();
+            ClewdrError::ScopeDenied { .. } => StatusCode::FORBIDDEN,
+            ClewdrError::ClaudeHttpError { code, .. }
+            | ClewdrError::GeminiHttpError { code, .. }
+            | ClewdrError::UpstreamHttpError { code, .. }
+            | ClewdrError::InjectedFault { code } => *code,
+            ClewdrError::InvalidCookie {
+                reason: Reason::TooManyRequest(_) | Reason::Restricted(_),
+            } => StatusCode::TOO_MANY_REQUESTS,
+            ClewdrError::InvalidCookie { .. } => StatusCode::BAD_REQUEST,
+            ClewdrError::PathNotFound { .. } => StatusCode::NOT_FOUND,
+            ClewdrError::NoCookieAvailable
+            | ClewdrError::NoKeyAvailable
+            | ClewdrError::QueueTimeout
+            | ClewdrError::KeyWaitTimeout => StatusCode::SERVICE_UNAVAILABLE,
+            ClewdrError::QueueFull
+            | ClewdrError::ClientLimitExceeded { .. }
+            | ClewdrError::AuthLockedOut { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ClewdrError::PayloadTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            ClewdrError::TooManyRetries
+            | ClewdrError::StreamStalled { .. }
+            | ClewdrError::LatencyBudgetExceeded { .. } => StatusCode::GATEWAY_TIMEOUT,
+            e if e.is_timeout() => StatusCode::GATEWAY_TIMEOUT,
+            ClewdrError::EmptyChoices => StatusCode::NO_CONTENT,
+            ClewdrError::InvalidStructuredOutput { .. }
+            | ClewdrError::WreqError { .. }
+            | ClewdrError::EventSourceRquestError { .. }
+            | ClewdrError::BodyError { .. } => StatusCode::BAD_GATEWAY,
+            ClewdrError::TestMessage => StatusCode::OK,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Human readable message sent to the client
+    fn client_message(&self) -> Value {
+        match self {
+            ClewdrError::UrlError { loc, source, url } => {
+                json!(format!("{}: {} (URL: {})", loc, source, url))
             }
-            ClewdrError::GeminiHttpError { code, inner } => {
-                return (code, Json(inner)).into_response();
+            ClewdrError::PathRejection { source } => json!(source.body_text()),
+            ClewdrError::QueryRejection { source } => json!(source.body_text()),
+            ClewdrError::JsonRejection { source } => json!(source.body_text()),
+            ClewdrError::WreqError { msg, .. } if self.is_timeout() => {
+                json!(format!("Upstream timed out: {msg}"))
             }
+            _ => json!(self.to_string()),
+        }
+    }
+
+    /// Whether an upstream request hit its configured timeout, as opposed to
+    /// other network errors
+    pub fn is_timeout(&self) -> bool {
+        matches!(self, ClewdrError::WreqError { source, .. } if source.is_timeout())
+    }
+}
+
+/// Dialect independent description of an error response
+///
+/// Attached to the extensions of every error response, so that route specific
+/// middleware can re-render the error in the format the client expects
+#[derive(Debug, Clone)]
+pub struct ErrorDetail {
+    pub status: StatusCode,
+    /// Snake case name of the internal error variant
+    pub code: &'static str,
+    /// Anthropic style error type, e.g. `invalid_request_error`
+    pub r#type: String,
+    pub message: Value,
+    /// Raw error body returned by the upstream, if any
+    pub upstream: Option<Value>,
+}
+
+impl ErrorDetail {
+    /// Message flattened into a plain string
+    pub fn message_text(&self) -> String {
+        match self.message {
+            Value::String(ref s) => s.to_owned(),
+            ref v => v.to_string(),
+        }
+    }
+}
+
+/// Maps a HTTP status code to the matching Anthropic error type
+pub fn anthropic_error_type(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        404 => "not_found_error",
+        413 => "request_too_large",
+        429 => "rate_limit_error",
+        529 => "overloaded_error",
+        _ => "api_error",
+    }
+}
+
+impl IntoResponse for ClewdrError {
+    fn into_response(self) -> axum::response::Response {
+        let status = self.status();
+        let code = if self.is_timeout() {
+            "upstream_timeout"
+        } else {
+            <&str>::from(&self)
+        };
+        let (inner, upstream) = match self {
             ClewdrError::TestMessage => {
                 return (
                     StatusCode::OK,
@@ -212,37 +381,68 @@ impl IntoResponse for ClewdrError {
                 )
                     .into_response();
             }
-            ClewdrError::JsonRejection { ref source } => {
-                (source.status(), json!(source.body_text()))
+            ClewdrError::ClaudeHttpError { inner, .. } => {
+                let upstream = serde_json::to_value(&inner).ok();
+                (inner, upstream)
+            }
+            ClewdrError::GeminiHttpError { ref inner, .. }
+            | ClewdrError::UpstreamHttpError { ref inner, .. } => {
+                let message = inner
+                    .pointer("/error/message")
+                    .or_else(|| inner.pointer("/0/error/message"))
+                    .or_else(|| inner.get("message"))
+                    .cloned()
+                    .unwrap_or_else(|| json!(inner.to_string()));
+                let body = ClaudeErrorBody {
+                    message,
+                    r#type: anthropic_error_type(status).into(),
+                    code: Some(status.as_u16()),
+                };
+                (body, Some(inner.to_owned()))
             }
-            ClewdrError::TooManyRetries => (StatusCode::GATEWAY_TIMEOUT, json!(self.to_string())),
-            ClewdrError::InvalidCookie { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
-            ClewdrError::PathNotFound { .. } => (StatusCode::NOT_FOUND, json!(self.to_string())),
-            ClewdrError::InvalidAuth => (StatusCode::UNAUTHORIZED, json!(self.to_string())),
-            ClewdrError::BadRequest { .. } => (StatusCode::BAD_REQUEST, json!(self.to_string())),
-            ClewdrError::InvalidHeaderValue { .. } => {
-                (StatusCode::BAD_REQUEST, json!(self.to_string()))
+            _ => {
+                let body = ClaudeErrorBody {
+                    message: self.client_message(),
+                    r#type: anthropic_error_type(status).into(),
+                    code: Some(status.as_u16()),
+                };
+                (body, None)
             }
-            ClewdrError::EmptyChoices => (StatusCode::NO_CONTENT, json!(self.to_string())),
-            _ => (StatusCode::INTERNAL_SERVER_ERROR, json!(self.to_string())),
         };
-        let err = ClaudeError {
-            error: ClaudeErrorBody {
-                message: msg,
-                r#type: <&str>::from(self).into(),
-                code: Some(status.as_u16()),
-            },
+        let detail = ErrorDetail {
+            status,
+            code,
+            r#type: inner.r#type.to_owned(),
+            message: inner.message.to_owned(),
+            upstream,
         };
-        (status, Json(err)).into_response()
+        let mut resp = (status, Json(ClaudeError::from(inner))).into_response();
+        resp.extensions_mut().insert(detail);
+        resp
     }
 }
 
 /// HTTP error response
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ClaudeError {
+    #[serde(default = "default_error_tag")]
+    pub r#type: String,
     pub error: ClaudeErrorBody,
 }
 
+fn default_error_tag() -> String {
+    "error".to_string()
+}
+
+impl From<ClaudeErrorBody> for ClaudeError {
+    fn from(error: ClaudeErrorBody) -> Self {
+        ClaudeError {
+            r#type: default_error_tag(),
+            error,
+        }
+    }
+}
+
 /// Inner HTTP error response
 #[derive(Debug, Serialize, Clone)]
 pub struct ClaudeErrorBody {
@@ -0,0 +1,56 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use tracing::info;
+
+use crate::config::{CLEWDR_CONFIG, GeminiApiVersion};
+
+/// Versions models were found on after their configured one returned 404
+static FOUND: LazyLock<Mutex<HashMap<String, GeminiApiVersion>>> = LazyLock::new(Default::default);
+
+/// Versions to send a request for the model to, in order
+///
+/// The version the model was last found on comes first, then the configured
+/// one, then the others if `gemini_api_versions.fallback` is set.
+pub fn candidates(model: &str) -> Vec<GeminiApiVersion> {
+    let config = CLEWDR_CONFIG.load();
+    let configured = config.gemini_api_versions.version(model);
+    let found = FOUND
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(model)
+        .copied();
+    let mut versions = found.into_iter().collect::<Vec<_>>();
+    versions.push(configured);
+    if config.gemini_api_versions.fallback {
+        versions.extend([
+            GeminiApiVersion::V1beta,
+            GeminiApiVersion::V1alpha,
+            GeminiApiVersion::V1,
+        ]);
+    }
+    let mut seen = Vec::new();
+    versions.retain(|v| {
+        let new = !seen.contains(v);
+        seen.push(*v);
+        new
+    });
+    versions
+}
+
+/// Remembers the version that served the model, so later requests skip the
+/// versions answering 404
+pub fn found(model: &str, version: GeminiApiVersion) {
+    let mut found = FOUND.lock().unwrap_or_else(|e| e.into_inner());
+    if found.get(model) == Some(&version) {
+        return;
+    }
+    if found.contains_key(model)
+        || version != CLEWDR_CONFIG.load().gemini_api_versions.version(model)
+    {
+        info!("Gemini model {} is served by {}", model, version);
+        found.insert(model.to_owned(), version);
+    }
+}
@@ -1,25 +1,49 @@
 use std::sync::LazyLock;
 
-use axum::response::Response;
+use axum::BoxError;
+use axum::{body::Body, response::Response};
+use bytes::Bytes;
 use colored::Colorize;
-use http::header::CONTENT_TYPE;
-use hyper_util::client::legacy::connect::HttpConnector;
+use eventsource_stream::{EventStreamError, Eventsource};
+use futures::{Stream, StreamExt, stream::BoxStream};
+use http::{HeaderValue, header::CONTENT_TYPE};
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Value, json};
 use snafu::ResultExt;
 use strum::Display;
 use tokio::spawn;
-use tracing::{error, info};
-use wreq::{Client, ClientBuilder, header::AUTHORIZATION};
-use yup_oauth2::{CustomHyperClientBuilder, ServiceAccountAuthenticator, ServiceAccountKey};
+use tracing::{Instrument, debug, error, info, warn};
+use wreq::{Client, ClientBuilder, StatusCode, header::AUTHORIZATION};
+
+pub(crate) mod api_version;
+pub(crate) mod vertex_region;
+pub(crate) mod vertex_token;
 
 use crate::{
-    config::{CLEWDR_CONFIG, GEMINI_ENDPOINT, KeyStatus},
-    error::{CheckGeminiErr, ClewdrError, InvalidUriSnafu, WreqSnafu},
+    config::{Backend, CLEWDR_CONFIG, ErrorAction, GEMINI_ENDPOINT, GeminiKey, KeyStatus},
+    error::{CheckGeminiErr, ClewdrError, WreqSnafu},
     middleware::gemini::*,
-    services::key_actor::KeyActorHandle,
-    types::gemini::response::{FinishReason, GeminiResponse},
-    utils::forward_response,
+    provider::{self, Provider, Verdict},
+    services::{
+        continuation, daily_report, gemini_endpoint,
+        key_actor::{KeyActorHandle, KeyRequest},
+        mock,
+        proxy_pool::{PROXY_POOL, to_wreq_proxy},
+        quality_gate,
+    },
+    streaming::{
+        StreamDialect, fallback,
+        gemini::{self, GeminiFraming},
+        watchdog,
+    },
+    types::{
+        gemini::response::{FinishReason, GeminiResponse, UsageMetadata},
+        oai::{
+            CompletionUsage, ResponseFormat, ThoughtSplitter, normalize_grounding,
+            normalize_logprobs,
+        },
+    },
+    utils::forward_guarded,
 };
 
 #[derive(Clone, Display, PartialEq, Eq)]
@@ -28,42 +52,126 @@ pub enum GeminiApiFormat {
     OpenAI,
 }
 
-static DUMMY_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+/// Rewrites OpenAI format stream chunks carrying grounding metadata or
+/// thoughts, other events are forwarded untouched
+fn transform_oai_stream(
+    status: StatusCode,
+    stream: impl Stream<Item = Result<Bytes, wreq::Error>> + Send + 'static,
+    mut meter: TokenMeter,
+) -> Response {
+    let expose = CLEWDR_CONFIG.load().gemini_thinking.expose_thoughts;
+    let mut splitter = ThoughtSplitter::default();
+    let stream = stream.eventsource().map(move |event| {
+        let event = event?;
+        let data = match serde_json::from_str::<Value>(&event.data) {
+            Ok(mut chunk) => {
+                meter.observe(&chunk);
+                let grounded = normalize_grounding(&mut chunk);
+                let logprobs = normalize_logprobs(&mut chunk);
+                if splitter.apply(&mut chunk, expose) || grounded || logprobs {
+                    chunk.to_string()
+                } else {
+                    event.data
+                }
+            }
+            // e.g. `[DONE]`
+            Err(_) => event.data,
+        };
+        Ok::<_, EventStreamError<wreq::Error>>(Bytes::from(format!("data: {data}\n\n")))
+    });
+    let mut res = Response::new(Body::from_stream(stream));
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    res
+}
 
-// TODO: replace yup-oauth2 with oauth2 crate
-async fn get_token(sa_key: ServiceAccountKey) -> Result<String, ClewdrError> {
-    const SCOPES: [&str; 1] = ["https://www.googleapis.com/auth/cloud-platform"];
-    let token = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
-        let proxy = proxy
-            .trim_start_matches("http://")
-            .trim_start_matches("https://")
-            .trim_start_matches("socks5://");
-        let proxy = format!("http://{proxy}");
-        let proxy_uri = proxy.parse().context(InvalidUriSnafu {
-            uri: proxy.to_owned(),
-        })?;
-        let proxy = hyper_http_proxy::Proxy::new(hyper_http_proxy::Intercept::All, proxy_uri);
-        let connector = HttpConnector::new();
-        let proxy_connector = hyper_http_proxy::ProxyConnector::from_proxy(connector, proxy)?;
-        let client =
-            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
-                .pool_max_idle_per_host(0)
-                .build(proxy_connector);
-        let client_builder = CustomHyperClientBuilder::from(client);
-        let auth = ServiceAccountAuthenticator::with_client(sa_key, client_builder)
-            .build()
-            .await?;
-        auth.token(&SCOPES).await?
-    } else {
-        let auth = ServiceAccountAuthenticator::builder(sa_key).build().await?;
-        auth.token(&SCOPES).await?
-    };
-    let token = token.token().ok_or(ClewdrError::UnexpectedNone {
-        msg: "Oauth token is None",
-    })?;
-    Ok(token.into())
+/// Counts the tokens a completion reports for the daily summary, and against
+/// the daily token budget of its key if it has one, once the response is
+/// dropped
+struct TokenMeter {
+    budget: Option<(KeyActorHandle, GeminiKey)>,
+    tokens: u64,
+}
+
+impl TokenMeter {
+    /// Records the usage a response or chunk reports, usage is cumulative so
+    /// the last one wins
+    fn observe(&mut self, res: &Value) {
+        if let Some(tokens) = res["usageMetadata"]["totalTokenCount"]
+            .as_u64()
+            .or_else(|| res["usage"]["total_tokens"].as_u64())
+        {
+            self.tokens = tokens;
+        }
+    }
+
+    /// Meters a stream of native chunks
+    fn chunks(
+        mut self,
+        chunks: BoxStream<'static, Result<Value, BoxError>>,
+    ) -> BoxStream<'static, Result<Value, BoxError>> {
+        chunks
+            .map(move |chunk| {
+                if let Ok(ref chunk) = chunk {
+                    self.observe(chunk);
+                }
+                chunk
+            })
+            .boxed()
+    }
 }
 
+impl Drop for TokenMeter {
+    fn drop(&mut self) {
+        daily_report::add_tokens(self.tokens);
+        if let Some((ref handle, ref key)) = self.budget
+            && self.tokens > 0
+        {
+            handle.consume(key.to_owned(), self.tokens);
+        }
+    }
+}
+
+/// Reasons a Gemini error body reports, used to match the error policy
+///
+/// Native endpoints return an `error` object, the OpenAI compatible endpoint
+/// wraps it in an array. Includes the `ErrorInfo` reasons and the error status.
+fn error_reasons(inner: &Value) -> Vec<String> {
+    let error = inner.get(0).unwrap_or(inner);
+    let error = error.get("error").unwrap_or(error);
+    let mut reasons = error["details"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|d| d["reason"].as_str())
+        .chain(error["status"].as_str())
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    // some endpoints only describe an invalid key in the message
+    if error["message"]
+        .as_str()
+        .is_some_and(|m| m.contains("API key not valid"))
+    {
+        reasons.push("API_KEY_INVALID".to_string());
+    }
+    reasons
+}
+
+/// Action of the first matching rule in `gemini_error_policy`
+fn error_action(code: StatusCode, inner: &Value) -> ErrorAction {
+    let reasons = error_reasons(inner);
+    CLEWDR_CONFIG
+        .load()
+        .gemini_error_policy
+        .iter()
+        .find(|rule| rule.matches(code.as_u16(), &reasons))
+        .map(|rule| rule.action)
+        .unwrap_or(ErrorAction::Retry)
+}
+
+static DUMMY_CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
 #[derive(Clone)]
 pub struct GeminiState {
     pub model: String,
@@ -75,6 +183,19 @@ pub struct GeminiState {
     pub key_handle: KeyActorHandle,
     pub api_format: GeminiApiFormat,
     pub client: Client,
+    /// Proxy used by the current client
+    pub proxy: Option<String>,
+    /// Base URL of the Gemini API for the current key, Google's or a mirror
+    pub endpoint: String,
+    /// Hash pinning the conversation to a key
+    pub session_hash: Option<u64>,
+    /// Structured output requested by an OpenAI format client
+    pub response_format: Option<ResponseFormat>,
+    /// OpenAI format stream served by the native endpoint, the body is
+    /// already a native one
+    pub native_stream: bool,
+    /// Key tiers the client asked for, routed by model if `None`
+    pub key_tiers: Option<Vec<String>>,
 }
 
 impl GeminiState {
@@ -90,22 +211,133 @@ impl GeminiState {
             key_handle: tx,
             api_format: GeminiApiFormat::Gemini,
             client: DUMMY_CLIENT.to_owned(),
+            proxy: None,
+            endpoint: GEMINI_ENDPOINT.to_string(),
+            session_hash: None,
+            response_format: None,
+            native_stream: false,
+            key_tiers: None,
         }
     }
 
-    pub async fn report_403(&self) -> Result<(), ClewdrError> {
-        if let Some(mut key) = self.key.to_owned() {
+    /// Updates the key after an upstream error according to the policy action
+    pub async fn report_error(
+        &self,
+        code: StatusCode,
+        action: ErrorAction,
+    ) -> Result<(), ClewdrError> {
+        let Some(mut key) = self.key.to_owned() else {
+            return Ok(());
+        };
+        match action {
+            ErrorAction::DeleteKey => {
+                warn!("Removing key: {}", key.key.ellipse());
+                daily_report::record_key_lost();
+                return self.key_handle.delete_key(key).await;
+            }
+            // quotas are per model, the key may still serve other models
+            ErrorAction::Cooldown(secs)
+                if code == StatusCode::TOO_MANY_REQUESTS && !self.model.is_empty() =>
+            {
+                let model = self.model.trim_start_matches("models/");
+                warn!(
+                    "Cooling down key {} for {} for {}s",
+                    key.key.ellipse(),
+                    model,
+                    secs
+                );
+                key.model_cooldowns.insert(
+                    model.to_string(),
+                    chrono::Utc::now().timestamp() + secs as i64,
+                );
+            }
+            ErrorAction::Cooldown(secs) => {
+                warn!("Cooling down key {} for {}s", key.key.ellipse(), secs);
+                key.suspended_until = Some(chrono::Utc::now().timestamp() + secs as i64);
+            }
+            ErrorAction::Quarantine => {
+                warn!("Quarantining key: {}", key.key.ellipse());
+                key.quarantined = true;
+            }
+            ErrorAction::Retry | ErrorAction::FailFast => {}
+        }
+        if code == StatusCode::FORBIDDEN {
             key.count_403 += 1;
-            self.key_handle.return_key(key).await?;
         }
-        Ok(())
+        self.key_handle.return_key(key).await
+    }
+
+    /// Reports the key of a stalled stream, as a 504 matched against the
+    /// error policy
+    fn report_stall(&self) {
+        let state = self.to_owned();
+        let code = StatusCode::GATEWAY_TIMEOUT;
+        let action = error_action(code, &json!({ "error": { "status": "DEADLINE_EXCEEDED" } }));
+        spawn(
+            async move {
+                state.report_error(code, action).await.unwrap_or_else(|e| {
+                    error!("Failed to report error: {}", e);
+                });
+            }
+            .in_current_span(),
+        );
     }
 
     pub async fn request_key(&mut self) -> Result<(), ClewdrError> {
-        let key = self.key_handle.request().await?;
+        let key = self.key_handle.request_waiting(self.key_request()).await?;
+        self.endpoint = gemini_endpoint::resolve(key.endpoint.as_deref());
         self.key = Some(key.to_owned());
-        let client = ClientBuilder::new();
-        let client = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
+        self.build_client(key.proxy.as_deref())
+    }
+
+    /// What the request needs from a key, tiers are tried in fallback order
+    fn key_request(&self) -> KeyRequest {
+        let model = self.model.trim_start_matches("models/");
+        KeyRequest {
+            session: self.session_hash,
+            tiers: self
+                .key_tiers
+                .to_owned()
+                .unwrap_or_else(|| CLEWDR_CONFIG.load().key_tiers.route(model)),
+            model: (!model.is_empty()).then(|| model.to_string()),
+        }
+    }
+
+    /// Meter for the completion, charging its key if it has a daily token
+    /// budget
+    fn token_meter(&self) -> TokenMeter {
+        let budget = self
+            .key
+            .as_ref()
+            .filter(|k| k.daily_token_budget.is_some() && !self.vertex)
+            .map(|k| (self.key_handle.to_owned(), k.key.to_owned()));
+        TokenMeter { budget, tokens: 0 }
+    }
+
+    /// Whether a request would have to wait for a key, Vertex needs none
+    pub async fn must_wait_for_key(&self) -> bool {
+        !self.vertex
+            && CLEWDR_CONFIG.load().key_wait_secs > 0
+            && matches!(
+                self.key_handle.request(self.key_request()).await,
+                Err(ClewdrError::NoKeyAvailable)
+            )
+    }
+
+    /// Builds the upstream client through the resolved proxy
+    fn build_client(&mut self, assigned_proxy: Option<&str>) -> Result<(), ClewdrError> {
+        let backend = if self.vertex {
+            Backend::Vertex
+        } else {
+            Backend::Gemini
+        };
+        let client =
+            CLEWDR_CONFIG
+                .load()
+                .timeouts
+                .client(ClientBuilder::new(), backend, Some(&self.model));
+        self.proxy = PROXY_POOL.resolve(assigned_proxy);
+        let client = if let Some(proxy) = self.proxy.as_deref().and_then(to_wreq_proxy) {
             client.proxy(proxy)
         } else {
             client
@@ -116,6 +348,22 @@ impl GeminiState {
         Ok(())
     }
 
+    /// Whether the request calls Imagen's `predict` rather than `generateContent`
+    pub fn is_predict(&self) -> bool {
+        self.path.ends_with(":predict")
+    }
+
+    /// Query sent upstream, native streams are always requested as SSE and
+    /// framed the way the client asked for on the way back
+    fn upstream_query(&self) -> Vec<(&'static str, &str)> {
+        let mut query = self.query.to_vec();
+        if self.stream && !self.is_predict() {
+            query.retain(|(k, _)| *k != "alt");
+            query.push(("alt", "sse"));
+        }
+        query
+    }
+
     pub fn update_from_ctx(&mut self, ctx: &GeminiContext) {
         self.path = ctx.path.to_owned();
         self.stream = ctx.stream.to_owned();
@@ -123,22 +371,18 @@ impl GeminiState {
         self.model = ctx.model.to_owned();
         self.vertex = ctx.vertex.to_owned();
         self.api_format = ctx.api_format.to_owned();
+        self.session_hash = ctx.session_hash;
+        self.key_tiers = ctx.key_tiers.to_owned();
     }
 
     async fn vertex_response(
         &mut self,
         p: impl Sized + Serialize,
     ) -> Result<wreq::Response, ClewdrError> {
-        let client = ClientBuilder::new();
-        let client = if let Some(proxy) = CLEWDR_CONFIG.load().proxy.to_owned() {
-            client.proxy(proxy)
-        } else {
-            client
-        };
-        self.client = client.build().context(WreqSnafu {
-            msg: "Failed to build Gemini client",
-        })?;
-        let method = if self.stream {
+        self.build_client(None)?;
+        let method = if self.is_predict() {
+            "predict"
+        } else if self.stream {
             "streamGenerateContent"
         } else {
             "generateContent"
@@ -151,18 +395,20 @@ impl GeminiState {
             });
         };
 
-        let access_token = get_token(cred.to_owned()).await?;
+        let access_token = vertex_token::get_token(&cred, &self.client).await?;
         let bearer = format!("Bearer {access_token}");
+        let region = vertex_region::next_region();
+        let base_url = vertex_region::base_url(&region);
         let res = match self.api_format {
             GeminiApiFormat::Gemini => {
                 let endpoint = format!(
-                    "https://aiplatform.googleapis.com/v1/projects/{}/locations/global/publishers/google/models/{}:{method}",
+                    "{base_url}/v1/projects/{}/locations/{region}/publishers/google/models/{}:{method}",
                     cred.project_id.unwrap_or_default(),
                     self.model
                 );
-                let query_vec = self.query.to_vec();
-                self
-                    .client
+                debug!("[UPSTREAM] {}", endpoint);
+                let query_vec = self.upstream_query();
+                self.client
                     .post(endpoint)
                     .query(&query_vec)
                     .header(AUTHORIZATION, bearer)
@@ -174,11 +420,13 @@ impl GeminiState {
                     })?
             }
             GeminiApiFormat::OpenAI => {
+                let endpoint = format!(
+                    "{base_url}/v1beta1/projects/{}/locations/{region}/endpoints/openapi/chat/completions",
+                    cred.project_id.unwrap_or_default(),
+                );
+                debug!("[UPSTREAM] {}", endpoint);
                 self.client
-                    .post(format!(
-                        "https://aiplatform.googleapis.com/v1beta1/projects/{}/locations/global/endpoints/openapi/chat/completions",
-                        cred.project_id.unwrap_or_default(),
-                    ))
+                    .post(endpoint)
                     .header(AUTHORIZATION, bearer)
                     .json(&p)
                     .send()
@@ -200,7 +448,6 @@ impl GeminiState {
             let res = self.vertex_response(p).await?;
             return Ok(res);
         }
-        self.request_key().await?;
         let Some(key) = self.key.to_owned() else {
             return Err(ClewdrError::UnexpectedNone {
                 msg: "Key is None, did you request a key?",
@@ -210,107 +457,295 @@ impl GeminiState {
         let key = key.key.to_string();
         let res = match self.api_format {
             GeminiApiFormat::Gemini => {
-                let mut query_vec = self.query.to_vec();
+                let mut query_vec = self.upstream_query();
                 query_vec.push(("key", key.as_str()));
+                return self.send_native(&self.path, &query_vec, &p).await;
+            }
+            GeminiApiFormat::OpenAI if self.native_stream => {
+                let path = format!("models/{}:streamGenerateContent", self.model);
+                let query = [("alt", "sse"), ("key", key.as_str())];
+                return self.send_native(&path, &query, &p).await;
+            }
+            GeminiApiFormat::OpenAI => {
+                let endpoint = format!("{}/v1beta/openai/chat/completions", self.endpoint);
+                debug!("[UPSTREAM] {}", endpoint);
                 self.client
-                    .post(format!("{}/v1beta/{}", GEMINI_ENDPOINT, self.path))
-                    .query(&query_vec)
+                    .post(endpoint)
+                    .header(AUTHORIZATION, format!("Bearer {key}"))
                     .json(&p)
                     .send()
                     .await
                     .context(WreqSnafu {
-                        msg: "Failed to send request to Gemini API",
+                        msg: "Failed to send request to Gemini OpenAI API",
                     })?
             }
-            GeminiApiFormat::OpenAI => self
+        };
+        let res = res.check_gemini().await?;
+        Ok(res)
+    }
+
+    /// Sends a native request to the API version of the model, trying the
+    /// other versions while the model is not found
+    ///
+    /// # Returns
+    /// The error of the first version if no version serves the model
+    async fn send_native(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        p: &impl Serialize,
+    ) -> Result<wreq::Response, ClewdrError> {
+        let mut not_found = None;
+        for version in api_version::candidates(&self.model) {
+            let endpoint = format!("{}/{version}/{path}", self.endpoint);
+            debug!("[UPSTREAM] {}", endpoint);
+            let res = self
                 .client
-                .post(format!("{GEMINI_ENDPOINT}/v1beta/openai/chat/completions",))
-                .header(AUTHORIZATION, format!("Bearer {key}"))
-                .json(&p)
+                .post(endpoint)
+                .query(query)
+                .json(p)
                 .send()
                 .await
                 .context(WreqSnafu {
-                    msg: "Failed to send request to Gemini OpenAI API",
-                })?,
+                    msg: "Failed to send request to Gemini API",
+                })?
+                .check_gemini()
+                .await;
+            match res {
+                Err(ClewdrError::GeminiHttpError { code, inner })
+                    if code == StatusCode::NOT_FOUND =>
+                {
+                    warn!("Gemini model {} not found on {}", self.model, version);
+                    not_found.get_or_insert(ClewdrError::GeminiHttpError { code, inner });
+                }
+                Ok(res) => {
+                    api_version::found(&self.model, version);
+                    return Ok(res);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(not_found.unwrap_or(ClewdrError::UnexpectedNone {
+            msg: "No Gemini API version to send the request to",
+        }))
+    }
+
+    /// Opens a Live API session upstream with a key from the pool
+    ///
+    /// # Arguments
+    /// * `service` - Live API service, e.g.
+    ///   `google.ai.generativelanguage.v1beta.GenerativeService.BidiGenerateContent`
+    pub async fn connect_live(&mut self, service: &str) -> Result<wreq::WebSocket, ClewdrError> {
+        self.request_key().await?;
+        let Some(key) = self.key.to_owned() else {
+            return Err(ClewdrError::UnexpectedNone {
+                msg: "Key is None, did you request a key?",
+            });
         };
-        let res = res.check_gemini().await?;
-        Ok(res)
+        info!("[KEY] {}", key.key.ellipse().green());
+        let endpoint = self
+            .endpoint
+            .replacen("https://", "wss://", 1)
+            .replacen("http://", "ws://", 1);
+        let res = self
+            .client
+            .websocket(format!("{endpoint}/ws/{service}"))
+            .query(&[("key", key.key.to_string())])
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to connect to Gemini Live API",
+            })?;
+        res.into_websocket().await.context(WreqSnafu {
+            msg: "Gemini Live API refused the WebSocket upgrade",
+        })
     }
 
     pub async fn try_chat(&mut self, p: impl Serialize + Clone) -> Result<Response, ClewdrError> {
-        let mut err = None;
-        for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
-            if i > 0 {
-                info!("[RETRY] attempt: {}", i.to_string().green());
-            }
-            let mut state = self.to_owned();
-            let p = p.to_owned();
-
-            match state.send_chat(p).await {
-                Ok(resp) => match state.check_empty_choices(resp).await {
-                    Ok(resp) => return Ok(resp),
-                    Err(e) => {
-                        error!("Failed to check empty choices: {}", e);
-                        err = Some(e);
-                        continue;
-                    }
-                },
-                Err(e) => {
-                    if let Some(key) = state.key.to_owned() {
-                        error!("[{}] {}", key.key.ellipse().green(), e);
-                    } else {
-                        error!("{}", e);
+        if mock::enabled() {
+            let input_tokens = mock::estimate_tokens(&p);
+            let format = self.api_format.to_owned();
+            return mock::gemini(&self.model, self.stream, format, input_tokens).await;
+        }
+        let body = serde_json::to_value(&p)?;
+        match provider::chat_with_retries(self, body.to_owned()).await {
+            // a native stream body can not be sent to the OpenAI endpoint
+            Err(e)
+                if self.stream
+                    && !self.is_predict()
+                    && !self.native_stream
+                    && fallback::applies(&e) =>
+            {
+                warn!(
+                    "[FALLBACK] streaming failed: {}, retrying without streaming",
+                    e
+                );
+                let framing = GeminiFraming::from_alt(self.query.alt.as_deref());
+                let mut state = self.to_owned();
+                state.stream = false;
+                let mut body = body;
+                let dialect = match state.api_format {
+                    GeminiApiFormat::Gemini => {
+                        state.path = state
+                            .path
+                            .replace(":streamGenerateContent", ":generateContent");
+                        state.query.alt = None;
+                        StreamDialect::Gemini
                     }
-                    match e {
-                        ClewdrError::GeminiHttpError { code, .. } => {
-                            if code == 403 {
-                                spawn(async move {
-                                    state.report_403().await.unwrap_or_else(|e| {
-                                        error!("Failed to report 403: {}", e);
-                                    });
-                                });
-                            }
-                            err = Some(e);
-                            continue;
+                    GeminiApiFormat::OpenAI => {
+                        if let Some(obj) = body.as_object_mut() {
+                            obj.insert("stream".to_string(), json!(false));
+                            obj.remove("stream_options");
                         }
-                        e => return Err(e),
+                        StreamDialect::OpenAI
                     }
+                };
+                let res = provider::chat_with_retries(&state, body).await?;
+                if dialect == StreamDialect::Gemini {
+                    return gemini::replay(res, framing).await;
                 }
+                fallback::into_sse(res, dialect).await
+            }
+            Ok(res) if !self.stream && !self.is_predict() => {
+                let mut state = self.to_owned();
+                let dialect = match self.api_format {
+                    GeminiApiFormat::Gemini => StreamDialect::Gemini,
+                    GeminiApiFormat::OpenAI => StreamDialect::OpenAI,
+                };
+                let res = if dialect == StreamDialect::Gemini {
+                    continuation::complete(res, &body, dialect, |body| {
+                        let state = state.to_owned();
+                        async move { provider::chat_with_retries(&state, body).await }
+                    })
+                    .await?
+                } else {
+                    res
+                };
+                // the sticky key answered poorly, a retry may take any other
+                state.session_hash = None;
+                quality_gate::enforce(res, dialect, || {
+                    let state = state.to_owned();
+                    let body = body.to_owned();
+                    async move { provider::chat_with_retries(&state, body).await }
+                })
+                .await
             }
+            res => res,
         }
-        error!("Max retries exceeded");
-        if let Some(e) = err {
-            return Err(e);
+    }
+
+    /// Validates the completion against the requested response format, an
+    /// invalid one fails the attempt when `structured_output_retry` is set
+    fn check_structured_output(&self, res: &Value) -> Result<(), ClewdrError> {
+        let Some(ref format) = self.response_format else {
+            return Ok(());
+        };
+        let Some(msg) = res["choices"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find_map(|c| {
+                let content = c["message"]["content"].as_str().unwrap_or_default();
+                format.validate(content).err()
+            })
+        else {
+            return Ok(());
+        };
+        if CLEWDR_CONFIG.load().structured_output_retry {
+            return Err(ClewdrError::InvalidStructuredOutput { msg });
         }
-        Err(ClewdrError::TooManyRetries)
+        warn!("Structured output does not match the schema: {}", msg);
+        Ok(())
     }
 
     async fn check_empty_choices(&self, resp: wreq::Response) -> Result<Response, ClewdrError> {
         if self.stream {
-            return forward_response(resp);
+            let state = self.to_owned();
+            let on_stall = move || state.report_stall();
+            if self.native_stream {
+                let status = resp.status();
+                let stream =
+                    watchdog::guard(resp.bytes_stream(), StreamDialect::Gemini, on_stall).await?;
+                let chunks = gemini::validate(gemini::chunks(stream, GeminiFraming::Sse)).await?;
+                let chunks = self.token_meter().chunks(chunks);
+                let expose = CLEWDR_CONFIG.load().gemini_thinking.expose_thoughts;
+                let chunks = gemini::to_openai(chunks, self.model.to_owned(), expose);
+                return Ok(gemini::into_openai_response(status, chunks));
+            }
+            if self.api_format == GeminiApiFormat::OpenAI {
+                let status = resp.status();
+                let stream =
+                    watchdog::guard(resp.bytes_stream(), StreamDialect::OpenAI, on_stall).await?;
+                return Ok(transform_oai_stream(status, stream, self.token_meter()));
+            }
+            if self.is_predict() {
+                return forward_guarded(resp, StreamDialect::Gemini, on_stall).await;
+            }
+            // upstream always streams SSE, see `upstream_query`
+            let status = resp.status();
+            let stream =
+                watchdog::guard(resp.bytes_stream(), StreamDialect::Gemini, on_stall).await?;
+            let chunks = gemini::validate(gemini::chunks(stream, GeminiFraming::Sse)).await?;
+            let chunks = self.token_meter().chunks(chunks);
+            let framing = GeminiFraming::from_alt(self.query.alt.as_deref());
+            return Ok(gemini::into_response(status, chunks, framing));
         }
         let bytes = resp.bytes().await.context(WreqSnafu {
             msg: "Failed to get bytes from Gemini response",
         })?;
+        if let Ok(res) = serde_json::from_slice::<Value>(&bytes) {
+            self.token_meter().observe(&res);
+        }
 
         match self.api_format {
+            // predictions are forwarded as is
+            GeminiApiFormat::Gemini if self.is_predict() => {}
             GeminiApiFormat::Gemini => {
                 let res = serde_json::from_slice::<GeminiResponse>(&bytes)?;
                 if res.candidates.is_empty() {
                     return Err(ClewdrError::EmptyChoices);
                 }
-                if res.candidates[0].finishReason == Some(FinishReason::OTHER) {
+                // with several candidates, one usable candidate is enough
+                if res
+                    .candidates
+                    .iter()
+                    .all(|c| c.finishReason == Some(FinishReason::OTHER))
+                {
                     return Err(ClewdrError::EmptyChoices);
                 }
             }
             GeminiApiFormat::OpenAI => {
-                let res = serde_json::from_slice::<Value>(&bytes)?;
+                let mut res = serde_json::from_slice::<Value>(&bytes)?;
                 if res["choices"].as_array().is_some_and(|v| v.is_empty()) {
                     return Err(ClewdrError::EmptyChoices);
                 }
-                if res["choices"][0]["finish_reason"] == "OTHER" {
+                if res["choices"]
+                    .as_array()
+                    .is_some_and(|v| v.iter().all(|c| c["finish_reason"] == "OTHER"))
+                {
                     return Err(ClewdrError::EmptyChoices);
                 }
+                let expose = CLEWDR_CONFIG.load().gemini_thinking.expose_thoughts;
+                let mut modified = ThoughtSplitter::default().apply(&mut res, expose);
+                self.check_structured_output(&res)?;
+                modified |= normalize_grounding(&mut res);
+                modified |= normalize_logprobs(&mut res);
+                if res["usage"].is_null()
+                    && let Ok(meta) =
+                        serde_json::from_value::<UsageMetadata>(res["usageMetadata"].take())
+                {
+                    // some endpoints only report Gemini style usage, map it for OpenAI clients
+                    res["usage"] = serde_json::to_value(CompletionUsage::from(&meta))?;
+                    if let Some(obj) = res.as_object_mut() {
+                        obj.remove("usageMetadata");
+                    }
+                    modified = true;
+                }
+                if modified {
+                    return Ok(Response::builder()
+                        .header(CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&res)?.into())?);
+                }
             }
         }
         Ok(Response::builder()
@@ -318,3 +753,78 @@ impl GeminiState {
             .body(bytes.into())?)
     }
 }
+
+impl Provider for GeminiState {
+    type Request = Value;
+
+    const NAME: &'static str = "gemini";
+
+    async fn prepare(&mut self) -> Result<(), ClewdrError> {
+        // Vertex authenticates with the service account instead of a key
+        if self.vertex {
+            return Ok(());
+        }
+        self.request_key().await
+    }
+
+    async fn send(&mut self, body: Value) -> Result<wreq::Response, ClewdrError> {
+        self.send_chat(body).await
+    }
+
+    async fn decode(&mut self, res: wreq::Response) -> Result<Response, ClewdrError> {
+        self.check_empty_choices(res).await
+    }
+
+    async fn classify_error(&mut self, e: ClewdrError) -> Verdict {
+        match e {
+            ClewdrError::GeminiHttpError { code, ref inner } => {
+                let action = error_action(code, inner);
+                let state = self.to_owned();
+                spawn(
+                    async move {
+                        state.report_error(code, action).await.unwrap_or_else(|e| {
+                            error!("Failed to report error: {}", e);
+                        });
+                    }
+                    .in_current_span(),
+                );
+                if action == ErrorAction::FailFast {
+                    Verdict::Fail(e)
+                } else {
+                    Verdict::Retry(e)
+                }
+            }
+            ClewdrError::StreamStalled { .. } => {
+                self.report_stall();
+                Verdict::Retry(e)
+            }
+            // another key may answer properly
+            ClewdrError::EmptyChoices
+            | ClewdrError::InvalidStructuredOutput { .. }
+            | ClewdrError::JsonError { .. } => Verdict::Retry(e),
+            e => {
+                // the next attempt goes around a broken mirror
+                let mirror = matches!(e, ClewdrError::WreqError { .. })
+                    && gemini_endpoint::mark_unhealthy(&self.endpoint);
+                if provider::transient(&e, self.proxy.as_deref()) || mirror {
+                    Verdict::Retry(e)
+                } else {
+                    Verdict::Fail(e)
+                }
+            }
+        }
+    }
+
+    fn credential(&self) -> Option<String> {
+        self.key.as_ref().map(|k| k.key.ellipse())
+    }
+
+    fn credential_id(&self) -> Option<String> {
+        self.key.as_ref().map(|k| k.key.to_string())
+    }
+
+    /// The last upstream error tells more than a bare retry count
+    fn exhausted(&self, last: ClewdrError) -> ClewdrError {
+        last
+    }
+}
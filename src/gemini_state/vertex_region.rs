@@ -0,0 +1,32 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::CLEWDR_CONFIG;
+
+/// Location of the multi-region endpoint, used when no regions are set
+pub const GLOBAL: &str = "global";
+
+static NEXT_REGION: AtomicUsize = AtomicUsize::new(0);
+
+/// Next region of `vertex.regions` in rotation, shared by every Vertex
+/// publisher so load spreads over the regional quotas
+///
+/// # Returns
+/// [`GLOBAL`] if no regions are set
+pub fn next_region() -> String {
+    let config = CLEWDR_CONFIG.load();
+    let regions = &config.vertex.regions;
+    if regions.is_empty() {
+        return GLOBAL.to_string();
+    }
+    let i = NEXT_REGION.fetch_add(1, Ordering::Relaxed);
+    regions[i % regions.len()].to_owned()
+}
+
+/// Base URL of the Vertex AI endpoint serving a region
+pub fn base_url(region: &str) -> String {
+    if region == GLOBAL {
+        "https://aiplatform.googleapis.com".to_string()
+    } else {
+        format!("https://{region}-aiplatform.googleapis.com")
+    }
+}
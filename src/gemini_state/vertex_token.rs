@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{
+    Engine,
+    prelude::{BASE64_STANDARD, BASE64_URL_SAFE_NO_PAD},
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{RSA_PKCS1_SHA256, RsaKeyPair},
+};
+use serde::Deserialize;
+use serde_json::json;
+use snafu::ResultExt;
+use tracing::debug;
+use wreq::Client;
+
+use crate::{
+    config::ServiceAccountKey,
+    error::{ClewdrError, WreqSnafu},
+};
+
+const SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const DEFAULT_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+/// Lifetime requested for the signed assertion, Google caps it at one hour
+const ASSERTION_LIFETIME: i64 = 3600;
+/// Tokens are refreshed this long before they expire
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+
+/// Token slots, keyed by service account email
+static TOKEN_CACHE: LazyLock<Mutex<HashMap<String, Arc<TokenSlot>>>> =
+    LazyLock::new(Default::default);
+
+/// Cached token of a service account
+#[derive(Default)]
+struct TokenSlot {
+    token: Mutex<Option<CachedToken>>,
+    /// Held by the one caller refreshing the token, others wait for its result
+    refresh: tokio::sync::Mutex<()>,
+}
+
+impl TokenSlot {
+    /// Returns the cached token unless it is close to expiry
+    fn fresh(&self) -> Option<String> {
+        let token = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        token
+            .as_ref()
+            .filter(|t| t.expires_at > Instant::now() + EXPIRY_MARGIN)
+            .map(|t| t.token.to_owned())
+    }
+}
+
+struct CachedToken {
+    token: String,
+    expires_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+}
+
+fn auth_error(msg: impl Into<String>) -> ClewdrError {
+    ClewdrError::VertexAuthError { msg: msg.into() }
+}
+
+/// Decodes the DER body of a PEM encoded private key
+fn pem_to_der(pem: &str) -> Result<Vec<u8>, ClewdrError> {
+    let body = pem
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with("-----"))
+        .collect::<String>();
+    BASE64_STANDARD
+        .decode(body)
+        .map_err(|e| auth_error(format!("Invalid private key PEM: {e}")))
+}
+
+/// Builds the RS256 signed JWT assertion for the service account
+fn sign_assertion(key: &ServiceAccountKey, token_uri: &str) -> Result<String, ClewdrError> {
+    let now = chrono::Utc::now().timestamp();
+    let mut header = json!({ "alg": "RS256", "typ": "JWT" });
+    if let Some(ref kid) = key.private_key_id {
+        header["kid"] = json!(kid);
+    }
+    let claims = json!({
+        "iss": key.client_email,
+        "scope": SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + ASSERTION_LIFETIME,
+    });
+    let message = format!(
+        "{}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+        BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims)?)
+    );
+
+    let der = pem_to_der(&key.private_key)?;
+    let key_pair = RsaKeyPair::from_pkcs8(&der)
+        .map_err(|e| auth_error(format!("Invalid private key: {e}")))?;
+    let mut signature = vec![0; key_pair.public().modulus_len()];
+    key_pair
+        .sign(
+            &RSA_PKCS1_SHA256,
+            &SystemRandom::new(),
+            message.as_bytes(),
+            &mut signature,
+        )
+        .map_err(|_| auth_error("Failed to sign JWT assertion"))?;
+    Ok(format!(
+        "{message}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
+
+/// Exchanges a signed assertion for an access token
+async fn fetch_token(key: &ServiceAccountKey, client: &Client) -> Result<CachedToken, ClewdrError> {
+    let token_uri = key.token_uri.as_deref().unwrap_or(DEFAULT_TOKEN_URI);
+    let assertion = sign_assertion(key, token_uri)?;
+    let res = client
+        .post(token_uri)
+        .form(&[
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+            ("assertion", assertion.as_str()),
+        ])
+        .send()
+        .await
+        .context(WreqSnafu {
+            msg: "Failed to request Vertex access token",
+        })?;
+    let status = res.status();
+    let text = res.text().await.context(WreqSnafu {
+        msg: "Failed to read Vertex token response",
+    })?;
+    if !status.is_success() {
+        return Err(auth_error(format!(
+            "Token endpoint returned {status}: {text}"
+        )));
+    }
+    let token = serde_json::from_str::<TokenResponse>(&text)?;
+    let lifetime = Duration::from_secs(token.expires_in.unwrap_or(ASSERTION_LIFETIME as u64));
+    Ok(CachedToken {
+        token: token.access_token,
+        expires_at: Instant::now() + lifetime,
+    })
+}
+
+/// Returns a cached access token for the service account, refreshing it
+/// through `client` (and therefore its proxy) when close to expiry
+///
+/// # Arguments
+/// * `key` - Service account credential
+/// * `client` - Client used for the token exchange
+pub async fn get_token(key: &ServiceAccountKey, client: &Client) -> Result<String, ClewdrError> {
+    let slot = TOKEN_CACHE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(key.client_email.to_owned())
+        .or_default()
+        .to_owned();
+    if let Some(token) = slot.fresh() {
+        return Ok(token);
+    }
+    // only one request refreshes, the others reuse the token it fetched
+    let _refresh = slot.refresh.lock().await;
+    if let Some(token) = slot.fresh() {
+        return Ok(token);
+    }
+    debug!("Refreshing Vertex access token for {}", key.client_email);
+    let fresh = fetch_token(key, client).await?;
+    let token = fresh.token.to_owned();
+    *slot.token.lock().unwrap_or_else(|e| e.into_inner()) = Some(fresh);
+    Ok(token)
+}
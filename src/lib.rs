@@ -1,19 +1,23 @@
 use std::{path::PathBuf, sync::LazyLock};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 
 use crate::config::CLEWDR_CONFIG;
 
 pub mod api;
 pub mod claude_code_state;
+pub mod claude_vertex_state;
 pub mod claude_web_state;
 pub mod config;
 pub mod error;
 pub mod gemini_state;
 pub mod middleware;
+pub mod openai_state;
+pub mod provider;
 pub mod router;
 pub mod services;
+pub mod streaming;
 pub mod types;
 pub mod utils;
 
@@ -68,4 +72,92 @@ pub struct Args {
     #[arg(short, long)]
     /// Alternative log directory
     pub log_dir: Option<PathBuf>,
+    /// Run under a service manager: systemd notify and watchdog on Linux,
+    /// Windows service control on Windows
+    #[arg(long)]
+    pub daemon: bool,
+    /// Answer chat requests with canned responses instead of contacting
+    /// upstream, see the `mock` config section
+    #[arg(long)]
+    pub mock: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Check the config, proxies and credentials, then exit
+    Doctor,
+    /// Probe keys and cookies from files and import the live ones, then exit
+    Import(ImportArgs),
+    /// Write a snapshot of keys, cookies and their state, then exit
+    Export(ExportArgs),
+    /// Replay a recorded request on a running instance, then exit
+    Replay(ReplayArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    /// File with one Gemini key per line, `-` for stdin
+    #[arg(long)]
+    pub keys: Option<PathBuf>,
+    /// File with one Claude cookie per line, `-` for stdin
+    #[arg(long)]
+    pub cookies: Option<PathBuf>,
+    /// Address of a running instance to import into through the admin API,
+    /// e.g. `http://127.0.0.1:8484`, the config file is updated otherwise
+    #[arg(long, requires = "admin_password")]
+    pub remote: Option<String>,
+    /// Admin password of the running instance
+    #[arg(long)]
+    pub admin_password: Option<String>,
+    /// Number of credentials probed at once
+    #[arg(long, default_value_t = 8)]
+    pub concurrency: usize,
+    /// Snapshot written by `clewdr export` to restore into the config file,
+    /// cookies and keys keep their cooldowns and usage without being probed
+    #[arg(long, conflicts_with = "remote")]
+    pub snapshot: Option<PathBuf>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Toml,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ExportArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+    /// File to write, stdout otherwise
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Shorten cookies and keys and leave out OAuth tokens
+    #[arg(long)]
+    pub redact: bool,
+    /// Address of a running instance to read the live state from through the
+    /// admin API, the config file is read otherwise
+    #[arg(long, requires = "admin_password")]
+    pub remote: Option<String>,
+    /// Admin password of the running instance
+    #[arg(long)]
+    pub admin_password: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ReplayArgs {
+    /// Request ID of the recorded request, as returned in `x-request-id`
+    pub id: String,
+    /// Send the request to this path instead, e.g. `/gemini/chat/completions`
+    #[arg(long)]
+    pub path: Option<String>,
+    /// Address of the running instance, e.g. `http://127.0.0.1:8484`
+    #[arg(long)]
+    pub remote: String,
+    /// Admin password of the running instance
+    #[arg(long)]
+    pub admin_password: String,
 }
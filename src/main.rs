@@ -1,17 +1,21 @@
+use clap::Parser;
 use clewdr::{
-    self, FIG, IS_DEBUG, VERSION_INFO,
-    config::{CLEWDR_CONFIG, CONFIG_PATH, LOG_DIR},
+    self, Args, Command, FIG, IS_DEBUG, VERSION_INFO,
+    config::{CLEWDR_CONFIG, CONFIG_PATH, LOG_DIR, LogFormat},
     error::ClewdrError,
+    services::{connection_registry::CONNECTION_REGISTRY, daemon, log_filter, mock},
 };
 use colored::Colorize;
+use futures::{FutureExt, future::BoxFuture};
 #[cfg(feature = "mimalloc")]
 use mimalloc::MiMalloc;
 use tracing::Subscriber;
 use tracing_subscriber::{
     Layer, Registry,
-    fmt::{self, time::ChronoLocal},
+    fmt::{self, MakeWriter, time::ChronoLocal},
     layer::SubscriberExt,
     registry::LookupSpan,
+    reload,
 };
 
 #[cfg(feature = "mimalloc")]
@@ -41,6 +45,20 @@ where
     tracing::subscriber::set_global_default(subscriber).expect("unable to set global subscriber");
 }
 
+/// Log output layer, one JSON object per line when `json` is set
+fn fmt_layer<S, W>(writer: W, timer: ChronoLocal, json: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: for<'writer> MakeWriter<'writer> + Send + Sync + 'static,
+{
+    let layer = fmt::Layer::default().with_writer(writer).with_timer(timer);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
 /// Application entry point
 /// Sets up logging, checks for updates, initializes the application state,
 /// creates the router, and starts the server
@@ -66,34 +84,53 @@ async fn main() -> Result<(), ClewdrError> {
     let env_filter = tracing_subscriber::EnvFilter::builder()
         .with_default_directive(filter.into())
         .from_env_lossy();
-    let subscriber = Registry::default().with(
-        fmt::Layer::default()
-            .with_writer(std::io::stdout)
-            .with_timer(timer.to_owned())
-            .with_filter(env_filter),
-    );
+    // a single filter for every layer, so the admin API can change it at runtime
+    let (env_filter, filter_handle) = reload::Layer::new(env_filter);
+    log_filter::install(filter_handle);
+    let json = CLEWDR_CONFIG.load().log_format == LogFormat::Json;
+    let subscriber = Registry::default().with(env_filter).with(fmt_layer(
+        std::io::stdout,
+        timer.to_owned(),
+        json,
+    ));
     let _guard = if !CLEWDR_CONFIG.load().no_fs && CLEWDR_CONFIG.load().log_to_file {
         std::fs::create_dir_all(LOG_DIR.as_path()).expect("Failed to create log directory");
         let file_appender = tracing_appender::rolling::daily(LOG_DIR.as_path(), "clewdr.log");
         let (file_writer, guard) = tracing_appender::non_blocking(file_appender);
-        let filter = tracing_subscriber::EnvFilter::builder()
-            .with_default_directive(filter.into())
-            .from_env_lossy();
-        let subscriber = subscriber.with(
-            fmt::Layer::default()
-                .with_writer(file_writer)
-                .with_timer(timer)
-                .with_filter(filter),
-        );
-        setup_subscriber(subscriber);
+        setup_subscriber(subscriber.with(fmt_layer(file_writer, timer, json)));
         Some(guard)
     } else {
         setup_subscriber(subscriber);
         None
     };
 
+    // one-shot commands print their own output and exit
+    match Args::parse().command {
+        Some(Command::Doctor) => {
+            let passed = clewdr::services::doctor::run().await;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Some(Command::Import(args)) => {
+            let passed = clewdr::services::import::run(args).await?;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Some(Command::Export(args)) => {
+            clewdr::services::export::run(args).await?;
+            return Ok(());
+        }
+        Some(Command::Replay(args)) => {
+            let passed = clewdr::services::audit::run(args).await?;
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        None => {}
+    }
+
     println!("{}\n{}", FIG, *VERSION_INFO);
 
+    if Args::parse().daemon {
+        daemon::start();
+    }
+
     #[cfg(feature = "portable")]
     {
         use tracing::warn;
@@ -106,21 +143,94 @@ async fn main() -> Result<(), ClewdrError> {
     // print info
     println!("Config dir: {}", CONFIG_PATH.display().to_string().blue());
     println!("{}", *CLEWDR_CONFIG);
+    if mock::enabled() {
+        println!(
+            "{}",
+            "Mock mode: chat requests are answered without contacting upstream".yellow()
+        );
+    }
+    if CLEWDR_CONFIG.load().chaos.enabled {
+        println!(
+            "{}",
+            "Chaos mode: faults are injected into API responses".yellow()
+        );
+    }
+
+    CONNECTION_REGISTRY.spawn_janitor();
 
     // build axum router
-    // create a TCP listener
-    let addr = CLEWDR_CONFIG.load().address();
-    let listener = tokio::net::TcpListener::bind(addr).await?;
     let router = clewdr::router::RouterBuilder::new()
         .await
-        .with_default_setup()
-        .build();
+        .with_default_setup();
+    let config = CLEWDR_CONFIG.load();
+    let (api_router, admin_router) = if config.admin_address.is_some() {
+        let (api, admin) = router.build_split();
+        (api, Some(admin))
+    } else {
+        (router.build(), None)
+    };
+    let mut servers: Vec<BoxFuture<'static, std::io::Result<()>>> = vec![];
+    if config.unix_socket.as_ref().is_none_or(|u| u.tcp) {
+        // create a TCP listener
+        let listener = tokio::net::TcpListener::bind(config.address()).await?;
+        servers.push(
+            axum::serve(
+                listener,
+                api_router
+                    .to_owned()
+                    .into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal())
+            .into_future()
+            .boxed(),
+        );
+    }
+    if let Some(ref uds) = config.unix_socket {
+        #[cfg(unix)]
+        {
+            let listener = uds.bind()?;
+            println!("Unix socket: {}", uds.path.display().to_string().blue());
+            servers.push(
+                axum::serve(listener, api_router.to_owned())
+                    .with_graceful_shutdown(shutdown_signal())
+                    .into_future()
+                    .boxed(),
+            );
+        }
+        #[cfg(not(unix))]
+        tracing::warn!(
+            "Unix socket {} is not supported on this platform",
+            uds.path.display()
+        );
+    }
+    if let (Some(admin_addr), Some(admin_router)) = (config.admin_address, admin_router) {
+        // serve admin routes on their own listener
+        let admin_listener = tokio::net::TcpListener::bind(admin_addr).await?;
+        servers.push(
+            axum::serve(
+                admin_listener,
+                admin_router.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal())
+            .into_future()
+            .boxed(),
+        );
+    }
+    daemon::notify_ready();
     // serve the application
-    Ok(axum::serve(listener, router)
-        .with_graceful_shutdown(async {
-            tokio::signal::ctrl_c()
-                .await
-                .expect("Failed to install Ctrl-C handler");
-        })
-        .await?)
+    let res = futures::future::try_join_all(servers).await;
+    daemon::notify_stopped();
+    res?;
+    Ok(())
+}
+
+/// Resolves when Ctrl-C is received or the service manager asks to stop,
+/// closing realtime connections so the servers can drain
+async fn shutdown_signal() {
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res.expect("Failed to install Ctrl-C handler"),
+        _ = daemon::stop_requested() => {}
+    }
+    daemon::notify_stopping();
+    CONNECTION_REGISTRY.cancel_all();
 }
@@ -0,0 +1,41 @@
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use http::StatusCode;
+
+use super::usage::backend;
+use crate::{error::ErrorDetail, services::adaptive_limit::ADAPTIVE_LIMIT};
+
+/// Holds a slot of the adaptive concurrency limit of the backend until the
+/// response body is sent, reporting whether upstream rate limited the request
+///
+/// Must run inside the keep-alive layer, which hides the status of errors.
+pub async fn limit_adaptive(req: Request, next: Next) -> Response {
+    let backend = backend(req.uri().path());
+    let permit = match ADAPTIVE_LIMIT.acquire(backend).await {
+        Ok(Some(permit)) => permit,
+        Ok(None) => return next.run(req).await,
+        Err(e) => return e.into_response(),
+    };
+    let res = next.run(req).await;
+    let status = res
+        .extensions()
+        .get::<ErrorDetail>()
+        .map_or(res.status(), |d| d.status);
+    // other errors say nothing about the load upstream accepts
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        permit.record(true);
+    } else if status.is_success() {
+        permit.record(false);
+    }
+    let (parts, body) = res.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
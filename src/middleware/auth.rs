@@ -1,9 +1,194 @@
-use axum::extract::FromRequestParts;
+use std::net::SocketAddr;
+
+use axum::{
+    body::{self, Body},
+    extract::{ConnectInfo, FromRequestParts, OriginalUri, Query, Request},
+    http::{Method, Uri, request::Parts},
+    middleware::Next,
+    response::Response,
+};
 use axum_auth::AuthBearer;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use tracing::warn;
 
-use super::gemini::GeminiArgs;
-use crate::{config::CLEWDR_CONFIG, error::ClewdrError};
+use super::{
+    context_limit::gemini_model,
+    gemini::GeminiArgs,
+    usage::{backend, routed_backend},
+};
+use crate::{
+    config::{AdminRole, Backend, CLEWDR_CONFIG},
+    error::ClewdrError,
+    services::{
+        auth_guard::{AUTH_GUARD, AuthEvent, AuthOutcome, Login, client_ip},
+        jwt::{self, TokenScopes},
+        shared_state,
+    },
+    types::realtime::realtime_model,
+};
+
+/// Who sent an API request, left in the request extensions by the auth
+/// extractors
+///
+/// Requests ClewdR sends through its own router (batch lines, replays and
+/// latency fallbacks) carry the caller of the original request instead of
+/// credentials, and are held to its scopes again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Caller {
+    /// `password`, or `jwt:` and the subject of the token, a hash of the
+    /// token if it has none
+    pub id: String,
+    /// Scopes of a JWT, `None` for the password
+    #[serde(default)]
+    pub scopes: Option<TokenScopes>,
+}
+
+impl Caller {
+    fn password() -> Self {
+        Self {
+            id: "password".to_string(),
+            scopes: None,
+        }
+    }
+
+    fn token(key: &str, scopes: TokenScopes) -> Self {
+        let id = match scopes.subject {
+            Some(ref sub) => format!("jwt:{sub}"),
+            None => format!("jwt:{}", shared_state::id(key)),
+        };
+        Self {
+            id,
+            scopes: Some(scopes),
+        }
+    }
+
+    /// Checks the expiry of a token, and its route and backend scopes
+    fn check(&self, path: &str, backend: Backend) -> Result<(), ClewdrError> {
+        let Some(ref scopes) = self.scopes else {
+            return Ok(());
+        };
+        if scopes.expired() {
+            warn!("Token of {} has expired", self.id);
+            return Err(ClewdrError::InvalidAuth);
+        }
+        if !scopes.allows_route(path) {
+            return Err(ClewdrError::ScopeDenied {
+                msg: format!("route {path}"),
+            });
+        }
+        if !scopes.allows_backend(backend) {
+            return Err(ClewdrError::ScopeDenied {
+                msg: format!("backend {backend:?}"),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Checks a request ClewdR sent itself against the caller it carries
+///
+/// # Returns
+/// `None` for requests from clients, which must bring credentials
+fn recheck(parts: &Parts) -> Option<Result<(), ClewdrError>> {
+    let caller = parts.extensions.get::<Caller>()?;
+    let path = parts.uri.path();
+    Some(caller.check(path, backend(path)))
+}
+
+/// Checks a user key: the password, or a JWT when `jwt_auth` is set
+///
+/// The route and backend scopes of a token are checked here, the [`Caller`]
+/// is left in the request extensions for [`check_token_scope`].
+async fn authorize(parts: &mut Parts, key: &str) -> Result<(), ClewdrError> {
+    let caller = if !key.is_empty() && CLEWDR_CONFIG.load().user_auth(key) {
+        Caller::password()
+    } else if CLEWDR_CONFIG.load().jwt_auth.is_some() && jwt::looks_like_jwt(key) {
+        Caller::token(key, jwt::verify(key).await?)
+    } else {
+        return Err(ClewdrError::InvalidAuth);
+    };
+    let path = parts.uri.path();
+    caller.check(path, backend(path))?;
+    parts.extensions.insert(caller);
+    Ok(())
+}
+
+/// Checks the scopes of a JWT again once the routing script ran: the backend
+/// it picked and the model asked for must be covered, requests authenticated
+/// with the password pass
+pub async fn check_token_scope(req: Request, next: Next) -> Result<Response, ClewdrError> {
+    let Some(scopes) = req
+        .extensions()
+        .get::<Caller>()
+        .and_then(|c| c.scopes.to_owned())
+    else {
+        return Ok(next.run(req).await);
+    };
+    let path = req.uri().path().to_owned();
+    let backend = routed_backend(&path, req.extensions());
+    if !scopes.allows_backend(backend) {
+        warn!(
+            "Token of {} denied routed backend {:?}",
+            scopes.subject.as_deref().unwrap_or("unknown subject"),
+            backend
+        );
+        return Err(ClewdrError::ScopeDenied {
+            msg: format!("backend {backend:?}"),
+        });
+    }
+    if !scopes.limits_models() {
+        return Ok(next.run(req).await);
+    }
+    let (parts, body) = req.into_parts();
+    // already buffered by `limit_body`, upgrades carry none
+    let bytes = body::to_bytes(body, usize::MAX).await?;
+    let models = requested_models(&parts.uri, &bytes);
+    if let Some(model) = models.into_iter().find(|m| !scopes.allows_model(m)) {
+        warn!(
+            "Token of {} denied model {}",
+            scopes.subject.as_deref().unwrap_or("unknown subject"),
+            model
+        );
+        return Err(ClewdrError::ScopeDenied {
+            msg: format!("model {model}"),
+        });
+    }
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
+
+#[derive(Deserialize)]
+struct ModelQuery {
+    model: Option<String>,
+}
+
+/// Models a request asks for: the model in a Gemini path, the realtime
+/// `model` query parameter, the body `model` and the `model` of each request
+/// in a message batch
+fn requested_models(uri: &Uri, body: &[u8]) -> Vec<String> {
+    let mut models = Vec::from_iter(gemini_model(uri.path()));
+    if uri.path() == "/gemini/realtime" {
+        let query = Query::<ModelQuery>::try_from_uri(uri)
+            .ok()
+            .and_then(|Query(q)| q.model);
+        models.push(realtime_model(query.as_deref()));
+    }
+    let Ok(body) = serde_json::from_slice::<Value>(body) else {
+        return models;
+    };
+    models.extend(body["model"].as_str().map(str::to_string));
+    if let Some(requests) = body["requests"].as_array() {
+        models.extend(
+            requests
+                .iter()
+                .filter_map(|r| r["params"]["model"].as_str().map(str::to_string)),
+        );
+    }
+    models
+}
 
 /// Extractor for the X-API-Key header used in Claude API compatibility
 ///
@@ -39,29 +224,56 @@ where
         parts: &mut axum::http::request::Parts,
         _: &S,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(checked) = recheck(parts) {
+            return checked.map(|_| Self);
+        }
         let query = GeminiArgs::from_request_parts(parts, &()).await?;
-        if !CLEWDR_CONFIG.load().user_auth(&query.key) {
+        authorize(parts, &query.key).await.inspect_err(|_| {
             warn!("Invalid query key: {}", query.key);
-            return Err(ClewdrError::InvalidAuth);
-        }
+        })?;
         Ok(Self)
     }
 }
 
+/// Admin reads a read-only admin key may not make: the config holds the
+/// passwords, audit entries hold whole requests, the auth log client addresses
+const FULL_ADMIN_READS: [&str; 3] = ["/api/config", "/api/audit/", "/api/admin/auth_log"];
+
+/// Admin routes listing raw cookies, keys and proxy URLs, denied to
+/// read-only admin keys; usage and latency below them are shortened
+const CREDENTIAL_READS: [&str; 3] = ["/api/cookies", "/api/keys", "/api/tokens"];
+
+/// Whether a read-only admin key may make the request
+fn read_only_allows(method: &Method, path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    matches!(*method, Method::GET | Method::HEAD)
+        && !FULL_ADMIN_READS.iter().any(|p| path.starts_with(p))
+        && !CREDENTIAL_READS.contains(&path)
+}
+
 /// Middleware guard that ensures requests have valid admin authentication
 ///
 /// This extractor checks for a valid admin authorization token in the Bearer Auth header.
 /// It can be used on routes that should only be accessible to administrators.
+/// The read-only admin password passes for GET requests to status and usage
+/// endpoints, anything that changes state needs the full admin password.
+/// Addresses failing too often are locked out, behind a trusted proxy the
+/// forwarded address counts. Too many failures from all addresses together
+/// turn away wrong keys from everyone for a while, the right key still gets
+/// in. Every attempt goes to the auth log.
 ///
 /// # Example
 ///
 /// ```
+/// use axum::response::IntoResponse;
+/// use clewdr::middleware::RequireAdminAuth;
+///
 /// async fn admin_only_handler(
 ///     _: RequireAdminAuth,
 ///     // other extractors...
 /// ) -> impl IntoResponse {
 ///     // This handler only executes if admin authentication succeeds
-///     // ...
+///     "ok"
 /// }
 /// ```
 pub struct RequireAdminAuth;
@@ -74,13 +286,56 @@ where
         parts: &mut axum::http::request::Parts,
         _: &S,
     ) -> Result<Self, Self::Rejection> {
-        let AuthBearer(key) = AuthBearer::from_request_parts(parts, &())
+        // the Unix socket has no peer address, forwarded addresses are used
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let ip = client_ip(peer, &parts.headers, &CLEWDR_CONFIG.load().trusted_proxies);
+        let method = parts.method.to_owned();
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(parts.uri.path(), |u| u.path())
+            .to_string();
+        let record = |role, outcome| {
+            AUTH_GUARD.record(AuthEvent {
+                time: Utc::now(),
+                ip,
+                role,
+                method: method.to_string(),
+                route: path.to_owned(),
+                outcome,
+            })
+        };
+        let role = AuthBearer::from_request_parts(parts, &())
             .await
-            .map_err(|_| ClewdrError::InvalidAuth)?;
-        if !CLEWDR_CONFIG.load().admin_auth(&key) {
-            warn!("Invalid admin key");
-            return Err(ClewdrError::InvalidAuth);
+            .ok()
+            .and_then(|AuthBearer(key)| CLEWDR_CONFIG.load().admin_role(&key));
+        let role = match (AUTH_GUARD.login(ip, role.is_some()), role) {
+            (Login::Locked(left), _) => {
+                record(None, AuthOutcome::Locked);
+                return Err(ClewdrError::AuthLockedOut {
+                    retry_after_secs: left.as_secs().max(1),
+                });
+            }
+            (Login::Allowed, Some(role)) => role,
+            _ => {
+                warn!("Invalid admin key");
+                record(None, AuthOutcome::Failure);
+                return Err(ClewdrError::InvalidAuth);
+            }
+        };
+        if role == AdminRole::ReadOnly {
+            if !read_only_allows(&method, &path) {
+                warn!("Read-only admin key denied {} {}", method, path);
+                record(Some(role), AuthOutcome::Denied);
+                return Err(ClewdrError::ScopeDenied {
+                    msg: format!("{method} {path} with a read-only admin key"),
+                });
+            }
         }
+        record(Some(role), AuthOutcome::Success);
         Ok(Self)
     }
 }
@@ -93,12 +348,15 @@ where
 /// # Example
 ///
 /// ```
+/// use axum::response::IntoResponse;
+/// use clewdr::middleware::RequireBearerAuth;
+///
 /// async fn openai_handler(
-///     _: RequireOaiAuth,
+///     _: RequireBearerAuth,
 ///     // other extractors...
 /// ) -> impl IntoResponse {
 ///     // This handler only executes if OpenAI authentication succeeds
-///     // ...
+///     "ok"
 /// }
 /// ```
 pub struct RequireBearerAuth;
@@ -111,13 +369,15 @@ where
         parts: &mut axum::http::request::Parts,
         _: &S,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(checked) = recheck(parts) {
+            return checked.map(|_| Self);
+        }
         let AuthBearer(key) = AuthBearer::from_request_parts(parts, &())
             .await
             .map_err(|_| ClewdrError::InvalidAuth)?;
-        if !CLEWDR_CONFIG.load().user_auth(&key) {
+        authorize(parts, &key).await.inspect_err(|_| {
             warn!("Invalid Bearer key: {}", key);
-            return Err(ClewdrError::InvalidAuth);
-        }
+        })?;
         Ok(Self)
     }
 }
@@ -136,11 +396,45 @@ where
         parts: &mut axum::http::request::Parts,
         _: &S,
     ) -> Result<Self, Self::Rejection> {
+        if let Some(checked) = recheck(parts) {
+            return checked.map(|_| Self);
+        }
         let XApiKey(key) = XApiKey::from_request_parts(parts, &()).await?;
-        if !CLEWDR_CONFIG.load().user_auth(&key) {
+        authorize(parts, &key).await.inspect_err(|_| {
             warn!("Invalid x-api-key: {}", key);
-            return Err(ClewdrError::InvalidAuth);
-        }
+        })?;
         Ok(Self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_only_key_cannot_list_credentials() {
+        for path in ["/api/cookies", "/api/keys", "/api/tokens", "/api/keys/"] {
+            assert!(!read_only_allows(&Method::GET, path), "{path}");
+        }
+        assert!(!read_only_allows(&Method::GET, "/api/config"));
+        assert!(!read_only_allows(&Method::GET, "/api/audit/abc"));
+    }
+
+    #[test]
+    fn read_only_key_reads_status() {
+        assert!(read_only_allows(&Method::GET, "/api/cookies/usage"));
+        assert!(read_only_allows(&Method::GET, "/api/admin/latency"));
+        assert!(!read_only_allows(&Method::POST, "/api/cookie"));
+    }
+
+    #[test]
+    fn batch_and_realtime_models_are_checked() {
+        let uri = Uri::from_static("/code/v1/messages/batches");
+        let body = br#"{"requests":[{"params":{"model":"a"}},{"params":{"model":"b"}}]}"#;
+        assert_eq!(requested_models(&uri, body), ["a", "b"]);
+        let uri = Uri::from_static("/gemini/realtime?model=gemini-2.5-pro");
+        assert_eq!(requested_models(&uri, b""), ["gemini-2.5-pro"]);
+        let uri = Uri::from_static("/gemini/realtime");
+        assert_eq!(requested_models(&uri, b""), [realtime_model(None)]);
+    }
+}
@@ -0,0 +1,43 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use http::header::CONTENT_LENGTH;
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError, services::audit};
+
+/// Rejects request bodies larger than `max_body_size` with a 413
+///
+/// Replaces axum's fixed 2 MB limit, which must be disabled on the routes
+/// using this middleware. The body is buffered, so extractors downstream read
+/// it without further checks.
+pub async fn limit_body(req: Request, next: Next) -> Result<Response, ClewdrError> {
+    let limit = CLEWDR_CONFIG.load().max_body_size;
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared.is_some_and(|l| l > limit) {
+        return Err(ClewdrError::PayloadTooLarge { limit });
+    }
+    let (parts, body) = req.into_parts();
+    // chunked bodies carry no length, so count while reading
+    let mut stream = body.into_data_stream();
+    let mut buf = Vec::with_capacity(declared.unwrap_or_default());
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > limit {
+            return Err(ClewdrError::PayloadTooLarge { limit });
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    if audit::enabled() {
+        audit::record(&parts, &buf);
+    }
+    let req = Request::from_parts(parts, Body::from(Bytes::from(buf)));
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use http::{StatusCode, header::CONTENT_TYPE};
+use tracing::warn;
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    middleware::API_PREFIXES,
+    utils::{random_index, roll},
+};
+
+/// Streamed chunks kept at most before a truncated stream is cut
+const MAX_TRUNCATED_CHUNKS: usize = 32;
+
+/// Injects faults into API responses when `chaos.enabled` is set
+///
+/// Each request may be answered with an error without reaching a route, have
+/// its first byte delayed, or have its event stream cut off mid-response. The
+/// cut is an aborted body, so clients see it as a dropped connection.
+pub async fn chaos(req: Request, next: Next) -> Response {
+    let chaos = CLEWDR_CONFIG.load().chaos.to_owned();
+    let path = req.uri().path();
+    if !chaos.enabled || !API_PREFIXES.iter().any(|p| path.starts_with(p)) {
+        return next.run(req).await;
+    }
+    if roll(chaos.error_rate) && !chaos.error_statuses.is_empty() {
+        let status = chaos.error_statuses[random_index(chaos.error_statuses.len())];
+        let code = StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        warn!("[CHAOS] injected {}", code);
+        return ClewdrError::InjectedFault { code }.into_response();
+    }
+    let delay = roll(chaos.delay_rate);
+    let resp = next.run(req).await;
+    if delay {
+        warn!("[CHAOS] delaying first byte by {}ms", chaos.delay_ms);
+        tokio::time::sleep(Duration::from_millis(chaos.delay_ms)).await;
+    }
+    let streaming = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if !streaming || !roll(chaos.truncate_rate) {
+        return resp;
+    }
+    let keep = random_index(MAX_TRUNCATED_CHUNKS) + 1;
+    warn!("[CHAOS] truncating stream after {} chunks", keep);
+    let (parts, body) = resp.into_parts();
+    let stream = body
+        .into_data_stream()
+        .take(keep)
+        .chain(futures::stream::once(async {
+            Err(axum::Error::new(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                "stream truncated by chaos middleware",
+            )))
+        }));
+    Response::from_parts(parts, Body::from_stream(stream))
+}
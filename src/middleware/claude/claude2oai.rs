@@ -1,9 +1,18 @@
+use std::collections::HashMap;
+
+use async_stream::try_stream;
 use axum::response::sse::Event;
-use futures::{Stream, TryStreamExt};
+use futures::Stream;
 use serde::Serialize;
-use serde_json::Value;
+use serde_json::{Value, json};
 
-use crate::types::claude::{ContentBlockDelta, CreateMessageResponse, StreamEvent};
+use crate::{
+    config::CLEWDR_CONFIG,
+    types::{
+        claude::{ContentBlock, ContentBlockDelta, CreateMessageResponse, StopReason, StreamEvent},
+        oai::CompletionUsage,
+    },
+};
 
 /// Represents the data structure for streaming events in OpenAI API format
 /// Contains a choices array with deltas of content
@@ -22,7 +31,10 @@ impl StreamEventData {
     /// A new StreamEventData instance with the content wrapped in choices array
     fn new(content: EventContent) -> Self {
         Self {
-            choices: vec![StreamEventDelta { delta: content }],
+            choices: vec![StreamEventDelta {
+                delta: content,
+                finish_reason: None,
+            }],
         }
     }
 }
@@ -32,6 +44,8 @@ impl StreamEventData {
 #[derive(Debug, Serialize)]
 struct StreamEventDelta {
     delta: EventContent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    finish_reason: Option<&'static str>,
 }
 
 /// Content of an event, either regular content or reasoning (thinking mode)
@@ -41,6 +55,8 @@ struct StreamEventDelta {
 pub enum EventContent {
     Content { content: String },
     Reasoning { reasoning_content: String },
+    ToolCalls { tool_calls: Vec<Value> },
+    Empty {},
 }
 
 /// Creates an SSE event with the given content in OpenAI format
@@ -56,11 +72,30 @@ pub fn build_event(content: EventContent) -> Event {
     event.json_data(data).unwrap()
 }
 
+/// Creates the closing SSE event carrying the finish reason
+fn build_finish_event(reason: &'static str) -> Event {
+    let mut data = StreamEventData::new(EventContent::Empty {});
+    data.choices[0].finish_reason = Some(reason);
+    Event::default().json_data(data).unwrap()
+}
+
+/// Maps a Claude stop reason to the OpenAI finish reason
+fn finish_reason(reason: Option<&StopReason>) -> &'static str {
+    match reason {
+        Some(StopReason::MaxTokens) => "length",
+        Some(StopReason::ToolUse) => "tool_calls",
+        Some(StopReason::Refusal) => "content_filter",
+        Some(StopReason::EndTurn) | Some(StopReason::StopSequence) | None => "stop",
+    }
+}
+
 /// Transforms a Claude.ai event stream into an OpenAI-compatible event stream
 ///
 /// Extracts content from Claude events and reformats them to match OpenAI's streaming format.
 /// This function processes each event in the stream, identifying the delta content type
 /// (text or thinking), and converting it to the appropriate OpenAI-compatible event format.
+/// Thinking is left out when `claude_thinking.strip_for_oai` is set. Tool use
+/// blocks become `tool_calls` deltas, indexed in the order the tools are called.
 ///
 /// # Arguments
 /// * `s` - The input stream of Claude.ai events
@@ -75,23 +110,69 @@ pub fn transform_stream<I, E>(s: I) -> impl Stream<Item = Result<Event, E>>
 where
     I: Stream<Item = Result<eventsource_stream::Event, E>>,
 {
-    s.try_filter_map(async |eventsource_stream::Event { data, .. }| {
-        let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
-            return Ok(None);
-        };
-        let StreamEvent::ContentBlockDelta { delta, .. } = parsed else {
-            return Ok(None);
-        };
-        match delta {
-            ContentBlockDelta::TextDelta { text } => {
-                Ok(Some(build_event(EventContent::Content { content: text })))
-            }
-            ContentBlockDelta::ThinkingDelta { thinking } => {
-                Ok(Some(build_event(EventContent::Reasoning {
-                    reasoning_content: thinking,
-                })))
+    let strip = CLEWDR_CONFIG.load().claude_thinking.strip_for_oai;
+    try_stream!({
+        // content block index to tool call index
+        let mut tools = HashMap::new();
+        for await event in s {
+            let eventsource_stream::Event { data, .. } = event?;
+            let Ok(parsed) = serde_json::from_str::<StreamEvent>(&data) else {
+                continue;
+            };
+            let (index, delta) = match parsed {
+                StreamEvent::ContentBlockDelta { index, delta } => (index, delta),
+                StreamEvent::ContentBlockStart {
+                    index,
+                    content_block: ContentBlock::ToolUse { id, name, .. },
+                } => {
+                    let tool_index = tools.len();
+                    tools.insert(index, tool_index);
+                    yield build_event(EventContent::ToolCalls {
+                        tool_calls: vec![json!({
+                            "index": tool_index,
+                            "id": id,
+                            "type": "function",
+                            "function": { "name": name, "arguments": "" },
+                        })],
+                    });
+                    continue;
+                }
+                StreamEvent::MessageDelta { delta, .. } => {
+                    yield build_finish_event(finish_reason(delta.stop_reason.as_ref()));
+                    continue;
+                }
+                // e.g. a stalled upstream, OpenAI clients expect an `error` object
+                StreamEvent::Error { error } => {
+                    let data = json!({
+                        "error": { "message": error.message, "type": error.type_ },
+                    });
+                    yield Event::default().data(data.to_string());
+                    continue;
+                }
+                _ => continue,
+            };
+            match delta {
+                ContentBlockDelta::TextDelta { text } => {
+                    yield build_event(EventContent::Content { content: text });
+                }
+                ContentBlockDelta::ThinkingDelta { thinking } if !strip => {
+                    yield build_event(EventContent::Reasoning {
+                        reasoning_content: thinking,
+                    });
+                }
+                ContentBlockDelta::InputJsonDelta { partial_json } => {
+                    let Some(tool_index) = tools.get(&index) else {
+                        continue;
+                    };
+                    yield build_event(EventContent::ToolCalls {
+                        tool_calls: vec![json!({
+                            "index": tool_index,
+                            "function": { "arguments": partial_json },
+                        })],
+                    });
+                }
+                _ => {}
             }
-            _ => Ok(None),
         }
     })
 }
@@ -101,30 +182,49 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         .content
         .iter()
         .filter_map(|block| match block {
-            crate::types::claude::ContentBlock::Text { text } => Some(text.clone()),
+            ContentBlock::Text { text, .. } => Some(text.clone()),
             _ => None,
         })
         .collect::<Vec<_>>()
         .join("");
-
-    let usage = input.usage.as_ref().map(|u| {
-        serde_json::json!({
-            "prompt_tokens": u.input_tokens,
-            "completion_tokens": u.output_tokens,
-            "total_tokens": u.input_tokens + u.output_tokens
+    let reasoning = input
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::Thinking { thinking, .. } => Some(thinking.as_str()),
+            _ => None,
+        })
+        .collect::<String>();
+    let tool_calls = input
+        .content
+        .iter()
+        .filter_map(|block| match block {
+            ContentBlock::ToolUse {
+                id, name, input, ..
+            } => Some(json!({
+                "id": id,
+                "type": "function",
+                "function": { "name": name, "arguments": input.to_string() },
+            })),
+            _ => None,
         })
+        .collect::<Vec<_>>();
+    let mut message = json!({
+        "role": "assistant",
+        "content": content
     });
+    if !reasoning.is_empty() && !CLEWDR_CONFIG.load().claude_thinking.strip_for_oai {
+        message["reasoning_content"] = reasoning.into();
+    }
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = tool_calls.into();
+    }
+
+    let usage = input.usage.as_ref().map(CompletionUsage::from);
 
-    let finish_reason = match input.stop_reason {
-        Some(crate::types::claude::StopReason::EndTurn) => "stop",
-        Some(crate::types::claude::StopReason::MaxTokens) => "length",
-        Some(crate::types::claude::StopReason::StopSequence) => "stop",
-        Some(crate::types::claude::StopReason::ToolUse) => "tool_calls",
-        Some(crate::types::claude::StopReason::Refusal) => "content_filter",
-        None => "stop",
-    };
+    let finish_reason = finish_reason(input.stop_reason.as_ref());
 
-    serde_json::json!({
+    json!({
         "id": input.id,
         "object": "chat.completion",
         "created": std::time::SystemTime::now()
@@ -134,10 +234,7 @@ pub fn transforms_json(input: CreateMessageResponse) -> Value {
         "model": input.model,
         "choices": [{
             "index": 0,
-            "message": {
-                "role": "assistant",
-                "content": content
-            },
+            "message": message,
             "finish_reason": finish_reason
         }],
         "usage": usage
@@ -2,6 +2,7 @@ mod claude2oai;
 mod request;
 mod response;
 mod stop_sequences;
+mod validate;
 
 pub(crate) use claude2oai::*;
 pub use request::*;
@@ -67,6 +68,17 @@ impl ClaudeContext {
         }
     }
 
+    /// Hash used to pin the request to a cookie
+    ///
+    /// A conversation ID provided by the client wins, Claude Code requests fall
+    /// back to the hash of the cached system prompt
+    pub fn session_hash(&self) -> Option<u64> {
+        match self {
+            ClaudeContext::Web(ctx) => ctx.session_hash,
+            ClaudeContext::Code(ctx) => ctx.session_hash.or(ctx.system_prompt_hash),
+        }
+    }
+
     pub fn usage(&self) -> &Usage {
         match self {
             ClaudeContext::Web(ctx) => &ctx.usage,
@@ -9,14 +9,19 @@ use axum::{
     extract::{FromRequest, Request},
 };
 use serde_json::{Value, json};
+use tiktoken_rs::o200k_base;
 
 use crate::{
-    config::CLEWDR_CONFIG,
+    config::{AUTO_CACHE_MIN_TOKENS, CLEWDR_CONFIG, MAX_CACHE_BREAKPOINTS},
     error::ClewdrError,
-    middleware::claude::{ClaudeApiFormat, ClaudeContext},
+    middleware::{
+        claude::{ClaudeApiFormat, ClaudeContext, validate::validate},
+        session_hash,
+    },
+    services::image_fetch::inline_images,
     types::{
-        claude::{ContentBlock, CreateMessageParams, Message, Role, Thinking, Usage},
-        oai::CreateMessageParams as OaiCreateMessageParams,
+        claude::{CacheControl, ContentBlock, CreateMessageParams, Message, Role, Thinking, Usage},
+        oai::{CreateMessageParams as OaiCreateMessageParams, normalize_tool_calls},
     },
 };
 
@@ -51,6 +56,8 @@ pub struct ClaudeWebContext {
     pub(super) stop_sequences: Vec<String>,
     /// User information about input and output tokens
     pub(super) usage: Usage,
+    /// The hash of the client provided conversation ID
+    pub(super) session_hash: Option<u64>,
 }
 
 /// Predefined test message in Claude format for connection testing
@@ -58,19 +65,43 @@ pub struct ClaudeWebContext {
 /// This is a standard test message sent by clients like SillyTavern
 /// to verify connectivity. The system detects these messages and
 /// responds with a predefined test response to confirm service availability.
-static TEST_MESSAGE_CLAUDE: LazyLock<Message> = LazyLock::new(|| {
-    Message::new_blocks(
-        Role::User,
-        vec![ContentBlock::Text {
-            text: "Hi".to_string(),
-        }],
-    )
-});
+static TEST_MESSAGE_CLAUDE: LazyLock<Message> =
+    LazyLock::new(|| Message::new_blocks(Role::User, vec![ContentBlock::text("Hi")]));
 
 /// Predefined test message in OpenAI format for connection testing
 static TEST_MESSAGE_OAI: LazyLock<Message> = LazyLock::new(|| Message::new_text(Role::User, "Hi"));
 
-struct NormalizeRequest(CreateMessageParams, ClaudeApiFormat);
+/// Adds a cache breakpoint to the end of a large system prompt
+///
+/// Skipped when the client already placed breakpoints in the system prompt,
+/// or the request already uses every breakpoint Anthropic allows
+fn inject_system_cache_control(body: &mut CreateMessageParams) {
+    if body.cache_breakpoints() >= MAX_CACHE_BREAKPOINTS {
+        return;
+    }
+    let Some(Value::Array(systems)) = body.system.as_mut() else {
+        return;
+    };
+    if systems
+        .iter()
+        .any(|s| s.get("cache_control").is_some_and(|c| !c.is_null()))
+    {
+        return;
+    }
+    let text = systems
+        .iter()
+        .filter_map(|s| s["text"].as_str())
+        .collect::<String>();
+    let bpe = o200k_base().expect("Failed to get encoding");
+    if bpe.encode_with_special_tokens(&text).len() < AUTO_CACHE_MIN_TOKENS {
+        return;
+    }
+    if let Some(last) = systems.last_mut().and_then(Value::as_object_mut) {
+        last.insert("cache_control".into(), json!(CacheControl::ephemeral()));
+    }
+}
+
+struct NormalizeRequest(CreateMessageParams, ClaudeApiFormat, Option<u64>);
 
 impl<S> FromRequest<S> for NormalizeRequest
 where
@@ -80,6 +111,7 @@ where
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
         let uri = req.uri().to_string();
+        let headers = req.headers().to_owned();
         let format = if uri.contains("chat/completions") {
             ClaudeApiFormat::OpenAI
         } else {
@@ -87,16 +119,31 @@ where
         };
         let Json(mut body) = match format {
             ClaudeApiFormat::OpenAI => {
-                let Json(json) = Json::<OaiCreateMessageParams>::from_request(req, &()).await?;
+                let Json(mut json) = Json::<Value>::from_request(req, &()).await?;
+                normalize_tool_calls(&mut json);
+                let json: OaiCreateMessageParams =
+                    serde_path_to_error::deserialize(json).map_err(|e| {
+                        ClewdrError::InvalidRequest {
+                            errors: vec![format!("{}: {}", e.path(), e.inner())],
+                        }
+                    })?;
                 Json(json.into())
             }
             ClaudeApiFormat::Claude => Json::<CreateMessageParams>::from_request(req, &()).await?,
         };
         if body.model.ends_with("-thinking") {
             body.model = body.model.trim_end_matches("-thinking").to_string();
-            body.thinking.get_or_insert(Thinking::new(4096));
+            let budget = CLEWDR_CONFIG.load().claude_thinking.budget;
+            body.thinking.get_or_insert(Thinking::new(budget));
         }
-        Ok(Self(body, format))
+        body.max_tokens = CLEWDR_CONFIG
+            .load()
+            .output_limits
+            .apply(&body.model, body.max_tokens)?;
+        body.fit_thinking();
+        inline_images(&mut body.messages).await?;
+        let session_hash = session_hash(&headers, body.metadata.as_ref());
+        Ok(Self(body, format, session_hash))
     }
 }
 
@@ -107,7 +154,8 @@ where
     type Rejection = ClewdrError;
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
-        let NormalizeRequest(body, format) = NormalizeRequest::from_request(req, &()).await?;
+        let NormalizeRequest(body, format, session_hash) =
+            NormalizeRequest::from_request(req, &()).await?;
 
         // Check for test messages and respond appropriately
         if !body.stream.unwrap_or_default()
@@ -118,6 +166,8 @@ where
             return Err(ClewdrError::TestMessage);
         }
 
+        validate(&body, true)?;
+
         // Determine streaming status and API format
         let stream = body.stream.unwrap_or_default();
 
@@ -126,9 +176,11 @@ where
             stream,
             api_format: format,
             stop_sequences: body.stop_sequences.to_owned().unwrap_or_default(),
+            session_hash,
             usage: Usage {
                 input_tokens,
                 output_tokens: 0, // Placeholder for output token count
+                ..Default::default()
             },
         };
 
@@ -144,6 +196,8 @@ pub struct ClaudeCodeContext {
     pub(super) api_format: ClaudeApiFormat,
     /// The hash of the system messages for caching purposes
     pub(super) system_prompt_hash: Option<u64>,
+    /// The hash of the client provided conversation ID
+    pub(super) session_hash: Option<u64>,
     // Usage information for the request
     pub(super) usage: Usage,
 }
@@ -157,7 +211,10 @@ where
     type Rejection = ClewdrError;
 
     async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
-        let NormalizeRequest(mut body, format) = NormalizeRequest::from_request(req, &()).await?;
+        // Vertex serves the plain API, which needs no Claude Code prelude
+        let vertex = req.uri().path().starts_with("/vertex/");
+        let NormalizeRequest(mut body, format, session_hash) =
+            NormalizeRequest::from_request(req, &()).await?;
         // Handle thinking mode by modifying the model name
         if body.model.contains("opus-4-1") && body.temperature.is_some() {
             body.top_p = None; // temperature and top_p cannot be used together in Opus-4-1
@@ -172,26 +229,27 @@ where
             return Err(ClewdrError::TestMessage);
         }
 
+        validate(&body, false)?;
+
         // Determine streaming status and API format
         let stream = body.stream.unwrap_or_default();
 
         // Add a prelude text block to the system messages
         const PRELUDE_TEXT: &str = "You are Claude Code, Anthropic's official CLI for Claude.";
         let prelude_blk = || -> ContentBlock {
-            ContentBlock::Text {
-                text: CLEWDR_CONFIG
+            ContentBlock::text(
+                CLEWDR_CONFIG
                     .load()
                     .custom_system
                     .clone()
                     .unwrap_or_else(|| PRELUDE_TEXT.to_string()),
-            }
+            )
         };
         match body.system {
+            _ if vertex => {}
             Some(Value::String(ref text)) => {
                 if text != PRELUDE_TEXT {
-                    let text_content = ContentBlock::Text {
-                        text: text.to_owned(),
-                    };
+                    let text_content = ContentBlock::text(text.to_owned());
                     body.system = Some(json!([prelude_blk(), text_content]));
                 }
             }
@@ -206,13 +264,16 @@ where
             }
         }
 
+        if CLEWDR_CONFIG.load().auto_cache_control {
+            inject_system_cache_control(&mut body);
+        }
+
         let cache_systems = body
             .system
             .as_mut()
-            .expect("System messages should be present")
-            .as_array_mut()
-            .expect("System messages should be an array")
-            .iter_mut()
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten()
             .filter_map(|s| {
                 // Claude Code does not allow TTLs in system prompts
                 s["cache_control"].as_object_mut()?.remove("ttl");
@@ -231,9 +292,11 @@ where
             stream,
             api_format: format,
             system_prompt_hash,
+            session_hash,
             usage: Usage {
                 input_tokens,
                 output_tokens: 0, // Placeholder for output token count
+                ..Default::default()
             },
         };
 
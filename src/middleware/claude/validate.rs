@@ -0,0 +1,95 @@
+use crate::{
+    error::ClewdrError,
+    types::claude::{ContentBlock, CreateMessageParams, MessageContent, Role},
+};
+
+/// Checks a request for mistakes Anthropic would reject with a 400
+///
+/// Every problem found is listed by its field path, so the client can fix
+/// them all at once instead of the request being retried upstream.
+///
+/// # Arguments
+/// * `body` - The normalized request
+/// * `allow_system` - Whether `system` messages may appear among the messages,
+///   claude.ai folds them into the prompt but the API rejects them
+pub(super) fn validate(body: &CreateMessageParams, allow_system: bool) -> Result<(), ClewdrError> {
+    let mut errors = vec![];
+    if body.max_tokens == 0 {
+        errors.push("max_tokens: must be at least 1".to_string());
+    }
+    if body.messages.is_empty() {
+        errors.push("messages: at least one message is required".to_string());
+    }
+    let last = body.messages.len().saturating_sub(1);
+    let mut tool_uses: Vec<&str> = vec![];
+    for (i, msg) in body.messages.iter().enumerate() {
+        if msg.role == Role::System && !allow_system {
+            errors.push(format!(
+                "messages[{i}].role: `system` is not allowed, use the top-level `system` field"
+            ));
+        }
+        // only a final assistant message may be empty, it prefills the reply
+        let empty = match msg.content {
+            MessageContent::Text { ref content } => content.trim().is_empty(),
+            MessageContent::Blocks { ref content } => content.is_empty(),
+        };
+        if empty && !(i == last && msg.role == Role::Assistant) {
+            errors.push(format!("messages[{i}].content: must not be empty"));
+        }
+        let MessageContent::Blocks { ref content } = msg.content else {
+            tool_uses.clear();
+            continue;
+        };
+        for (j, block) in content.iter().enumerate() {
+            match block {
+                ContentBlock::ToolUse { .. }
+                | ContentBlock::Thinking { .. }
+                | ContentBlock::RedactedThinking { .. }
+                    if msg.role != Role::Assistant =>
+                {
+                    errors.push(format!(
+                        "messages[{i}].content[{j}]: `{}` blocks must be sent by the assistant",
+                        block_type(block)
+                    ));
+                }
+                ContentBlock::ToolResult { .. } if msg.role != Role::User => {
+                    errors.push(format!(
+                        "messages[{i}].content[{j}]: `tool_result` blocks must be sent by the user"
+                    ));
+                }
+                ContentBlock::ToolResult { tool_use_id, .. }
+                    if !tool_uses.contains(&tool_use_id.as_str()) =>
+                {
+                    errors.push(format!(
+                        "messages[{i}].content[{j}].tool_use_id: no `tool_use` with id `{tool_use_id}` in the previous message"
+                    ));
+                }
+                _ => {}
+            }
+        }
+        tool_uses = content
+            .iter()
+            .filter_map(|b| match b {
+                ContentBlock::ToolUse { id, .. } => Some(id.as_str()),
+                _ => None,
+            })
+            .collect();
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ClewdrError::InvalidRequest { errors })
+    }
+}
+
+fn block_type(block: &ContentBlock) -> &'static str {
+    match block {
+        ContentBlock::ToolUse { .. } => "tool_use",
+        ContentBlock::Thinking { .. } => "thinking",
+        ContentBlock::RedactedThinking { .. } => "redacted_thinking",
+        ContentBlock::ToolResult { .. } => "tool_result",
+        ContentBlock::Text { .. } => "text",
+        ContentBlock::Image { .. } => "image",
+        ContentBlock::ImageUrl { .. } => "image_url",
+    }
+}
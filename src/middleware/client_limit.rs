@@ -0,0 +1,43 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use tracing::warn;
+
+use crate::{
+    config::CLEWDR_CONFIG, error::ClewdrError, middleware::API_PREFIXES,
+    services::connection_registry::CONNECTION_REGISTRY,
+};
+
+/// Rejects API requests with 429 when the client address already has
+/// `max_requests_per_client` requests in flight
+///
+/// A request counts until its response body is fully sent. Clients behind the
+/// same reverse proxy or on the Unix socket share the proxy's address or are
+/// not limited at all.
+pub async fn limit_per_client(req: Request, next: Next) -> Response {
+    let limit = CLEWDR_CONFIG.load().max_requests_per_client;
+    let path = req.uri().path();
+    if limit == 0 || !API_PREFIXES.iter().any(|p| path.starts_with(p)) {
+        return next.run(req).await;
+    }
+    let Some(ConnectInfo(addr)) = req.extensions().get::<ConnectInfo<SocketAddr>>().copied() else {
+        return next.run(req).await;
+    };
+    let Some(slot) = CONNECTION_REGISTRY.acquire_request(addr.ip(), limit) else {
+        warn!("{} exceeded {} concurrent requests", addr.ip(), limit);
+        return ClewdrError::ClientLimitExceeded { limit }.into_response();
+    };
+    let res = next.run(req).await;
+    let (parts, body) = res.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _ = &slot;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
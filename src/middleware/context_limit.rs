@@ -0,0 +1,196 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use http::{HeaderValue, header::CONTENT_LENGTH};
+use serde_json::Value;
+use tiktoken_rs::{CoreBPE, o200k_base};
+use tracing::info;
+
+use crate::{
+    config::{CLEWDR_CONFIG, ContextStrategy},
+    error::ClewdrError,
+};
+
+/// Header naming the strategy that shortened the prompt and the turns dropped
+const X_CLEWDR_TRUNCATION: &str = "x-clewdr-truncation";
+
+/// Fields that carry no prompt text, e.g. base64 images or identifiers
+const SKIPPED_FIELDS: [&str; 11] = [
+    "data",
+    "url",
+    "signature",
+    "mime_type",
+    "mimeType",
+    "media_type",
+    "type",
+    "role",
+    "id",
+    "tool_use_id",
+    "tool_call_id",
+];
+
+/// Estimated token count of the text in a JSON value
+pub(super) fn tokens(bpe: &CoreBPE, value: &Value) -> usize {
+    match value {
+        Value::String(s) => bpe.encode_ordinary(s).len(),
+        Value::Array(a) => a.iter().map(|v| tokens(bpe, v)).sum(),
+        Value::Object(o) => o
+            .iter()
+            .filter(|(k, _)| !SKIPPED_FIELDS.contains(&k.as_str()))
+            .map(|(_, v)| tokens(bpe, v))
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// Model of a native Gemini `generateContent` path
+pub(super) fn gemini_model(path: &str) -> Option<String> {
+    let (model, method) = path.rsplit('/').next()?.split_once(':')?;
+    method
+        .to_ascii_lowercase()
+        .ends_with("generatecontent")
+        .then(|| model.to_string())
+}
+
+/// Whether a turn is pinned in place, system prompts among OpenAI messages
+fn is_system(turn: &Value) -> bool {
+    matches!(turn["role"].as_str(), Some("system" | "developer"))
+}
+
+/// Whether a conversation may resume at this turn, a user turn that does not
+/// answer a tool call dropped before it
+fn opens_turn(turn: &Value) -> bool {
+    if !matches!(turn["role"].as_str(), Some("user") | None) {
+        return false;
+    }
+    let blocks = turn["content"].as_array().or(turn["parts"].as_array());
+    !blocks.into_iter().flatten().any(|b| {
+        b["type"] == "tool_result"
+            || b.get("functionResponse").is_some()
+            || b.get("function_response").is_some()
+    })
+}
+
+/// Drops turns in `order` until the prompt fits `limit`, then as many more
+/// as needed for the conversation to resume at a user turn
+///
+/// # Returns
+/// The number of turns dropped, `None` if the prompt cannot be made to fit
+fn drop_turns(
+    turns: &mut Vec<Value>,
+    costs: &mut Vec<usize>,
+    order: &[usize],
+    mut total: usize,
+    limit: usize,
+) -> Option<usize> {
+    let mut dropped = vec![];
+    for &i in order {
+        // the turn after the gap must start a new exchange
+        if total <= limit && opens_turn(&turns[i]) {
+            break;
+        }
+        total -= costs[i];
+        dropped.push(i);
+    }
+    if total > limit {
+        return None;
+    }
+    dropped.sort_unstable();
+    for &i in dropped.iter().rev() {
+        turns.remove(i);
+        costs.remove(i);
+    }
+    Some(dropped.len())
+}
+
+/// Shortens prompts exceeding the context window of the model following the
+/// `context_limits` strategy
+///
+/// The token count is estimated with the o200k tokenizer and includes the
+/// requested output tokens. The applied strategy is reported in the
+/// `x-clewdr-truncation` response header.
+pub async fn fit_context(req: Request, next: Next) -> Result<Response, ClewdrError> {
+    let config = CLEWDR_CONFIG.load().context_limits.to_owned();
+    if config.strategy == ContextStrategy::Off {
+        return Ok(next.run(req).await);
+    }
+    let path = req.uri().path().to_owned();
+    let gemini_model = gemini_model(&path);
+    if gemini_model.is_none() && !path.ends_with("chat/completions") && !path.ends_with("/messages")
+    {
+        return Ok(next.run(req).await);
+    }
+    let (mut parts, body) = req.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX).await?;
+    let Ok(mut json) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    };
+    let key = if gemini_model.is_some() {
+        "contents"
+    } else {
+        "messages"
+    };
+    let model = gemini_model.or_else(|| json["model"].as_str().map(str::to_string));
+    let window = model.as_deref().and_then(|m| config.window(m));
+    let (Some(model), Some(window)) = (model, window) else {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    };
+    let output = ["max_tokens", "max_completion_tokens"]
+        .iter()
+        .find_map(|k| json[k].as_u64())
+        .or_else(|| json["generationConfig"]["maxOutputTokens"].as_u64())
+        .unwrap_or_default() as usize;
+    let limit = (window as usize).saturating_sub(output);
+    let bpe = o200k_base().expect("Failed to get encoding");
+    let total = tokens(&bpe, &json) + output;
+    if total <= window as usize {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    }
+    let overflow = || ClewdrError::ContextOverflow {
+        model: model.to_owned(),
+        tokens: total,
+        limit: window as usize,
+    };
+    let Some(turns) = json[key].as_array_mut() else {
+        return Err(overflow());
+    };
+    let mut costs = turns.iter().map(|t| tokens(&bpe, t)).collect::<Vec<_>>();
+    let prompt = total - output;
+    // the latest turn is always kept
+    let mut movable = (0..turns.len().saturating_sub(1))
+        .filter(|&i| !is_system(&turns[i]))
+        .collect::<Vec<_>>();
+    let dropped = match config.strategy {
+        ContextStrategy::Off | ContextStrategy::Reject => None,
+        ContextStrategy::DropOldestTurns => drop_turns(turns, &mut costs, &movable, prompt, limit),
+        ContextStrategy::MiddleOut => {
+            if !movable.is_empty() {
+                movable.remove(0);
+            }
+            drop_turns(turns, &mut costs, &movable, prompt, limit)
+        }
+    };
+    let Some(dropped) = dropped else {
+        return Err(overflow());
+    };
+    info!(
+        "Dropped {} turns of {} to fit its context window, strategy: {}",
+        dropped, model, config.strategy
+    );
+    parts.headers.remove(CONTENT_LENGTH);
+    let body = Body::from(serde_json::to_vec(&json)?);
+    let mut resp = next.run(Request::from_parts(parts, body)).await;
+    if let Ok(value) = HeaderValue::from_str(&format!("{}; dropped={}", config.strategy, dropped)) {
+        resp.headers_mut().insert(X_CLEWDR_TRUNCATION, value);
+    }
+    Ok(resp)
+}
@@ -0,0 +1,122 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use serde_json::{Value, json};
+
+use crate::{error::ErrorDetail, streaming::StreamDialect};
+
+/// Maps a HTTP status code to the matching OpenAI error type
+fn oai_error_type(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 404 | 413 | 422 => "invalid_request_error",
+        401 => "authentication_error",
+        403 => "permission_error",
+        429 => "rate_limit_error",
+        _ => "server_error",
+    }
+}
+
+/// Maps a HTTP status code to the matching Google RPC status
+fn google_rpc_status(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 | 413 | 422 => "INVALID_ARGUMENT",
+        401 => "UNAUTHENTICATED",
+        403 => "PERMISSION_DENIED",
+        404 => "NOT_FOUND",
+        409 => "ABORTED",
+        429 => "RESOURCE_EXHAUSTED",
+        499 => "CANCELLED",
+        501 => "UNIMPLEMENTED",
+        503 => "UNAVAILABLE",
+        504 => "DEADLINE_EXCEEDED",
+        _ => "INTERNAL",
+    }
+}
+
+/// Returns the upstream error body if it already has the `{error: {...}}` shape
+///
+/// Google's OpenAI compatible endpoint sometimes wraps the error in an array,
+/// which is unwrapped here
+fn upstream_error_object(upstream: Option<&Value>) -> Option<Value> {
+    let upstream = upstream?;
+    let obj = match upstream {
+        Value::Array(arr) => arr.first()?,
+        v => v,
+    };
+    obj.get("error")?.is_object().then(|| obj.to_owned())
+}
+
+/// Renders error responses as OpenAI style error objects
+///
+/// `{"error": {"message", "type", "param", "code"}}`
+pub async fn to_oai_error(resp: Response) -> Response {
+    let Some(detail) = resp.extensions().get::<ErrorDetail>().cloned() else {
+        return resp;
+    };
+    let body = json!({
+        "error": {
+            "message": detail.message_text(),
+            "type": oai_error_type(detail.status),
+            "param": null,
+            "code": detail.code,
+        }
+    });
+    let mut resp = (detail.status, Json(body)).into_response();
+    resp.extensions_mut().insert(detail);
+    resp
+}
+
+/// Renders error responses as Google style error objects
+///
+/// `{"error": {"code", "message", "status"}}`, errors returned by Google are
+/// forwarded as is
+pub async fn to_gemini_error(resp: Response) -> Response {
+    let Some(detail) = resp.extensions().get::<ErrorDetail>().cloned() else {
+        return resp;
+    };
+    let body = upstream_error_object(detail.upstream.as_ref()).unwrap_or_else(|| {
+        json!({
+            "error": {
+                "code": detail.status.as_u16(),
+                "message": detail.message_text(),
+                "status": google_rpc_status(detail.status),
+            }
+        })
+    });
+    let mut resp = (detail.status, Json(body)).into_response();
+    resp.extensions_mut().insert(detail);
+    resp
+}
+
+/// Renders error responses in the format of `dialect`, Claude style errors
+/// are what [`ClewdrError`](crate::error::ClewdrError) renders already
+pub async fn to_dialect_error(resp: Response, dialect: StreamDialect) -> Response {
+    match dialect {
+        StreamDialect::Claude => resp,
+        StreamDialect::OpenAI => to_oai_error(resp).await,
+        StreamDialect::Gemini => to_gemini_error(resp).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body;
+
+    use super::*;
+    use crate::error::ClewdrError;
+
+    #[tokio::test]
+    async fn errors_are_rendered_in_the_dialect() {
+        let error = || ClewdrError::BadRequest { msg: "bad" }.into_response();
+        let res = to_dialect_error(error(), StreamDialect::OpenAI).await;
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(body["error"]["type"], "invalid_request_error");
+        let res = to_dialect_error(error(), StreamDialect::Gemini).await;
+        let body = body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(body["error"]["status"], "INVALID_ARGUMENT");
+    }
+}
@@ -0,0 +1,119 @@
+use axum::{
+    Json,
+    extract::{FromRequest, Request},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+
+use super::{
+    GeminiArgs, GeminiContext,
+    request::{key_tiers, priority},
+};
+use crate::{
+    error::ClewdrError,
+    gemini_state::{GeminiApiFormat, GeminiState},
+    middleware::{multipart::Form, session_hash},
+    types::oai::{SpeechParams, TranscriptionParams},
+};
+
+/// Largest audio upload accepted, Gemini rejects larger inline data
+const MAX_AUDIO_BYTES: usize = 20 * 1024 * 1024;
+
+pub struct GeminiSpeechPreprocess(pub SpeechParams, pub GeminiContext);
+
+impl FromRequest<GeminiState> for GeminiSpeechPreprocess {
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &GeminiState) -> Result<Self, Self::Rejection> {
+        let session_hash = session_hash(req.headers(), None);
+        let priority = priority(&req);
+        let key_tiers = key_tiers(&req);
+        let Json(body) = Json::<SpeechParams>::from_request(req, &()).await?;
+        // audio is streamed back as it is generated
+        let ctx = GeminiContext {
+            vertex: false,
+            model: body.model().to_string(),
+            stream: true,
+            path: format!("models/{}:streamGenerateContent", body.model()),
+            query: GeminiArgs {
+                alt: Some("sse".to_string()),
+                ..Default::default()
+            },
+            api_format: GeminiApiFormat::Gemini,
+            session_hash,
+            priority,
+            key_tiers,
+        };
+        Ok(GeminiSpeechPreprocess(body, ctx))
+    }
+}
+
+pub struct GeminiTranscriptionPreprocess(pub TranscriptionParams, pub GeminiContext);
+
+impl FromRequest<GeminiState> for GeminiTranscriptionPreprocess {
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &GeminiState) -> Result<Self, Self::Rejection> {
+        let session_hash = session_hash(req.headers(), None);
+        let priority = priority(&req);
+        let key_tiers = key_tiers(&req);
+        let Form(fields) = Form::from_request(req, &()).await?;
+        let mut params = TranscriptionParams::default();
+        for field in fields {
+            let text = || field.text();
+            match field.name.as_str() {
+                "file" => {
+                    if field.data.len() > MAX_AUDIO_BYTES {
+                        return Err(ClewdrError::PayloadTooLarge {
+                            limit: MAX_AUDIO_BYTES,
+                        });
+                    }
+                    params.mime_type = field
+                        .content_type
+                        .to_owned()
+                        .filter(|c| c.starts_with("audio/") || c.starts_with("video/"))
+                        .or_else(|| field.file_name.as_deref().and_then(mime_from_name))
+                        .unwrap_or_else(|| "audio/mpeg".to_string());
+                    params.audio = BASE64_STANDARD.encode(&field.data);
+                }
+                "model" => params.model = text(),
+                "prompt" => params.prompt = Some(text()),
+                "language" => params.language = Some(text()),
+                "response_format" => params.response_format = Some(text()),
+                _ => {}
+            }
+        }
+        if params.audio.is_empty() {
+            return Err(ClewdrError::BadRequest {
+                msg: "Missing audio file",
+            });
+        }
+        let ctx = GeminiContext {
+            vertex: false,
+            model: params.model().to_string(),
+            stream: false,
+            path: format!("models/{}:generateContent", params.model()),
+            query: GeminiArgs::default(),
+            api_format: GeminiApiFormat::Gemini,
+            session_hash,
+            priority,
+            key_tiers,
+        };
+        Ok(GeminiTranscriptionPreprocess(params, ctx))
+    }
+}
+
+/// Guesses the audio MIME type from a file name
+fn mime_from_name(name: &str) -> Option<String> {
+    let ext = name.rsplit_once('.')?.1.to_ascii_lowercase();
+    let mime = match ext.as_str() {
+        "mp3" | "mpga" | "mpeg" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "oga" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" | "aac" => "audio/aac",
+        "webm" => "audio/webm",
+        "mp4" => "video/mp4",
+        _ => return None,
+    };
+    Some(mime.to_string())
+}
@@ -1,5 +1,7 @@
+mod audio;
 mod path;
 mod request;
 
+pub use audio::{GeminiSpeechPreprocess, GeminiTranscriptionPreprocess};
 pub use path::GeminiArgs;
-pub use request::{GeminiContext, GeminiOaiPreprocess, GeminiPreprocess};
+pub use request::{GeminiContext, GeminiImagePreprocess, GeminiOaiPreprocess, GeminiPreprocess};
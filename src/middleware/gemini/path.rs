@@ -2,7 +2,7 @@ use axum::extract::{FromRequestParts, Query};
 use serde::Deserialize;
 use struct_iterable::Iterable;
 
-use crate::error::ClewdrError;
+use crate::{error::ClewdrError, middleware::Caller};
 
 #[derive(Debug, Clone, Deserialize, Iterable, Default)]
 pub struct GeminiArgs {
@@ -33,8 +33,13 @@ where
                 let key = parts
                     .headers
                     .get("x-goog-api-key")
-                    .and_then(|v| v.to_str().ok())
-                    .ok_or(ClewdrError::InvalidAuth)?;
+                    .and_then(|v| v.to_str().ok());
+                // requests sent by ClewdR itself carry their caller instead
+                let key = match key {
+                    Some(key) => key,
+                    None if parts.extensions.get::<Caller>().is_some() => "",
+                    None => return Err(ClewdrError::InvalidAuth),
+                };
                 Ok(Self {
                     key: key.to_string(),
                     alt: q.alt,
@@ -3,12 +3,23 @@ use axum::{
     extract::{FromRequest, Path, Request},
 };
 
+use serde_json::Value;
+
 use super::GeminiArgs;
 use crate::{
     config::CLEWDR_CONFIG,
     error::ClewdrError,
     gemini_state::{GeminiApiFormat, GeminiState},
-    types::{gemini::request::GeminiRequestBody, oai::CreateMessageParams},
+    middleware::session_hash,
+    services::{
+        image_fetch::inline_image_urls,
+        key_actor::KEY_TIER_HEADER,
+        request_queue::{PRIORITY_HEADER, Priority},
+    },
+    types::{
+        gemini::request::{GeminiBody, GeminiRequestBody},
+        oai::{CreateMessageParams, ImageGenerationParams},
+    },
 };
 
 pub struct GeminiContext {
@@ -18,9 +29,44 @@ pub struct GeminiContext {
     pub path: String,
     pub query: GeminiArgs,
     pub api_format: GeminiApiFormat,
+    /// The hash of the client provided conversation ID
+    pub session_hash: Option<u64>,
+    /// Priority class in the request queue
+    pub priority: Priority,
+    /// Key tiers picked by the client, routed by model if `None`
+    pub key_tiers: Option<Vec<String>>,
+}
+
+/// Reads the priority class from the request headers
+pub(super) fn priority(req: &Request) -> Priority {
+    req.headers()
+        .get(PRIORITY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default()
 }
 
-pub struct GeminiPreprocess(pub GeminiRequestBody, pub GeminiContext);
+/// Reads the key tiers the client picked from the request headers
+pub(super) fn key_tiers(req: &Request) -> Option<Vec<String>> {
+    let tiers = req
+        .headers()
+        .get(KEY_TIER_HEADER)?
+        .to_str()
+        .ok()?
+        .split(',')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    (!tiers.is_empty()).then_some(tiers)
+}
+
+pub struct GeminiPreprocess(pub GeminiBody, pub GeminiContext);
+
+/// Whether the path calls `generateContent` or `streamGenerateContent`
+fn is_generate(path: &str) -> bool {
+    path.ends_with("generateContent") || path.ends_with("GenerateContent")
+}
 
 impl FromRequest<GeminiState> for GeminiPreprocess {
     type Rejection = ClewdrError;
@@ -46,6 +92,9 @@ impl FromRequest<GeminiState> for GeminiPreprocess {
             });
         };
         let query = req.extract_parts::<GeminiArgs>().await?;
+        let session_hash = session_hash(req.headers(), None);
+        let priority = priority(&req);
+        let key_tiers = key_tiers(&req);
         let ctx = GeminiContext {
             vertex,
             model,
@@ -53,9 +102,26 @@ impl FromRequest<GeminiState> for GeminiPreprocess {
             path,
             query,
             api_format: GeminiApiFormat::Gemini,
+            session_hash,
+            priority,
+            key_tiers,
+        };
+        let body = if is_generate(&ctx.path) {
+            let Json(mut body) = Json::<GeminiRequestBody>::from_request(req, &()).await?;
+            body.safety_off();
+            body.limit_output(&ctx.model)?;
+            body.apply_thinking(&ctx.model);
+            if CLEWDR_CONFIG.load().gemini_system_as_user {
+                body.system_to_user();
+            }
+            if CLEWDR_CONFIG.load().gemini_merge_turns {
+                body.merge_turns();
+            }
+            GeminiBody::Generate(body)
+        } else {
+            let Json(body) = Json::<Value>::from_request(req, &()).await?;
+            GeminiBody::Raw(body)
         };
-        let Json(mut body) = Json::<GeminiRequestBody>::from_request(req, &()).await?;
-        body.safety_off();
         let mut state = state.clone();
         state.update_from_ctx(&ctx);
         Ok(GeminiPreprocess(body, ctx))
@@ -74,11 +140,27 @@ impl FromRequest<GeminiState> for GeminiOaiPreprocess {
                 msg: "Vertex is not configured",
             });
         }
+        let headers = req.headers().to_owned();
+        let priority = priority(&req);
+        let key_tiers = key_tiers(&req);
         let Json(mut body) = Json::<CreateMessageParams>::from_request(req, &()).await?;
+        let session_hash = session_hash(&headers, body.metadata.as_ref());
+        inline_image_urls(&mut body.messages).await?;
         let model = body.model.to_owned();
         if vertex {
             body.preprocess_vertex();
         }
+        body.limit_output()?;
+        body.request_stream_usage();
+        body.enable_search_grounding();
+        body.map_thinking_for_gemini();
+        body.merge_system_messages(CLEWDR_CONFIG.load().gemini_system_as_user);
+        if CLEWDR_CONFIG.load().gemini_merge_turns {
+            body.merge_turns();
+        }
+        if let Some(ref mut format) = body.response_format {
+            format.sanitize_for_gemini();
+        }
         let stream = body.stream.unwrap_or_default();
         let ctx = GeminiContext {
             vertex,
@@ -87,9 +169,42 @@ impl FromRequest<GeminiState> for GeminiOaiPreprocess {
             path: String::new(),
             query: GeminiArgs::default(),
             api_format: GeminiApiFormat::OpenAI,
+            session_hash,
+            priority,
+            key_tiers,
         };
         let mut state = state.clone();
         state.update_from_ctx(&ctx);
         Ok(GeminiOaiPreprocess(body, ctx))
     }
 }
+
+pub struct GeminiImagePreprocess(pub ImageGenerationParams, pub GeminiContext);
+
+impl FromRequest<GeminiState> for GeminiImagePreprocess {
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &GeminiState) -> Result<Self, Self::Rejection> {
+        let session_hash = session_hash(req.headers(), None);
+        let priority = priority(&req);
+        let key_tiers = key_tiers(&req);
+        let Json(body) = Json::<ImageGenerationParams>::from_request(req, &()).await?;
+        let method = if body.is_imagen() {
+            "predict"
+        } else {
+            "generateContent"
+        };
+        let ctx = GeminiContext {
+            vertex: false,
+            model: body.model().to_string(),
+            stream: false,
+            path: format!("models/{}:{method}", body.model()),
+            query: GeminiArgs::default(),
+            api_format: GeminiApiFormat::Gemini,
+            session_hash,
+            priority,
+            key_tiers,
+        };
+        Ok(GeminiImagePreprocess(body, ctx))
+    }
+}
@@ -0,0 +1,61 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use serde_json::Value;
+
+use crate::{config::HookStage, error::ClewdrError, services::hooks, streaming::PaddedJson};
+
+/// Runs the configured hooks on the JSON request body before the handler,
+/// and on successful non-streaming JSON responses after it, except those
+/// padded by the keep-alive
+///
+/// Sits innermost, so hooks see the bodies as exchanged with the handler and
+/// their rewrites are not second-guessed by other middleware.
+pub async fn run_hooks(req: Request, next: Next) -> Result<Response, ClewdrError> {
+    let path = req.uri().path().to_owned();
+    let req = if hooks::enabled(HookStage::Request, &path) {
+        let (mut parts, body) = req.into_parts();
+        let bytes = body::to_bytes(body, usize::MAX).await?;
+        // e.g. multipart uploads
+        let Ok(json) = serde_json::from_slice::<Value>(&bytes) else {
+            return Ok(next
+                .run(Request::from_parts(parts, Body::from(bytes)))
+                .await);
+        };
+        let json = hooks::run(HookStage::Request, &path, json).await?;
+        parts.headers.remove(CONTENT_LENGTH);
+        Request::from_parts(parts, Body::from(serde_json::to_vec(&json)?))
+    } else {
+        req
+    };
+    let res = next.run(req).await;
+    let is_json = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    // buffering padded JSON would hold back the keep-alive
+    let padded = res.extensions().get::<PaddedJson>().is_some();
+    if !res.status().is_success()
+        || !is_json
+        || padded
+        || !hooks::enabled(HookStage::Response, &path)
+    {
+        return Ok(res);
+    }
+    let (mut parts, body) = res.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX).await?;
+    let Ok(json) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    };
+    let json = hooks::run(HookStage::Response, &path, json).await?;
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(Response::from_parts(
+        parts,
+        Body::from(serde_json::to_vec(&json)?),
+    ))
+}
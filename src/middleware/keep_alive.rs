@@ -0,0 +1,45 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::Value;
+
+use super::{salvage::dialect, usage::routed_backend};
+use crate::{
+    config::CLEWDR_CONFIG,
+    streaming::{StreamDialect, json_keep_alive},
+};
+
+/// Keeps non-streaming Claude requests alive with [`json_keep_alive`] when
+/// `keep_alive.claude_non_stream` is set
+///
+/// Errors and timeouts are written in the dialect of the route, waiting at
+/// most the request timeout of the routed backend and model. Must wrap the
+/// response transforms of the route, they read the whole body.
+pub async fn keep_alive_non_stream(req: Request, next: Next) -> Response {
+    if !CLEWDR_CONFIG.load().keep_alive.claude_non_stream {
+        return next.run(req).await;
+    }
+    let (parts, body) = req.into_parts();
+    // already buffered by `limit_body`
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return crate::error::ClewdrError::from(e).into_response(),
+    };
+    let body = serde_json::from_slice::<Value>(&bytes).unwrap_or_default();
+    if body["stream"].as_bool().unwrap_or_default() {
+        return next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+    }
+    let path = parts.uri.path();
+    let dialect = dialect(path).unwrap_or(StreamDialect::Claude);
+    let timeout = CLEWDR_CONFIG.load().timeouts.request(
+        routed_backend(path, &parts.extensions),
+        body["model"].as_str(),
+    );
+    let req = Request::from_parts(parts, Body::from(bytes));
+    json_keep_alive(next.run(req), dialect, timeout)
+}
@@ -0,0 +1,128 @@
+use std::time::Duration;
+
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{HeaderMap, HeaderValue, Method, header::AUTHORIZATION};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use super::{auth::Caller, response_cache::stale};
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError, services::audit};
+
+/// Header setting the latency budget of a single request, in milliseconds
+const X_LATENCY_BUDGET: &str = "x-clewdr-latency-budget";
+/// Header telling the client which path served a request with a budget:
+/// `primary`, `fallback` or `cache`
+const X_SERVED_BY: &str = "x-clewdr-served-by";
+
+/// Marks a request handed over by [`latency_budget`], which runs without a
+/// budget of its own
+#[derive(Clone, Copy)]
+struct Fallback;
+
+fn served_by(mut resp: Response, by: &'static str) -> Response {
+    resp.headers_mut()
+        .insert(X_SERVED_BY, HeaderValue::from_static(by));
+    resp
+}
+
+/// Sends the request to the fallback route through the whole router on
+/// behalf of the original caller, whose scopes must cover the fallback too
+async fn run_fallback(
+    caller: Caller,
+    method: Method,
+    path: &str,
+    query: Option<&str>,
+    mut headers: HeaderMap,
+    body: body::Bytes,
+) -> Option<Response> {
+    let router = audit::router()?;
+    let uri = match query {
+        Some(query) => format!("{path}?{query}"),
+        None => path.to_string(),
+    };
+    headers.remove(X_LATENCY_BUDGET);
+    for name in [AUTHORIZATION.as_str(), "x-api-key", "x-goog-api-key"] {
+        headers.remove(name);
+    }
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .extension(Fallback)
+        .extension(caller)
+        .body(Body::from(body))
+        .ok()?;
+    *req.headers_mut() = headers;
+    let Ok(resp) = router.oneshot(req).await;
+    Some(resp)
+}
+
+/// Cancels the backend of a request that has not sent the response head within
+/// its latency budget, and serves the request from the fallback route of its
+/// path or from the response cache instead
+///
+/// The budget comes from `x-clewdr-latency-budget` or `latency_budget.budget_ms`.
+/// Responses to requests with a budget carry `x-clewdr-served-by`.
+pub async fn latency_budget(req: Request, next: Next) -> Response {
+    if req.extensions().get::<Fallback>().is_some() {
+        return next.run(req).await;
+    }
+    let config = CLEWDR_CONFIG.load().latency_budget.to_owned();
+    let budget = req
+        .headers()
+        .get(X_LATENCY_BUDGET)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(config.budget_ms);
+    if budget == 0 {
+        return next.run(req).await;
+    }
+    let (parts, body) = req.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return ClewdrError::from(e).into_response(),
+    };
+    let method = parts.method.to_owned();
+    let path = parts.uri.path().to_owned();
+    let query = parts.uri.query().map(ToOwned::to_owned);
+    let headers = parts.headers.to_owned();
+    let caller = parts.extensions.get::<Caller>().cloned();
+    let primary = next.run(Request::from_parts(parts, Body::from(bytes.to_owned())));
+    // dropping the primary request on timeout cancels it upstream
+    let Ok(resp) = tokio::time::timeout(Duration::from_millis(budget), primary).await else {
+        warn!("{} exceeded its latency budget of {}ms", path, budget);
+        let mut failed = None;
+        if let Some(target) = config.fallbacks.get(&path)
+            && let Some(caller) = caller
+        {
+            info!("Falling back from {} to {}", path, target);
+            match run_fallback(
+                caller,
+                method,
+                target,
+                query.as_deref(),
+                headers,
+                bytes.to_owned(),
+            )
+            .await
+            {
+                Some(resp) if resp.status().is_success() => return served_by(resp, "fallback"),
+                resp => failed = resp,
+            }
+        }
+        if config.serve_stale
+            && let Some(resp) = stale(&path, &bytes).await
+        {
+            return served_by(resp, "cache");
+        }
+        return match failed {
+            Some(resp) => served_by(resp, "fallback"),
+            None => ClewdrError::LatencyBudgetExceeded { budget_ms: budget }.into_response(),
+        };
+    };
+    served_by(resp, "primary")
+}
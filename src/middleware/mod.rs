@@ -6,8 +6,70 @@
 /// - Authentication: Verify API keys for different authentication methods (admin, OpenAI, Claude)
 /// - Request preprocessing: Normalize requests from different API formats
 /// - Response transformation: Convert between different response formats and handle streaming
+/// - Error rendering: Render errors in the dialect of the client API
+/// - Request ID: Tag every request with an ID for log correlation
+/// - Sticky sessions: Pin a client conversation to the same cookie or key
+/// - Response cache: Serve repeated non-streaming completions without upstream requests
+/// - Parameter checks: Report generation parameters the upstream cannot honor
+/// - Context limits: Shorten or reject prompts exceeding the context window
+/// - Body limit: Reject oversized request bodies, and parse multipart uploads
+/// - Chaos: Inject faults into responses for resilience testing
+/// - Keep-alive: Keep connections busy while waiting on upstream
+/// - Stream resumption: Let clients reconnect to a stream with `Last-Event-ID`
+/// - Stream salvage: End interrupted streams cleanly, keeping the partial output
+/// - Client limit: Cap the requests a single client address has in flight
+/// - Usage: Count requests and errors for the daily summary
+/// - Adaptive concurrency: Cap requests in flight per backend, backing off on 429s
+/// - Hooks: Let external programs inspect or rewrite request and response bodies
+/// - Routing: Let a script pick the backend, key tiers and parameters of a request
+/// - Latency budget: Hand requests to a fallback route when the backend is slow
+/// - Signing: Sign responses so downstream services can verify their origin
+mod adaptive;
 mod auth;
+mod body_limit;
+mod chaos;
 pub mod claude;
+mod client_limit;
+mod context_limit;
+mod error;
 pub mod gemini;
+mod hooks;
+mod keep_alive;
+mod latency_budget;
+pub mod multipart;
+mod params;
+mod request_id;
+mod response_cache;
+mod routing;
+mod salvage;
+mod session;
+mod signing;
+mod stream_resume;
+mod usage;
 
-pub use auth::{RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth};
+pub use adaptive::limit_adaptive;
+pub use auth::{
+    Caller, RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth,
+    check_token_scope,
+};
+pub use body_limit::limit_body;
+pub use chaos::chaos;
+pub use client_limit::limit_per_client;
+pub use context_limit::fit_context;
+pub use error::{to_dialect_error, to_gemini_error, to_oai_error};
+pub use hooks::run_hooks;
+pub use keep_alive::keep_alive_non_stream;
+pub use latency_budget::latency_budget;
+pub use params::check_params;
+pub use request_id::{RequestId, X_REQUEST_ID, attach_request_id, request_id};
+pub use response_cache::response_cache;
+pub use routing::route_script;
+pub use salvage::salvage_stream;
+pub use session::session_hash;
+pub use signing::sign_response;
+pub use stream_resume::resume_stream;
+pub use usage::record_usage;
+
+/// Path prefixes of API routes, health probes and the frontend are spared by
+/// middleware meant for API traffic
+pub(crate) const API_PREFIXES: [&str; 5] = ["/v1/", "/code/", "/gemini/", "/openai/", "/vertex/"];
@@ -0,0 +1,111 @@
+use axum::{
+    body::Bytes,
+    extract::{FromRequest, Request},
+};
+use http::header::CONTENT_TYPE;
+
+use crate::error::ClewdrError;
+
+/// Fields of a `multipart/form-data` body
+pub struct Form(pub Vec<FormField>);
+
+impl<S> FromRequest<S> for Form
+where
+    S: Send + Sync,
+{
+    type Rejection = ClewdrError;
+
+    async fn from_request(req: Request, _: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        // the size is bounded by the body limit middleware
+        let body = axum::body::to_bytes(req.into_body(), usize::MAX).await?;
+        parse_multipart(&content_type, &body)
+            .map(Form)
+            .ok_or(ClewdrError::BadRequest {
+                msg: "Expected a multipart/form-data body",
+            })
+    }
+}
+
+impl FormField {
+    /// Field value as trimmed text
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).trim().to_string()
+    }
+}
+
+/// Whether a request carries a `multipart/form-data` body
+pub fn is_multipart(req: &Request) -> bool {
+    req.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("multipart/form-data"))
+}
+
+/// A field of a `multipart/form-data` body
+pub struct FormField {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+/// Position of `needle` in `haystack`
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Reads a parameter such as `name="file"` from a header value
+fn header_param(header: &str, param: &str) -> Option<String> {
+    header.split(';').find_map(|p| {
+        let (k, v) = p.trim().split_once('=')?;
+        k.eq_ignore_ascii_case(param)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Minimal `multipart/form-data` parser, enough for OpenAI style uploads
+pub fn parse_multipart(content_type: &str, body: &Bytes) -> Option<Vec<FormField>> {
+    if !content_type.starts_with("multipart/form-data") {
+        return None;
+    }
+    let boundary = header_param(content_type, "boundary")?;
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+    let mut rest = &body[find(body, delimiter)? + delimiter.len()..];
+    let mut fields = Vec::new();
+    // the closing delimiter is followed by `--`
+    while !rest.starts_with(b"--") {
+        let end = find(rest, delimiter)?;
+        let part = rest[..end].strip_prefix(b"\r\n").unwrap_or(&rest[..end]);
+        let part = part.strip_suffix(b"\r\n").unwrap_or(part);
+        rest = &rest[end + delimiter.len()..];
+
+        let header_end = find(part, b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&part[..header_end]).ok()?;
+        let mut field = FormField {
+            name: String::new(),
+            file_name: None,
+            content_type: None,
+            data: body.slice_ref(&part[header_end + 4..]),
+        };
+        for line in headers.lines() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            if key.eq_ignore_ascii_case("content-disposition") {
+                field.name = header_param(value, "name").unwrap_or_default();
+                field.file_name = header_param(value, "filename");
+            } else if key.eq_ignore_ascii_case("content-type") {
+                field.content_type = Some(value.trim().to_string());
+            }
+        }
+        fields.push(field);
+    }
+    Some(fields)
+}
@@ -0,0 +1,180 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use http::{HeaderValue, StatusCode};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+/// Header listing parameters the upstream dialect cannot honor
+const X_CLEWDR_WARNINGS: &str = "x-clewdr-warnings";
+
+/// API dialect of a request or of the upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dialect {
+    OpenAI,
+    Anthropic,
+    Gemini,
+}
+
+/// Name of a generation parameter in each dialect, `None` when the dialect has
+/// no equivalent
+///
+/// Gemini names refer to fields of `generationConfig`.
+struct ParamMapping {
+    openai: Option<&'static str>,
+    anthropic: Option<&'static str>,
+    gemini: Option<&'static str>,
+    /// Largest accepted value per dialect, larger values are clamped
+    anthropic_max: Option<f64>,
+}
+
+impl ParamMapping {
+    const fn new(
+        openai: Option<&'static str>,
+        anthropic: Option<&'static str>,
+        gemini: Option<&'static str>,
+    ) -> Self {
+        Self {
+            openai,
+            anthropic,
+            gemini,
+            anthropic_max: None,
+        }
+    }
+
+    fn name(&self, dialect: Dialect) -> Option<&'static str> {
+        match dialect {
+            Dialect::OpenAI => self.openai,
+            Dialect::Anthropic => self.anthropic,
+            Dialect::Gemini => self.gemini,
+        }
+    }
+}
+
+/// Generation parameters across the OpenAI, Anthropic and Gemini dialects
+const PARAM_TABLE: [ParamMapping; 13] = [
+    ParamMapping {
+        anthropic_max: Some(1.0),
+        ..ParamMapping::new(
+            Some("temperature"),
+            Some("temperature"),
+            Some("temperature"),
+        )
+    },
+    ParamMapping::new(Some("top_p"), Some("top_p"), Some("topP")),
+    ParamMapping::new(Some("top_k"), Some("top_k"), Some("topK")),
+    ParamMapping::new(Some("stop"), Some("stop_sequences"), Some("stopSequences")),
+    ParamMapping::new(
+        Some("max_tokens"),
+        Some("max_tokens"),
+        Some("maxOutputTokens"),
+    ),
+    ParamMapping::new(
+        Some("max_completion_tokens"),
+        Some("max_tokens"),
+        Some("maxOutputTokens"),
+    ),
+    ParamMapping::new(Some("presence_penalty"), None, Some("presencePenalty")),
+    ParamMapping::new(Some("frequency_penalty"), None, Some("frequencyPenalty")),
+    ParamMapping::new(Some("seed"), None, Some("seed")),
+    ParamMapping::new(Some("n"), None, Some("candidateCount")),
+    ParamMapping::new(Some("logprobs"), None, Some("responseLogprobs")),
+    ParamMapping::new(Some("top_logprobs"), None, Some("logprobs")),
+    ParamMapping::new(Some("logit_bias"), None, None),
+];
+
+impl Dialect {
+    /// Dialect spoken by the client, from the route
+    fn of_client(path: &str) -> Option<Self> {
+        if path.ends_with("chat/completions") {
+            Some(Dialect::OpenAI)
+        } else if path.ends_with("/messages") {
+            Some(Dialect::Anthropic)
+        } else {
+            None
+        }
+    }
+
+    /// Dialect spoken by the upstream, from the route
+    fn of_upstream(path: &str) -> Self {
+        if path.starts_with("/gemini") {
+            Dialect::Gemini
+        } else {
+            Dialect::Anthropic
+        }
+    }
+}
+
+/// Lists the parameters of a request that the upstream ignores or clamps
+fn param_warnings(body: &Value, from: Dialect, to: Dialect) -> Vec<String> {
+    let mut warnings = vec![];
+    for mapping in PARAM_TABLE.iter() {
+        let Some(name) = mapping.name(from) else {
+            continue;
+        };
+        let Some(value) = body.get(name).filter(|v| !v.is_null()) else {
+            continue;
+        };
+        // defaults sent by many clients are harmless
+        let is_default = match name {
+            "n" => value.as_u64() == Some(1),
+            "presence_penalty" | "frequency_penalty" => value.as_f64() == Some(0.0),
+            "logprobs" => value.as_bool() == Some(false),
+            _ => false,
+        };
+        if is_default {
+            continue;
+        }
+        if mapping.name(to).is_none() {
+            warnings.push(format!("{name} unsupported"));
+        } else if to == Dialect::Anthropic
+            && let Some(max) = mapping.anthropic_max
+            && value.as_f64().is_some_and(|v| v > max)
+        {
+            warnings.push(format!("{name} clamped to {max}"));
+        }
+    }
+    warnings
+}
+
+/// Reports request parameters the upstream cannot honor
+///
+/// Parameters are translated between dialects following `PARAM_TABLE`, those
+/// without an equivalent are dropped. They are listed in the
+/// `x-clewdr-warnings` response header so clients notice instead of getting
+/// silently different sampling.
+pub async fn check_params(req: Request, next: Next) -> Response {
+    let path = req.uri().path().to_owned();
+    let Some(from) = Dialect::of_client(&path) else {
+        return next.run(req).await;
+    };
+    let to = Dialect::of_upstream(&path);
+    let (parts, body) = req.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read request body: {}", e);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .unwrap_or_default();
+        }
+    };
+    let warnings = serde_json::from_slice::<Value>(&bytes)
+        .map(|body| param_warnings(&body, from, to))
+        .unwrap_or_default();
+    let mut resp = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    if warnings.is_empty() {
+        return resp;
+    }
+    debug!("Parameter warnings for {:?}: {:?}", to, warnings);
+    if let Ok(value) = HeaderValue::from_str(&warnings.join(", ")) {
+        resp.headers_mut().insert(X_CLEWDR_WARNINGS, value);
+    }
+    resp
+}
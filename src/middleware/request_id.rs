@@ -0,0 +1,111 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use http::{HeaderName, HeaderValue, header::CONTENT_LENGTH};
+use serde_json::Value;
+use tracing::{Instrument, info_span, warn};
+
+use crate::{error::ErrorDetail, services::audit};
+
+/// Header used to receive and return the request ID
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Maximum length of a client supplied request ID
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Request ID of the current request, stored in request extensions
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    /// Honors a sane incoming `x-request-id` no recorded request has,
+    /// otherwise generates a new one, so no client can take over the audit
+    /// entry of another request
+    fn from_request(req: &Request) -> Self {
+        let incoming = req
+            .headers()
+            .get(&X_REQUEST_ID)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| {
+                !v.is_empty()
+                    && v.len() <= MAX_REQUEST_ID_LEN
+                    && v.chars().all(|c| c.is_ascii_graphic())
+                    && !audit::contains(v)
+            });
+        match incoming {
+            Some(id) => Self(id.to_string()),
+            None => Self(uuid::Uuid::new_v4().simple().to_string()),
+        }
+    }
+}
+
+/// Assigns a request ID to every request
+///
+/// The ID is attached to a tracing span wrapping the whole request, so every log
+/// line emitted while handling it (middleware, state, upstream requests) carries
+/// the ID. It is returned to the client in the `x-request-id` header and added
+/// as `request_id` to JSON error bodies rendered outside of compression, e.g.
+/// auth rejections; routes add it to their own errors with
+/// [`attach_request_id`].
+pub async fn request_id(mut req: Request, next: Next) -> Response {
+    let id = RequestId::from_request(&req);
+    let span = info_span!("req", id = %id.0);
+    if let Ok(value) = HeaderValue::from_str(&id.0) {
+        req.headers_mut().insert(X_REQUEST_ID.to_owned(), value);
+    }
+    req.extensions_mut().insert(id.to_owned());
+
+    let mut resp = next.run(req).instrument(span).await;
+    if resp.extensions().get::<ErrorDetail>().is_some() {
+        resp = attach_to_error_body(resp, &id.0).await;
+    }
+    if let Ok(value) = HeaderValue::from_str(&id.0) {
+        resp.headers_mut().insert(X_REQUEST_ID.to_owned(), value);
+    }
+    resp
+}
+
+/// Adds the request ID of [`request_id`] to JSON error bodies
+///
+/// Must be layered inside `CompressionLayer`, where error bodies are still
+/// plain JSON.
+pub async fn attach_request_id(req: Request, next: Next) -> Response {
+    let id = req.extensions().get::<RequestId>().cloned();
+    let resp = next.run(req).await;
+    match id {
+        Some(id) if resp.extensions().get::<ErrorDetail>().is_some() => {
+            attach_to_error_body(resp, &id.0).await
+        }
+        _ => resp,
+    }
+}
+
+/// Adds the request ID to the top level of a JSON error body
+async fn attach_to_error_body(resp: Response, id: &str) -> Response {
+    // compressed bodies are left untouched
+    if resp.headers().contains_key(http::header::CONTENT_ENCODING) {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read error body: {}", e);
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let body = match serde_json::from_slice::<Value>(&bytes) {
+        Ok(Value::Object(mut obj)) => {
+            obj.insert("request_id".into(), id.into());
+            let bytes = serde_json::to_vec(&obj).unwrap_or_else(|_| bytes.to_vec());
+            parts.headers.remove(CONTENT_LENGTH);
+            Body::from(bytes)
+        }
+        _ => Body::from(bytes),
+    };
+    Response::from_parts(parts, body)
+}
@@ -0,0 +1,235 @@
+use std::{
+    path::PathBuf,
+    sync::LazyLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_stream::stream;
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use http::{HeaderValue, StatusCode, header::CONTENT_TYPE};
+use moka::sync::Cache;
+use ring::digest::{SHA256, digest};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::{debug, warn};
+
+use crate::config::{CLEWDR_CONFIG, CONFIG_PATH};
+
+/// Header telling the client whether the response came from the cache
+const X_CLEWDR_CACHE: &str = "x-clewdr-cache";
+
+/// Directory holding cached responses when disk caching is enabled
+static CACHE_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    CONFIG_PATH
+        .parent()
+        .map(|p| p.join("cache"))
+        .unwrap_or_else(|| PathBuf::from("cache"))
+});
+
+/// In-memory cache, sized and timed by the config at first use
+static RESPONSE_CACHE: LazyLock<Cache<String, CachedResponse>> = LazyLock::new(|| {
+    let config = CLEWDR_CONFIG
+        .load()
+        .response_cache
+        .to_owned()
+        .unwrap_or_default();
+    Cache::builder()
+        .max_capacity(config.max_entries)
+        .time_to_live(Duration::from_secs(config.ttl))
+        .build()
+});
+
+/// A successful non-streaming completion
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedResponse {
+    created_at: u64,
+    content_type: Option<String>,
+    body: String,
+}
+
+impl CachedResponse {
+    fn into_response(self) -> Response {
+        let mut resp = Response::new(Body::from(self.body));
+        if let Some(ct) = self
+            .content_type
+            .and_then(|c| HeaderValue::from_str(&c).ok())
+        {
+            resp.headers_mut().insert(CONTENT_TYPE, ct);
+        }
+        resp.headers_mut()
+            .insert(X_CLEWDR_CACHE, HeaderValue::from_static("hit"));
+        resp
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Hashes the route and the normalized request body
+///
+/// Object keys are sorted by `serde_json`, so clients sending the same
+/// parameters in a different order share the cache entry. Returns `None` for
+/// streaming requests and bodies that are not JSON objects.
+fn cache_key(path: &str, body: &[u8]) -> Option<String> {
+    if path.contains("streamGenerateContent") {
+        return None;
+    }
+    let mut body = serde_json::from_slice::<Value>(body).ok()?;
+    let obj = body.as_object_mut()?;
+    if obj
+        .get("stream")
+        .and_then(Value::as_bool)
+        .unwrap_or_default()
+    {
+        return None;
+    }
+    obj.remove("stream");
+    let normalized = serde_json::to_vec(&body).ok()?;
+    let mut input = path.as_bytes().to_vec();
+    input.push(b'\n');
+    input.extend(normalized);
+    let hash = digest(&SHA256, &input);
+    Some(hash.as_ref().iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Whether a response body is a completion rather than an error rendered with
+/// a success status, e.g. by the Gemini keep-alive stream
+fn is_completion(body: &[u8]) -> bool {
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|v| v.as_object().map(|o| !o.contains_key("error")))
+        .unwrap_or_default()
+}
+
+async fn load_from_disk(key: &str, ttl: u64) -> Option<CachedResponse> {
+    let bytes = tokio::fs::read(CACHE_DIR.join(format!("{key}.json")))
+        .await
+        .ok()?;
+    let cached = serde_json::from_slice::<CachedResponse>(&bytes).ok()?;
+    (cached.created_at.saturating_add(ttl) > now()).then_some(cached)
+}
+
+async fn save_to_disk(key: &str, cached: &CachedResponse) {
+    let result = async {
+        tokio::fs::create_dir_all(CACHE_DIR.as_path()).await?;
+        let bytes = serde_json::to_vec(cached)?;
+        tokio::fs::write(CACHE_DIR.join(format!("{key}.json")), bytes).await?;
+        Ok::<_, crate::error::ClewdrError>(())
+    };
+    if let Err(e) = result.await {
+        warn!("Failed to write response cache: {}", e);
+    }
+}
+
+/// Cached response to a request, including expired entries still on disk,
+/// for requests whose backend ran out of latency budget
+pub(super) async fn stale(path: &str, body: &[u8]) -> Option<Response> {
+    let config = CLEWDR_CONFIG.load().response_cache.to_owned()?;
+    let key = cache_key(path, body)?;
+    if let Some(cached) = RESPONSE_CACHE.get(&key) {
+        return Some(cached.into_response());
+    }
+    if !config.disk || CLEWDR_CONFIG.load().no_fs {
+        return None;
+    }
+    load_from_disk(&key, u64::MAX)
+        .await
+        .map(CachedResponse::into_response)
+}
+
+/// Serves repeated non-streaming completions from a cache
+///
+/// Responses are cached in memory and, if enabled, on disk. A hit is returned
+/// without dispatching a cookie or key, and carries `x-clewdr-cache: hit`.
+pub async fn response_cache(req: Request, next: Next) -> Response {
+    let Some(config) = CLEWDR_CONFIG.load().response_cache.to_owned() else {
+        return next.run(req).await;
+    };
+    let (parts, body) = req.into_parts();
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("Failed to read request body: {}", e);
+            return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .unwrap_or_default();
+        }
+    };
+    let path = parts.uri.path().to_owned();
+    let Some(key) = cache_key(&path, &bytes) else {
+        return next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await;
+    };
+    if let Some(cached) = RESPONSE_CACHE.get(&key) {
+        debug!("Response cache hit: {}", key);
+        return cached.into_response();
+    }
+    let use_disk = config.disk && !CLEWDR_CONFIG.load().no_fs;
+    if use_disk && let Some(cached) = load_from_disk(&key, config.ttl).await {
+        debug!("Response cache disk hit: {}", key);
+        RESPONSE_CACHE.insert(key, cached.to_owned());
+        return cached.into_response();
+    }
+
+    let resp = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    if resp.status() != StatusCode::OK {
+        return resp;
+    }
+    let (mut parts, body) = resp.into_parts();
+    parts
+        .headers
+        .insert(X_CLEWDR_CACHE, HeaderValue::from_static("miss"));
+    let content_type = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(ToOwned::to_owned);
+    // pass chunks through as they come, keep-alive padding included, and
+    // store the full body once it is complete
+    let body = stream! {
+        let mut buf = Vec::new();
+        let mut body = body.into_data_stream();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    buf.extend_from_slice(&chunk);
+                    yield Ok(chunk);
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if !is_completion(&buf) {
+            return;
+        }
+        let Ok(text) = String::from_utf8(buf) else {
+            return;
+        };
+        let cached = CachedResponse {
+            created_at: now(),
+            content_type,
+            body: text,
+        };
+        if use_disk {
+            save_to_disk(&key, &cached).await;
+        }
+        RESPONSE_CACHE.insert(key, cached);
+    };
+    Response::from_parts(parts, Body::from_stream(body))
+}
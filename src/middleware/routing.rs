@@ -0,0 +1,94 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::{self, Body},
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{Local, Timelike};
+use http::{HeaderValue, header::CONTENT_LENGTH};
+use serde_json::Value;
+use tiktoken_rs::o200k_base;
+use tracing::info;
+
+use super::context_limit::{gemini_model, tokens};
+use crate::{
+    error::ClewdrError,
+    services::{
+        key_actor::KEY_TIER_HEADER,
+        routing::{self, RouteInput},
+    },
+};
+
+/// Applies the decision of the routing script: the backend is handed to the
+/// handler as an extension, key tiers as the `x-clewdr-key-tier` header and
+/// overrides are merged into the body
+///
+/// Runs before parameter checks and context fitting, so overridden parameters
+/// go through them like the client's own.
+pub async fn route_script(req: Request, next: Next) -> Result<Response, ClewdrError> {
+    if !routing::enabled() {
+        return Ok(next.run(req).await);
+    }
+    let path = req.uri().path().to_owned();
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let (mut parts, body) = req.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX).await?;
+    let Ok(mut json) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    };
+    let bpe = o200k_base().expect("Failed to get encoding");
+    let now = Local::now();
+    let input = RouteInput {
+        model: gemini_model(&path).or_else(|| json["model"].as_str().map(str::to_string)),
+        tokens: tokens(&bpe, &json),
+        stream: json["stream"].as_bool().unwrap_or_default()
+            || path.contains("streamGenerateContent"),
+        path,
+        client,
+        time: now.format("%H:%M").to_string(),
+        hour: now.hour(),
+        weekday: now.format("%a").to_string(),
+    };
+    let Some(decision) = routing::decide(&input).await else {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    };
+    info!("[ROUTE] {:?}", decision);
+    if let Some(backend) = decision.backend {
+        parts.extensions.insert(backend);
+    }
+    if let Some(tiers) = decision.key_tier
+        && let Ok(value) = HeaderValue::from_str(&tiers.join(","))
+    {
+        parts.headers.insert(KEY_TIER_HEADER, value);
+    }
+    if decision.overrides.is_empty() {
+        return Ok(next
+            .run(Request::from_parts(parts, Body::from(bytes)))
+            .await);
+    }
+    if let Some(body) = json.as_object_mut() {
+        for (k, v) in decision.overrides {
+            if v.is_null() {
+                body.remove(&k);
+            } else {
+                body.insert(k, v);
+            }
+        }
+    }
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(next
+        .run(Request::from_parts(
+            parts,
+            Body::from(serde_json::to_vec(&json)?),
+        ))
+        .await)
+}
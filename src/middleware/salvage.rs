@@ -0,0 +1,85 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    middleware::Next,
+    response::Response,
+};
+use futures::StreamExt;
+use http::header::CONTENT_TYPE;
+use tracing::warn;
+
+use crate::{
+    config::{CLEWDR_CONFIG, StreamSalvage},
+    middleware::RequestId,
+    services::audit,
+    streaming::{StreamDialect, ends_event},
+};
+
+/// Dialect of the stream a route returns, from its path
+pub(super) fn dialect(path: &str) -> Option<StreamDialect> {
+    if path.ends_with("/chat/completions") {
+        Some(StreamDialect::OpenAI)
+    } else if path.contains("/v1beta/") {
+        Some(StreamDialect::Gemini)
+    } else if path.ends_with("/messages") {
+        Some(StreamDialect::Claude)
+    } else {
+        None
+    }
+}
+
+/// Handles streamed API responses whose upstream dies mid-response
+///
+/// The truncation is noted in the audit log. With `stream_salvage` set, the
+/// stream is ended with a finish reason in the route's dialect instead of an
+/// aborted body, so clients keep the partial generation.
+pub async fn salvage_stream(req: Request, next: Next) -> Response {
+    let salvage = CLEWDR_CONFIG.load().stream_salvage;
+    let Some(dialect) = dialect(req.uri().path()) else {
+        return next.run(req).await;
+    };
+    if salvage == StreamSalvage::Off && !audit::enabled() {
+        return next.run(req).await;
+    }
+    let request_id = req.extensions().get::<RequestId>().map(|r| r.0.to_owned());
+    let res = next.run(req).await;
+    let streaming = res.status().is_success()
+        && res
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if !streaming {
+        return res;
+    }
+    let (parts, body) = res.into_parts();
+    let mut body = body.into_data_stream();
+    let stream = async_stream::stream! {
+        // whether the bytes sent so far end on an event boundary
+        let mut clean = true;
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    if !chunk.is_empty() {
+                        clean = ends_event(&chunk);
+                    }
+                    yield Ok(chunk);
+                }
+                Err(e) => {
+                    warn!("Upstream stream interrupted: {}", e);
+                    if let Some(ref id) = request_id {
+                        audit::mark_truncated(id, &e.to_string());
+                    }
+                    if salvage == StreamSalvage::Off {
+                        yield Err(e);
+                        break;
+                    }
+                    let mut events = if clean { String::new() } else { "\n\n".to_string() };
+                    events.push_str(&dialect.closing_events(salvage));
+                    yield Ok(Bytes::from(events));
+                    break;
+                }
+            }
+        }
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
@@ -0,0 +1,40 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use http::HeaderMap;
+
+use crate::{config::CLEWDR_CONFIG, types::claude::Metadata};
+
+/// Headers a client can use to identify a conversation, checked in order
+const SESSION_HEADERS: [&str; 2] = ["x-session-id", "x-conversation-id"];
+
+/// Metadata fields a client can use to identify a conversation, checked in order
+const SESSION_METADATA: [&str; 2] = ["conversation_id", "user_id"];
+
+/// Hashes the conversation ID provided by the client, if any
+///
+/// Requests sharing the hash are pinned to the same cookie or key, so
+/// consecutive turns of a conversation hit the same upstream account and
+/// reuse its prompt cache. Returns `None` when sticky sessions are disabled.
+///
+/// # Arguments
+/// * `headers` - Request headers, `x-session-id` or `x-conversation-id`
+/// * `metadata` - Request metadata, `conversation_id` or `user_id`
+pub fn session_hash(headers: &HeaderMap, metadata: Option<&Metadata>) -> Option<u64> {
+    if !CLEWDR_CONFIG.load().sticky_session {
+        return None;
+    }
+    let id = SESSION_HEADERS
+        .iter()
+        .find_map(|h| headers.get(*h)?.to_str().ok())
+        .or_else(|| {
+            let fields = &metadata?.fields;
+            SESSION_METADATA
+                .iter()
+                .find_map(|f| fields.get(*f).map(String::as_str))
+        })
+        .map(str::trim)
+        .filter(|id| !id.is_empty())?;
+    let mut hasher = DefaultHasher::new();
+    id.hash(&mut hasher);
+    Some(hasher.finish())
+}
@@ -0,0 +1,165 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_stream::stream;
+use axum::{
+    body::{self, Body, Bytes},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use futures::StreamExt;
+use http::{
+    HeaderMap, HeaderValue, StatusCode,
+    header::{CONTENT_LENGTH, CONTENT_TYPE, TRAILER},
+};
+use http_body::Frame;
+use http_body_util::StreamBody;
+use ring::hmac;
+
+use crate::{
+    config::CLEWDR_CONFIG, error::ClewdrError, middleware::API_PREFIXES, streaming::PaddedJson,
+};
+
+/// Header, trailer and closing SSE comment of streams carrying the signature
+const X_CLEWDR_SIGNATURE: &str = "x-clewdr-signature";
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Starts the HMAC over the timestamp and the status code, each followed by a
+/// dot, the body is added as it is sent
+fn context(secret: &str, timestamp: u64, status: StatusCode) -> hmac::Context {
+    let mut ctx = hmac::Context::with_key(&hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()));
+    ctx.update(format!("{timestamp}.{}.", status.as_u16()).as_bytes());
+    ctx
+}
+
+/// Signature in the `t=<unix time>,v1=<hex HMAC-SHA256>` format
+fn signature(timestamp: u64, ctx: hmac::Context) -> String {
+    format!("t={timestamp},v1={}", hex(ctx.sign().as_ref()))
+}
+
+/// Whether a body is JSON, or has no content type like most error bodies
+fn is_json(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    mime.is_empty() || mime == "application/json" || mime.ends_with("+json")
+}
+
+/// Signs API responses with `response_signing_secret`, so services behind
+/// another hop can tell they come from this instance
+///
+/// The HMAC covers the timestamp, the status code and the body as sent,
+/// compressed ones included, as `<timestamp>.<status>.<body>`. JSON
+/// responses are buffered and carry the signature in `x-clewdr-signature`.
+/// Event streams end with an SSE comment `: x-clewdr-signature t=...,v1=...`
+/// signing every byte before it, which clients skip. Any other body, padded
+/// JSON from the keep-alive, streamed audio and other binary content, is not
+/// buffered, that would hold it back, and carries the signature as a
+/// trailer, which HTTP/1.1 clients only receive when they send
+/// `TE: trailers`.
+pub async fn sign_response(req: Request, next: Next) -> Response {
+    let secret = CLEWDR_CONFIG.load().response_signing_secret.to_owned();
+    let path = req.uri().path();
+    let Some(secret) = secret.filter(|_| API_PREFIXES.iter().any(|p| path.starts_with(p))) else {
+        return next.run(req).await;
+    };
+    let resp = next.run(req).await;
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut ctx = context(&secret, timestamp, resp.status());
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let sse = content_type.starts_with("text/event-stream");
+    let padded = resp.extensions().get::<PaddedJson>().is_some();
+    let buffered = !padded && is_json(content_type);
+    let (mut parts, body) = resp.into_parts();
+    if buffered {
+        let bytes = match body::to_bytes(body, usize::MAX).await {
+            Ok(bytes) => bytes,
+            Err(e) => return ClewdrError::from(e).into_response(),
+        };
+        ctx.update(&bytes);
+        if let Ok(value) = HeaderValue::from_str(&signature(timestamp, ctx)) {
+            parts.headers.insert(X_CLEWDR_SIGNATURE, value);
+        }
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    if !sse {
+        // trailers need a chunked body
+        parts.headers.remove(CONTENT_LENGTH);
+        parts
+            .headers
+            .insert(TRAILER, HeaderValue::from_static(X_CLEWDR_SIGNATURE));
+    }
+    let frames = stream! {
+        let mut body = body.into_data_stream();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    ctx.update(&chunk);
+                    yield Ok(Frame::data(chunk));
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        let signature = signature(timestamp, ctx);
+        if sse {
+            let closing = format!(": {X_CLEWDR_SIGNATURE} {signature}\n\n");
+            yield Ok::<_, axum::Error>(Frame::data(Bytes::from(closing)));
+        } else if let Ok(value) = HeaderValue::from_str(&signature) {
+            let mut trailers = HeaderMap::new();
+            trailers.insert(X_CLEWDR_SIGNATURE, value);
+            yield Ok(Frame::trailers(trailers));
+        }
+    };
+    Response::from_parts(parts, Body::new(StreamBody::new(frames)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_json_is_buffered() {
+        assert!(is_json("application/json"));
+        assert!(is_json("application/problem+json; charset=utf-8"));
+        assert!(is_json(""));
+        assert!(!is_json("audio/wav"));
+        assert!(!is_json("application/octet-stream"));
+        assert!(!is_json("text/event-stream"));
+    }
+
+    #[test]
+    fn status_is_signed() {
+        let sign = |status| {
+            let mut ctx = context("secret", 1_700_000_000, status);
+            ctx.update(br#"{"ok":true}"#);
+            signature(1_700_000_000, ctx)
+        };
+        assert!(sign(StatusCode::OK).starts_with("t=1700000000,v1="));
+        assert_ne!(sign(StatusCode::OK), sign(StatusCode::TOO_MANY_REQUESTS));
+    }
+
+    #[test]
+    fn signature_matches_the_documented_material() {
+        let mut ctx = context("secret", 42, StatusCode::OK);
+        ctx.update(b"body");
+        let expected = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, b"secret"),
+            b"42.200.body",
+        );
+        assert_eq!(
+            signature(42, ctx),
+            format!("t=42,v1={}", hex(expected.as_ref()))
+        );
+    }
+}
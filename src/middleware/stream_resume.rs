@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
+use http::{
+    HeaderMap, HeaderValue,
+    header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE},
+};
+use ring::digest::{SHA256, digest};
+use tracing::info;
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    middleware::RequestId,
+    services::connection_registry::CONNECTION_REGISTRY,
+    streaming::resume::{self, parse_last_event_id},
+};
+
+/// Header a reconnecting SSE client sends with the last event it received
+const LAST_EVENT_ID: &str = "last-event-id";
+
+/// Digest of the credentials a request carries, a reconnect must present the
+/// same ones
+fn credential(req: &Request) -> Vec<u8> {
+    let headers: &HeaderMap = req.headers();
+    let mut material = Vec::new();
+    for name in [AUTHORIZATION.as_str(), "x-api-key", "x-goog-api-key"] {
+        if let Some(value) = headers.get(name) {
+            material.extend_from_slice(value.as_bytes());
+        }
+        material.push(0);
+    }
+    if let Some(query) = req.uri().query() {
+        for pair in query.split('&').filter(|p| p.starts_with("key=")) {
+            material.extend_from_slice(pair.as_bytes());
+        }
+    }
+    digest(&SHA256, &material).as_ref().to_vec()
+}
+
+/// Makes streamed API responses resumable when `stream_resume_secs` is set
+///
+/// Every event of a streamed response gets an `id:` line. The upstream stream
+/// is drained into a short ring buffer, so when the client connection drops
+/// and the client sends the same request again with `Last-Event-ID` within
+/// the window, it is served the rest of the original stream instead of a new
+/// completion. Reconnects that can not be resumed are handled as new
+/// requests.
+pub async fn resume_stream(req: Request, next: Next) -> Response {
+    let config = CLEWDR_CONFIG.load();
+    if config.stream_resume_secs == 0 {
+        return next.run(req).await;
+    }
+    let window = Duration::from_secs(config.stream_resume_secs);
+    let capacity = config.stream_resume_events;
+    let credential = credential(&req);
+    if let Some((id, from)) = req
+        .headers()
+        .get(LAST_EVENT_ID)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_last_event_id)
+        && let Some(buffer) = CONNECTION_REGISTRY.find_stream(id)
+        && buffer.owned_by(&credential)
+        && buffer.can_resume_from(from)
+    {
+        info!("[RESUME] resuming stream {} from event {}", id, from);
+        let mut res = Response::new(Body::from_stream(buffer.reader(from)));
+        let headers = res.headers_mut();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        return res;
+    }
+    let Some(RequestId(request_id)) = req.extensions().get::<RequestId>().cloned() else {
+        return next.run(req).await;
+    };
+    let res = next.run(req).await;
+    let streaming = res.status().is_success()
+        && res
+            .headers()
+            .get(CONTENT_TYPE)
+            .is_some_and(|v| v.as_bytes().starts_with(b"text/event-stream"));
+    if !streaming {
+        return res;
+    }
+    let (mut parts, body) = res.into_parts();
+    parts.headers.remove(CONTENT_LENGTH);
+    let buffer = resume::buffer(request_id, credential, body, capacity, window);
+    Response::from_parts(parts, Body::from_stream(buffer.reader(0)))
+}
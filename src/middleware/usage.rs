@@ -0,0 +1,87 @@
+use axum::{
+    body::{self, Body},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{Extensions, Method};
+use serde::Deserialize;
+
+use super::context_limit::gemini_model;
+use crate::{
+    config::{Backend, CLEWDR_CONFIG, ClaudeBackend},
+    error::{ClewdrError, ErrorDetail},
+    services::daily_report,
+};
+
+/// Model field of a request body, the rest is skipped
+#[derive(Deserialize)]
+struct ModelField {
+    model: Option<String>,
+}
+
+/// Upstream serving an API path
+pub(super) fn backend(path: &str) -> Backend {
+    if path.contains("/vertex/") {
+        Backend::Vertex
+    } else if path.starts_with("/gemini/")
+        || path.starts_with("/v1/v1beta/")
+        || path.starts_with("/v1/ws/")
+    {
+        Backend::Gemini
+    } else if path.starts_with("/openai/") {
+        Backend::OpenAI
+    } else if path.starts_with("/code/") {
+        Backend::ClaudeCode
+    } else if path == "/v1/chat/completions" {
+        match CLEWDR_CONFIG.load().oai_backend {
+            ClaudeBackend::Web => Backend::ClaudeWeb,
+            ClaudeBackend::Code => Backend::ClaudeCode,
+        }
+    } else {
+        Backend::ClaudeWeb
+    }
+}
+
+/// Upstream serving a request, with the Claude backend the routing script
+/// picked for `/v1/chat/completions`
+pub(super) fn routed_backend(path: &str, extensions: &Extensions) -> Backend {
+    match extensions.get::<ClaudeBackend>() {
+        Some(ClaudeBackend::Web) if path == "/v1/chat/completions" => Backend::ClaudeWeb,
+        Some(ClaudeBackend::Code) if path == "/v1/chat/completions" => Backend::ClaudeCode,
+        _ => backend(path),
+    }
+}
+
+/// Counts requests per backend and model, and failed requests by error code,
+/// for the daily summary
+///
+/// Must run inside the keep-alive layer, which hides the status of errors.
+pub async fn record_usage(req: Request, next: Next) -> Response {
+    // e.g. model listings
+    if req.method() != Method::POST {
+        return next.run(req).await;
+    }
+    let path = req.uri().path().to_owned();
+    let (parts, body) = req.into_parts();
+    // already buffered by `limit_body`
+    let bytes = match body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return ClewdrError::from(e).into_response(),
+    };
+    let model = gemini_model(&path).or_else(|| {
+        serde_json::from_slice::<ModelField>(&bytes)
+            .ok()
+            .and_then(|f| f.model)
+    });
+    let resp = next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await;
+    let error = match resp.extensions().get::<ErrorDetail>() {
+        Some(detail) => Some(detail.code),
+        None if !resp.status().is_success() => Some("upstream_status"),
+        None => None,
+    };
+    daily_report::record_request(backend(&path), model.as_deref(), error);
+    resp
+}
@@ -0,0 +1,186 @@
+use serde_json::Value;
+use snafu::ResultExt;
+use tracing::info;
+use wreq::{Client, ClientBuilder, StatusCode};
+
+use crate::{
+    config::{Backend, CLEWDR_CONFIG, OpenAIUpstream},
+    error::{CheckGeminiErr, ClewdrError, WreqSnafu},
+    provider::{self, Provider, Verdict},
+    services::{
+        openai_pool::OPENAI_POOL,
+        proxy_pool::{PROXY_POOL, to_wreq_proxy},
+    },
+    streaming::StreamDialect,
+    utils::{forward_guarded, forward_response},
+};
+
+/// Seconds a rate limited key stays out of rotation
+const RATE_LIMIT_COOLDOWN: i64 = 60;
+/// Seconds a rejected key stays out of rotation
+const AUTH_COOLDOWN: i64 = 60 * 60;
+
+/// Finds the upstream serving a model, by its `<name>/` prefix or its
+/// `models` list
+///
+/// # Returns
+/// The upstream and the model name to send it
+pub fn resolve(model: &str) -> Result<(OpenAIUpstream, String), ClewdrError> {
+    let config = CLEWDR_CONFIG.load();
+    let upstreams = &config.openai_upstreams;
+    if let Some((name, rest)) = model.split_once('/')
+        && let Some(upstream) = upstreams.iter().find(|u| u.name == name)
+    {
+        return Ok((upstream.to_owned(), rest.to_string()));
+    }
+    upstreams
+        .iter()
+        .find(|u| u.models.iter().any(|m| m == model))
+        .map(|u| (u.to_owned(), model.to_string()))
+        .ok_or_else(|| ClewdrError::PathNotFound {
+            msg: format!("No OpenAI upstream serves model `{model}`"),
+        })
+}
+
+/// Request state of an OpenAI compatible upstream, see
+/// [`crate::config::ClewdrConfig::openai_upstreams`]
+#[derive(Clone)]
+pub struct OpenAIState {
+    pub upstream: Option<OpenAIUpstream>,
+    /// Model as sent upstream, without the upstream prefix
+    pub model: String,
+    pub stream: bool,
+    key: Option<String>,
+    proxy: Option<String>,
+    client: Client,
+}
+
+impl Default for OpenAIState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenAIState {
+    pub fn new() -> Self {
+        OpenAIState {
+            upstream: None,
+            model: String::new(),
+            stream: false,
+            key: None,
+            proxy: None,
+            client: Client::new(),
+        }
+    }
+
+    fn upstream(&self) -> Result<&OpenAIUpstream, ClewdrError> {
+        self.upstream.as_ref().ok_or(ClewdrError::UnexpectedNone {
+            msg: "Upstream is None, did you resolve the model?",
+        })
+    }
+
+    fn build_client(&mut self) -> Result<(), ClewdrError> {
+        let client = CLEWDR_CONFIG.load().timeouts.client(
+            ClientBuilder::new(),
+            Backend::OpenAI,
+            Some(&self.model),
+        );
+        self.proxy = PROXY_POOL.resolve(self.upstream()?.proxy.as_deref());
+        let client = if let Some(proxy) = self.proxy.as_deref().and_then(to_wreq_proxy) {
+            client.proxy(proxy)
+        } else {
+            client
+        };
+        self.client = client.build().context(WreqSnafu {
+            msg: "Failed to build OpenAI upstream client",
+        })?;
+        Ok(())
+    }
+}
+
+impl Provider for OpenAIState {
+    type Request = Value;
+
+    const NAME: &'static str = "openai";
+
+    async fn prepare(&mut self) -> Result<(), ClewdrError> {
+        self.key = OPENAI_POOL.dispatch(self.upstream()?)?;
+        self.build_client()
+    }
+
+    async fn send(&mut self, body: Value) -> Result<wreq::Response, ClewdrError> {
+        let upstream = self.upstream()?;
+        info!("[UPSTREAM] {} {}", upstream.name, self.model);
+        let endpoint = format!(
+            "{}/chat/completions",
+            upstream.base_url.as_str().trim_end_matches('/')
+        );
+        let req = self.client.post(endpoint).json(&body);
+        let req = match self.key {
+            Some(ref key) => req.bearer_auth(key),
+            None => req,
+        };
+        req.send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to send request to OpenAI upstream",
+            })?
+            // same JSON error bodies as the Gemini OpenAI endpoint
+            .check_gemini()
+            .await
+            .map_err(|e| match e {
+                ClewdrError::GeminiHttpError { code, inner } => {
+                    ClewdrError::UpstreamHttpError { code, inner }
+                }
+                e => e,
+            })
+    }
+
+    async fn decode(
+        &mut self,
+        res: wreq::Response,
+    ) -> Result<axum::response::Response, ClewdrError> {
+        if self.stream {
+            return forward_guarded(res, StreamDialect::OpenAI, || ()).await;
+        }
+        forward_response(res)
+    }
+
+    async fn classify_error(&mut self, e: ClewdrError) -> Verdict {
+        let ClewdrError::UpstreamHttpError { code, .. } = e else {
+            if provider::transient(&e, self.proxy.as_deref()) {
+                return Verdict::Retry(e);
+            }
+            return Verdict::Fail(e);
+        };
+        let cooldown = match code {
+            StatusCode::TOO_MANY_REQUESTS => RATE_LIMIT_COOLDOWN,
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AUTH_COOLDOWN,
+            code if code.is_server_error() => return Verdict::Retry(e),
+            _ => return Verdict::Fail(e),
+        };
+        match (&self.upstream, &self.key) {
+            (Some(upstream), Some(key)) => {
+                OPENAI_POOL.cooldown(&upstream.name, key, cooldown);
+                Verdict::Retry(e)
+            }
+            // without a key to rotate the next attempt fails the same way
+            _ => Verdict::Fail(e),
+        }
+    }
+
+    fn credential(&self) -> Option<String> {
+        self.key
+            .as_ref()
+            .map(|k| format!("{}...", k.chars().take(10).collect::<String>()))
+    }
+
+    fn credential_id(&self) -> Option<String> {
+        self.key.to_owned()
+    }
+
+    /// The last upstream error tells more than a bare retry count
+    fn exhausted(&self, last: ClewdrError) -> ClewdrError {
+        last
+    }
+}
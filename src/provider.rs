@@ -0,0 +1,137 @@
+use std::time::Instant;
+
+use colored::Colorize;
+use tracing::{Instrument, error, field, info, info_span};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    services::{latency_stats, proxy_pool::PROXY_POOL},
+};
+
+/// What the retry loop does with the error of a failed attempt
+#[derive(Debug)]
+pub enum Verdict {
+    /// Try again, with a fresh credential
+    Retry(ClewdrError),
+    /// Return the error to the client
+    Fail(ClewdrError),
+}
+
+/// Upstream backend driven by [`chat_with_retries`]
+///
+/// Each attempt runs on a fresh clone of the provider: [`Provider::prepare`]
+/// takes a credential, [`Provider::send`] sends the request and
+/// [`Provider::decode`] turns the upstream response into the client response.
+/// A new backend implements these steps and leaves retries, logging and proxy
+/// rotation to the shared loop.
+pub trait Provider: Clone + Send + Sync + 'static {
+    /// Request body sent upstream, cloned for every attempt
+    type Request: Clone + Send + Sync;
+
+    /// Name of the backend in logs and tracing spans
+    const NAME: &'static str;
+
+    /// Takes a credential for the attempt, e.g. a cookie or key from its pool
+    fn prepare(&mut self) -> impl Future<Output = Result<(), ClewdrError>> + Send;
+
+    /// Sends the request upstream, failing on error statuses
+    fn send(
+        &mut self,
+        req: Self::Request,
+    ) -> impl Future<Output = Result<wreq::Response, ClewdrError>> + Send;
+
+    /// Turns the upstream response into the response for the client, checking
+    /// streams and bodies as far as needed to decide on a retry
+    fn decode(
+        &mut self,
+        res: wreq::Response,
+    ) -> impl Future<Output = Result<axum::response::Response, ClewdrError>> + Send;
+
+    /// Reports the credential of a failed attempt and decides whether another
+    /// attempt may succeed
+    fn classify_error(&mut self, e: ClewdrError) -> impl Future<Output = Verdict> + Send;
+
+    /// Runs after every attempt, successful or not, e.g. to clean up upstream
+    fn report_outcome(&mut self, success: bool) -> impl Future<Output = ()> + Send {
+        let _ = success;
+        async {}
+    }
+
+    /// Credential of the attempt, shortened for logs
+    fn credential(&self) -> Option<String>;
+
+    /// Full credential of the attempt, identifying it in the latency stats
+    fn credential_id(&self) -> Option<String> {
+        None
+    }
+
+    /// Error returned once every attempt failed
+    fn exhausted(&self, last: ClewdrError) -> ClewdrError {
+        let _ = last;
+        ClewdrError::TooManyRetries
+    }
+}
+
+/// Whether an error is worth a retry on any backend: the upstream never
+/// started streaming, or a pooled proxy failed and was rotated out
+pub fn transient(e: &ClewdrError, proxy: Option<&str>) -> bool {
+    match e {
+        ClewdrError::StreamStalled { .. } => true,
+        ClewdrError::WreqError { .. } => proxy.is_some_and(|p| PROXY_POOL.mark_unhealthy(p)),
+        _ => false,
+    }
+}
+
+/// Sends a request through the provider, retrying failed attempts up to
+/// `max_retries` times as [`Provider::classify_error`] decides
+pub async fn chat_with_retries<P: Provider>(
+    provider: &P,
+    req: P::Request,
+) -> Result<axum::response::Response, ClewdrError> {
+    let mut last = None;
+    for i in 0..CLEWDR_CONFIG.load().max_retries + 1 {
+        if i > 0 {
+            info!("[RETRY] attempt: {}", i.to_string().green());
+        }
+        let mut state = provider.to_owned();
+        let req = req.to_owned();
+        let span = info_span!("upstream", backend = P::NAME, credential = field::Empty);
+        let res = async {
+            state.prepare().await?;
+            if let Some(credential) = state.credential() {
+                tracing::Span::current().record("credential", credential);
+            }
+            let started = Instant::now();
+            let res = state.send(req).await?;
+            let res = state.decode(res).await?;
+            Ok(match state.credential_id() {
+                Some(id) => latency_stats::meter(
+                    P::NAME,
+                    id,
+                    state.credential().unwrap_or_default(),
+                    started,
+                    res,
+                ),
+                None => res,
+            })
+        }
+        .instrument(span.to_owned())
+        .await;
+        state.report_outcome(res.is_ok()).instrument(span).await;
+        let e = match res {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        match state.credential() {
+            Some(credential) => error!("[{}] {}", credential.green(), e),
+            None => error!("{}", e),
+        }
+        match state.classify_error(e).await {
+            Verdict::Retry(e) => last = Some(e),
+            Verdict::Fail(e) => return Err(e),
+        }
+    }
+    error!("Max retries exceeded");
+    Err(last.map_or(ClewdrError::TooManyRetries, |e| provider.exhausted(e)))
+}
@@ -1,22 +1,37 @@
 use axum::{
     Router,
-    http::Method,
-    middleware::{from_extractor, map_response},
-    routing::{delete, get, post},
+    extract::DefaultBodyLimit,
+    http::{HeaderName, HeaderValue, Method},
+    middleware::{from_extractor, from_fn, map_response},
+    routing::{delete, get, post, put},
 };
 use tower::ServiceBuilder;
-use tower_http::{compression::CompressionLayer, cors::CorsLayer};
+use tower_http::{
+    compression::CompressionLayer,
+    cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer},
+};
 
 use crate::{
     api::*,
     claude_code_state::ClaudeCodeState,
+    claude_vertex_state::ClaudeVertexState,
     claude_web_state::ClaudeWebState,
+    config::CLEWDR_CONFIG,
     gemini_state::GeminiState,
     middleware::{
-        RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth,
+        RequireAdminAuth, RequireBearerAuth, RequireQueryKeyAuth, RequireXApiKeyAuth, X_REQUEST_ID,
+        attach_request_id, chaos, check_params, check_token_scope,
         claude::{add_usage_info, apply_stop_sequences, check_overloaded, to_oai},
+        fit_context, keep_alive_non_stream, latency_budget, limit_adaptive, limit_body,
+        limit_per_client, record_usage, request_id, response_cache, resume_stream, route_script,
+        run_hooks, salvage_stream, sign_response, to_gemini_error, to_oai_error,
+    },
+    openai_state::OpenAIState,
+    services::{
+        audit, batch::BatchManager, chat_sweeper, cookie_actor::CookieActorHandle, cookie_keeper,
+        daily_report, gemini_endpoint, key_actor::KeyActorHandle, remote_config, shared_state,
+        token_actor::TokenActorHandle,
     },
-    services::{cookie_actor::CookieActorHandle, key_actor::KeyActorHandle},
 };
 
 /// RouterBuilder for the application
@@ -25,8 +40,13 @@ pub struct RouterBuilder {
     claude_code_state: ClaudeCodeState,
     cookie_actor_handle: CookieActorHandle,
     key_actor_handle: KeyActorHandle,
+    token_actor_handle: TokenActorHandle,
     gemini_state: GeminiState,
+    openai_state: OpenAIState,
+    batch_manager: BatchManager,
     inner: Router,
+    /// Admin API and dashboard, may be served on a separate listener
+    admin: Router,
 }
 
 impl RouterBuilder {
@@ -39,19 +59,32 @@ impl RouterBuilder {
         let cookie_handle = CookieActorHandle::start()
             .await
             .expect("Failed to start CookieActor");
+        cookie_keeper::spawn(cookie_handle.to_owned());
+        chat_sweeper::spawn(cookie_handle.to_owned());
+        daily_report::spawn();
+        gemini_endpoint::spawn();
+        remote_config::spawn();
+        let token_actor_handle = TokenActorHandle::start(cookie_handle.to_owned())
+            .await
+            .expect("Failed to start TokenActor");
         let claude_web_state = ClaudeWebState::new(cookie_handle.to_owned());
         let claude_code_state = ClaudeCodeState::new(cookie_handle.to_owned());
         let key_tx = KeyActorHandle::start()
             .await
             .expect("Failed to start KeyActorHandle");
+        shared_state::spawn_sync(key_tx.to_owned(), cookie_handle.to_owned());
         let gemini_state = GeminiState::new(key_tx.to_owned());
         RouterBuilder {
             claude_web_state,
             claude_code_state,
             cookie_actor_handle: cookie_handle,
             key_actor_handle: key_tx,
+            token_actor_handle,
             gemini_state,
+            openai_state: OpenAIState::new(),
+            batch_manager: BatchManager::new(),
             inner: Router::new(),
+            admin: Router::new(),
         }
     }
 
@@ -63,9 +96,19 @@ impl RouterBuilder {
             .route_admin_endpoints()
             .route_claude_web_oai_endpoints()
             .route_claude_code_oai_endpoints()
+            .route_claude_vertex_endpoints()
             .route_gemini_endpoints()
+            .route_openai_endpoints()
+            .route_batch_endpoints()
+            .route_health_endpoints()
             .setup_static_serving()
+            .with_stream_salvage()
+            .with_stream_resume()
+            .with_chaos()
+            .with_client_limit()
             .with_tower_trace()
+            .with_request_id()
+            .with_response_signing()
             .with_cors()
     }
 
@@ -73,16 +116,76 @@ impl RouterBuilder {
         let router_gemini = Router::new()
             .route("/v1/v1beta/{*path}", post(api_post_gemini))
             .route("/v1/vertex/v1beta/{*path}", post(api_post_gemini))
-            .layer(from_extractor::<RequireQueryKeyAuth>())
-            .layer(CompressionLayer::new())
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_gemini_error))
+                    .layer(from_extractor::<RequireQueryKeyAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(from_fn(run_hooks)),
+            )
             .with_state(self.gemini_state.to_owned());
         let router_oai = Router::new()
             .route("/gemini/chat/completions", post(api_post_gemini_oai))
             .route("/gemini/vertex/chat/completions", post(api_post_gemini_oai))
-            .layer(from_extractor::<RequireBearerAuth>())
-            .layer(CompressionLayer::new())
+            .route("/gemini/images/generations", post(api_post_gemini_images))
+            .route("/gemini/audio/speech", post(api_post_gemini_speech))
+            .route(
+                "/gemini/audio/transcriptions",
+                post(api_post_gemini_transcriptions),
+            )
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(from_fn(run_hooks)),
+            )
             .with_state(self.gemini_state.to_owned());
-        let router = router_gemini.merge(router_oai);
+        // upgrades carry no body and must not be compressed
+        let router_live = Router::new()
+            .route("/v1/ws/{service}", get(api_gemini_live))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_gemini_error))
+                    .layer(from_extractor::<RequireQueryKeyAuth>())
+                    .layer(from_fn(check_token_scope)),
+            )
+            .with_state(self.gemini_state.to_owned());
+        let router_realtime = Router::new()
+            .route("/gemini/realtime", get(api_oai_realtime))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(from_fn(check_token_scope)),
+            )
+            .with_state(self.gemini_state.to_owned());
+        let router = router_gemini
+            .merge(router_oai)
+            .merge(router_live)
+            .merge(router_realtime);
         self.inner = self.inner.merge(router);
         self
     }
@@ -94,10 +197,23 @@ impl RouterBuilder {
             .layer(
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireXApiKeyAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
                     .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
                     .layer(map_response(add_usage_info))
                     .layer(map_response(apply_stop_sequences))
-                    .layer(map_response(check_overloaded)),
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(run_hooks)),
             )
             .with_state(self.claude_web_state.to_owned().with_claude_format());
         self.inner = self.inner.merge(router);
@@ -111,9 +227,113 @@ impl RouterBuilder {
             .layer(
                 ServiceBuilder::new()
                     .layer(from_extractor::<RequireXApiKeyAuth>())
-                    .layer(CompressionLayer::new()),
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(from_fn(run_hooks)),
+            )
+            .with_state(self.claude_code_state.to_owned());
+        // batches must never be served from the response cache
+        let router_batch = Router::new()
+            .route(
+                "/code/v1/messages/batches",
+                post(api_claude_code_create_batch),
+            )
+            .route(
+                "/code/v1/messages/batches/{id}",
+                get(api_claude_code_get_batch),
+            )
+            .route(
+                "/code/v1/messages/batches/{id}/results",
+                get(api_claude_code_batch_results),
+            )
+            .route(
+                "/code/v1/messages/batches/{id}/cancel",
+                post(api_claude_code_cancel_batch),
+            )
+            .layer(
+                ServiceBuilder::new()
+                    .layer(from_extractor::<RequireXApiKeyAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(check_token_scope)),
             )
             .with_state(self.claude_code_state.to_owned());
+        self.inner = self.inner.merge(router).merge(router_batch);
+        self
+    }
+
+    /// Sets up unauthenticated liveness and readiness probes
+    fn route_health_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/healthz", get(api_healthz))
+            .route("/readyz", get(api_readyz))
+            .with_state(HealthState {
+                cookie_actor_handle: self.cookie_actor_handle.to_owned(),
+                key_actor_handle: self.key_actor_handle.to_owned(),
+            });
+        self.inner = self.inner.merge(router);
+        self
+    }
+
+    /// Sets up routes for the configured OpenAI compatible upstreams
+    fn route_openai_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/openai/chat/completions", post(api_openai_chat))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(from_fn(run_hooks)),
+            )
+            .with_state(self.openai_state.to_owned())
+            .route(
+                "/openai/models",
+                get(api_openai_models).layer(from_extractor::<RequireBearerAuth>()),
+            );
+        self.inner = self.inner.merge(router);
+        self
+    }
+
+    /// Sets up routes for OpenAI style batch endpoints
+    fn route_batch_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/v1/batches", post(api_create_batch).get(api_list_batches))
+            .route("/v1/batches/{id}", get(api_get_batch))
+            .route("/v1/batches/{id}/cancel", post(api_cancel_batch))
+            .route("/v1/batches/{id}/results", get(api_get_batch_results))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id)),
+            )
+            .with_state(self.batch_manager.to_owned());
         self.inner = self.inner.merge(router);
         self
     }
@@ -122,42 +342,79 @@ impl RouterBuilder {
     fn route_admin_endpoints(mut self) -> Self {
         let cookie_router = Router::new()
             .route("/cookies", get(api_get_cookies))
+            .route("/cookies/usage", get(api_get_cookie_usage))
             .route("/cookie", delete(api_delete_cookie).post(api_post_cookie))
+            .route("/admin/cookies/{id}/test", post(api_test_cookie))
             .with_state(self.cookie_actor_handle.to_owned());
         let key_router = Router::new()
             .route("/key", post(api_post_key).delete(api_delete_key))
             .route("/keys", get(api_get_keys))
+            .route("/admin/keys/{id}/test", post(api_test_key))
+            .route("/admin/keys/{id}/pin", post(api_pin_key))
+            .route("/admin/keys/cooldowns", post(api_cooldown_keys))
+            .route("/admin/keys/cooldowns/clear", post(api_clear_key_cooldowns))
+            .route("/admin/keys/order", put(api_reorder_keys))
             .with_state(self.key_actor_handle.to_owned());
+        let token_router = Router::new()
+            .route("/tokens", get(api_get_tokens))
+            .with_state(self.token_actor_handle.to_owned());
         let admin_router = Router::new()
             .route("/auth", get(api_auth))
-            .route("/config", get(api_get_config).put(api_post_config));
+            .route("/config", get(api_get_config).put(api_post_config))
+            .route("/admin/concurrency", get(api_get_concurrency))
+            .route("/admin/latency", get(api_get_latency))
+            .route("/admin/auth_log", get(api_get_auth_log))
+            .route("/audit/{id}", get(api_get_audit_entry))
+            .route("/audit/{id}/replay", post(api_replay_request))
+            .route(
+                "/log_filter",
+                get(api_get_log_filter).put(api_put_log_filter),
+            );
         let router = Router::new()
             .nest(
                 "/api",
                 cookie_router
                     .merge(key_router)
+                    .merge(token_router)
                     .merge(admin_router)
                     .layer(from_extractor::<RequireAdminAuth>()),
             )
             .route("/api/version", get(api_version));
-        self.inner = self.inner.merge(router);
+        self.admin = self.admin.merge(router);
         self
     }
 
     /// Sets up routes for OpenAI compatible endpoints
     fn route_claude_web_oai_endpoints(mut self) -> Self {
         let router = Router::new()
-            .route("/v1/chat/completions", post(api_claude_web))
+            .route("/v1/chat/completions", post(api_claude_oai))
             .route("/v1/models", get(api_get_models))
             .layer(
                 ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
                     .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
                     .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
                     .layer(map_response(to_oai))
                     .layer(map_response(apply_stop_sequences))
-                    .layer(map_response(check_overloaded)),
+                    .layer(map_response(check_overloaded))
+                    .layer(from_fn(run_hooks)),
             )
-            .with_state(self.claude_web_state.to_owned().with_openai_format());
+            .with_state(ClaudeOaiState {
+                web: self.claude_web_state.to_owned().with_openai_format(),
+                code: self.claude_code_state.to_owned(),
+            });
         self.inner = self.inner.merge(router);
         self
     }
@@ -169,48 +426,186 @@ impl RouterBuilder {
             .route("/code/v1/models", get(api_get_models))
             .layer(
                 ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
                     .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
                     .layer(CompressionLayer::new())
-                    .layer(map_response(to_oai)),
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(map_response(to_oai))
+                    .layer(from_fn(run_hooks)),
             )
             .with_state(self.claude_code_state.to_owned());
         self.inner = self.inner.merge(router);
         self
     }
 
-    /// Sets up static file serving
+    /// Sets up routes for Claude on Vertex AI, in both API formats
+    fn route_claude_vertex_endpoints(mut self) -> Self {
+        let router = Router::new()
+            .route("/vertex/v1/messages", post(api_claude_vertex))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(from_extractor::<RequireXApiKeyAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(from_fn(run_hooks)),
+            )
+            .with_state(ClaudeVertexState::new());
+        let router_oai = Router::new()
+            .route("/vertex/v1/chat/completions", post(api_claude_vertex))
+            .layer(
+                ServiceBuilder::new()
+                    .layer(map_response(to_oai_error))
+                    .layer(from_extractor::<RequireBearerAuth>())
+                    .layer(DefaultBodyLimit::disable())
+                    .layer(from_fn(limit_body))
+                    .layer(CompressionLayer::new())
+                    .layer(from_fn(attach_request_id))
+                    .layer(from_fn(route_script))
+                    .layer(from_fn(check_token_scope))
+                    .layer(from_fn(check_params))
+                    .layer(from_fn(fit_context))
+                    .layer(from_fn(keep_alive_non_stream))
+                    .layer(from_fn(record_usage))
+                    .layer(from_fn(response_cache))
+                    .layer(from_fn(latency_budget))
+                    .layer(from_fn(limit_adaptive))
+                    .layer(map_response(to_oai))
+                    .layer(from_fn(run_hooks)),
+            )
+            .with_state(ClaudeVertexState::new());
+        self.inner = self.inner.merge(router).merge(router_oai);
+        self
+    }
+
+    /// Sets up static file serving, the dashboard answers every path no
+    /// route matches
     fn setup_static_serving(mut self) -> Self {
-        #[cfg(feature = "embed-resource")]
-        {
-            use include_dir::{Dir, include_dir};
-            const INCLUDE_STATIC: Dir = include_dir!("$CARGO_MANIFEST_DIR/static");
-            self.inner = self
-                .inner
-                .fallback_service(tower_serve_static::ServeDir::new(&INCLUDE_STATIC));
-        }
-        #[cfg(feature = "external-resource")]
-        {
-            use const_format::formatc;
-            use tower_http::services::ServeDir;
-            self.inner = self.inner.fallback_service(ServeDir::new(formatc!(
-                "{}/static",
-                env!("CARGO_MANIFEST_DIR")
-            )));
-        }
+        self.admin = self.admin.fallback(serve_frontend);
         self
     }
 
-    /// Adds CORS support to the router
+    /// Adds CORS support to the router, the API routes follow the `cors`
+    /// config and the admin API is same-origin unless `cors.admin_origins`
+    /// lists other origins
     fn with_cors(mut self) -> Self {
+        let config = CLEWDR_CONFIG.load().cors.to_owned();
+        let credentials = config.allow_credentials;
+        let wildcard = |list: &[String]| list.iter().any(|v| v == "*");
+        let origins = |list: &[String]| {
+            list.iter()
+                .filter(|o| *o != "*")
+                .filter_map(|o| HeaderValue::from_str(o.trim_end_matches('/')).ok())
+                .collect::<Vec<_>>()
+        };
+        let origin = match wildcard(&config.allowed_origins) {
+            true if credentials => AllowOrigin::mirror_request(),
+            true => AllowOrigin::any(),
+            false => AllowOrigin::list(origins(&config.allowed_origins)),
+        };
+        let headers = match wildcard(&config.allowed_headers) {
+            true if credentials => AllowHeaders::mirror_request(),
+            true => AllowHeaders::any(),
+            false => AllowHeaders::list(
+                config
+                    .allowed_headers
+                    .iter()
+                    .filter_map(|h| HeaderName::from_bytes(h.as_bytes()).ok()),
+            ),
+        };
+        let methods = match wildcard(&config.allowed_methods) {
+            true if credentials => AllowMethods::mirror_request(),
+            true => AllowMethods::any(),
+            false => AllowMethods::list(
+                config
+                    .allowed_methods
+                    .iter()
+                    .filter_map(|m| Method::from_bytes(m.to_uppercase().as_bytes()).ok()),
+            ),
+        };
         let cors = CorsLayer::new()
-            .allow_origin(tower_http::cors::Any)
-            .allow_methods([Method::GET, Method::POST, Method::DELETE])
-            .allow_headers([
-                axum::http::header::AUTHORIZATION,
-                axum::http::header::CONTENT_TYPE,
-            ]);
-
+            .allow_origin(origin)
+            .allow_headers(headers)
+            .allow_methods(methods)
+            .allow_credentials(credentials)
+            .expose_headers([X_REQUEST_ID.to_owned()]);
         self.inner = self.inner.layer(cors);
+
+        let admin_origins = origins(&config.admin_origins);
+        if !admin_origins.is_empty() {
+            let admin_cors = CorsLayer::new()
+                .allow_origin(AllowOrigin::list(admin_origins))
+                .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+                .allow_headers([
+                    axum::http::header::AUTHORIZATION,
+                    axum::http::header::CONTENT_TYPE,
+                ])
+                .expose_headers([X_REQUEST_ID.to_owned()]);
+            self.admin = self.admin.layer(admin_cors);
+        }
+        self
+    }
+
+    /// Tags every request with a request ID, must wrap the trace layer
+    fn with_request_id(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(request_id));
+        self.admin = self.admin.layer(from_fn(request_id));
+        self
+    }
+
+    /// Signs API responses outside every layer that rewrites bodies, so the
+    /// signature covers what the client receives
+    fn with_response_signing(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(sign_response));
+        self
+    }
+
+    /// Ends streams cut off by upstream cleanly, inside the resumption layer
+    /// so resumed clients receive the closing events too
+    fn with_stream_salvage(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(salvage_stream));
+        self
+    }
+
+    /// Buffers streamed API responses so clients can resume them, inside the
+    /// chaos layer so truncated streams can be recovered
+    fn with_stream_resume(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(resume_stream));
+        self
+    }
+
+    /// Injects faults into API responses when `chaos.enabled` is set, inside
+    /// the trace layer so injected failures are traced like real ones
+    fn with_chaos(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(chaos));
+        self
+    }
+
+    /// Caps the requests a client address has in flight, outside the other
+    /// API middleware so rejected requests cost nothing
+    fn with_client_limit(mut self) -> Self {
+        self.inner = self.inner.layer(from_fn(limit_per_client));
         self
     }
 
@@ -219,13 +614,24 @@ impl RouterBuilder {
 
         let layer = TraceLayer::new_for_http();
 
-        self.inner = self.inner.layer(layer);
+        self.inner = self.inner.layer(layer.to_owned());
+        self.admin = self.admin.layer(layer);
         self
     }
 
     /// Returns the configured router
     /// Finalizes the router configuration for use with axum
     pub fn build(self) -> Router {
-        self.inner
+        self.batch_manager.attach(self.inner.to_owned());
+        audit::attach(self.inner.to_owned());
+        self.inner.merge(self.admin)
+    }
+
+    /// Returns the API router and the admin router separately
+    /// Used when the admin surface is bound to its own listener
+    pub fn build_split(self) -> (Router, Router) {
+        self.batch_manager.attach(self.inner.to_owned());
+        audit::attach(self.inner.to_owned());
+        (self.inner, self.admin)
     }
 }
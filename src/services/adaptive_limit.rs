@@ -0,0 +1,179 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::{config::Backend, config::CLEWDR_CONFIG, error::ClewdrError};
+
+/// 429s within this long of a decrease belong to the same burst and do not
+/// lower the limit again
+const BURST_WINDOW: Duration = Duration::from_secs(2);
+
+/// Concurrency limits of all backends
+pub static ADAPTIVE_LIMIT: LazyLock<AdaptiveLimit> = LazyLock::new(AdaptiveLimit::default);
+
+#[derive(Debug)]
+struct BackendLimit {
+    limit: f64,
+    in_flight: usize,
+    last_decrease: Option<Instant>,
+}
+
+/// Current limit of a backend, for the admin API
+#[derive(Debug, Serialize)]
+pub struct LimitStatus {
+    pub backend: Backend,
+    pub limit: usize,
+    pub in_flight: usize,
+}
+
+/// Additive increase, multiplicative decrease limiter of concurrent upstream
+/// requests per backend
+///
+/// Each success raises the limit by `1 / limit`, so by one per limit's worth
+/// of successes, and a 429 multiplies it by `backoff`. Per-key and per-cookie
+/// cooldowns still apply, this only smooths the load the pool sees as a whole.
+#[derive(Default)]
+pub struct AdaptiveLimit {
+    backends: Mutex<HashMap<Backend, BackendLimit>>,
+    released: Notify,
+}
+
+/// Slot of a backend, released when dropped
+pub struct AdaptivePermit {
+    backend: Backend,
+}
+
+impl AdaptivePermit {
+    /// Reports the upstream answer of the request holding the slot
+    pub fn record(&self, throttled: bool) {
+        ADAPTIVE_LIMIT.record(self.backend, throttled);
+    }
+}
+
+impl Drop for AdaptivePermit {
+    fn drop(&mut self) {
+        let mut backends = ADAPTIVE_LIMIT.lock();
+        if let Some(b) = backends.get_mut(&self.backend) {
+            b.in_flight = b.in_flight.saturating_sub(1);
+        }
+        drop(backends);
+        ADAPTIVE_LIMIT.released.notify_waiters();
+    }
+}
+
+impl AdaptiveLimit {
+    fn lock(&self) -> MutexGuard<'_, HashMap<Backend, BackendLimit>> {
+        self.backends.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes a slot if the backend is below its limit
+    fn try_take(&self, backend: Backend, max: usize, min: usize) -> bool {
+        let mut backends = self.lock();
+        let b = backends.entry(backend).or_insert(BackendLimit {
+            limit: max as f64,
+            in_flight: 0,
+            last_decrease: None,
+        });
+        let limit = (b.limit as usize).clamp(min, max);
+        if b.in_flight < limit {
+            b.in_flight += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Waits for a slot of the backend
+    ///
+    /// # Returns
+    /// `None` if adaptive concurrency is disabled, otherwise a permit to hold
+    /// until the response is sent
+    pub async fn acquire(
+        &'static self,
+        backend: Backend,
+    ) -> Result<Option<AdaptivePermit>, ClewdrError> {
+        let (max, min, timeout) = {
+            let config = CLEWDR_CONFIG.load();
+            let adaptive = &config.adaptive_concurrency;
+            if !adaptive.enabled {
+                return Ok(None);
+            }
+            let max = adaptive.max_concurrency.max(1);
+            (
+                max,
+                adaptive.min_concurrency.clamp(1, max),
+                config.queue_timeout,
+            )
+        };
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(timeout);
+        loop {
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+            if self.try_take(backend, max, min) {
+                return Ok(Some(AdaptivePermit { backend }));
+            }
+            if tokio::time::timeout_at(deadline, released).await.is_err() {
+                return Err(ClewdrError::QueueTimeout);
+            }
+        }
+    }
+
+    /// Raises the limit of the backend after a success, or lowers it after a
+    /// 429 unless it was lowered for the same burst already
+    fn record(&self, backend: Backend, throttled: bool) {
+        let (max, min, backoff) = {
+            let config = CLEWDR_CONFIG.load();
+            let adaptive = &config.adaptive_concurrency;
+            let max = adaptive.max_concurrency.max(1);
+            (
+                max as f64,
+                adaptive.min_concurrency.clamp(1, max) as f64,
+                adaptive.backoff.clamp(0.0, 1.0),
+            )
+        };
+        let mut backends = self.lock();
+        let Some(b) = backends.get_mut(&backend) else {
+            return;
+        };
+        if !throttled {
+            b.limit = (b.limit + 1.0 / b.limit.max(1.0)).min(max);
+            drop(backends);
+            // a grown limit may admit waiters
+            self.released.notify_waiters();
+            return;
+        }
+        let now = Instant::now();
+        if b.last_decrease.is_some_and(|t| now - t < BURST_WINDOW) {
+            return;
+        }
+        b.last_decrease = Some(now);
+        let before = b.limit;
+        b.limit = (b.limit * backoff).max(min);
+        warn!(
+            "Rate limited by {:?}, concurrency limit {} -> {}",
+            backend, before as usize, b.limit as usize
+        );
+    }
+
+    /// Limits of the backends that have served requests
+    pub fn status(&self) -> Vec<LimitStatus> {
+        let mut status = self
+            .lock()
+            .iter()
+            .map(|(&backend, b)| LimitStatus {
+                backend,
+                limit: b.limit as usize,
+                in_flight: b.in_flight,
+            })
+            .collect::<Vec<_>>();
+        status.sort_unstable_by_key(|s| s.backend as u8);
+        status
+    }
+}
@@ -0,0 +1,254 @@
+use std::{
+    io::Write,
+    sync::{Arc, LazyLock, OnceLock},
+};
+
+use axum::{Router, body::Body, extract::Request, response::Response};
+use http::{
+    HeaderName, HeaderValue, Method, Uri,
+    header::{AUTHORIZATION, CONNECTION, CONTENT_LENGTH, COOKIE, HOST, TRANSFER_ENCODING},
+    request::Parts,
+};
+use moka::sync::Cache;
+use serde::{Deserialize, Serialize};
+use snafu::ResultExt;
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use crate::{
+    ReplayArgs,
+    config::CLEWDR_CONFIG,
+    error::{ClewdrError, WreqSnafu},
+    middleware::{Caller, RequestId, X_REQUEST_ID},
+};
+
+/// Header on replayed responses naming the original request
+pub static X_REPLAY_OF: HeaderName = HeaderName::from_static("x-replay-of");
+
+/// Headers never stored, replays are sent as the recorded caller instead
+const DROPPED_HEADERS: [&str; 3] = ["x-api-key", "x-goog-api-key", "proxy-authorization"];
+
+/// Recent API requests by request ID, sized by `audit_log_size` at startup
+static AUDIT_LOG: LazyLock<Cache<String, Arc<AuditEntry>>> =
+    LazyLock::new(|| Cache::new(CLEWDR_CONFIG.load().audit_log_size));
+
+/// Router requests are replayed against, set once the router is built
+static REPLAY_ROUTER: OnceLock<Router> = OnceLock::new();
+
+/// A request as received, minus credentials
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub id: String,
+    pub time: i64,
+    pub method: String,
+    /// Path and query, with the `key` query parameter removed
+    pub uri: String,
+    /// The client authenticated with the `key` query parameter
+    pub query_key: bool,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// Who sent the request, replays are sent on their behalf
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caller: Option<Caller>,
+    /// Why the streamed response ended early, if it did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub truncated: Option<String>,
+}
+
+/// Removes `key` from a query string
+///
+/// # Returns
+/// The cleaned path and query, and whether a key was present
+fn strip_key(uri: &Uri) -> (String, bool) {
+    let Some(query) = uri.query() else {
+        return (uri.path().to_string(), false);
+    };
+    let (keys, rest): (Vec<&str>, Vec<&str>) = query
+        .split('&')
+        .partition(|p| *p == "key" || p.starts_with("key="));
+    if rest.is_empty() {
+        (uri.path().to_string(), !keys.is_empty())
+    } else {
+        (
+            format!("{}?{}", uri.path(), rest.join("&")),
+            !keys.is_empty(),
+        )
+    }
+}
+
+/// Whether requests are recorded
+pub fn enabled() -> bool {
+    CLEWDR_CONFIG.load().audit_log_size > 0
+}
+
+/// Whether a request with this ID is recorded, client supplied IDs that are
+/// must not be reused
+pub fn contains(id: &str) -> bool {
+    AUDIT_LOG.contains_key(id)
+}
+
+/// Records a buffered API request, bodies that are not UTF-8 are skipped
+pub fn record(parts: &Parts, body: &[u8]) {
+    let Some(RequestId(id)) = parts.extensions.get::<RequestId>() else {
+        return;
+    };
+    let Ok(body) = std::str::from_utf8(body) else {
+        return;
+    };
+    let (uri, query_key) = strip_key(&parts.uri);
+    let headers = parts
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            ![
+                AUTHORIZATION,
+                COOKIE,
+                HOST,
+                CONNECTION,
+                CONTENT_LENGTH,
+                TRANSFER_ENCODING,
+            ]
+            .contains(name)
+                && **name != X_REQUEST_ID
+                && !DROPPED_HEADERS.contains(&name.as_str())
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let entry = || AuditEntry {
+        id: id.to_owned(),
+        time: chrono::Utc::now().timestamp(),
+        method: parts.method.to_string(),
+        uri,
+        query_key,
+        headers,
+        body: body.to_string(),
+        caller: parts.extensions.get::<Caller>().cloned(),
+        truncated: None,
+    };
+    // never replaces the entry of another request that got the same ID
+    let recorded = AUDIT_LOG
+        .entry_by_ref(id)
+        .or_insert_with(|| Arc::new(entry()));
+    if !recorded.is_fresh() {
+        warn!(
+            "Request ID {} is already recorded, not recording it again",
+            id
+        );
+    }
+}
+
+/// Notes that the response to a recorded request was cut off
+pub fn mark_truncated(id: &str, reason: &str) {
+    if let Some(entry) = AUDIT_LOG.get(id) {
+        let mut entry = (*entry).to_owned();
+        entry.truncated = Some(reason.to_string());
+        AUDIT_LOG.insert(id.to_string(), Arc::new(entry));
+    }
+}
+
+/// Looks up a recorded request
+pub fn get(id: &str) -> Option<Arc<AuditEntry>> {
+    AUDIT_LOG.get(id)
+}
+
+/// Sets the router requests are replayed against
+pub fn attach(router: Router) {
+    if REPLAY_ROUTER.set(router).is_err() {
+        warn!("Replay router already attached");
+    }
+}
+
+/// Router requests are replayed against, also used to hand a request over to
+/// another route
+pub(crate) fn router() -> Option<Router> {
+    REPLAY_ROUTER.get().cloned()
+}
+
+/// Sends a recorded request through the API router again, with the current
+/// pool and on behalf of its original caller, whose scopes are checked again
+///
+/// # Arguments
+/// * `id` - Request ID of the recorded request
+/// * `path` - Send the request to this path instead, e.g. to try another
+///   backend with the same body
+pub async fn replay(id: &str, path: Option<&str>) -> Result<Response, ClewdrError> {
+    let Some(entry) = get(id) else {
+        return Err(ClewdrError::PathNotFound {
+            msg: format!("Request {id} is not in the audit log"),
+        });
+    };
+    let Some(router) = REPLAY_ROUTER.get() else {
+        return Err(ClewdrError::UnexpectedNone {
+            msg: "Replay router not attached",
+        });
+    };
+    let Some(ref caller) = entry.caller else {
+        return Err(ClewdrError::BadRequest {
+            msg: "Request was recorded without a caller and cannot be replayed",
+        });
+    };
+    let uri = path.unwrap_or(&entry.uri);
+    let method = Method::from_bytes(entry.method.as_bytes()).unwrap_or(Method::POST);
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .extension(caller.to_owned())
+        .body(Body::from(entry.body.to_owned()))
+        .map_err(|_| ClewdrError::BadRequest {
+            msg: "Invalid replay path",
+        })?;
+    let headers = req.headers_mut();
+    for (name, value) in &entry.headers {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            headers.append(name, value);
+        }
+    }
+    info!("Replaying request {} of {} to {}", id, caller.id, uri);
+    let Ok(mut resp) = router.to_owned().oneshot(req).await;
+    if let Ok(value) = HeaderValue::from_str(id) {
+        resp.headers_mut().insert(X_REPLAY_OF.to_owned(), value);
+    }
+    Ok(resp)
+}
+
+/// Replays a recorded request on a running instance through the admin API and
+/// streams the response to stdout, for `clewdr replay`
+///
+/// # Returns
+/// Whether the replayed request succeeded
+pub async fn run(args: ReplayArgs) -> Result<bool, ClewdrError> {
+    let url = format!(
+        "{}/api/audit/{}/replay",
+        args.remote.trim_end_matches('/'),
+        args.id
+    );
+    let mut res = wreq::Client::new()
+        .post(url)
+        .bearer_auth(&args.admin_password)
+        .json(&serde_json::json!({ "path": args.path }))
+        .send()
+        .await
+        .context(WreqSnafu {
+            msg: "Failed to reach the admin API",
+        })?;
+    let status = res.status();
+    let request_id = res
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+        .to_string();
+    eprintln!("{status}, request ID {request_id}");
+    let mut stdout = std::io::stdout();
+    while let Some(chunk) = res.chunk().await.context(WreqSnafu {
+        msg: "Failed to read the replayed response",
+    })? {
+        stdout.write_all(&chunk)?;
+        stdout.flush()?;
+    }
+    println!();
+    Ok(status.is_success())
+}
@@ -0,0 +1,349 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::IpAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use http::HeaderMap;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::config::AdminRole;
+
+/// Failed logins an address may make before it is locked out
+const FREE_FAILURES: u32 = 5;
+
+/// Lockout after the first failure past the free ones, doubled with each
+/// further failure
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+/// How long an address without new failures is remembered
+const FAILURE_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Failed logins from all addresses together that lock the admin API for
+/// wrong keys from everyone, against guessing from many addresses
+const GLOBAL_BUDGET: usize = 100;
+
+/// Window the global failure budget is counted in
+const GLOBAL_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// Auth events kept for the admin API
+const MAX_EVENTS: usize = 1000;
+
+/// Failed admin logins per address and the recent admin auth events
+pub static AUTH_GUARD: LazyLock<AuthGuard> = LazyLock::new(AuthGuard::default);
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Result of an admin auth attempt
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthOutcome {
+    Success,
+    /// Wrong or missing admin password
+    Failure,
+    /// A valid key without the role the request needs
+    Denied,
+    /// The address is locked out, or the key was wrong while the global
+    /// failure budget is used up
+    Locked,
+}
+
+/// Decision on an admin login
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Login {
+    Allowed,
+    /// Wrong key, counted against the address
+    Failed,
+    /// Turned away for the time left
+    Locked(Duration),
+}
+
+/// An admin auth attempt, for the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct AuthEvent {
+    pub time: DateTime<Utc>,
+    /// Client address, missing on the Unix socket without `X-Forwarded-For`
+    pub ip: Option<IpAddr>,
+    /// Role of the key, missing if it is no admin password
+    pub role: Option<AdminRole>,
+    pub method: String,
+    pub route: String,
+    pub outcome: AuthOutcome,
+}
+
+/// Address of the client behind a request, for the lockout
+///
+/// The peer address, unless the peer is a trusted proxy or the request came
+/// in over the Unix socket: then the nearest address in `X-Forwarded-For`
+/// that is no trusted proxy. `None` if there is no address at all, such
+/// requests only count against the global budget.
+///
+/// # Arguments
+/// * `peer` - Address of the connection, `None` on the Unix socket
+/// * `headers` - Request headers
+/// * `trusted` - `trusted_proxies` of the config
+pub fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpAddr]) -> Option<IpAddr> {
+    if let Some(peer) = peer
+        && !trusted.contains(&peer)
+    {
+        return Some(peer);
+    }
+    let hops = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    // proxies append, so the nearest hop is last and the rest may be forged
+    for hop in hops.into_iter().rev() {
+        match hop.parse::<IpAddr>() {
+            Ok(ip) if trusted.contains(&ip) => continue,
+            Ok(ip) => return Some(ip),
+            Err(_) => break,
+        }
+    }
+    peer
+}
+
+/// Locks out addresses guessing the admin password, with a lockout doubling
+/// on every failure, turns away wrong keys from everyone once all addresses
+/// together failed too often, and keeps a log of who used the admin routes
+#[derive(Default)]
+pub struct AuthGuard {
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+    /// Times of the recent failures of all addresses
+    global: Mutex<VecDeque<Instant>>,
+    events: Mutex<VecDeque<AuthEvent>>,
+}
+
+impl AuthGuard {
+    /// Decides a login with a key that is `valid` or not, and counts it
+    ///
+    /// A locked out address is turned away whatever the key. Once the global
+    /// budget is used up only wrong keys are, so guessing from many addresses
+    /// cannot lock the admin out.
+    pub fn login(&self, ip: Option<IpAddr>, valid: bool) -> Login {
+        if let Some(left) = self.locked(ip) {
+            return Login::Locked(left);
+        }
+        if valid {
+            self.succeed(ip);
+            return Login::Allowed;
+        }
+        self.fail(ip);
+        match self.globally_locked() {
+            Some(left) => Login::Locked(left),
+            None => Login::Failed,
+        }
+    }
+
+    /// Time left until the address may try again, if it is locked out
+    fn locked(&self, ip: Option<IpAddr>) -> Option<Duration> {
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        let until = failures.get(&ip?)?.locked_until?;
+        until.checked_duration_since(Instant::now())
+    }
+
+    /// Time left until wrong keys are checked again, if all addresses
+    /// together failed too often
+    fn globally_locked(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let mut global = self.global.lock().unwrap_or_else(|e| e.into_inner());
+        while global.front().is_some_and(|t| now - *t >= GLOBAL_WINDOW) {
+            global.pop_front();
+        }
+        if global.len() < GLOBAL_BUDGET {
+            return None;
+        }
+        let oldest = global.front()?;
+        Some(GLOBAL_WINDOW - (now - *oldest))
+    }
+
+    /// Counts a failed login, locking the address out once it has used up
+    /// its free failures
+    fn fail(&self, ip: Option<IpAddr>) {
+        let now = Instant::now();
+        {
+            let mut global = self.global.lock().unwrap_or_else(|e| e.into_inner());
+            global.push_back(now);
+            if global.len() == GLOBAL_BUDGET {
+                warn!(
+                    "Admin API locked for wrong keys after {} failed logins",
+                    GLOBAL_BUDGET
+                );
+            }
+            // never needs more than the budget to decide
+            while global.len() > GLOBAL_BUDGET {
+                global.pop_front();
+            }
+        }
+        let Some(ip) = ip else {
+            return;
+        };
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        failures.retain(|_, f| now - f.last < FAILURE_MEMORY);
+        let entry = failures.entry(ip).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        entry.count += 1;
+        entry.last = now;
+        if entry.count > FREE_FAILURES {
+            let exponent = (entry.count - FREE_FAILURES - 1).min(31);
+            let lockout = BASE_LOCKOUT.saturating_mul(1 << exponent).min(MAX_LOCKOUT);
+            warn!(
+                "{} locked out of the admin API for {}s after {} failed logins",
+                ip,
+                lockout.as_secs(),
+                entry.count
+            );
+            entry.locked_until = Some(now + lockout);
+        }
+    }
+
+    /// Forgets the failures of an address after a successful login
+    fn succeed(&self, ip: Option<IpAddr>) {
+        let Some(ip) = ip else {
+            return;
+        };
+        self.failures
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&ip);
+    }
+
+    pub fn record(&self, event: AuthEvent) {
+        let mut events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        if events.len() >= MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+    }
+
+    /// Recent admin auth events, newest first
+    pub fn events(&self) -> Vec<AuthEvent> {
+        let events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        events.iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_lockout_after_free_failures() {
+        let guard = AuthGuard::default();
+        let client = Some(ip("203.0.113.7"));
+        for _ in 0..FREE_FAILURES {
+            guard.fail(client);
+            assert!(guard.locked(client).is_none());
+        }
+        guard.fail(client);
+        assert!(guard.locked(client).is_some());
+        // other addresses are not affected
+        assert!(guard.locked(Some(ip("203.0.113.8"))).is_none());
+        guard.succeed(client);
+        assert!(guard.locked(client).is_none());
+    }
+
+    #[test]
+    fn test_lockout_doubles() {
+        let guard = AuthGuard::default();
+        let client = Some(ip("2001:db8::1"));
+        for _ in 0..FREE_FAILURES + 4 {
+            guard.fail(client);
+        }
+        // 1s, 2s, 4s, then 8s
+        let left = guard.locked(client).unwrap();
+        assert!(left > Duration::from_secs(4) && left <= Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_global_budget() {
+        let guard = AuthGuard::default();
+        for i in 0..GLOBAL_BUDGET {
+            guard.fail(Some(IpAddr::from([198, 51, (i / 256) as u8, i as u8])));
+        }
+        let Login::Locked(left) = guard.login(Some(ip("192.0.2.1")), false) else {
+            panic!("wrong key not locked out");
+        };
+        assert!(left <= GLOBAL_WINDOW);
+        // unknown addresses are locked out as well
+        assert!(matches!(guard.login(None, false), Login::Locked(_)));
+    }
+
+    #[test]
+    fn test_valid_key_passes_global_lock() {
+        let guard = AuthGuard::default();
+        for _ in 0..GLOBAL_BUDGET {
+            guard.fail(None);
+        }
+        assert_eq!(guard.login(Some(ip("192.0.2.1")), true), Login::Allowed);
+        assert_eq!(guard.login(None, true), Login::Allowed);
+        // a locked out address stays locked out
+        let client = Some(ip("203.0.113.7"));
+        for _ in 0..=FREE_FAILURES {
+            guard.fail(client);
+        }
+        assert!(matches!(guard.login(client, true), Login::Locked(_)));
+    }
+
+    #[test]
+    fn test_unix_socket_failures_count_globally() {
+        let guard = AuthGuard::default();
+        for _ in 0..GLOBAL_BUDGET - 1 {
+            guard.fail(None);
+        }
+        assert!(guard.globally_locked().is_none());
+        guard.fail(None);
+        assert!(guard.globally_locked().is_some());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let proxy = ip("10.0.0.1");
+        let trusted = [proxy];
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("1.2.3.4, 203.0.113.7, 10.0.0.1"),
+        );
+        // untrusted peers cannot pick their address
+        assert_eq!(
+            client_ip(Some(ip("192.0.2.9")), &headers, &trusted),
+            Some(ip("192.0.2.9"))
+        );
+        // the forged first hop is skipped
+        assert_eq!(
+            client_ip(Some(proxy), &headers, &trusted),
+            Some(ip("203.0.113.7"))
+        );
+        // the Unix socket is trusted
+        assert_eq!(client_ip(None, &headers, &[]), Some(ip("10.0.0.1")));
+        assert_eq!(client_ip(None, &HeaderMap::new(), &[]), None);
+        assert_eq!(
+            client_ip(Some(proxy), &HeaderMap::new(), &trusted),
+            Some(proxy)
+        );
+        headers.insert("x-forwarded-for", HeaderValue::from_static("garbage"));
+        assert_eq!(client_ip(Some(proxy), &headers, &trusted), Some(proxy));
+    }
+}
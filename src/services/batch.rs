@@ -0,0 +1,468 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, LazyLock, OnceLock},
+};
+
+use axum::{Router, body::Body, extract::Request};
+use futures::{StreamExt, stream};
+use http::{Method, header::CONTENT_TYPE};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use strum::Display;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, CONFIG_PATH, MAX_BATCH_REQUESTS},
+    error::ClewdrError,
+    middleware::{Caller, X_REQUEST_ID},
+    services::request_queue::{PRIORITY_HEADER, Priority},
+};
+
+/// Endpoints a batch request may target
+const BATCH_ENDPOINTS: [&str; 6] = [
+    "/v1/chat/completions",
+    "/v1/messages",
+    "/code/v1/chat/completions",
+    "/code/v1/messages",
+    "/gemini/chat/completions",
+    "/gemini/vertex/chat/completions",
+];
+
+/// Directory holding finished batches
+static BATCH_DIR: LazyLock<PathBuf> = LazyLock::new(|| {
+    CONFIG_PATH
+        .parent()
+        .map(|p| p.join("batches"))
+        .unwrap_or_else(|| PathBuf::from("batches"))
+});
+
+/// Lifecycle of a batch, named after the OpenAI batch statuses
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum BatchStatus {
+    InProgress,
+    Completed,
+    Cancelling,
+    Cancelled,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RequestCounts {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// OpenAI style batch object
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Batch {
+    pub id: String,
+    pub object: String,
+    pub endpoint: String,
+    pub status: BatchStatus,
+    pub created_at: i64,
+    #[serde(default)]
+    pub completed_at: Option<i64>,
+    #[serde(default)]
+    pub cancelled_at: Option<i64>,
+    pub request_counts: RequestCounts,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// A single line of the batch input
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchRequest {
+    pub custom_id: String,
+    #[serde(default = "default_batch_method")]
+    pub method: String,
+    /// Path of the endpoint, e.g. `/v1/chat/completions`
+    #[serde(default)]
+    pub url: Option<String>,
+    pub body: Value,
+}
+
+fn default_batch_method() -> String {
+    "POST".to_string()
+}
+
+/// Body of a batch creation request
+///
+/// Unlike OpenAI, requests are sent inline or uploaded along with the batch,
+/// instead of through a separate file upload
+#[derive(Debug, Deserialize)]
+pub struct CreateBatchParams {
+    /// Default endpoint for requests without `url`
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub requests: Vec<BatchRequest>,
+    #[serde(default)]
+    pub metadata: Option<HashMap<String, String>>,
+}
+
+/// A single line of the batch output
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchResult {
+    pub id: String,
+    pub custom_id: String,
+    pub response: Option<BatchResponse>,
+    pub error: Option<Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchResponse {
+    pub status_code: u16,
+    pub request_id: Option<String>,
+    pub body: Value,
+}
+
+/// A batch with its input and output, as persisted on disk
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct BatchRecord {
+    batch: Batch,
+    /// Who created the batch, its lines are sent on their behalf
+    #[serde(default)]
+    caller: Option<Caller>,
+    requests: Vec<BatchRequest>,
+    results: Vec<BatchResult>,
+}
+
+/// Queues batches and runs them through the API router
+///
+/// Requests are replayed against the router itself, so they go through the
+/// same scope checks, cookie and key dispatch as regular requests
+#[derive(Clone, Default)]
+pub struct BatchManager {
+    batches: Arc<RwLock<HashMap<String, BatchRecord>>>,
+    router: Arc<OnceLock<Router>>,
+}
+
+impl BatchManager {
+    /// Creates the manager, loading batches persisted by earlier runs
+    pub fn new() -> Self {
+        let manager = Self::default();
+        if CLEWDR_CONFIG.load().no_fs {
+            return manager;
+        }
+        let Ok(dir) = std::fs::read_dir(BATCH_DIR.as_path()) else {
+            return manager;
+        };
+        let batches = dir
+            .filter_map(|e| e.ok())
+            .filter_map(|e| std::fs::read(e.path()).ok())
+            .filter_map(|b| serde_json::from_slice::<BatchRecord>(&b).ok())
+            .map(|r| (r.batch.id.to_owned(), r))
+            .collect();
+        Self {
+            batches: Arc::new(RwLock::new(batches)),
+            ..manager
+        }
+    }
+
+    /// Sets the router batch requests are sent to, must be called once the
+    /// router is built
+    pub fn attach(&self, router: Router) {
+        if self.router.set(router).is_err() {
+            warn!("Batch router already attached");
+        }
+    }
+
+    /// Validates and queues a new batch, its lines are sent as `caller`
+    pub async fn create(
+        &self,
+        caller: Caller,
+        params: CreateBatchParams,
+    ) -> Result<Batch, ClewdrError> {
+        if params.requests.is_empty() {
+            return Err(ClewdrError::BadRequest {
+                msg: "Batch contains no requests",
+            });
+        }
+        if params.requests.len() > MAX_BATCH_REQUESTS {
+            return Err(ClewdrError::BadRequest {
+                msg: "Batch contains too many requests",
+            });
+        }
+        let mut requests = params.requests;
+        for req in requests.iter_mut() {
+            let url = req
+                .url
+                .get_or_insert_with(|| params.endpoint.to_owned().unwrap_or_default());
+            if !BATCH_ENDPOINTS.contains(&url.as_str()) {
+                return Err(ClewdrError::BadRequest {
+                    msg: "Unsupported batch endpoint",
+                });
+            }
+            if !req.method.eq_ignore_ascii_case("POST") {
+                return Err(ClewdrError::BadRequest {
+                    msg: "Batch requests must use POST",
+                });
+            }
+            // results are collected as a whole, streaming makes no sense
+            if let Some(body) = req.body.as_object_mut() {
+                body.insert("stream".into(), json!(false));
+            }
+        }
+        let batch = Batch {
+            id: format!("batch_{}", uuid::Uuid::new_v4().simple()),
+            object: "batch".to_string(),
+            endpoint: params
+                .endpoint
+                .or_else(|| requests.first().and_then(|r| r.url.to_owned()))
+                .unwrap_or_default(),
+            status: BatchStatus::InProgress,
+            created_at: chrono::Utc::now().timestamp(),
+            completed_at: None,
+            cancelled_at: None,
+            request_counts: RequestCounts {
+                total: requests.len(),
+                ..Default::default()
+            },
+            metadata: params.metadata,
+        };
+        let record = BatchRecord {
+            batch: batch.to_owned(),
+            caller: Some(caller),
+            requests,
+            results: Vec::new(),
+        };
+        self.batches
+            .write()
+            .await
+            .insert(batch.id.to_owned(), record);
+        info!(
+            "Batch {} queued with {} requests",
+            batch.id, batch.request_counts.total
+        );
+        let this = self.to_owned();
+        let id = batch.id.to_owned();
+        tokio::spawn(async move { this.run(id).await });
+        Ok(batch)
+    }
+
+    /// Gets a batch of the caller by ID
+    pub async fn get(&self, caller: &Caller, id: &str) -> Result<Batch, ClewdrError> {
+        self.batches
+            .read()
+            .await
+            .get(id)
+            .filter(|r| r.owned_by(caller))
+            .map(|r| r.batch.to_owned())
+            .ok_or_else(|| not_found(id))
+    }
+
+    /// Lists the batches of the caller, newest first
+    pub async fn list(&self, caller: &Caller) -> Vec<Batch> {
+        let mut batches = self
+            .batches
+            .read()
+            .await
+            .values()
+            .filter(|r| r.owned_by(caller))
+            .map(|r| r.batch.to_owned())
+            .collect::<Vec<_>>();
+        batches.sort_by_key(|b| std::cmp::Reverse(b.created_at));
+        batches
+    }
+
+    /// Gets the results of a batch of the caller collected so far
+    pub async fn results(
+        &self,
+        caller: &Caller,
+        id: &str,
+    ) -> Result<Vec<BatchResult>, ClewdrError> {
+        self.batches
+            .read()
+            .await
+            .get(id)
+            .filter(|r| r.owned_by(caller))
+            .map(|r| r.results.to_owned())
+            .ok_or_else(|| not_found(id))
+    }
+
+    /// Cancels a batch of the caller, requests already sent still finish
+    pub async fn cancel(&self, caller: &Caller, id: &str) -> Result<Batch, ClewdrError> {
+        let mut batches = self.batches.write().await;
+        let record = batches
+            .get_mut(id)
+            .filter(|r| r.owned_by(caller))
+            .ok_or_else(|| not_found(id))?;
+        if record.batch.status == BatchStatus::InProgress {
+            record.batch.status = BatchStatus::Cancelling;
+        }
+        Ok(record.batch.to_owned())
+    }
+
+    /// Executes the requests of a batch with limited concurrency
+    async fn run(&self, id: String) {
+        let Some(router) = self.router.get().cloned() else {
+            error!("Batch router not attached");
+            return;
+        };
+        let (caller, requests) = match self.batches.read().await.get(&id) {
+            Some(BatchRecord {
+                caller: Some(caller),
+                requests,
+                ..
+            }) => (caller.to_owned(), requests.to_owned()),
+            _ => return,
+        };
+        let concurrency = CLEWDR_CONFIG.load().batch_concurrency.max(1);
+        let id = id.as_str();
+        stream::iter(requests)
+            .map(|req| {
+                let router = router.to_owned();
+                let caller = caller.to_owned();
+                async move {
+                    if self.is_cancelling(id).await {
+                        return BatchResult::error(&req, "batch_cancelled", "Batch was cancelled");
+                    }
+                    execute(router, caller, req).await
+                }
+            })
+            .buffer_unordered(concurrency)
+            .for_each(|result| self.record(id, result))
+            .await;
+        self.finish(id).await;
+    }
+
+    async fn is_cancelling(&self, id: &str) -> bool {
+        self.batches
+            .read()
+            .await
+            .get(id)
+            .is_some_and(|r| r.batch.status == BatchStatus::Cancelling)
+    }
+
+    /// Stores the result of a single request
+    async fn record(&self, id: &str, result: BatchResult) {
+        let mut batches = self.batches.write().await;
+        let Some(record) = batches.get_mut(id) else {
+            return;
+        };
+        let counts = &mut record.batch.request_counts;
+        if result
+            .response
+            .as_ref()
+            .is_some_and(|r| (200..300).contains(&r.status_code))
+        {
+            counts.completed += 1;
+        } else {
+            counts.failed += 1;
+        }
+        record.results.push(result);
+    }
+
+    /// Marks a batch as done and persists it
+    async fn finish(&self, id: &str) {
+        let record = {
+            let mut batches = self.batches.write().await;
+            let Some(record) = batches.get_mut(id) else {
+                return;
+            };
+            let now = chrono::Utc::now().timestamp();
+            if record.batch.status == BatchStatus::Cancelling {
+                record.batch.status = BatchStatus::Cancelled;
+                record.batch.cancelled_at = Some(now);
+            } else {
+                record.batch.status = BatchStatus::Completed;
+                record.batch.completed_at = Some(now);
+            }
+            record.to_owned()
+        };
+        let counts = &record.batch.request_counts;
+        info!(
+            "Batch {} {}: {} completed, {} failed",
+            id, record.batch.status, counts.completed, counts.failed
+        );
+        if let Err(e) = persist(&record).await {
+            error!("Failed to persist batch {}: {}", id, e);
+        }
+    }
+}
+
+impl BatchRecord {
+    /// Whether the batch belongs to the caller, batches persisted before
+    /// batches had owners belong to the password
+    fn owned_by(&self, caller: &Caller) -> bool {
+        match self.caller {
+            Some(ref owner) => owner.id == caller.id,
+            None => caller.scopes.is_none(),
+        }
+    }
+}
+
+impl BatchResult {
+    fn error(req: &BatchRequest, code: &str, message: impl Into<String>) -> Self {
+        Self {
+            id: format!("batch_req_{}", uuid::Uuid::new_v4().simple()),
+            custom_id: req.custom_id.to_owned(),
+            response: None,
+            error: Some(json!({ "code": code, "message": message.into() })),
+        }
+    }
+}
+
+fn not_found(id: &str) -> ClewdrError {
+    ClewdrError::PathNotFound {
+        msg: format!("Batch not found: {id}"),
+    }
+}
+
+/// Sends a single batch request through the router on behalf of the batch
+/// creator, whose token is checked again for every line
+async fn execute(router: Router, caller: Caller, req: BatchRequest) -> BatchResult {
+    let body = match serde_json::to_vec(&req.body) {
+        Ok(body) => body,
+        Err(e) => return BatchResult::error(&req, "invalid_body", e.to_string()),
+    };
+    let http_req = Request::builder()
+        .method(Method::POST)
+        .uri(req.url.as_deref().unwrap_or_default())
+        .header(CONTENT_TYPE, "application/json")
+        .header(PRIORITY_HEADER, Priority::Batch.to_string())
+        .extension(caller)
+        .body(Body::from(body));
+    let http_req = match http_req {
+        Ok(r) => r,
+        Err(e) => return BatchResult::error(&req, "invalid_request", e.to_string()),
+    };
+    let Ok(resp) = router.oneshot(http_req).await;
+    let status_code = resp.status().as_u16();
+    let request_id = resp
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .map(ToOwned::to_owned);
+    let bytes = match axum::body::to_bytes(resp.into_body(), usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => return BatchResult::error(&req, "read_error", e.to_string()),
+    };
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    BatchResult {
+        id: format!("batch_req_{}", uuid::Uuid::new_v4().simple()),
+        custom_id: req.custom_id,
+        response: Some(BatchResponse {
+            status_code,
+            request_id,
+            body,
+        }),
+        error: None,
+    }
+}
+
+/// Writes a finished batch to the batch directory
+async fn persist(record: &BatchRecord) -> Result<(), ClewdrError> {
+    if CLEWDR_CONFIG.load().no_fs {
+        return Ok(());
+    }
+    tokio::fs::create_dir_all(BATCH_DIR.as_path()).await?;
+    let path = BATCH_DIR.join(format!("{}.json", record.batch.id));
+    tokio::fs::write(path, serde_json::to_vec(record)?).await?;
+    Ok(())
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::{
+    claude_web_state::ClaudeWebState,
+    config::{CLEWDR_CONFIG, ChatCleanup},
+    services::{cookie_actor::CookieActorHandle, mock},
+};
+
+/// How often accounts are swept for expired conversations
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Spawns the task deleting conversations older than the `chat_cleanup` TTL
+/// on every valid cookie's account
+pub fn spawn(handle: CookieActorHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let ChatCleanup::Ttl(_) = CLEWDR_CONFIG.load().cleanup_policy() else {
+                continue;
+            };
+            if mock::enabled() {
+                continue;
+            }
+            let cookies = match handle.get_status().await {
+                Ok(status) => status.valid,
+                // the actor is gone
+                Err(_) => break,
+            };
+            for cookie in cookies {
+                let ellipse = cookie.cookie.ellipse();
+                let mut state = ClaudeWebState::new(handle.to_owned());
+                let res = match state.use_cookie(cookie) {
+                    Ok(()) => state.bootstrap().await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = res.and(state.sweep_chats().await) {
+                    warn!("Failed to sweep chats of cookie {}: {}", ellipse, e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,235 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        Arc, LazyLock, Mutex,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::{config::CLEWDR_CONFIG, streaming::resume::StreamBuffer};
+
+/// How often the janitor looks for stale entries
+const JANITOR_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Global registry of long-lived client connections
+pub static CONNECTION_REGISTRY: LazyLock<ConnectionRegistry> =
+    LazyLock::new(ConnectionRegistry::default);
+
+struct Entry {
+    label: String,
+    cancel: watch::Sender<bool>,
+    registered: Instant,
+}
+
+/// Counters of the registry, for the status endpoint
+#[derive(Debug, Serialize)]
+pub struct ConnectionStats {
+    pub active: usize,
+    /// Most connections open at once since startup
+    pub peak: usize,
+    /// Connections registered since startup
+    pub total: u64,
+    /// Connections closed by the janitor for exceeding the max age
+    pub evicted: u64,
+    /// Client addresses with requests in flight
+    pub clients: usize,
+    pub resumable_streams: usize,
+}
+
+/// Tracks realtime connections so they can be torn down together, e.g. on
+/// shutdown, and resumable streams so a reconnecting client finds its stream
+#[derive(Default)]
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    peak: AtomicUsize,
+    evicted: AtomicU64,
+    entries: Mutex<HashMap<u64, Entry>>,
+    in_flight: Mutex<HashMap<IpAddr, usize>>,
+    streams: Mutex<HashMap<String, Arc<StreamBuffer>>>,
+}
+
+/// Registration of a single connection, removed from the registry when dropped
+pub struct ConnectionHandle {
+    pub id: u64,
+    registry: &'static ConnectionRegistry,
+    cancel: watch::Receiver<bool>,
+}
+
+impl ConnectionHandle {
+    /// Resolves once the connection is cancelled through the registry
+    pub async fn cancelled(&mut self) {
+        // the sender lives in the registry until this handle is dropped
+        let _ = self.cancel.wait_for(|c| *c).await;
+    }
+}
+
+impl Drop for ConnectionHandle {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.id);
+    }
+}
+
+/// In-flight request of a client address, released when dropped
+pub struct RequestSlot {
+    registry: &'static ConnectionRegistry,
+    ip: IpAddr,
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        let mut clients = self.registry.clients();
+        if let Some(count) = clients.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                clients.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl ConnectionRegistry {
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a new connection
+    ///
+    /// # Arguments
+    /// * `label` - What the connection is for, used in logs
+    pub fn register(&'static self, label: impl Into<String>) -> ConnectionHandle {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = watch::channel(false);
+        let mut entries = self.lock();
+        entries.insert(
+            id,
+            Entry {
+                label: label.into(),
+                cancel: tx,
+                registered: Instant::now(),
+            },
+        );
+        self.peak.fetch_max(entries.len(), Ordering::Relaxed);
+        drop(entries);
+        ConnectionHandle {
+            id,
+            registry: self,
+            cancel: rx,
+        }
+    }
+
+    fn streams(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<StreamBuffer>>> {
+        self.streams.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Makes a streamed response resumable under its request ID
+    pub fn track_stream(&self, request_id: &str, buffer: Arc<StreamBuffer>) {
+        self.streams().insert(request_id.to_string(), buffer);
+    }
+
+    /// Looks up the stream a reconnecting client asks for
+    pub fn find_stream(&self, request_id: &str) -> Option<Arc<StreamBuffer>> {
+        self.streams().get(request_id).cloned()
+    }
+
+    /// Stops tracking a stream, unless the ID was reused by a newer one
+    pub fn forget_stream(&self, request_id: &str, buffer: &Arc<StreamBuffer>) {
+        let mut streams = self.streams();
+        if streams
+            .get(request_id)
+            .is_some_and(|b| Arc::ptr_eq(b, buffer))
+        {
+            streams.remove(request_id);
+        }
+    }
+
+    fn clients(&self) -> std::sync::MutexGuard<'_, HashMap<IpAddr, usize>> {
+        self.in_flight.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Takes one of the `limit` request slots of a client address
+    ///
+    /// # Returns
+    /// `None` when the client already has `limit` requests in flight
+    pub fn acquire_request(&'static self, ip: IpAddr, limit: usize) -> Option<RequestSlot> {
+        let mut clients = self.clients();
+        let count = clients.entry(ip).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(RequestSlot { registry: self, ip })
+    }
+
+    /// Current counters
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            active: self.lock().len(),
+            peak: self.peak.load(Ordering::Relaxed),
+            total: self.next_id.load(Ordering::Relaxed),
+            evicted: self.evicted.load(Ordering::Relaxed),
+            clients: self.clients().len(),
+            resumable_streams: self.streams().len(),
+        }
+    }
+
+    /// Removes entries that outlived `max_age` or whose handle is gone
+    /// without having unregistered, cancelling them first
+    ///
+    /// # Returns
+    /// Number of removed entries
+    pub fn cleanup(&self, max_age: Option<Duration>) -> usize {
+        let mut entries = self.lock();
+        let before = entries.len();
+        entries.retain(|id, entry| {
+            let expired = max_age.is_some_and(|age| entry.registered.elapsed() > age);
+            let orphaned = entry.cancel.is_closed();
+            if expired || orphaned {
+                warn!(
+                    "Evicting connection {} ({}), open for {}s",
+                    id,
+                    entry.label,
+                    entry.registered.elapsed().as_secs()
+                );
+                entry.cancel.send_replace(true);
+            }
+            !expired && !orphaned
+        });
+        let removed = before - entries.len();
+        self.evicted.fetch_add(removed as u64, Ordering::Relaxed);
+        removed
+    }
+
+    /// Starts the task evicting stale entries every minute, with the
+    /// `connection_max_age_secs` of the moment
+    pub fn spawn_janitor(&'static self) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(JANITOR_INTERVAL);
+            loop {
+                interval.tick().await;
+                let max_age = match CLEWDR_CONFIG.load().connection_max_age_secs {
+                    0 => None,
+                    secs => Some(Duration::from_secs(secs)),
+                };
+                self.cleanup(max_age);
+            }
+        });
+    }
+
+    /// Cancels every open connection
+    pub fn cancel_all(&self) {
+        let entries = self.lock();
+        if !entries.is_empty() {
+            info!("Closing {} long-lived connection(s)", entries.len());
+        }
+        for (id, entry) in entries.iter() {
+            debug!("Cancelling connection {} ({})", id, entry.label);
+            entry.cancel.send_replace(true);
+        }
+    }
+}
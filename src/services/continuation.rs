@@ -0,0 +1,198 @@
+use axum::{body::Body, response::Response};
+use http::{HeaderValue, header::CONTENT_LENGTH};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError, streaming::StreamDialect};
+
+/// Header counting the continuation requests a response was stitched from
+const X_CLEWDR_CONTINUATIONS: &str = "x-clewdr-continuations";
+
+/// Key of the turns in the request body and role of the model's turns
+fn turns(dialect: StreamDialect) -> (&'static str, &'static str) {
+    match dialect {
+        StreamDialect::Gemini => ("contents", "model"),
+        _ => ("messages", "assistant"),
+    }
+}
+
+/// Text of a Claude message or content, or of Gemini parts, without thoughts
+fn text_of(blocks: &Value) -> String {
+    if let Some(text) = blocks.as_str() {
+        return text.to_string();
+    }
+    blocks
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|b| b["thought"] != true)
+        .filter_map(|b| b["text"].as_str())
+        .collect()
+}
+
+/// Content of the response that can be continued
+fn content_mut(msg: &mut Value, dialect: StreamDialect) -> Option<&mut Vec<Value>> {
+    match dialect {
+        StreamDialect::Gemini => msg["candidates"][0]["content"]["parts"].as_array_mut(),
+        _ => msg["content"].as_array_mut(),
+    }
+}
+
+/// Text of a completion cut off by the output limit
+///
+/// # Returns
+/// `None` if the completion ended otherwise, or holds more than text, e.g.
+/// tool calls, which a prefilled turn cannot carry on
+fn truncated(msg: &Value, body: &Value, dialect: StreamDialect) -> Option<String> {
+    let content = match dialect {
+        StreamDialect::Gemini => {
+            let candidates = msg["candidates"].as_array()?;
+            if candidates.len() != 1 || candidates[0]["finishReason"] != "MAX_TOKENS" {
+                return None;
+            }
+            &candidates[0]["content"]["parts"]
+        }
+        _ => {
+            // Claude does not accept a prefill with extended thinking
+            let thinking = !body["thinking"].is_null() && body["thinking"]["type"] != "disabled";
+            if msg["stop_reason"] != "max_tokens" || thinking {
+                return None;
+            }
+            &msg["content"]
+        }
+    };
+    let blocks = content.as_array()?;
+    let text_only = blocks.iter().all(|b| match dialect {
+        StreamDialect::Gemini => b["text"].is_string(),
+        _ => b["type"] == "text",
+    });
+    let text = text_of(content);
+    (text_only && !text.trim().is_empty()).then_some(text)
+}
+
+/// Request body with the text so far as the model's turn, merged into the
+/// client's own prefill if the conversation already ends with one
+fn with_prefill(body: &Value, text: &str, dialect: StreamDialect) -> Value {
+    let (key, role) = turns(dialect);
+    let mut body = body.to_owned();
+    let Some(turns) = body[key].as_array_mut() else {
+        return body;
+    };
+    let mut prefill = String::new();
+    if turns.last().is_some_and(|t| t["role"] == role)
+        && let Some(last) = turns.pop()
+    {
+        prefill = text_of(match dialect {
+            StreamDialect::Gemini => &last["parts"],
+            _ => &last["content"],
+        });
+    }
+    // Claude rejects a prefill ending in whitespace
+    prefill = (prefill + text).trim_end().to_string();
+    turns.push(match dialect {
+        StreamDialect::Gemini => json!({ "role": role, "parts": [{ "text": prefill }] }),
+        _ => json!({ "role": role, "content": prefill }),
+    });
+    body
+}
+
+/// Appends the text of a continuation to the last text of the completion,
+/// adding up output tokens and taking over how the continuation ended
+fn stitch(msg: &mut Value, next: &Value, dialect: StreamDialect) {
+    let next_text = match dialect {
+        StreamDialect::Gemini => text_of(&next["candidates"][0]["content"]["parts"]),
+        _ => text_of(&next["content"]),
+    };
+    if let Some(last) =
+        content_mut(msg, dialect).and_then(|c| c.iter_mut().rev().find(|b| b["text"].is_string()))
+    {
+        let text = last["text"].as_str().unwrap_or_default().trim_end();
+        last["text"] = json!(format!("{text}{next_text}"));
+    }
+    let add = |total: &mut Value, more: &Value| {
+        *total = json!(total.as_u64().unwrap_or_default() + more.as_u64().unwrap_or_default());
+    };
+    match dialect {
+        StreamDialect::Gemini => {
+            msg["candidates"][0]["finishReason"] = next["candidates"][0]["finishReason"].to_owned();
+            let more = &next["usageMetadata"]["candidatesTokenCount"];
+            add(&mut msg["usageMetadata"]["candidatesTokenCount"], more);
+            add(&mut msg["usageMetadata"]["totalTokenCount"], more);
+        }
+        _ => {
+            msg["stop_reason"] = next["stop_reason"].to_owned();
+            msg["stop_sequence"] = next["stop_sequence"].to_owned();
+            add(
+                &mut msg["usage"]["output_tokens"],
+                &next["usage"]["output_tokens"],
+            );
+        }
+    }
+}
+
+/// Continues a non-streaming completion cut off by the output limit, up to
+/// `max_continuations` times, and returns the parts as one response
+///
+/// Each continuation resends the request with the text so far as the model's
+/// turn. A failed continuation ends the loop, the client still gets the text
+/// received until then.
+///
+/// # Arguments
+/// * `res` - Response to the original request
+/// * `body` - Original request body, in the upstream format of `dialect`
+/// * `send` - Sends a continuation request
+pub async fn complete<F, Fut>(
+    res: Response,
+    body: &Value,
+    dialect: StreamDialect,
+    mut send: F,
+) -> Result<Response, ClewdrError>
+where
+    F: FnMut(Value) -> Fut,
+    Fut: Future<Output = Result<Response, ClewdrError>>,
+{
+    let max = CLEWDR_CONFIG.load().max_continuations;
+    if max == 0 || !res.status().is_success() {
+        return Ok(res);
+    }
+    let (mut parts, res_body) = res.into_parts();
+    let bytes = axum::body::to_bytes(res_body, usize::MAX).await?;
+    let Ok(mut msg) = serde_json::from_slice::<Value>(&bytes) else {
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    };
+    let mut count = 0;
+    while count < max {
+        let Some(text) = truncated(&msg, body, dialect) else {
+            break;
+        };
+        count += 1;
+        info!("[CONTINUE] output limit reached, continuation {count}/{max}");
+        let next = match send(with_prefill(body, &text, dialect)).await {
+            Ok(next) if next.status().is_success() => next,
+            Ok(next) => {
+                warn!("Continuation failed with {}", next.status());
+                break;
+            }
+            Err(e) => {
+                warn!("Continuation failed: {}", e);
+                break;
+            }
+        };
+        let bytes = axum::body::to_bytes(next.into_body(), usize::MAX).await?;
+        let Ok(next) = serde_json::from_slice::<Value>(&bytes) else {
+            break;
+        };
+        stitch(&mut msg, &next, dialect);
+    }
+    if count == 0 {
+        return Ok(Response::from_parts(parts, Body::from(bytes)));
+    }
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(X_CLEWDR_CONTINUATIONS, HeaderValue::from(count));
+    Ok(Response::from_parts(
+        parts,
+        Body::from(serde_json::to_vec(&msg)?),
+    ))
+}
@@ -1,18 +1,123 @@
-use std::collections::{HashSet, VecDeque};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::ErrorKind,
+    path::PathBuf,
+    sync::LazyLock,
+    time::Duration,
+};
 
 use moka::sync::Cache;
 use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
 use serde::Serialize;
 use snafu::{GenerateImplicitData, Location};
-use tracing::{error, info, warn};
+use tracing::{Span, error, info, warn};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, CookieStatus, Reason, UselessCookie},
+    config::{
+        CLEWDR_CONFIG, CONFIG_PATH, ClaudeQuotaConfig, ClewdrConfig, ClewdrCookie, CookieDispatch,
+        CookieStatus, CookieUsage, Reason, UselessCookie,
+    },
     error::ClewdrError,
+    services::{daily_report, shared_state},
 };
 
 const INTERVAL: u64 = 300;
 
+/// How long config writes are held back, so bursts of changes are written once
+const SAVE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often changed usage counters are written, they change with every request
+const USAGE_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// File holding the usage counters of cookies, keyed by an ID that does not
+/// reveal the cookie
+static USAGE_PATH: LazyLock<PathBuf> = LazyLock::new(|| {
+    CONFIG_PATH
+        .parent()
+        .map(|p| p.join("cookie_usage.json"))
+        .unwrap_or_else(|| PathBuf::from("cookie_usage.json"))
+});
+
+/// Reads the persisted usage counters, keyed by [`shared_state::id`] of the
+/// cookie
+pub async fn load_usage() -> HashMap<String, CookieUsage> {
+    if CLEWDR_CONFIG.load().no_fs {
+        return HashMap::new();
+    }
+    match tokio::fs::read(USAGE_PATH.as_path()).await {
+        Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+            warn!("Failed to parse cookie usage: {}", e);
+            HashMap::new()
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => HashMap::new(),
+        Err(e) => {
+            warn!("Failed to read cookie usage: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+/// Writes usage counters through a temporary file, so a crash never leaves a
+/// partial file
+pub async fn save_usage(usage: &HashMap<String, impl Serialize>) -> Result<(), ClewdrError> {
+    let tmp = USAGE_PATH.with_extension("json.tmp");
+    tokio::fs::write(&tmp, serde_json::to_vec(usage)?).await?;
+    tokio::fs::rename(&tmp, USAGE_PATH.as_path()).await?;
+    Ok(())
+}
+
+/// Usage analytics of a single cookie
+#[derive(Debug, Serialize, Clone)]
+pub struct CookieUsageInfo {
+    pub cookie: String,
+    pub state: &'static str,
+    /// Start of the current 5-hour window
+    pub window_start: Option<i64>,
+    pub window_requests: u32,
+    /// Estimated requests left in the window, if a limit is configured
+    pub window_remaining: Option<u32>,
+    pub week_requests: u32,
+    /// Estimated requests left in the week, if a limit is configured
+    pub week_remaining: Option<u32>,
+    pub total_requests: u64,
+    /// Projected reset of the current quota window
+    pub window_reset_at: Option<i64>,
+    /// Projected reset of the weekly cap
+    pub week_reset_at: Option<i64>,
+    /// Time the cookie becomes usable again after a rate limit
+    pub reset_time: Option<i64>,
+    pub last_used: Option<i64>,
+    pub last_429: Option<i64>,
+}
+
+impl CookieUsageInfo {
+    fn new(
+        cookie: &CookieStatus,
+        state: &'static str,
+        now: i64,
+        quota: &ClaudeQuotaConfig,
+    ) -> Self {
+        let window_reset_at = cookie.usage.window_reset_at(now);
+        let week_reset_at = cookie.usage.week_reset_at(now);
+        let (window_remaining, week_remaining) = cookie.usage.remaining(now, quota);
+        Self {
+            cookie: cookie.cookie.ellipse(),
+            state,
+            window_start: window_reset_at.and(cookie.usage.window_start),
+            window_requests: window_reset_at.map_or(0, |_| cookie.usage.window_requests),
+            window_remaining,
+            week_requests: week_reset_at.map_or(0, |_| cookie.usage.week_requests),
+            week_remaining,
+            total_requests: cookie.usage.total_requests,
+            window_reset_at,
+            week_reset_at,
+            reset_time: cookie.reset_time,
+            last_used: cookie.usage.last_used,
+            last_429: cookie.usage.last_429,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct CookieStatusInfo {
     pub valid: Vec<CookieStatus>,
@@ -23,18 +128,39 @@ pub struct CookieStatusInfo {
 /// Messages that the CookieActor can handle
 #[derive(Debug)]
 enum CookieActorMessage {
-    /// Return a Cookie
-    Return(CookieStatus, Option<Reason>),
+    /// Return a Cookie, with the span of the request that used it
+    Return(CookieStatus, Option<Reason>, Span),
     /// Submit a new Cookie
     Submit(CookieStatus),
     /// Check for timed out Cookies
     CheckReset,
-    /// Request to get a Cookie
-    Request(Option<u64>, RpcReplyPort<Result<CookieStatus, ClewdrError>>),
+    /// Request to get a Cookie, with the span of the request asking for it
+    Request(
+        Option<u64>,
+        Span,
+        RpcReplyPort<Result<CookieStatus, ClewdrError>>,
+    ),
+    /// Request a specific Cookie, e.g. the one that owns a batch
+    Lookup(
+        ClewdrCookie,
+        RpcReplyPort<Result<CookieStatus, ClewdrError>>,
+    ),
     /// Get all Cookie status information
     GetStatus(RpcReplyPort<CookieStatusInfo>),
+    /// Get usage analytics of all usable Cookies
+    GetUsage(RpcReplyPort<Vec<CookieUsageInfo>>),
+    /// Get the valid Cookies whose OAuth token expires before the timestamp
+    ExpiringTokens(i64, RpcReplyPort<Vec<CookieStatus>>),
+    /// Take the valid Cookies neither used nor checked for this many seconds,
+    /// marking them checked
+    TakeIdle(u64, RpcReplyPort<Vec<CookieStatus>>),
+    /// Move the valid Cookies other instances found rate limited out of
+    /// rotation until the timestamp
+    SyncCooldowns(Vec<(ClewdrCookie, i64)>),
     /// Delete a Cookie
     Delete(CookieStatus, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Write pending changes to the config file
+    Flush,
 }
 
 /// CookieActor state - manages collections of cookies
@@ -44,14 +170,21 @@ struct CookieActorState {
     exhausted: HashSet<CookieStatus>,
     invalid: HashSet<UselessCookie>,
     moka: Cache<u64, CookieStatus>,
+    /// Cookies changed since the config file was last written
+    dirty: bool,
+    /// Usage counters changed since the usage file was last written
+    usage_dirty: bool,
+    /// A flush is already on its way
+    flush_scheduled: bool,
 }
 
 /// Cookie actor that handles cookie distribution, collection, and status tracking using Ractor
 struct CookieActor;
 
 impl CookieActor {
-    /// Saves the current state of cookies to the configuration
-    fn save(state: &CookieActorState) {
+    /// Saves the current state of cookies to the configuration, the file is
+    /// written by the next [`CookieActorMessage::Flush`]
+    fn save(state: &mut CookieActorState) {
         CLEWDR_CONFIG.rcu(|config| {
             let mut config = ClewdrConfig::clone(config);
             config.cookie_array = state
@@ -63,14 +196,46 @@ impl CookieActor {
             config.wasted_cookie = state.invalid.clone();
             config
         });
+        state.dirty = true;
+    }
 
-        tokio::spawn(async move {
-            let result = CLEWDR_CONFIG.load().save().await;
-            match result {
-                Ok(_) => info!("Configuration saved successfully"),
-                Err(e) => error!("Save task panicked: {}", e),
+    /// Writes the configuration to disk if cookies changed since the last
+    /// write, the actor is the only writer of cookie changes so writes never
+    /// interleave
+    async fn flush(state: &mut CookieActorState) {
+        if state.usage_dirty {
+            state.usage_dirty = false;
+            if let Err(e) = Self::write_usage(state).await {
+                error!("Failed to save cookie usage: {}", e);
+                state.usage_dirty = true;
             }
-        });
+        }
+        if !state.dirty {
+            return;
+        }
+        state.dirty = false;
+        match ClewdrConfig::save().await {
+            Ok(_) => info!("Configuration saved successfully"),
+            Err(e) => {
+                error!("Failed to save configuration: {}", e);
+                // retried with the next change
+                state.dirty = true;
+            }
+        }
+    }
+
+    /// Writes the usage counters of all usable cookies
+    async fn write_usage(state: &CookieActorState) -> Result<(), ClewdrError> {
+        if CLEWDR_CONFIG.load().no_fs {
+            return Ok(());
+        }
+        let usage = state
+            .valid
+            .iter()
+            .chain(state.exhausted.iter())
+            .map(|c| (shared_state::id(&c.cookie.to_string()), &c.usage))
+            .collect::<HashMap<_, _>>();
+        save_usage(&usage).await
     }
 
     /// Logs the current state of cookie collections
@@ -104,23 +269,51 @@ impl CookieActor {
     }
 
     /// Dispatches a cookie for use
+    ///
+    /// Skips cookies at a configured window or weekly limit, then picks by
+    /// `claude_quota.dispatch`, falling back to round-robin order between equals
     fn dispatch(
         state: &mut CookieActorState,
         hash: Option<u64>,
     ) -> Result<CookieStatus, ClewdrError> {
         Self::reset(state);
+        let now = chrono::Utc::now().timestamp();
+        let config = CLEWDR_CONFIG.load();
+        let quota = &config.claude_quota;
         if let Some(hash) = hash
             && let Some(cookie) = state.moka.get(&hash)
-            && let Some(cookie) = state.valid.iter().find(|&c| c == &cookie)
+            && let Some(cookie) = state.valid.iter_mut().find(|c| **c == cookie)
+            && cookie.usage.within_quota(now, quota)
         {
+            cookie.usage.record_use(now);
+            state.usage_dirty = true;
             // renew moka cache
             state.moka.insert(hash, cookie.clone());
             return Ok(cookie.clone());
         }
-        let cookie = state
+        let candidates = state
+            .valid
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.usage.within_quota(now, quota));
+        let best = match quota.dispatch {
+            CookieDispatch::Headroom => {
+                candidates.min_by_key(|(_, c)| u32::MAX - c.usage.headroom(now))
+            }
+            CookieDispatch::Spread => candidates.min_by(|(_, a), (_, b)| {
+                a.usage
+                    .utilization(now, quota)
+                    .total_cmp(&b.usage.utilization(now, quota))
+            }),
+        }
+        .map(|(i, _)| i)
+        .ok_or(ClewdrError::NoCookieAvailable)?;
+        let mut cookie = state
             .valid
-            .pop_front()
+            .remove(best)
             .ok_or(ClewdrError::NoCookieAvailable)?;
+        cookie.usage.record_use(now);
+        state.usage_dirty = true;
         state.valid.push_back(cookie.clone());
         if let Some(hash) = hash {
             state.moka.insert(hash, cookie.clone());
@@ -128,6 +321,60 @@ impl CookieActor {
         Ok(cookie)
     }
 
+    /// Valid cookies neither used nor checked for `secs`, marked as checked
+    fn take_idle(state: &mut CookieActorState, secs: u64) -> Vec<CookieStatus> {
+        let now = chrono::Utc::now().timestamp();
+        let idle = state
+            .valid
+            .iter_mut()
+            .filter(|c| {
+                let seen = c.usage.last_used.max(c.usage.last_checked);
+                seen.is_none_or(|t| now - t >= secs as i64)
+            })
+            .map(|c| {
+                c.usage.last_checked = Some(now);
+                c.to_owned()
+            })
+            .collect::<Vec<_>>();
+        state.usage_dirty |= !idle.is_empty();
+        idle
+    }
+
+    /// Moves valid cookies rate limited elsewhere to the exhausted set
+    fn sync_cooldowns(state: &mut CookieActorState, cooldowns: Vec<(ClewdrCookie, i64)>) {
+        let mut moved = false;
+        for (cookie, until) in cooldowns {
+            let Some(pos) = state.valid.iter().position(|c| c.cookie == cookie) else {
+                continue;
+            };
+            let Some(mut cookie) = state.valid.remove(pos) else {
+                continue;
+            };
+            cookie.reset_time = Some(until);
+            state.exhausted.insert(cookie);
+            moved = true;
+        }
+        if moved {
+            Self::log(state);
+            Self::save(state);
+        }
+    }
+
+    /// Finds a cookie that is still usable, exhausted ones included since
+    /// quota only limits new messages
+    fn lookup(
+        state: &CookieActorState,
+        cookie: &ClewdrCookie,
+    ) -> Result<CookieStatus, ClewdrError> {
+        state
+            .valid
+            .iter()
+            .chain(state.exhausted.iter())
+            .find(|c| c.cookie == *cookie)
+            .cloned()
+            .ok_or(ClewdrError::NoCookieAvailable)
+    }
+
     /// Collects a returned cookie and processes it based on the return reason
     fn collect(state: &mut CookieActorState, mut cookie: CookieStatus, reason: Option<Reason>) {
         let Some(reason) = reason else {
@@ -135,11 +382,17 @@ impl CookieActor {
             if cookie.token.is_some()
                 && let Some(c) = state.valid.iter_mut().find(|c| **c == cookie)
             {
+                // usage is tracked by the actor, the returned copy may be stale
+                cookie.usage = c.usage.to_owned();
                 *c = cookie;
                 Self::save(state);
             }
             return;
         };
+        // keep the latest usage record of the cookie
+        if let Some(c) = state.valid.iter().find(|c| **c == cookie) {
+            cookie.usage = c.usage.to_owned();
+        }
         let mut find_remove = |cookie: &CookieStatus| {
             state.valid.retain(|c| c != cookie);
         };
@@ -148,13 +401,17 @@ impl CookieActor {
                 return;
             }
             Reason::TooManyRequest(i) => {
+                shared_state::publish_cookie_cooldown(&cookie.cookie, i);
                 find_remove(&cookie);
                 cookie.reset_time = Some(i);
+                cookie.usage.last_429 = Some(chrono::Utc::now().timestamp());
+                state.usage_dirty = true;
                 if !state.exhausted.insert(cookie) {
                     return;
                 }
             }
             Reason::Restricted(i) => {
+                shared_state::publish_cookie_cooldown(&cookie.cookie, i);
                 find_remove(&cookie);
                 cookie.reset_time = Some(i);
                 if !state.exhausted.insert(cookie) {
@@ -169,6 +426,7 @@ impl CookieActor {
                 {
                     return;
                 }
+                daily_report::record_cookie_lost();
             }
             _ => {
                 find_remove(&cookie);
@@ -178,6 +436,7 @@ impl CookieActor {
                 {
                     return;
                 }
+                daily_report::record_cookie_lost();
             }
         }
         Self::save(state);
@@ -210,6 +469,23 @@ impl CookieActor {
         }
     }
 
+    /// Creates a usage report of all valid and exhausted cookies
+    fn usage_report(state: &CookieActorState) -> Vec<CookieUsageInfo> {
+        let now = chrono::Utc::now().timestamp();
+        let quota = &CLEWDR_CONFIG.load().claude_quota;
+        state
+            .valid
+            .iter()
+            .map(|c| CookieUsageInfo::new(c, "valid", now, quota))
+            .chain(
+                state
+                    .exhausted
+                    .iter()
+                    .map(|c| CookieUsageInfo::new(c, "exhausted", now, quota)),
+            )
+            .collect()
+    }
+
     /// Deletes a cookie from all collections
     fn delete(state: &mut CookieActorState, cookie: CookieStatus) -> Result<(), ClewdrError> {
         let mut found = false;
@@ -242,13 +518,21 @@ impl Actor for CookieActor {
         _myself: ActorRef<Self::Msg>,
         _arguments: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
+        let usage = load_usage().await;
+        let with_usage = |mut cookie: CookieStatus| {
+            if let Some(u) = usage.get(&shared_state::id(&cookie.cookie.to_string())) {
+                cookie.usage = u.to_owned();
+            }
+            cookie
+        };
         let valid = VecDeque::from_iter(
             CLEWDR_CONFIG
                 .load()
                 .cookie_array
                 .iter()
                 .filter(|c| c.reset_time.is_none())
-                .cloned(),
+                .cloned()
+                .map(with_usage),
         );
         let exhausted = HashSet::from_iter(
             CLEWDR_CONFIG
@@ -256,13 +540,16 @@ impl Actor for CookieActor {
                 .cookie_array
                 .iter()
                 .filter(|c| c.reset_time.is_some())
-                .cloned(),
+                .cloned()
+                .map(with_usage),
         );
         let invalid = HashSet::from_iter(CLEWDR_CONFIG.load().wasted_cookie.iter().cloned());
 
         let moka = Cache::builder()
             .max_capacity(1000)
-            .time_to_idle(std::time::Duration::from_secs(60 * 60))
+            .time_to_idle(std::time::Duration::from_secs(
+                CLEWDR_CONFIG.load().sticky_session_ttl_secs,
+            ))
             .build();
 
         let state = CookieActorState {
@@ -270,6 +557,9 @@ impl Actor for CookieActor {
             exhausted,
             invalid,
             moka,
+            dirty: false,
+            usage_dirty: false,
+            flush_scheduled: false,
         };
 
         CookieActor::log(&state);
@@ -278,13 +568,17 @@ impl Actor for CookieActor {
 
     async fn handle(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match message {
-            CookieActorMessage::Return(cookie, reason) => {
-                Self::collect(state, cookie, reason);
+            CookieActorMessage::Flush => {
+                state.flush_scheduled = false;
+                Self::flush(state).await;
+            }
+            CookieActorMessage::Return(cookie, reason, span) => {
+                span.in_scope(|| Self::collect(state, cookie, reason));
             }
             CookieActorMessage::Submit(cookie) => {
                 Self::accept(state, cookie);
@@ -292,19 +586,53 @@ impl Actor for CookieActor {
             CookieActorMessage::CheckReset => {
                 Self::reset(state);
             }
-            CookieActorMessage::Request(cache_hash, reply_port) => {
-                let result = Self::dispatch(state, cache_hash);
+            CookieActorMessage::Request(cache_hash, span, reply_port) => {
+                let result = span.in_scope(|| Self::dispatch(state, cache_hash));
                 reply_port.send(result)?;
             }
+            CookieActorMessage::Lookup(cookie, reply_port) => {
+                reply_port.send(Self::lookup(state, &cookie))?;
+            }
             CookieActorMessage::GetStatus(reply_port) => {
                 let status_info = Self::report(state);
                 reply_port.send(status_info)?;
             }
+            CookieActorMessage::GetUsage(reply_port) => {
+                reply_port.send(Self::usage_report(state))?;
+            }
+            CookieActorMessage::ExpiringTokens(before, reply_port) => {
+                let expiring = state
+                    .valid
+                    .iter()
+                    .filter(|c| {
+                        c.token
+                            .as_ref()
+                            .is_some_and(|t| t.expires_at.timestamp() < before)
+                    })
+                    .cloned()
+                    .collect();
+                reply_port.send(expiring)?;
+            }
+            CookieActorMessage::TakeIdle(secs, reply_port) => {
+                reply_port.send(Self::take_idle(state, secs))?;
+            }
+            CookieActorMessage::SyncCooldowns(cooldowns) => {
+                Self::sync_cooldowns(state, cooldowns);
+            }
             CookieActorMessage::Delete(cookie, reply_port) => {
                 let result = Self::delete(state, cookie);
                 reply_port.send(result)?;
             }
         }
+        if (state.dirty || state.usage_dirty) && !state.flush_scheduled {
+            state.flush_scheduled = true;
+            let delay = if state.dirty {
+                SAVE_DEBOUNCE
+            } else {
+                USAGE_SAVE_INTERVAL
+            };
+            myself.send_after(delay, || CookieActorMessage::Flush);
+        }
         Ok(())
     }
 
@@ -314,6 +642,8 @@ impl Actor for CookieActor {
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         CookieActor::save(state);
+        state.usage_dirty = true;
+        CookieActor::flush(state).await;
         Ok(())
     }
 }
@@ -354,10 +684,24 @@ impl CookieActorHandle {
 
     /// Request a cookie from the cookie actor
     pub async fn request(&self, cache_hash: Option<u64>) -> Result<CookieStatus, ClewdrError> {
-        ractor::call!(self.actor_ref, CookieActorMessage::Request, cache_hash).map_err(|e| {
+        ractor::call!(
+            self.actor_ref,
+            CookieActorMessage::Request,
+            cache_hash,
+            Span::current()
+        )
+        .map_err(|e| ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with CookieActor for request operation: {e}"),
+        })?
+    }
+
+    /// Request a specific cookie from the cookie actor
+    pub async fn lookup(&self, cookie: ClewdrCookie) -> Result<CookieStatus, ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::Lookup, cookie).map_err(|e| {
             ClewdrError::RactorError {
                 loc: Location::generate(),
-                msg: format!("Failed to communicate with CookieActor for request operation: {e}"),
+                msg: format!("Failed to communicate with CookieActor for lookup operation: {e}"),
             }
         })?
     }
@@ -368,14 +712,24 @@ impl CookieActorHandle {
         cookie: CookieStatus,
         reason: Option<Reason>,
     ) -> Result<(), ClewdrError> {
-        ractor::cast!(self.actor_ref, CookieActorMessage::Return(cookie, reason)).map_err(|e| {
-            ClewdrError::RactorError {
-                loc: Location::generate(),
-                msg: format!("Failed to communicate with CookieActor for return operation: {e}"),
-            }
+        ractor::cast!(
+            self.actor_ref,
+            CookieActorMessage::Return(cookie, reason, Span::current())
+        )
+        .map_err(|e| ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with CookieActor for return operation: {e}"),
         })
     }
 
+    /// Take the cookies other instances found rate limited out of rotation
+    pub fn sync_cooldowns(&self, cooldowns: Vec<(ClewdrCookie, i64)>) {
+        if let Err(e) = ractor::cast!(self.actor_ref, CookieActorMessage::SyncCooldowns(cooldowns))
+        {
+            error!("Failed to communicate with CookieActor for sync operation: {e}");
+        }
+    }
+
     /// Submit a new cookie to the cookie actor
     pub async fn submit(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
         ractor::cast!(self.actor_ref, CookieActorMessage::Submit(cookie)).map_err(|e| {
@@ -398,6 +752,38 @@ impl CookieActorHandle {
         })
     }
 
+    /// Get the valid cookies whose OAuth token expires before `before`
+    pub async fn expiring_tokens(&self, before: i64) -> Result<Vec<CookieStatus>, ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::ExpiringTokens, before).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!(
+                    "Failed to communicate with CookieActor for expiring tokens operation: {e}"
+                ),
+            }
+        })
+    }
+
+    /// Take the valid cookies idle for `secs`, marking them checked
+    pub async fn take_idle(&self, secs: u64) -> Result<Vec<CookieStatus>, ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::TakeIdle, secs).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with CookieActor for take idle operation: {e}"),
+            }
+        })
+    }
+
+    /// Get usage analytics of all usable cookies
+    pub async fn get_usage(&self) -> Result<Vec<CookieUsageInfo>, ClewdrError> {
+        ractor::call!(self.actor_ref, CookieActorMessage::GetUsage).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with CookieActor for get usage operation: {e}"),
+            }
+        })
+    }
+
     /// Delete a cookie from the cookie actor
     pub async fn delete_cookie(&self, cookie: CookieStatus) -> Result<(), ClewdrError> {
         ractor::call!(self.actor_ref, CookieActorMessage::Delete, cookie).map_err(|e| {
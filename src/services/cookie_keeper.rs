@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::{
+    claude_web_state::ClaudeWebState,
+    config::{CLEWDR_CONFIG, CookieStatus},
+    error::ClewdrError,
+    services::{cookie_actor::CookieActorHandle, mock},
+};
+
+/// How often idle cookies are looked for
+const KEEPER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Spawns the task keeping idle cookies warm, see `cookie_keepalive_secs`
+///
+/// Each idle cookie is bootstrapped like a request would, which refreshes
+/// its session and runs the usual account checks. Cookies found expired,
+/// banned or restricted are returned with the reason, moving them out of the
+/// valid list; network errors leave the cookie alone.
+pub fn spawn(handle: CookieActorHandle) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KEEPER_INTERVAL);
+        loop {
+            interval.tick().await;
+            let secs = CLEWDR_CONFIG.load().cookie_keepalive_secs;
+            if secs == 0 || mock::enabled() {
+                continue;
+            }
+            let cookies = match handle.take_idle(secs).await {
+                Ok(cookies) => cookies,
+                // the actor is gone
+                Err(_) => break,
+            };
+            for cookie in cookies {
+                check(&handle, cookie).await;
+            }
+        }
+    });
+}
+
+/// Bootstraps a single cookie, returning it with the reason if it is unusable
+async fn check(handle: &CookieActorHandle, cookie: CookieStatus) {
+    let mut state = ClaudeWebState::new(handle.to_owned());
+    let ellipse = cookie.cookie.ellipse();
+    let res = match state.use_cookie(cookie) {
+        Ok(()) => state.bootstrap().await,
+        Err(e) => Err(e),
+    };
+    match res {
+        Ok(()) => info!("[KEEPALIVE] cookie {} is alive", ellipse),
+        Err(ClewdrError::InvalidCookie { reason }) => {
+            warn!("[KEEPALIVE] cookie {} is unusable: {}", ellipse, reason);
+            state.return_cookie(Some(reason)).await;
+        }
+        Err(e) => warn!("[KEEPALIVE] failed to check cookie {}: {}", ellipse, e),
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::{
+    LazyLock,
+    atomic::{AtomicBool, Ordering},
+};
+
+use tokio::sync::watch;
+
+/// Set by `--daemon`, every other function is a no-op without it
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Flipped when the service manager asks the process to stop
+static STOP: LazyLock<watch::Sender<bool>> = LazyLock::new(|| watch::Sender::new(false));
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn request_stop() {
+    STOP.send_replace(true);
+}
+
+/// Hooks the process into the service manager
+///
+/// On Linux SIGTERM triggers a graceful shutdown, readiness and the watchdog
+/// are reported through `sd_notify`. On Windows the process registers as a
+/// service and answers Stop and Shutdown controls.
+pub fn start() {
+    ENABLED.store(true, Ordering::Relaxed);
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                term.recv().await;
+                tracing::info!("SIGTERM received, shutting down");
+                request_stop();
+            }
+            Err(e) => tracing::warn!("Failed to install SIGTERM handler: {}", e),
+        }
+    });
+    #[cfg(windows)]
+    windows::start();
+}
+
+/// Reports that the listeners are bound and requests can be served
+pub fn notify_ready() {
+    if !enabled() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    systemd::ready();
+    #[cfg(windows)]
+    windows::set_state(windows_service::service::ServiceState::Running);
+}
+
+/// Reports that the servers are draining
+pub fn notify_stopping() {
+    if !enabled() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    systemd::notify(sd_notify::NotifyState::Stopping);
+    #[cfg(windows)]
+    windows::set_state(windows_service::service::ServiceState::StopPending);
+}
+
+/// Reports that the servers are done, the process exits right after
+pub fn notify_stopped() {
+    // systemd tracks the process itself, only Windows needs the final state
+    #[cfg(windows)]
+    if enabled() {
+        windows::set_state(windows_service::service::ServiceState::Stopped);
+    }
+}
+
+/// Resolves when the service manager asks the process to stop, never
+/// without `--daemon`
+pub async fn stop_requested() {
+    if !enabled() {
+        return std::future::pending().await;
+    }
+    let mut rx = STOP.subscribe();
+    let _ = rx.wait_for(|stop| *stop).await;
+}
+
+#[cfg(target_os = "linux")]
+mod systemd {
+    use std::time::Duration;
+
+    use sd_notify::NotifyState;
+    use tracing::{info, warn};
+
+    pub fn notify(state: NotifyState) {
+        if let Err(e) = sd_notify::notify(false, &[state]) {
+            warn!("sd_notify failed: {}", e);
+        }
+    }
+
+    /// Sends READY=1 and starts pinging the watchdog if systemd set
+    /// `WatchdogSec`
+    pub fn ready() {
+        notify(NotifyState::Ready);
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return;
+        }
+        // ping twice per timeout so a single late tick is not fatal
+        let period = Duration::from_micros(usec / 2);
+        info!("systemd watchdog enabled, pinging every {:?}", period);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                notify(NotifyState::Watchdog);
+            }
+        });
+    }
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::OsString,
+        sync::{Mutex, OnceLock},
+        time::Duration,
+    };
+
+    use tracing::error;
+    use windows_service::{
+        define_windows_service,
+        service::{
+            ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+            ServiceType,
+        },
+        service_control_handler::{self, ServiceControlHandlerResult, ServiceStatusHandle},
+        service_dispatcher,
+    };
+
+    const SERVICE_NAME: &str = "clewdr";
+
+    static STATUS: OnceLock<ServiceStatusHandle> = OnceLock::new();
+    static STOPPED: OnceLock<std::sync::mpsc::SyncSender<()>> = OnceLock::new();
+    /// Last state reported, the dispatcher may register after the server
+    /// already became ready
+    static STATE: Mutex<ServiceState> = Mutex::new(ServiceState::StartPending);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Runs the service dispatcher on its own thread, it blocks until the
+    /// service is stopped
+    pub fn start() {
+        std::thread::spawn(|| {
+            if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+                error!("Failed to start the service dispatcher: {}", e);
+            }
+        });
+    }
+
+    fn service_main(_args: Vec<OsString>) {
+        let handler = |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                super::request_stop();
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let handle = match service_control_handler::register(SERVICE_NAME, handler) {
+            Ok(handle) => handle,
+            Err(e) => {
+                error!("Failed to register the service control handler: {}", e);
+                return;
+            }
+        };
+        let _ = STATUS.set(handle);
+        let (tx, rx) = std::sync::mpsc::sync_channel(1);
+        let _ = STOPPED.set(tx);
+        let state = *STATE.lock().unwrap_or_else(|e| e.into_inner());
+        set_state(state);
+        // returning from service_main tells the manager the service is gone
+        let _ = rx.recv();
+    }
+
+    pub fn set_state(state: ServiceState) {
+        *STATE.lock().unwrap_or_else(|e| e.into_inner()) = state;
+        let Some(handle) = STATUS.get() else {
+            return;
+        };
+        let controls_accepted = match state {
+            ServiceState::Running => ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+            _ => ServiceControlAccept::empty(),
+        };
+        let status = ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(0),
+            checkpoint: 0,
+            wait_hint: Duration::from_secs(10),
+            process_id: None,
+        };
+        if let Err(e) = handle.set_service_status(status) {
+            error!("Failed to report the service status: {}", e);
+        }
+        if state == ServiceState::Stopped
+            && let Some(tx) = STOPPED.get()
+        {
+            let _ = tx.send(());
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use chrono::{Local, NaiveDate};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::{Backend, CLEWDR_CONFIG, LOG_DIR};
+
+/// Models listed in a report, by request count
+const TOP_MODELS: usize = 10;
+
+/// How often the day is checked for a rollover
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Usage of the current day, taken when the report is written
+static STATS: LazyLock<Mutex<DailyStats>> = LazyLock::new(|| {
+    Mutex::new(DailyStats {
+        day: Local::now().date_naive(),
+        ..Default::default()
+    })
+});
+
+/// Usage accumulated since the start of the day
+#[derive(Debug, Default)]
+struct DailyStats {
+    day: NaiveDate,
+    requests: HashMap<Backend, u64>,
+    tokens: u64,
+    errors: HashMap<&'static str, u64>,
+    models: HashMap<String, u64>,
+    keys_lost: u64,
+    cookies_lost: u64,
+}
+
+/// Summary of a day of traffic, written to the log directory
+#[derive(Debug, Serialize)]
+pub struct DailyReport {
+    pub day: NaiveDate,
+    pub requests: u64,
+    pub requests_by_backend: HashMap<Backend, u64>,
+    /// Total tokens reported by Gemini plus estimated Claude prompt tokens
+    pub tokens: u64,
+    /// Failed requests by error code
+    pub errors: HashMap<&'static str, u64>,
+    pub keys_lost: u64,
+    pub cookies_lost: u64,
+    pub top_models: Vec<(String, u64)>,
+}
+
+impl From<DailyStats> for DailyReport {
+    fn from(stats: DailyStats) -> Self {
+        let mut top_models = stats.models.into_iter().collect::<Vec<_>>();
+        top_models.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_models.truncate(TOP_MODELS);
+        Self {
+            day: stats.day,
+            requests: stats.requests.values().sum(),
+            requests_by_backend: stats.requests,
+            tokens: stats.tokens,
+            errors: stats.errors,
+            keys_lost: stats.keys_lost,
+            cookies_lost: stats.cookies_lost,
+            top_models,
+        }
+    }
+}
+
+fn with_stats(f: impl FnOnce(&mut DailyStats)) {
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    f(&mut stats);
+}
+
+/// Counts an API request, with the error code it failed with if it did
+pub fn record_request(backend: Backend, model: Option<&str>, error: Option<&'static str>) {
+    with_stats(|s| {
+        *s.requests.entry(backend).or_default() += 1;
+        if let Some(model) = model {
+            *s.models.entry(model.to_string()).or_default() += 1;
+        }
+        if let Some(code) = error {
+            *s.errors.entry(code).or_default() += 1;
+        }
+    });
+}
+
+/// Counts tokens used by a request
+pub fn add_tokens(tokens: u64) {
+    if tokens > 0 {
+        with_stats(|s| s.tokens += tokens);
+    }
+}
+
+/// Counts a Gemini key removed as invalid
+pub fn record_key_lost() {
+    with_stats(|s| s.keys_lost += 1);
+}
+
+/// Counts a cookie moved to the invalid list
+pub fn record_cookie_lost() {
+    with_stats(|s| s.cookies_lost += 1);
+}
+
+/// Takes the stats of the finished day, if the day rolled over
+fn take_finished(today: NaiveDate) -> Option<DailyReport> {
+    let mut stats = STATS.lock().unwrap_or_else(|e| e.into_inner());
+    if stats.day >= today {
+        return None;
+    }
+    let finished = std::mem::replace(
+        &mut *stats,
+        DailyStats {
+            day: today,
+            ..Default::default()
+        },
+    );
+    Some(finished.into())
+}
+
+/// Writes a report to `summary-<day>.json` in the log directory and posts it
+/// to the webhook, if one is set
+async fn publish(report: DailyReport) {
+    info!(
+        "Daily summary for {}: {} requests, {} tokens, {} errors, {} keys and {} cookies lost",
+        report.day,
+        report.requests,
+        report.tokens,
+        report.errors.values().sum::<u64>(),
+        report.keys_lost,
+        report.cookies_lost
+    );
+    let path = LOG_DIR.join(format!("summary-{}.json", report.day));
+    let written = match serde_json::to_vec_pretty(&report) {
+        Ok(json) => tokio::fs::write(&path, json)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = written {
+        warn!("Failed to write {}: {}", path.display(), e);
+    }
+    let Some(webhook) = CLEWDR_CONFIG.load().daily_report.webhook.to_owned() else {
+        return;
+    };
+    match wreq::Client::new().post(webhook).json(&report).send().await {
+        Ok(res) if !res.status().is_success() => {
+            warn!("Daily summary webhook answered {}", res.status());
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to post the daily summary: {}", e),
+    }
+}
+
+/// Spawns the task publishing the summary of each day after midnight, when
+/// `daily_report.enabled` is set
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            let Some(report) = take_finished(Local::now().date_naive()) else {
+                continue;
+            };
+            if CLEWDR_CONFIG.load().daily_report.enabled {
+                publish(report).await;
+            }
+        }
+    });
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use colored::Colorize;
+use http::{
+    HeaderMap,
+    header::{COOKIE, ORIGIN},
+};
+use serde::Serialize;
+use serde_json::Value;
+use wreq::{Client, ClientBuilder, Proxy, Response};
+use wreq_util::Emulation;
+
+use crate::{
+    config::{
+        CLAUDE_ENDPOINT, CLEWDR_CONFIG, CONFIG_PATH, ClewdrConfig, ClewdrCookie, GeminiKey,
+        Severity, format_issues,
+    },
+    gemini_state::vertex_token,
+    services::gemini_endpoint,
+};
+
+/// Timeout of every probe
+const PROBE_TIMEOUT: Duration = Duration::from_secs(20);
+/// Endpoint used to test proxies, answers with an empty 204
+const CONNECTIVITY_URL: &str = "https://www.gstatic.com/generate_204";
+
+enum Outcome {
+    Ok(String),
+    Fail(String),
+    Skip(String),
+}
+
+/// Collects the outcome of each check and prints it as it goes
+#[derive(Default)]
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn record(&mut self, name: &str, outcome: Outcome) {
+        let (tag, detail) = match outcome {
+            Outcome::Ok(d) => ("  OK  ".green().bold(), d),
+            Outcome::Fail(d) => {
+                self.failures += 1;
+                (" FAIL ".red().bold(), d)
+            }
+            Outcome::Skip(d) => (" SKIP ".dimmed(), d),
+        };
+        println!("[{tag}] {:<14} {detail}", name.bold());
+    }
+}
+
+fn client(proxy: Option<&str>) -> Result<Client, String> {
+    let mut builder = ClientBuilder::new()
+        .emulation(Emulation::Chrome136)
+        .timeout(PROBE_TIMEOUT);
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("invalid proxy: {e}"))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Strict parse of the config file, the server itself loads it leniently
+fn check_config() -> Outcome {
+    if !CONFIG_PATH.exists() {
+        return Outcome::Skip(format!(
+            "{} not found, defaults are used",
+            CONFIG_PATH.display()
+        ));
+    }
+    match ClewdrConfig::figment().extract::<ClewdrConfig>() {
+        Ok(config) => {
+            let issues = config.check();
+            if issues.iter().any(|i| i.severity == Severity::Error) {
+                return Outcome::Fail(format_issues(&issues));
+            }
+            if !issues.is_empty() {
+                println!("{}", format_issues(&issues));
+            }
+            Outcome::Ok(format!(
+                "{}: {} cookies, {} keys",
+                CONFIG_PATH.display(),
+                config.cookie_array.len(),
+                config.gemini_keys.len()
+            ))
+        }
+        Err(e) => Outcome::Fail(format!("{}: {e}", CONFIG_PATH.display())),
+    }
+}
+
+async fn check_proxy(proxy: &str) -> Outcome {
+    let client = match client(Some(proxy)) {
+        Ok(client) => client,
+        Err(e) => return Outcome::Fail(format!("{proxy}: {e}")),
+    };
+    let start = Instant::now();
+    match client.get(CONNECTIVITY_URL).send().await {
+        Ok(res) if res.status().is_success() => Outcome::Ok(format!(
+            "{proxy} reachable in {}ms",
+            start.elapsed().as_millis()
+        )),
+        Ok(res) => Outcome::Fail(format!("{proxy}: unexpected status {}", res.status())),
+        Err(e) => Outcome::Fail(format!("{proxy}: {e}")),
+    }
+}
+
+/// Lists models with a key, which costs no quota
+///
+/// # Returns
+/// Error message if the key is rejected or the request fails
+pub(crate) async fn probe_key(key: &GeminiKey, proxy: Option<&str>) -> Result<(), String> {
+    let res = client(proxy)?
+        .get(format!("{}/v1beta/models", gemini_endpoint::resolve(None)))
+        .query(&[("key", key.as_ref()), ("pageSize", "1")])
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status().is_success() {
+        return Ok(());
+    }
+    let status = res.status();
+    let body = res.json::<Value>().await.unwrap_or_default();
+    let message = body["error"]["message"].as_str().unwrap_or_default();
+    Err(format!("{status} {message}"))
+}
+
+/// Loads the account behind a cookie
+///
+/// # Returns
+/// Email address of the account, or an error message if the cookie is invalid
+/// or the request fails
+pub(crate) async fn probe_cookie(
+    cookie: &ClewdrCookie,
+    proxy: Option<&str>,
+) -> Result<String, String> {
+    let res = client(proxy)?
+        .get(format!("{}api/bootstrap", CLEWDR_CONFIG.load().endpoint()))
+        .header(COOKIE, cookie.to_string())
+        .header(ORIGIN, CLAUDE_ENDPOINT)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !res.status().is_success() {
+        return Err(format!("unexpected status {}", res.status()));
+    }
+    let body = res.json::<Value>().await.unwrap_or_default();
+    body["account"]["email_address"]
+        .as_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| "no account, the cookie is invalid".to_string())
+}
+
+/// Outcome of a live probe of a single credential, for the admin API
+#[derive(Debug, Serialize, Default)]
+pub struct ProbeReport {
+    pub ok: bool,
+    /// Status of the probe response, missing if the request failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub latency_ms: u128,
+    /// Rate limit and quota headers of the probe response
+    pub quota: BTreeMap<String, String>,
+    /// Models a key can use
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub models: Vec<String>,
+    /// Capabilities of the organization behind a cookie, e.g. `claude_pro`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub capabilities: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ProbeReport {
+    /// Sends a probe and records its status, latency and quota headers
+    ///
+    /// # Returns
+    /// The response if it succeeded
+    async fn send(&mut self, req: wreq::RequestBuilder) -> Option<Response> {
+        let start = Instant::now();
+        let res = req.send().await;
+        self.latency_ms = start.elapsed().as_millis();
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                self.error = Some(e.to_string());
+                return None;
+            }
+        };
+        self.status = Some(res.status().as_u16());
+        self.quota = quota_headers(res.headers());
+        if res.status().is_success() {
+            return Some(res);
+        }
+        let status = res.status();
+        let body = res.json::<Value>().await.unwrap_or_default();
+        let message = body["error"]["message"].as_str().unwrap_or_default();
+        self.error = Some(format!("{status} {message}").trim_end().to_string());
+        None
+    }
+}
+
+/// Headers describing rate limits or remaining quota
+fn quota_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            let name = name.as_str();
+            name.contains("ratelimit") || name.contains("quota") || name == "retry-after"
+        })
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Lists every model a key can use
+pub(crate) async fn test_key(key: &GeminiKey, proxy: Option<&str>) -> ProbeReport {
+    let mut report = ProbeReport::default();
+    let client = match client(proxy) {
+        Ok(client) => client,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    let req = client
+        .get(format!("{}/v1beta/models", gemini_endpoint::resolve(None)))
+        .query(&[("key", key.as_ref()), ("pageSize", "1000")]);
+    let Some(res) = report.send(req).await else {
+        return report;
+    };
+    let body = res.json::<Value>().await.unwrap_or_default();
+    report.models = body["models"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["name"].as_str())
+        .map(|m| m.trim_start_matches("models/").to_string())
+        .collect();
+    report.ok = true;
+    report
+}
+
+/// Loads the account behind a cookie and the capabilities of its chat
+/// organization
+pub(crate) async fn test_cookie(cookie: &ClewdrCookie, proxy: Option<&str>) -> ProbeReport {
+    let mut report = ProbeReport::default();
+    let client = match client(proxy) {
+        Ok(client) => client,
+        Err(e) => {
+            report.error = Some(e);
+            return report;
+        }
+    };
+    let req = client
+        .get(format!("{}api/bootstrap", CLEWDR_CONFIG.load().endpoint()))
+        .header(COOKIE, cookie.to_string())
+        .header(ORIGIN, CLAUDE_ENDPOINT);
+    let Some(res) = report.send(req).await else {
+        return report;
+    };
+    let body = res.json::<Value>().await.unwrap_or_default();
+    if body["account"].is_null() {
+        report.error = Some("no account, the cookie is invalid".to_string());
+        return report;
+    }
+    report.capabilities = body["account"]["memberships"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|m| m["organization"]["capabilities"].as_array())
+        .find(|c| c.iter().any(|c| c.as_str() == Some("chat")))
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c.as_str().map(ToString::to_string))
+        .collect();
+    report.ok = true;
+    report
+}
+
+async fn check_gemini_key(config: &ClewdrConfig) -> Outcome {
+    let Some(key) = config.gemini_keys.iter().next() else {
+        return Outcome::Skip("no Gemini key configured".to_string());
+    };
+    let proxy = key.proxy.as_deref().or(config.proxy.as_deref());
+    match probe_key(&key.key, proxy).await {
+        Ok(()) => Outcome::Ok(format!("{} accepted", key.key.ellipse())),
+        Err(e) => Outcome::Fail(format!("{}: {e}", key.key.ellipse())),
+    }
+}
+
+async fn check_cookie(config: &ClewdrConfig) -> Outcome {
+    let Some(cookie) = config.cookie_array.iter().find(|c| c.reset_time.is_none()) else {
+        return Outcome::Skip("no usable Claude cookie configured".to_string());
+    };
+    let proxy = cookie.proxy.as_deref().or(config.proxy.as_deref());
+    let name = cookie.cookie.ellipse();
+    match probe_cookie(&cookie.cookie, proxy).await {
+        Ok(email) => Outcome::Ok(format!("{name} belongs to {email}")),
+        Err(e) => Outcome::Fail(format!("{name}: {e}")),
+    }
+}
+
+/// Exchanges the service account for an access token
+async fn check_vertex(config: &ClewdrConfig) -> Outcome {
+    let Some(ref credential) = config.vertex.credential else {
+        return Outcome::Skip("no Vertex credential configured".to_string());
+    };
+    let client = match client(config.proxy.as_deref()) {
+        Ok(client) => client,
+        Err(e) => return Outcome::Fail(e),
+    };
+    match vertex_token::get_token(credential, &client).await {
+        Ok(_) => Outcome::Ok(format!("token issued for {}", credential.client_email)),
+        Err(e) => Outcome::Fail(format!("{}: {e}", credential.client_email)),
+    }
+}
+
+/// Runs every check and prints a report, for `clewdr doctor`
+///
+/// # Returns
+/// Whether all checks passed or were skipped
+pub async fn run() -> bool {
+    println!("{}", "Running diagnostics...".bold());
+    let mut report = Report::default();
+    report.record("config", check_config());
+    let config = CLEWDR_CONFIG.load();
+
+    let proxies = config.proxy.iter().chain(config.proxy_pool.iter());
+    let mut any_proxy = false;
+    for proxy in proxies {
+        any_proxy = true;
+        report.record("proxy", check_proxy(proxy).await);
+    }
+    if !any_proxy {
+        report.record("proxy", Outcome::Skip("no proxy configured".to_string()));
+    }
+    report.record("gemini key", check_gemini_key(&config).await);
+    report.record("claude cookie", check_cookie(&config).await);
+    report.record("vertex", check_vertex(&config).await);
+
+    if report.failures == 0 {
+        println!("{}", "All checks passed".green().bold());
+    } else {
+        println!(
+            "{}",
+            format!("{} check(s) failed", report.failures).red().bold()
+        );
+    }
+    report.failures == 0
+}
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::ResultExt;
+use wreq::Client;
+
+use crate::{
+    ExportArgs, ExportFormat,
+    config::{CLEWDR_CONFIG, CookieStatus, KeyStatus, UselessCookie},
+    error::{ClewdrError, WreqSnafu},
+    services::{
+        cookie_actor::{CookieStatusInfo, load_usage},
+        key_actor::KeyStatusInfo,
+        shared_state,
+    },
+};
+
+/// Pool state written by `clewdr export`, kept apart from the main config so
+/// it can be backed up or moved to another machine on its own
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PoolSnapshot {
+    pub version: String,
+    pub exported_at: i64,
+    /// Secrets are shortened and tokens left out, the snapshot cannot be
+    /// imported back
+    pub redacted: bool,
+    pub cookies: Vec<CookieStatus>,
+    pub exhausted_cookies: Vec<CookieStatus>,
+    pub invalid_cookies: Vec<UselessCookie>,
+    pub gemini_keys: Vec<KeyStatus>,
+}
+
+impl PoolSnapshot {
+    fn new(cookies: CookieStatusInfo, keys: KeyStatusInfo) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            exported_at: chrono::Utc::now().timestamp(),
+            redacted: false,
+            cookies: cookies.valid,
+            exhausted_cookies: cookies.exhausted,
+            invalid_cookies: cookies.invalid,
+            gemini_keys: keys.valid,
+        }
+    }
+
+    /// State stored in the config file, with the usage counters the cookie
+    /// actor last wrote
+    async fn from_config() -> Self {
+        let config = CLEWDR_CONFIG.load();
+        let usage = load_usage().await;
+        let (exhausted, valid) = config
+            .cookie_array
+            .iter()
+            .cloned()
+            .map(|mut c| {
+                if let Some(u) = usage.get(&shared_state::id(&c.cookie.to_string())) {
+                    c.usage = u.to_owned();
+                }
+                c
+            })
+            .partition(|c| c.reset_time.is_some());
+        Self::new(
+            CookieStatusInfo {
+                valid,
+                exhausted,
+                invalid: config.wasted_cookie.iter().cloned().collect(),
+            },
+            KeyStatusInfo {
+                valid: config.gemini_keys.iter().cloned().collect(),
+            },
+        )
+    }
+
+    /// Live state of a running instance, including cooldowns not yet saved
+    async fn from_remote(url: &str, password: &str) -> Result<Self, ClewdrError> {
+        let client = Client::new();
+        let url = url.trim_end_matches('/');
+        let cookies = client
+            .get(format!("{url}/api/cookies"))
+            .bearer_auth(password)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to fetch cookies",
+            })?
+            .error_for_status()
+            .context(WreqSnafu {
+                msg: "Admin API rejected the cookie request",
+            })?
+            .json::<Value>()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to parse cookies",
+            })?;
+        let keys = client
+            .get(format!("{url}/api/keys"))
+            .bearer_auth(password)
+            .send()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to fetch keys",
+            })?
+            .error_for_status()
+            .context(WreqSnafu {
+                msg: "Admin API rejected the key request",
+            })?
+            .json::<Value>()
+            .await
+            .context(WreqSnafu {
+                msg: "Failed to parse keys",
+            })?;
+        Ok(Self::new(
+            CookieStatusInfo {
+                valid: serde_json::from_value(cookies["valid"].to_owned())?,
+                exhausted: serde_json::from_value(cookies["exhausted"].to_owned())?,
+                invalid: serde_json::from_value(cookies["invalid"].to_owned())?,
+            },
+            KeyStatusInfo {
+                valid: serde_json::from_value(keys["valid"].to_owned())?,
+            },
+        ))
+    }
+
+    /// Serializes the snapshot, shortening cookies and keys and dropping
+    /// OAuth tokens when `redact` is set
+    fn render(mut self, format: ExportFormat, redact: bool) -> Result<String, ClewdrError> {
+        self.redacted = redact;
+        let mut value = serde_json::to_value(&self)?;
+        if redact {
+            redact_secrets(&mut value);
+        }
+        Ok(match format {
+            ExportFormat::Json => serde_json::to_string_pretty(&value)?,
+            ExportFormat::Toml => {
+                // TOML has no null
+                strip_nulls(&mut value);
+                toml::ser::to_string_pretty(&value)?
+            }
+        })
+    }
+}
+
+/// Shortens secrets the same way `ellipse` does in logs
+fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("token");
+            for (name, v) in map.iter_mut() {
+                match (name.as_str(), v.as_str()) {
+                    ("cookie" | "key", Some(secret)) => {
+                        let secret = secret.trim_start_matches("sessionKey=sk-ant-sid01-");
+                        let short = secret.chars().take(10).collect::<String>();
+                        *v = format!("{short}...").into();
+                    }
+                    _ => redact_secrets(v),
+                }
+            }
+        }
+        Value::Array(list) => list.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            map.values_mut().for_each(strip_nulls);
+        }
+        Value::Array(list) => list.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+/// Writes a snapshot of cookies, keys, cooldowns and usage counters, for
+/// `clewdr export`
+///
+/// The snapshot is read from a running instance when `--remote` is given,
+/// otherwise from the config file.
+pub async fn run(args: ExportArgs) -> Result<(), ClewdrError> {
+    let snapshot = match args.remote {
+        Some(ref url) => {
+            let password = args.admin_password.as_deref().unwrap_or_default();
+            PoolSnapshot::from_remote(url, password).await?
+        }
+        None => PoolSnapshot::from_config().await,
+    };
+    let rendered = snapshot.render(args.format, args.redact)?;
+    match args.output {
+        Some(ref path) => tokio::fs::write(path, rendered).await?,
+        None => println!("{rendered}"),
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::{collections::HashSet, sync::LazyLock, time::Duration};
+
+use moka::sync::Cache;
+use serde_json::Value;
+use tracing::{info, warn};
+use wreq::ClientBuilder;
+
+use crate::{
+    config::{CLEWDR_CONFIG, GEMINI_ENDPOINT},
+    services::proxy_pool::to_wreq_proxy,
+};
+
+/// How long a failing mirror is passed over unless a health check clears it
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// How often the configured mirrors are checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Time a mirror has to answer a health check
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mirrors that recently failed, by base URL
+static UNHEALTHY: LazyLock<Cache<String, ()>> =
+    LazyLock::new(|| Cache::builder().time_to_live(UNHEALTHY_COOLDOWN).build());
+
+/// Base URL of the Gemini API for a key: its own mirror, the global
+/// `gemini_endpoint`, then Google's endpoint, skipping mirrors marked unhealthy
+///
+/// # Arguments
+/// * `assigned` - Mirror pinned to the key, if any
+pub fn resolve(assigned: Option<&str>) -> String {
+    let config = CLEWDR_CONFIG.load();
+    assigned
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .into_iter()
+        .chain(config.gemini_endpoint.as_ref().map(|u| u.as_str()))
+        .map(|e| e.trim_end_matches('/'))
+        .find(|e| !UNHEALTHY.contains_key(*e))
+        .unwrap_or(GEMINI_ENDPOINT)
+        .to_string()
+}
+
+/// Takes a mirror out of use until it passes a health check or the cooldown
+/// ends
+///
+/// # Returns
+/// Whether the endpoint is a mirror, i.e. a retry will use another endpoint
+pub fn mark_unhealthy(endpoint: &str) -> bool {
+    if endpoint == GEMINI_ENDPOINT {
+        return false;
+    }
+    if !UNHEALTHY.contains_key(endpoint) {
+        warn!("Gemini mirror marked unhealthy: {}", endpoint);
+    }
+    UNHEALTHY.insert(endpoint.to_string(), ());
+    true
+}
+
+/// Mirrors set globally or on a key
+fn mirrors() -> HashSet<String> {
+    let config = CLEWDR_CONFIG.load();
+    config
+        .gemini_keys
+        .iter()
+        .filter_map(|k| k.endpoint.as_deref())
+        .chain(config.gemini_endpoint.as_ref().map(|u| u.as_str()))
+        .map(|e| e.trim().trim_end_matches('/').to_string())
+        .filter(|e| !e.is_empty() && e != GEMINI_ENDPOINT)
+        .collect()
+}
+
+/// Whether a mirror forwards to the Gemini API: without a key Google answers
+/// with a JSON error, a broken mirror fails to connect, returns a 5xx or an
+/// error page
+async fn check(endpoint: &str) -> bool {
+    let builder = ClientBuilder::new().timeout(CHECK_TIMEOUT);
+    let builder = match CLEWDR_CONFIG
+        .load()
+        .proxy
+        .as_deref()
+        .and_then(to_wreq_proxy)
+    {
+        Some(proxy) => builder.proxy(proxy),
+        None => builder,
+    };
+    let Ok(client) = builder.build() else {
+        return false;
+    };
+    match client.get(format!("{endpoint}/v1beta/models")).send().await {
+        Ok(res) if !res.status().is_server_error() => res.json::<Value>().await.is_ok(),
+        _ => false,
+    }
+}
+
+/// Spawns the task checking the configured mirrors, marking broken ones
+/// unhealthy and bringing recovered ones back
+pub fn spawn() {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for endpoint in mirrors() {
+                let healthy = check(&endpoint).await;
+                let marked = UNHEALTHY.contains_key(&endpoint);
+                if healthy && marked {
+                    info!("Gemini mirror recovered: {}", endpoint);
+                    UNHEALTHY.invalidate(&endpoint);
+                } else if !healthy {
+                    mark_unhealthy(&endpoint);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,164 @@
+use std::{
+    process::{Output, Stdio},
+    time::Duration,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{debug, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, HookConfig, HookStage},
+    error::ClewdrError,
+    services::wasm_hook::{self, Outcome},
+};
+
+/// What a hook reads from stdin
+#[derive(Serialize)]
+struct HookInput<'a> {
+    stage: HookStage,
+    path: &'a str,
+    body: &'a Value,
+}
+
+impl HookConfig {
+    /// Program or module path, for logs
+    pub fn name(&self) -> String {
+        match self.wasm {
+            Some(ref path) => path.display().to_string(),
+            None => self.command.first().cloned().unwrap_or_default(),
+        }
+    }
+
+    fn applies(&self, stage: HookStage, path: &str) -> bool {
+        self.stages.contains(&stage)
+            && (self.paths.is_empty() || self.paths.iter().any(|p| path.starts_with(p)))
+    }
+}
+
+/// Whether any hook runs at the stage for the path
+pub fn enabled(stage: HookStage, path: &str) -> bool {
+    CLEWDR_CONFIG
+        .load()
+        .hooks
+        .iter()
+        .any(|h| h.applies(stage, path))
+}
+
+/// Passes a JSON body through every hook configured for the stage and path,
+/// in order
+///
+/// A hook reads `{"stage", "path", "body"}` from stdin and writes the new body
+/// to stdout, or nothing to keep it. Exiting with an error rejects the request
+/// with what the hook wrote to stderr. A WASM hook gets the same input and
+/// answers through its imports, see [`wasm_hook::call`]. A hook that cannot be
+/// started, times out or writes invalid JSON is skipped, so a broken script
+/// does not take the proxy down.
+pub async fn run(stage: HookStage, path: &str, mut body: Value) -> Result<Value, ClewdrError> {
+    let hooks = CLEWDR_CONFIG
+        .load()
+        .hooks
+        .iter()
+        .filter(|h| h.applies(stage, path))
+        .cloned()
+        .collect::<Vec<_>>();
+    for hook in hooks {
+        body = run_one(&hook, stage, path, body).await?;
+    }
+    Ok(body)
+}
+
+/// Runs a program with `input` on its stdin, killing it after `timeout_secs`
+///
+/// # Returns
+/// `None` if the program could not be started or timed out, logged as a
+/// warning
+async fn exec(command: &[String], input: Vec<u8>, timeout_secs: u64) -> Option<Output> {
+    let (program, args) = command.split_first()?;
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to start {}: {}", program, e);
+            return None;
+        }
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // written aside, a program answering before it read everything must not block
+    let writer = tokio::spawn(async move { stdin.write_all(&input).await });
+    let output =
+        tokio::time::timeout(Duration::from_secs(timeout_secs), child.wait_with_output()).await;
+    writer.abort();
+    match output {
+        Ok(Ok(output)) => Some(output),
+        Ok(Err(e)) => {
+            warn!("{} failed: {}", program, e);
+            None
+        }
+        Err(_) => {
+            warn!("{} timed out after {}s", program, timeout_secs);
+            None
+        }
+    }
+}
+
+async fn run_one(
+    hook: &HookConfig,
+    stage: HookStage,
+    path: &str,
+    body: Value,
+) -> Result<Value, ClewdrError> {
+    let input = serde_json::to_vec(&HookInput {
+        stage,
+        path,
+        body: &body,
+    })?;
+    let name = hook.name();
+    if let Some(ref module) = hook.wasm {
+        let stdout = match wasm_hook::exec(module, input, hook.timeout_secs).await {
+            None | Some(Outcome::Keep) => return Ok(body),
+            Some(Outcome::Reject(msg)) => return Err(ClewdrError::HookRejected { stage, msg }),
+            Some(Outcome::Replace(output)) => output,
+        };
+        return Ok(parse_body(&name, stage, &stdout, body));
+    }
+    let Some(output) = exec(&hook.command, input, hook.timeout_secs).await else {
+        return Ok(body);
+    };
+    if !output.status.success() {
+        let msg = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(ClewdrError::HookRejected {
+            stage,
+            msg: if msg.is_empty() {
+                output.status.to_string()
+            } else {
+                msg
+            },
+        });
+    }
+    Ok(parse_body(&name, stage, &output.stdout, body))
+}
+
+/// The body a hook wrote, or `body` if it wrote nothing or invalid JSON
+fn parse_body(name: &str, stage: HookStage, output: &[u8], body: Value) -> Value {
+    if output.iter().all(u8::is_ascii_whitespace) {
+        return body;
+    }
+    match serde_json::from_slice(output) {
+        Ok(new_body) => {
+            debug!("Hook {} rewrote the {} body", name, stage);
+            new_body
+        }
+        Err(e) => {
+            warn!("Hook {} wrote invalid JSON: {}", name, e);
+            body
+        }
+    }
+}
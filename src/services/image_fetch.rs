@@ -0,0 +1,297 @@
+use std::{error::Error, net::IpAddr, sync::Arc, time::Duration};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures::StreamExt;
+use tracing::{debug, warn};
+use url::{Host, Url};
+use wreq::{
+    Client,
+    dns::{Addrs, Name, Resolve, Resolving},
+    header::CONTENT_TYPE,
+    redirect::Policy,
+};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    services::proxy_pool::{PROXY_POOL, to_wreq_proxy},
+    types::claude::{ContentBlock, ImageSource, ImageUrl, Message, MessageContent},
+};
+
+/// Time allowed to download a remote image
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Redirects followed for a remote image
+const MAX_REDIRECTS: usize = 5;
+
+fn fetch_error(msg: impl Into<String>) -> ClewdrError {
+    ClewdrError::ImageFetchError { msg: msg.into() }
+}
+
+/// Parses a `data:` URL into a base64 image source
+pub fn parse_data_url(url: &str) -> Option<ImageSource> {
+    let (metadata, data) = url.strip_prefix("data:")?.split_once(',')?;
+    let (media_type, type_) = metadata.split_once(';')?;
+    Some(ImageSource {
+        type_: type_.to_string(),
+        media_type: media_type.to_string(),
+        data: data.to_owned(),
+    })
+}
+
+/// Whether an address is reachable on the public internet, not loopback,
+/// private, link-local (cloud metadata included), shared or reserved
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // shared address space
+                || (a == 100 && (64..128).contains(&b))
+                // benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // IETF protocol assignments
+                || ip.octets()[..3] == [192, 0, 0])
+        }
+        IpAddr::V6(ip) => {
+            if let Some(v4) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(v4));
+            }
+            let first = ip.segments()[0];
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // unique local
+                || (first & 0xfe00) == 0xfc00
+                // link-local
+                || (first & 0xffc0) == 0xfe80
+                // documentation
+                || (first == 0x2001 && ip.segments()[1] == 0x0db8)
+                // IPv4 compatible and translated
+                || ip.segments()[..6] == [0; 6]
+                || ip.segments()[..2] == [0x64, 0xff9b])
+        }
+    }
+}
+
+/// Whether an image may be fetched from the URL: http(s), a host from
+/// `image_hosts` if any are configured, and no literal non-public address
+fn allowed(url: &Url, hosts: &[String]) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let Some(host) = url.host() else {
+        return false;
+    };
+    let public = match host {
+        Host::Ipv4(ip) => is_public(IpAddr::V4(ip)),
+        Host::Ipv6(ip) => is_public(IpAddr::V6(ip)),
+        Host::Domain(domain) => !domain.eq_ignore_ascii_case("localhost"),
+    };
+    let name = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    public
+        && (hosts.is_empty()
+            || hosts.iter().any(|h| {
+                let h = h.to_ascii_lowercase();
+                name == h || name.ends_with(&format!(".{h}"))
+            }))
+}
+
+/// Addresses of a host, if all of them are public
+async fn lookup_public(host: String) -> Result<Addrs, Box<dyn Error + Send + Sync>> {
+    let addrs = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() || !addrs.iter().all(|a| is_public(a.ip())) {
+        return Err(format!("{host} resolves to a non-public address").into());
+    }
+    Ok(Box::new(addrs.into_iter()))
+}
+
+/// Resolver refusing hosts with a non-public address, it runs for every hop
+/// and connection, so neither redirects nor rebinding DNS answers reach the
+/// internal network
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(lookup_public(name.as_str().to_owned()))
+    }
+}
+
+/// Downloads a remote image through the proxy pool, up to `max_image_size`
+///
+/// Only public addresses are fetched, redirects included. What went wrong is
+/// logged, the client only learns that the image could not be fetched.
+async fn download(url: &str) -> Result<ImageSource, ClewdrError> {
+    let failed = |reason: String| {
+        warn!("Remote image {} not fetched: {}", url, reason);
+        fetch_error("Failed to fetch the image")
+    };
+    let config = CLEWDR_CONFIG.load();
+    let max_size = config.max_image_size;
+    let hosts = config.image_hosts.to_owned();
+    let parsed = Url::parse(url).map_err(|e| failed(e.to_string()))?;
+    if !allowed(&parsed, &hosts) {
+        return Err(failed("host not allowed".to_string()));
+    }
+    // a proxy resolves names itself, so check them up front as well
+    if let Some(Host::Domain(domain)) = parsed.host()
+        && let Err(e) = lookup_public(domain.to_owned()).await
+    {
+        return Err(failed(e.to_string()));
+    }
+    let redirect = Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if !allowed(attempt.url(), &hosts) {
+            attempt.error("redirect to a host not allowed")
+        } else {
+            attempt.follow()
+        }
+    });
+    let mut client = Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(redirect)
+        .dns_resolver(Arc::new(PublicResolver));
+    if let Some(proxy) = PROXY_POOL.resolve(None).as_deref().and_then(to_wreq_proxy) {
+        client = client.proxy(proxy);
+    }
+    let client = client.build().map_err(|e| failed(e.to_string()))?;
+    let res = client
+        .get(parsed)
+        .send()
+        .await
+        .map_err(|e| failed(e.to_string()))?;
+    if !res.status().is_success() {
+        return Err(failed(format!("returned {}", res.status())));
+    }
+    let media_type = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_string())
+        .unwrap_or_default();
+    if !media_type.starts_with("image/") {
+        return Err(failed(format!("not an image: {media_type}")));
+    }
+    if res.content_length().is_some_and(|l| l as usize > max_size) {
+        return Err(fetch_error(format!("Image exceeds {max_size} bytes")));
+    }
+    // the length header may be missing or wrong, so enforce the limit while reading
+    let mut body = res.bytes_stream();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk.map_err(|e| failed(e.to_string()))?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(fetch_error(format!("Image exceeds {max_size} bytes")));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    debug!("Inlined remote image {} ({} bytes)", url, bytes.len());
+    Ok(ImageSource {
+        type_: "base64".to_string(),
+        media_type,
+        data: BASE64_STANDARD.encode(bytes),
+    })
+}
+
+/// Resolves an OpenAI image URL, either a data URL or, with
+/// `fetch_remote_images`, a remote http(s) URL
+async fn resolve(url: &str) -> Result<ImageSource, ClewdrError> {
+    if url.starts_with("data:") {
+        return parse_data_url(url).ok_or_else(|| fetch_error("Malformed data URL"));
+    }
+    if !CLEWDR_CONFIG.load().fetch_remote_images {
+        return Err(fetch_error(
+            "Remote image URLs are disabled, send the image as a data URL",
+        ));
+    }
+    download(url).await
+}
+
+/// Blocks of all messages, as images only live in block content
+fn blocks_mut(messages: &mut [Message]) -> impl Iterator<Item = &mut ContentBlock> {
+    messages.iter_mut().flat_map(|m| match m.content {
+        MessageContent::Blocks { ref mut content } => content.iter_mut(),
+        MessageContent::Text { .. } => [].iter_mut(),
+    })
+}
+
+/// Converts OpenAI `image_url` parts into Claude base64 image blocks
+pub async fn inline_images(messages: &mut [Message]) -> Result<(), ClewdrError> {
+    for block in blocks_mut(messages) {
+        if let ContentBlock::ImageUrl { image_url } = block {
+            let source = resolve(&image_url.url).await?;
+            *block = ContentBlock::Image {
+                source,
+                cache_control: None,
+            };
+        }
+    }
+    Ok(())
+}
+
+/// Replaces remote OpenAI `image_url` parts with data URLs, for upstreams
+/// that only accept inline images
+pub async fn inline_image_urls(messages: &mut [Message]) -> Result<(), ClewdrError> {
+    for block in blocks_mut(messages) {
+        if let ContentBlock::ImageUrl { image_url } = block
+            && !image_url.url.starts_with("data:")
+        {
+            let source = resolve(&image_url.url).await?;
+            *image_url = ImageUrl {
+                url: format!("data:{};base64,{}", source.media_type, source.data),
+            };
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        assert!(is_public("1.1.1.1".parse().unwrap()));
+        assert!(is_public("2606:4700:4700::1111".parse().unwrap()));
+    }
+
+    #[test]
+    fn urls_are_checked_against_hosts() {
+        let url = |u: &str| Url::parse(u).unwrap();
+        assert!(!allowed(&url("http://169.254.169.254/latest"), &[]));
+        assert!(!allowed(&url("http://[::1]/a.png"), &[]));
+        assert!(!allowed(&url("http://localhost/a.png"), &[]));
+        assert!(!allowed(&url("file:///etc/passwd"), &[]));
+        assert!(allowed(&url("https://example.com/a.png"), &[]));
+        let hosts = ["example.com".to_string()];
+        assert!(allowed(&url("https://cdn.example.com/a.png"), &hosts));
+        assert!(!allowed(&url("https://example.org/a.png"), &hosts));
+        assert!(!allowed(&url("https://badexample.com/a.png"), &hosts));
+    }
+}
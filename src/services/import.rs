@@ -0,0 +1,252 @@
+use std::{io::Read, path::Path, str::FromStr, sync::Arc};
+
+use colored::Colorize;
+use futures::{StreamExt, stream};
+use serde_json::json;
+use wreq::Client;
+
+use crate::{
+    ImportArgs,
+    config::{CLEWDR_CONFIG, ClewdrConfig, ClewdrCookie, CookieStatus, GeminiKey, KeyStatus},
+    error::ClewdrError,
+    services::{
+        cookie_actor::{load_usage, save_usage},
+        doctor::{probe_cookie, probe_key},
+        export::PoolSnapshot,
+        shared_state,
+    },
+};
+
+enum Credential {
+    Key(GeminiKey),
+    Cookie(ClewdrCookie),
+}
+
+impl Credential {
+    fn name(&self) -> String {
+        match self {
+            Credential::Key(key) => format!("key {}", key.ellipse()),
+            Credential::Cookie(cookie) => format!("cookie {}", cookie.ellipse()),
+        }
+    }
+
+    async fn probe(&self, proxy: Option<&str>) -> Result<(), String> {
+        match self {
+            Credential::Key(key) => probe_key(key, proxy).await,
+            Credential::Cookie(cookie) => probe_cookie(cookie, proxy).await.map(|_| ()),
+        }
+    }
+}
+
+/// Non-empty lines of a file, `-` reads stdin
+fn read_lines(path: &Path) -> Result<Vec<String>, ClewdrError> {
+    let content = if path.as_os_str() == "-" {
+        let mut buf = String::new();
+        std::io::stdin().read_to_string(&mut buf)?;
+        buf
+    } else {
+        std::fs::read_to_string(path)?
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(ToString::to_string)
+        .collect())
+}
+
+/// Parses the input files, malformed lines are reported and dropped
+fn parse(args: &ImportArgs) -> Result<Vec<Credential>, ClewdrError> {
+    let mut credentials = vec![];
+    if let Some(ref path) = args.keys {
+        for line in read_lines(path)? {
+            let key = GeminiKey::from(&line);
+            if key.validate() {
+                credentials.push(Credential::Key(key));
+            } else {
+                println!("[{}] malformed key: {line}", " SKIP ".dimmed());
+            }
+        }
+    }
+    if let Some(ref path) = args.cookies {
+        for line in read_lines(path)? {
+            match ClewdrCookie::from_str(&line) {
+                Ok(cookie) => credentials.push(Credential::Cookie(cookie)),
+                Err(_) => println!("[{}] malformed cookie: {line}", " SKIP ".dimmed()),
+            }
+        }
+    }
+    Ok(credentials)
+}
+
+/// Submits a live credential to a running instance through the admin API
+async fn submit(
+    client: &Client,
+    url: &str,
+    password: &str,
+    credential: &Credential,
+) -> Result<(), String> {
+    let (path, body) = match credential {
+        Credential::Key(key) => ("key", json!({ "key": key })),
+        Credential::Cookie(cookie) => ("cookie", json!({ "cookie": cookie })),
+    };
+    let res = client
+        .post(format!("{}/api/{path}", url.trim_end_matches('/')))
+        .bearer_auth(password)
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if res.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("admin API answered {}", res.status()))
+    }
+}
+
+/// Merges live credentials into the config file
+async fn merge_into_config(live: Vec<Credential>) -> Result<(), ClewdrError> {
+    let mut config = CLEWDR_CONFIG.load().as_ref().to_owned();
+    if config.no_fs {
+        return Err(ClewdrError::UnexpectedNone {
+            msg: "no_fs is set, use --remote to import into a running instance",
+        });
+    }
+    for credential in live {
+        match credential {
+            Credential::Key(key) => {
+                config.gemini_keys.insert(KeyStatus::from(key));
+            }
+            Credential::Cookie(cookie) => {
+                config.cookie_array.insert(CookieStatus {
+                    cookie,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+    CLEWDR_CONFIG.store(Arc::new(config));
+    ClewdrConfig::save().await
+}
+
+/// Merges a snapshot written by `clewdr export` into the config file, cookies
+/// and keys already present take the snapshot's state, and the usage counters
+/// into `cookie_usage.json`
+async fn restore(path: &Path) -> Result<(), ClewdrError> {
+    let content = std::fs::read_to_string(path)?;
+    let snapshot = match serde_json::from_str::<PoolSnapshot>(&content) {
+        Ok(snapshot) => snapshot,
+        Err(_) => toml::from_str::<PoolSnapshot>(&content)?,
+    };
+    if snapshot.redacted {
+        return Err(ClewdrError::BadRequest {
+            msg: "The snapshot is redacted and cannot be imported",
+        });
+    }
+    let mut config = CLEWDR_CONFIG.load().as_ref().to_owned();
+    if config.no_fs {
+        return Err(ClewdrError::UnexpectedNone {
+            msg: "no_fs is set, the snapshot cannot be imported",
+        });
+    }
+    let mut usage = load_usage().await;
+    let cookies = snapshot
+        .cookies
+        .into_iter()
+        .chain(snapshot.exhausted_cookies);
+    let mut count = 0;
+    for cookie in cookies {
+        usage.insert(
+            shared_state::id(&cookie.cookie.to_string()),
+            cookie.usage.to_owned(),
+        );
+        config.cookie_array.replace(cookie);
+        count += 1;
+    }
+    for cookie in snapshot.invalid_cookies {
+        config.wasted_cookie.replace(cookie);
+        count += 1;
+    }
+    for key in snapshot.gemini_keys {
+        config.gemini_keys.replace(key);
+        count += 1;
+    }
+    CLEWDR_CONFIG.store(Arc::new(config));
+    ClewdrConfig::save().await?;
+    save_usage(&usage).await?;
+    println!("{count} restored from the snapshot of {}", snapshot.version);
+    Ok(())
+}
+
+/// Validates credentials from files with live probes and merges the live ones,
+/// for `clewdr import`
+///
+/// Live credentials are submitted to a running instance when `--remote` is
+/// given, otherwise they are written to the config file, which a running
+/// instance would overwrite. A `--snapshot` is restored first and not probed.
+///
+/// # Returns
+/// Whether every credential was live and imported
+pub async fn run(args: ImportArgs) -> Result<bool, ClewdrError> {
+    if let Some(ref path) = args.snapshot {
+        restore(path).await?;
+        if args.keys.is_none() && args.cookies.is_none() {
+            return Ok(true);
+        }
+    }
+    let credentials = parse(&args)?;
+    if credentials.is_empty() {
+        println!("Nothing to import, pass --keys, --cookies and/or --snapshot");
+        return Ok(false);
+    }
+    println!("Probing {} credential(s)...", credentials.len());
+    let proxy = CLEWDR_CONFIG.load().proxy.to_owned();
+    let results = stream::iter(credentials)
+        .map(|c| {
+            let proxy = proxy.to_owned();
+            async move {
+                let res = c.probe(proxy.as_deref()).await;
+                (c, res)
+            }
+        })
+        .buffer_unordered(args.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut live = vec![];
+    let mut dead = 0;
+    for (credential, res) in results {
+        match res {
+            Ok(()) => {
+                println!("[{}] {}", " LIVE ".green().bold(), credential.name());
+                live.push(credential);
+            }
+            Err(e) => {
+                dead += 1;
+                println!("[{}] {}: {e}", " DEAD ".red().bold(), credential.name());
+            }
+        }
+    }
+    println!("{} live, {} dead", live.len(), dead);
+    if live.is_empty() {
+        return Ok(false);
+    }
+
+    let mut failed = 0;
+    if let Some(ref url) = args.remote {
+        let password = args.admin_password.as_deref().unwrap_or_default();
+        let client = Client::new();
+        for credential in &live {
+            if let Err(e) = submit(&client, url, password, credential).await {
+                failed += 1;
+                println!("[{}] {}: {e}", " FAIL ".red().bold(), credential.name());
+            }
+        }
+        println!("{} submitted to {url}", live.len() - failed);
+    } else {
+        let count = live.len();
+        merge_into_config(live).await?;
+        println!("{count} merged into the config file");
+    }
+    Ok(dead == 0 && failed == 0)
+}
@@ -0,0 +1,498 @@
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ring::{hmac, signature};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+use url::Url;
+
+use crate::{
+    config::{Backend, CLEWDR_CONFIG, JwtAuth},
+    error::ClewdrError,
+};
+
+/// How long fetched signing keys are used before they are fetched again
+const JWKS_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Least time between two fetches, so tokens with unknown key IDs cannot
+/// flood the JWKS endpoint
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+const JWKS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Signing keys of `jwt_auth.jwks_url`, never locked across the fetch
+static JWKS: LazyLock<Mutex<JwksCache>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct JwksCache {
+    url: Option<Url>,
+    fetched: Option<Instant>,
+    keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Jwk {
+    kty: String,
+    #[serde(default)]
+    kid: Option<String>,
+    #[serde(default)]
+    crv: Option<String>,
+    #[serde(default)]
+    n: Option<String>,
+    #[serde(default)]
+    e: Option<String>,
+    #[serde(default)]
+    x: Option<String>,
+    #[serde(default)]
+    y: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    exp: Option<u64>,
+    #[serde(default)]
+    nbf: Option<u64>,
+    #[serde(default)]
+    iss: Option<String>,
+    /// A string or an array of strings
+    #[serde(default)]
+    aud: Option<Value>,
+    #[serde(default)]
+    sub: Option<String>,
+    /// Space separated, as in OAuth
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    scopes: Option<Vec<String>>,
+}
+
+/// What a verified token may use, carried by the
+/// [`Caller`](crate::middleware::Caller) of a request
+///
+/// Scopes are `route:<path prefix>`, `backend:<backend>` and
+/// `model:<model prefix>`. A token without scopes of a kind is not limited in
+/// that kind, e.g. `backend:gemini` alone allows every Gemini model.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenScopes {
+    pub subject: Option<String>,
+    /// `exp` of the token, checked again for requests sent on its behalf later
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    routes: Vec<String>,
+    #[serde(default)]
+    backends: Vec<String>,
+    #[serde(default)]
+    models: Vec<String>,
+}
+
+impl TokenScopes {
+    fn parse<'a>(
+        subject: Option<String>,
+        expires_at: u64,
+        scopes: impl Iterator<Item = &'a str>,
+    ) -> Self {
+        let mut parsed = Self {
+            subject,
+            expires_at: Some(expires_at),
+            ..Default::default()
+        };
+        for scope in scopes {
+            match scope.split_once(':') {
+                Some(("route", route)) => parsed.routes.push(route.to_string()),
+                Some(("backend", backend)) => parsed.backends.push(backend.to_string()),
+                Some(("model", model)) => parsed.models.push(model.to_string()),
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    /// Whether the token has expired since it was verified, with the
+    /// configured leeway
+    pub fn expired(&self) -> bool {
+        let leeway = CLEWDR_CONFIG
+            .load()
+            .jwt_auth
+            .as_ref()
+            .map_or(0, |c| c.leeway_secs);
+        self.expires_at
+            .is_some_and(|exp| exp.saturating_add(leeway) < unix_now())
+    }
+
+    pub fn allows_route(&self, path: &str) -> bool {
+        self.routes.is_empty() || self.routes.iter().any(|r| path.starts_with(r.as_str()))
+    }
+
+    pub fn allows_backend(&self, backend: Backend) -> bool {
+        let name = serde_json::to_value(backend).unwrap_or_default();
+        self.backends.is_empty() || self.backends.iter().any(|b| name == b.as_str())
+    }
+
+    /// Whether the token limits models at all, so the model needs checking
+    pub fn limits_models(&self) -> bool {
+        !self.models.is_empty()
+    }
+
+    /// Whether the model matches a `model:` prefix, a trailing `*` is allowed
+    pub fn allows_model(&self, model: &str) -> bool {
+        let model = model.trim_start_matches("models/");
+        !self.limits_models()
+            || self
+                .models
+                .iter()
+                .any(|m| model.starts_with(m.trim_end_matches('*')))
+    }
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp().max(0) as u64
+}
+
+/// Whether a key has the shape of a JWT rather than a password
+pub fn looks_like_jwt(key: &str) -> bool {
+    key.starts_with("eyJ") && key.split('.').count() == 3
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, ClewdrError> {
+    URL_SAFE_NO_PAD.decode(part).map_err(|e| {
+        warn!("Malformed JWT: {}", e);
+        ClewdrError::InvalidAuth
+    })
+}
+
+/// Finds the key the token names, fetching the key set if it is stale or
+/// does not know the key ID yet
+///
+/// The fetch time is claimed under the lock before fetching, so concurrent
+/// misses do not fetch again and verification of other tokens is not held up
+/// by a slow endpoint.
+async fn jwks_key(url: &Url, kid: Option<&str>) -> Option<Jwk> {
+    let find = |keys: &[Jwk]| {
+        keys.iter()
+            .find(|k| kid.is_none() || k.kid.as_deref() == kid)
+            .cloned()
+    };
+    {
+        let mut cache = JWKS.lock().unwrap_or_else(|e| e.into_inner());
+        if cache.url.as_ref() != Some(url) {
+            *cache = JwksCache {
+                url: Some(url.to_owned()),
+                ..Default::default()
+            };
+        }
+        let stale = cache.fetched.is_none_or(|t| t.elapsed() > JWKS_TTL);
+        let may_fetch = cache.fetched.is_none_or(|t| t.elapsed() > JWKS_MIN_REFRESH);
+        let key = find(&cache.keys);
+        if !(stale || key.is_none()) || !may_fetch {
+            return key;
+        }
+        cache.fetched = Some(Instant::now());
+    }
+    let fetched = async {
+        wreq::ClientBuilder::new()
+            .timeout(JWKS_TIMEOUT)
+            .build()?
+            .get(url.as_str())
+            .send()
+            .await?
+            .json::<Jwks>()
+            .await
+    }
+    .await;
+    let mut cache = JWKS.lock().unwrap_or_else(|e| e.into_inner());
+    match fetched {
+        // the configured key set changed during the fetch
+        Ok(_) if cache.url.as_ref() != Some(url) => {}
+        Ok(jwks) => cache.keys = jwks.keys,
+        Err(e) => warn!("Failed to fetch JWKS from {}: {}", url, e),
+    }
+    find(&cache.keys)
+}
+
+/// Checks the signature of `message` with a key of the key set
+fn verify_jwk(jwk: &Jwk, alg: &str, message: &[u8], sig: &[u8]) -> Result<(), ClewdrError> {
+    let verified = match (alg, jwk.kty.as_str()) {
+        ("RS256", "RSA") => {
+            let (Some(n), Some(e)) = (&jwk.n, &jwk.e) else {
+                return Err(ClewdrError::InvalidAuth);
+            };
+            signature::RsaPublicKeyComponents {
+                n: decode(n)?,
+                e: decode(e)?,
+            }
+            .verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig)
+            .is_ok()
+        }
+        ("ES256", "EC") if jwk.crv.as_deref() == Some("P-256") => {
+            let (Some(x), Some(y)) = (&jwk.x, &jwk.y) else {
+                return Err(ClewdrError::InvalidAuth);
+            };
+            let mut point = vec![0x04];
+            point.extend(decode(x)?);
+            point.extend(decode(y)?);
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                .verify(message, sig)
+                .is_ok()
+        }
+        _ => false,
+    };
+    if verified {
+        Ok(())
+    } else {
+        warn!("JWT signature does not verify");
+        Err(ClewdrError::InvalidAuth)
+    }
+}
+
+/// Verifies a downstream JWT against `jwt_auth`: HS256 with the shared
+/// secret, RS256 and ES256 with the key set, then expiry, issuer and audience
+///
+/// # Returns
+/// The scopes of the token
+pub async fn verify(token: &str) -> Result<TokenScopes, ClewdrError> {
+    let Some(config) = CLEWDR_CONFIG.load().jwt_auth.to_owned() else {
+        return Err(ClewdrError::InvalidAuth);
+    };
+    verify_with(&config, token, unix_now()).await
+}
+
+async fn verify_with(config: &JwtAuth, token: &str, now: u64) -> Result<TokenScopes, ClewdrError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(sig)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(ClewdrError::InvalidAuth);
+    };
+    let message = format!("{header}.{payload}");
+    let sig = decode(sig)?;
+    let header =
+        serde_json::from_slice::<Header>(&decode(header)?).map_err(|_| ClewdrError::InvalidAuth)?;
+    match (header.alg.as_str(), &config.secret, &config.jwks_url) {
+        ("HS256", Some(secret), _) => {
+            let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+            hmac::verify(&key, message.as_bytes(), &sig).map_err(|_| {
+                warn!("JWT signature does not verify");
+                ClewdrError::InvalidAuth
+            })?;
+        }
+        ("RS256" | "ES256", _, Some(url)) => {
+            let Some(jwk) = jwks_key(url, header.kid.as_deref()).await else {
+                warn!("No JWKS key for JWT key ID {:?}", header.kid);
+                return Err(ClewdrError::InvalidAuth);
+            };
+            verify_jwk(&jwk, &header.alg, message.as_bytes(), &sig)?;
+        }
+        (alg, _, _) => {
+            warn!("JWT algorithm {} is not accepted", alg);
+            return Err(ClewdrError::InvalidAuth);
+        }
+    }
+    let claims = serde_json::from_slice::<Claims>(&decode(payload)?)
+        .map_err(|_| ClewdrError::InvalidAuth)?;
+    let leeway = config.leeway_secs;
+    let Some(exp) = claims.exp else {
+        warn!("JWT without exp rejected");
+        return Err(ClewdrError::InvalidAuth);
+    };
+    if exp.saturating_add(leeway) < now
+        || claims
+            .nbf
+            .is_some_and(|nbf| nbf > now.saturating_add(leeway))
+    {
+        warn!("JWT expired or not yet valid");
+        return Err(ClewdrError::InvalidAuth);
+    }
+    if let Some(ref issuer) = config.issuer
+        && claims.iss.as_ref() != Some(issuer)
+    {
+        warn!("JWT issuer {:?} rejected", claims.iss);
+        return Err(ClewdrError::InvalidAuth);
+    }
+    if let Some(ref audience) = config.audience {
+        let matches = match claims.aud {
+            Some(Value::String(ref aud)) => aud == audience,
+            Some(Value::Array(ref auds)) => auds.iter().any(|a| a == audience.as_str()),
+            _ => false,
+        };
+        if !matches {
+            warn!("JWT audience rejected");
+            return Err(ClewdrError::InvalidAuth);
+        }
+    }
+    let scopes = claims
+        .scope
+        .iter()
+        .flat_map(|s| s.split_whitespace())
+        .chain(claims.scopes.iter().flatten().map(String::as_str));
+    Ok(TokenScopes::parse(claims.sub, exp, scopes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "test-secret";
+    const NOW: u64 = 1_700_000_000;
+
+    fn config() -> JwtAuth {
+        JwtAuth {
+            secret: Some(SECRET.to_string()),
+            jwks_url: None,
+            issuer: None,
+            audience: None,
+            leeway_secs: 30,
+        }
+    }
+
+    fn encode(value: Value) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&value).unwrap())
+    }
+
+    fn hs256(header: Value, claims: Value) -> String {
+        let message = format!("{}.{}", encode(header), encode(claims));
+        let key = hmac::Key::new(hmac::HMAC_SHA256, SECRET.as_bytes());
+        let sig = hmac::sign(&key, message.as_bytes());
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(sig.as_ref()))
+    }
+
+    fn token(claims: Value) -> String {
+        hs256(serde_json::json!({ "alg": "HS256", "typ": "JWT" }), claims)
+    }
+
+    async fn verify(token: &str) -> Result<TokenScopes, ClewdrError> {
+        verify_with(&config(), token, NOW).await
+    }
+
+    #[tokio::test]
+    async fn test_valid_token() {
+        let token = token(serde_json::json!({ "sub": "alice", "exp": NOW + 60 }));
+        assert!(looks_like_jwt(&token));
+        let scopes = verify(&token).await.unwrap();
+        assert_eq!(scopes.subject.as_deref(), Some("alice"));
+        assert!(scopes.allows_route("/v1/messages"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_token() {
+        // within the leeway
+        let token = token(serde_json::json!({ "exp": NOW - 10 }));
+        assert!(verify(&token).await.is_ok());
+        let token = token(serde_json::json!({ "exp": NOW - 31 }));
+        assert!(verify(&token).await.is_err());
+        let token = token(serde_json::json!({ "sub": "no exp" }));
+        assert!(verify(&token).await.is_err());
+        // no overflow near the end of time
+        let token = token(serde_json::json!({ "exp": u64::MAX, "nbf": u64::MAX }));
+        assert!(verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_not_yet_valid_token() {
+        let token = token(serde_json::json!({ "exp": NOW + 600, "nbf": NOW + 10 }));
+        assert!(verify(&token).await.is_ok());
+        let token = token(serde_json::json!({ "exp": NOW + 600, "nbf": NOW + 31 }));
+        assert!(verify(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_wrong_signature_or_alg() {
+        let claims = serde_json::json!({ "exp": NOW + 60 });
+        let signed = token(claims.to_owned());
+        let parts = signed.split('.').collect::<Vec<_>>();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            encode(serde_json::json!({ "exp": NOW + 6000 })),
+            parts[2]
+        );
+        assert!(verify(&forged).await.is_err());
+        let none = format!(
+            "{}.{}.",
+            encode(serde_json::json!({ "alg": "none" })),
+            encode(claims.to_owned())
+        );
+        assert!(verify(&none).await.is_err());
+        // signed with the secret, but claiming an algorithm without a key set
+        let rs256 = hs256(serde_json::json!({ "alg": "RS256" }), claims.to_owned());
+        assert!(verify(&rs256).await.is_err());
+        let hs384 = hs256(serde_json::json!({ "alg": "HS384" }), claims);
+        assert!(verify(&hs384).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_kid() {
+        let url = Url::parse("https://jwks.invalid/keys").unwrap();
+        // fetched just now, so the unknown key ID does not trigger a fetch
+        *JWKS.lock().unwrap() = JwksCache {
+            url: Some(url.to_owned()),
+            fetched: Some(Instant::now()),
+            keys: vec![Jwk {
+                kty: "EC".to_string(),
+                kid: Some("known".to_string()),
+                crv: Some("P-256".to_string()),
+                n: None,
+                e: None,
+                x: None,
+                y: None,
+            }],
+        };
+        let config = JwtAuth {
+            jwks_url: Some(url),
+            ..config()
+        };
+        let token = hs256(
+            serde_json::json!({ "alg": "ES256", "kid": "unknown" }),
+            serde_json::json!({ "exp": NOW + 60 }),
+        );
+        assert!(verify_with(&config, &token, NOW).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_issuer_and_audience() {
+        let config = JwtAuth {
+            issuer: Some("clewdr".to_string()),
+            audience: Some("api".to_string()),
+            ..config()
+        };
+        let good = token(serde_json::json!({
+            "exp": NOW + 60, "iss": "clewdr", "aud": ["other", "api"]
+        }));
+        assert!(verify_with(&config, &good, NOW).await.is_ok());
+        let bad = token(serde_json::json!({ "exp": NOW + 60, "iss": "clewdr", "aud": "other" }));
+        assert!(verify_with(&config, &bad, NOW).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_scopes() {
+        let token = token(serde_json::json!({
+            "exp": NOW + 60,
+            "scope": "route:/gemini/ backend:gemini",
+            "scopes": ["model:gemini-2.5-*"],
+        }));
+        let scopes = verify(&token).await.unwrap();
+        assert!(scopes.allows_route("/gemini/chat/completions"));
+        assert!(!scopes.allows_route("/v1/messages"));
+        assert!(scopes.allows_backend(Backend::Gemini));
+        assert!(!scopes.allows_backend(Backend::ClaudeCode));
+        assert!(scopes.limits_models());
+        assert!(scopes.allows_model("models/gemini-2.5-pro"));
+        assert!(!scopes.allows_model("gemini-2.0-flash"));
+        assert!(!scopes.allows_model("claude-sonnet-4"));
+    }
+}
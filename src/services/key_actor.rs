@@ -1,15 +1,73 @@
-use std::collections::{HashSet, VecDeque};
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
+use moka::sync::Cache;
 use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
 use serde::Serialize;
 use snafu::{GenerateImplicitData, Location};
-use tracing::{error, info};
+use tracing::{Span, debug, error, info, warn};
 
 use crate::{
-    config::{CLEWDR_CONFIG, ClewdrConfig, KeyStatus},
+    config::{
+        BudgetState, CLEWDR_CONFIG, ClewdrConfig, GeminiKey, KeyShard, KeyStatus, pacific_day,
+    },
     error::ClewdrError,
+    services::{
+        latency_stats::LATENCY_STATS,
+        shared_state::{self, SharedKeyState},
+    },
 };
 
+/// Header a client uses to pick the key tiers serving it, comma separated in
+/// fallback order
+pub const KEY_TIER_HEADER: &str = "x-clewdr-key-tier";
+
+/// How long config writes are held back, so bursts of changes are written once
+const SAVE_DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often a request waiting for a key retries
+const KEY_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keys answering this many times slower than the fastest usable key are
+/// passed over when `prefer_fast_keys` is set
+const SLOW_KEY_FACTOR: f64 = 2.0;
+
+/// Requests currently waiting for a key
+static KEY_WAITERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Place among the requests waiting for a key, left when dropped
+struct KeyWaiter;
+
+impl KeyWaiter {
+    fn enter(max_depth: usize) -> Result<Self, ClewdrError> {
+        if KEY_WAITERS.fetch_add(1, Ordering::Relaxed) >= max_depth {
+            KEY_WAITERS.fetch_sub(1, Ordering::Relaxed);
+            return Err(ClewdrError::QueueFull);
+        }
+        Ok(Self)
+    }
+}
+
+impl Drop for KeyWaiter {
+    fn drop(&mut self) {
+        KEY_WAITERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What a request needs from a key
+#[derive(Debug, Clone, Default)]
+pub struct KeyRequest {
+    /// Hash of the conversation, pins it to a key
+    pub session: Option<u64>,
+    /// Tiers to take the key from in order, any tier if empty
+    pub tiers: Vec<String>,
+    /// Model the key is used for, keys cooling down for it are skipped
+    pub model: Option<String>,
+}
+
 #[derive(Debug, Serialize, Clone)]
 pub struct KeyStatusInfo {
     pub valid: Vec<KeyStatus>,
@@ -18,56 +76,256 @@ pub struct KeyStatusInfo {
 /// Messages that the KeyActor can handle
 #[derive(Debug)]
 enum KeyActorMessage {
-    /// Return a Key
-    Return(KeyStatus),
+    /// Return a Key, with the span of the request that used it
+    Return(KeyStatus, Span),
+    /// Count tokens consumed through a Key against its daily budget
+    Consume(GeminiKey, u64),
+    /// Apply the cooldowns and usage recorded by other instances
+    Sync(Vec<SharedKeyState>),
     /// Submit a new Key
     Submit(KeyStatus),
-    /// Request to get a Key
-    Request(RpcReplyPort<Result<KeyStatus, ClewdrError>>),
+    /// Request to get a Key, with the span of the request asking for it
+    Request(
+        KeyRequest,
+        Span,
+        RpcReplyPort<Result<KeyStatus, ClewdrError>>,
+    ),
     /// Get all Key status information
     GetStatus(RpcReplyPort<KeyStatusInfo>),
     /// Delete a Key
     Delete(KeyStatus, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Lift the suspension and model cooldowns of every Key
+    ClearCooldowns(RpcReplyPort<usize>),
+    /// Suspend every Key until the given timestamp, e.g. for maintenance
+    CooldownAll(i64, RpcReplyPort<usize>),
+    /// Move the given Keys to the front of the rotation, in order
+    Reorder(Vec<GeminiKey>, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Dispatch a Key first for the given number of requests
+    Pin(GeminiKey, u32, RpcReplyPort<Result<(), ClewdrError>>),
+    /// Write pending changes to the config file
+    Flush,
 }
 
 /// KeyActor state - manages the collection of valid keys
-type KeyActorState = VecDeque<KeyStatus>;
+struct KeyActorState {
+    valid: VecDeque<KeyStatus>,
+    /// Keys pinned to a session hash
+    moka: Cache<u64, KeyStatus>,
+    /// Key dispatched before any other, and the requests it is pinned for
+    pinned: Option<(GeminiKey, u32)>,
+    /// Keys changed since the config file was last written
+    dirty: bool,
+    /// A flush is already on its way
+    flush_scheduled: bool,
+}
 
 /// Key actor that handles key distribution and status tracking using Ractor
 struct KeyActor;
 
 impl KeyActor {
-    /// Saves the current state of keys to the configuration
-    fn save(state: &KeyActorState) {
+    /// Saves the current state of keys to the configuration, the file is
+    /// written by the next [`KeyActorMessage::Flush`]
+    fn save(state: &mut KeyActorState) {
         CLEWDR_CONFIG.rcu(|config| {
             let mut config = ClewdrConfig::clone(config);
-            config.gemini_keys = state.iter().cloned().collect();
+            // keys of other shards are kept as they are
+            let foreign = config
+                .gemini_keys
+                .iter()
+                .filter(|k| !config.owns_key(&k.key))
+                .cloned()
+                .collect::<Vec<_>>();
+            config.gemini_keys = state.valid.iter().cloned().chain(foreign).collect();
             config
         });
+        state.dirty = true;
+    }
 
-        tokio::spawn(async move {
-            let result = CLEWDR_CONFIG.load().save().await;
-            match result {
-                Ok(_) => info!("Configuration saved successfully"),
-                Err(e) => error!("Save task panicked: {}", e),
+    /// Writes the configuration to disk if keys changed since the last write,
+    /// the actor is the only writer of key changes so writes never interleave
+    async fn flush(state: &mut KeyActorState) {
+        if !state.dirty {
+            return;
+        }
+        state.dirty = false;
+        match ClewdrConfig::save().await {
+            Ok(_) => info!("Configuration saved successfully"),
+            Err(e) => {
+                error!("Failed to save configuration: {}", e);
+                // retried with the next change
+                state.dirty = true;
             }
-        });
+        }
+    }
+
+    /// Moves the first usable key matching `filter` to the back of the
+    /// rotation, so each tier is rotated on its own
+    ///
+    /// Keys low on their daily budget are only picked when no other key is
+    /// left, so usage spreads across the pool before keys hit hard 429s. With
+    /// `prefer_fast_keys`, keys much slower to answer than the fastest usable
+    /// one are skipped, keys without latency samples are always tried.
+    fn rotate(
+        state: &mut KeyActorState,
+        model: Option<&str>,
+        now: i64,
+        today: &str,
+        filter: impl Fn(&KeyStatus) -> bool,
+    ) -> Option<KeyStatus> {
+        let config = CLEWDR_CONFIG.load();
+        let rotate_at = config.key_budget_rotate_at;
+        for budget in [BudgetState::Fresh, BudgetState::Low] {
+            let usable = |k: &KeyStatus| {
+                filter(k) && k.usable_for(model, now) && k.budget(today, rotate_at) <= budget
+            };
+            let latency = |k: &KeyStatus| LATENCY_STATS.first_byte_ms(&k.key.to_string());
+            let fastest = config
+                .prefer_fast_keys
+                .then(|| {
+                    state
+                        .valid
+                        .iter()
+                        .filter(|k| usable(k))
+                        .filter_map(latency)
+                        .reduce(f64::min)
+                })
+                .flatten();
+            let Some(pos) = state.valid.iter().position(|k| {
+                usable(k)
+                    && fastest.is_none_or(|fastest| {
+                        latency(k).is_none_or(|ms| ms <= fastest * SLOW_KEY_FACTOR)
+                    })
+            }) else {
+                continue;
+            };
+            let key = state.valid.remove(pos)?;
+            state.valid.push_back(key.to_owned());
+            return Some(key);
+        }
+        None
+    }
+
+    /// Counts a dispatched request against the budget of `key`
+    fn charge(state: &mut KeyActorState, key: &mut KeyStatus, today: &str) {
+        if let Some(current) = state.valid.iter_mut().find(|k| *k == key) {
+            current.usage.today(today).requests += 1;
+            key.usage = current.usage.to_owned();
+            shared_state::add_key_usage(&key.key, today, 1, 0);
+        }
     }
 
     /// Dispatches a key for use
-    fn dispatch(state: &mut KeyActorState) -> Result<KeyStatus, ClewdrError> {
-        let key = state.pop_front().ok_or(ClewdrError::NoKeyAvailable)?;
-        state.push_back(key.to_owned());
+    ///
+    /// Suspended and quarantined keys, and keys cooling down for the model,
+    /// are skipped by the rotation, tiers are tried in order
+    fn dispatch(state: &mut KeyActorState, req: KeyRequest) -> Result<KeyStatus, ClewdrError> {
+        let now = chrono::Utc::now().timestamp();
+        let today = pacific_day();
+        let rotate_at = CLEWDR_CONFIG.load().key_budget_rotate_at;
+        let KeyRequest {
+            session: hash,
+            tiers,
+            model,
+        } = req;
+        let model = model.as_deref();
+        let allowed = |k: &KeyStatus| tiers.is_empty() || tiers.iter().any(|t| k.in_tier(t));
+        let dispatchable = |k: &KeyStatus| {
+            k.usable_for(model, now)
+                && allowed(k)
+                && k.budget(&today, rotate_at) != BudgetState::Exhausted
+        };
+        if let Some((ref pinned, ref mut remaining)) = state.pinned
+            && let Some(key) = state
+                .valid
+                .iter()
+                .find(|&k| k.key == *pinned && dispatchable(k))
+        {
+            let mut key = key.to_owned();
+            *remaining -= 1;
+            if *remaining == 0 {
+                info!("Key {} is no longer pinned", key.key.ellipse());
+                state.pinned = None;
+            }
+            Self::charge(state, &mut key, &today);
+            return Ok(key);
+        }
+        if let Some(hash) = hash
+            && let Some(pinned) = state.moka.get(&hash)
+        {
+            if let Some(key) = state
+                .valid
+                .iter()
+                .find(|&k| k == &pinned && dispatchable(k))
+            {
+                let mut key = key.to_owned();
+                // renew moka cache
+                state.moka.insert(hash, key.to_owned());
+                Self::charge(state, &mut key, &today);
+                return Ok(key);
+            }
+            // cooling down or removed, the session moves to the next key
+            info!(
+                "Key {} of session {:016x} is unavailable, remapping",
+                pinned.key.ellipse(),
+                hash
+            );
+        }
+        let mut key = if tiers.is_empty() {
+            Self::rotate(state, model, now, &today, |_| true)
+        } else {
+            tiers
+                .iter()
+                .find_map(|tier| Self::rotate(state, model, now, &today, |k| k.in_tier(tier)))
+        }
+        .ok_or(ClewdrError::NoKeyAvailable)?;
+        Self::charge(state, &mut key, &today);
+        if let Some(hash) = hash {
+            state.moka.insert(hash, key.to_owned());
+        }
         Ok(key)
     }
 
     /// Collects (returns) a key back to the pool
-    fn collect(state: &mut KeyActorState, key: KeyStatus) {
-        let Some(pos) = state.iter().position(|k| *k == key) else {
+    fn collect(state: &mut KeyActorState, mut key: KeyStatus) {
+        let Some(pos) = state.valid.iter().position(|k| *k == key) else {
             error!("Key not found in valid keys");
             return;
         };
-        state[pos] = key;
+        // another request may have suspended the key since it was dispatched
+        let current = &state.valid[pos];
+        let cooled = key.suspended_until > current.suspended_until
+            || key
+                .model_cooldowns
+                .iter()
+                .any(|(m, until)| current.model_cooldowns.get(m) < Some(until));
+        key.merge_cooldowns(current, chrono::Utc::now().timestamp());
+        if cooled {
+            shared_state::publish_key_cooldowns(&key);
+        }
+        key.quarantined |= current.quarantined;
+        // the pool's copy counts the requests of every holder
+        key.usage = current.usage.to_owned();
+        state.valid[pos] = key;
+    }
+
+    /// Applies the state other instances shared, keeping the later cooldown
+    /// and the larger usage count
+    fn sync(state: &mut KeyActorState, shared: Vec<SharedKeyState>) {
+        let now = chrono::Utc::now().timestamp();
+        for s in shared {
+            let Some(current) = state.valid.iter_mut().find(|k| k.key == s.key) else {
+                continue;
+            };
+            let remote = KeyStatus {
+                suspended_until: s.suspended_until,
+                model_cooldowns: s.model_cooldowns.into_iter().collect(),
+                ..current.to_owned()
+            };
+            current.merge_cooldowns(&remote, now);
+            let usage = current.usage.today(&s.day);
+            usage.requests = usage.requests.max(s.requests);
+            usage.tokens = usage.tokens.max(s.tokens);
+        }
     }
 
     /// Accepts a new key into the valid collection
@@ -76,23 +334,115 @@ impl KeyActor {
             info!("Key already exists");
             return;
         }
-        state.push_back(key);
+        if !CLEWDR_CONFIG.load().owns_key(&key.key) {
+            info!("Key belongs to another shard, stored without using it");
+            CLEWDR_CONFIG.rcu(|config| {
+                let mut config = ClewdrConfig::clone(config);
+                config.gemini_keys.insert(key.to_owned());
+                config
+            });
+        } else {
+            state.valid.push_back(key);
+        }
         Self::save(state);
     }
 
+    /// Lifts every suspension and model cooldown, quarantined keys stay out
+    /// of rotation
+    ///
+    /// # Returns
+    /// Number of keys that were cooling down
+    fn clear_cooldowns(state: &mut KeyActorState) -> usize {
+        let mut cleared = 0;
+        for key in state.valid.iter_mut() {
+            if key.suspended_until.is_none() && key.model_cooldowns.is_empty() {
+                continue;
+            }
+            key.suspended_until = None;
+            key.model_cooldowns.clear();
+            shared_state::clear_key_cooldowns(&key.key);
+            cleared += 1;
+        }
+        if cleared > 0 {
+            Self::save(state);
+        }
+        cleared
+    }
+
+    /// Suspends every key until `until`, keeping later suspensions
+    ///
+    /// # Returns
+    /// Number of keys suspended
+    fn cooldown_all(state: &mut KeyActorState, until: i64) -> usize {
+        for key in state.valid.iter_mut() {
+            key.suspended_until = key.suspended_until.max(Some(until));
+            shared_state::publish_key_cooldowns(key);
+        }
+        Self::save(state);
+        state.valid.len()
+    }
+
+    /// Moves `keys` to the front of the rotation in the given order, the
+    /// other keys keep their relative order
+    fn reorder(state: &mut KeyActorState, keys: Vec<GeminiKey>) -> Result<(), ClewdrError> {
+        if let Some(missing) = keys
+            .iter()
+            .find(|k| !state.valid.iter().any(|v| v.key == **k))
+        {
+            return Err(ClewdrError::PathNotFound {
+                msg: format!("Key {} is not in rotation", missing.ellipse()),
+            });
+        }
+        for key in keys.iter().rev() {
+            if let Some(pos) = state.valid.iter().position(|k| k.key == *key)
+                && let Some(key) = state.valid.remove(pos)
+            {
+                state.valid.push_front(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pins `key` for the next `requests` requests it can serve, replacing
+    /// any earlier pin, 0 unpins
+    fn pin(state: &mut KeyActorState, key: GeminiKey, requests: u32) -> Result<(), ClewdrError> {
+        if !state.valid.iter().any(|k| k.key == key) {
+            return Err(ClewdrError::PathNotFound {
+                msg: format!("Key {} is not in rotation", key.ellipse()),
+            });
+        }
+        if requests == 0 {
+            state.pinned = None;
+            return Ok(());
+        }
+        info!("Key {} pinned for {} requests", key.ellipse(), requests);
+        state.pinned = Some((key, requests));
+        Ok(())
+    }
+
     /// Creates a report of all key statuses
     fn report(state: &KeyActorState) -> KeyStatusInfo {
         KeyStatusInfo {
-            valid: state.iter().cloned().collect(),
+            valid: state.valid.iter().cloned().collect(),
         }
     }
 
     /// Deletes a key from the collection
     fn delete(state: &mut KeyActorState, key: KeyStatus) -> Result<(), ClewdrError> {
-        let size_before = state.len();
-        state.retain(|k| *k != key);
+        let size_before = state.valid.len();
+        state.valid.retain(|k| *k != key);
+        let config = CLEWDR_CONFIG.load();
+        if !config.owns_key(&key.key) && config.gemini_keys.contains(&key) {
+            CLEWDR_CONFIG.rcu(|config| {
+                let mut config = ClewdrConfig::clone(config);
+                config.gemini_keys.remove(&key);
+                config
+            });
+            Self::save(state);
+            return Ok(());
+        }
 
-        if state.len() < size_before {
+        if state.valid.len() < size_before {
             Self::save(state);
             Ok(())
         } else {
@@ -113,25 +463,50 @@ impl Actor for KeyActor {
         _myself: ActorRef<Self::Msg>,
         args: Self::Arguments,
     ) -> Result<Self::State, ActorProcessingErr> {
-        let state: Self::State = VecDeque::from_iter(args);
-        Ok(state)
+        let moka = Cache::builder()
+            .max_capacity(1000)
+            .time_to_idle(std::time::Duration::from_secs(
+                CLEWDR_CONFIG.load().sticky_session_ttl_secs,
+            ))
+            .build();
+        Ok(KeyActorState {
+            valid: VecDeque::from_iter(args),
+            moka,
+            pinned: None,
+            dirty: false,
+            flush_scheduled: false,
+        })
     }
 
     async fn handle(
         &self,
-        _myself: ActorRef<Self::Msg>,
+        myself: ActorRef<Self::Msg>,
         message: Self::Msg,
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         match message {
-            KeyActorMessage::Return(key) => {
-                Self::collect(state, key);
+            KeyActorMessage::Flush => {
+                state.flush_scheduled = false;
+                Self::flush(state).await;
+            }
+            KeyActorMessage::Return(key, span) => {
+                span.in_scope(|| Self::collect(state, key));
+            }
+            KeyActorMessage::Consume(key, tokens) => {
+                if let Some(current) = state.valid.iter_mut().find(|k| k.key == key) {
+                    let today = pacific_day();
+                    current.usage.today(&today).tokens += tokens;
+                    shared_state::add_key_usage(&key, &today, 0, tokens);
+                }
+            }
+            KeyActorMessage::Sync(shared) => {
+                Self::sync(state, shared);
             }
             KeyActorMessage::Submit(key) => {
                 Self::accept(state, key);
             }
-            KeyActorMessage::Request(reply_port) => {
-                let result = Self::dispatch(state);
+            KeyActorMessage::Request(req, span, reply_port) => {
+                let result = span.in_scope(|| Self::dispatch(state, req));
                 reply_port.send(result)?;
             }
             KeyActorMessage::GetStatus(reply_port) => {
@@ -142,6 +517,22 @@ impl Actor for KeyActor {
                 let result = Self::delete(state, key);
                 reply_port.send(result)?;
             }
+            KeyActorMessage::ClearCooldowns(reply_port) => {
+                reply_port.send(Self::clear_cooldowns(state))?;
+            }
+            KeyActorMessage::CooldownAll(until, reply_port) => {
+                reply_port.send(Self::cooldown_all(state, until))?;
+            }
+            KeyActorMessage::Reorder(keys, reply_port) => {
+                reply_port.send(Self::reorder(state, keys))?;
+            }
+            KeyActorMessage::Pin(key, requests, reply_port) => {
+                reply_port.send(Self::pin(state, key, requests))?;
+            }
+        }
+        if state.dirty && !state.flush_scheduled {
+            state.flush_scheduled = true;
+            myself.send_after(SAVE_DEBOUNCE, || KeyActorMessage::Flush);
         }
         Ok(())
     }
@@ -152,10 +543,36 @@ impl Actor for KeyActor {
         state: &mut Self::State,
     ) -> Result<(), ActorProcessingErr> {
         KeyActor::save(state);
+        KeyActor::flush(state).await;
         Ok(())
     }
 }
 
+/// Logs the part of the key list this instance manages, warning about shard
+/// settings that make instances overlap or leave keys unused
+fn check_shard(shard: KeyShard, owned: usize, total: usize) {
+    if !shard.is_valid() {
+        warn!(
+            "Key shard {} of {} is out of range, managing all keys, which overlaps with every other instance",
+            shard.index, shard.count
+        );
+        return;
+    }
+    if shard.count == 1 {
+        warn!("Key shard count is 1, this instance manages all keys");
+    }
+    if owned == 0 && total > 0 {
+        warn!(
+            "Key shard {} of {} holds none of the {} keys",
+            shard.index, shard.count, total
+        );
+    }
+    info!(
+        "Managing {} of {} keys as shard {} of {}",
+        owned, total, shard.index, shard.count
+    );
+}
+
 /// Handle for interacting with the KeyActor
 #[derive(Clone)]
 pub struct KeyActorHandle {
@@ -164,32 +581,88 @@ pub struct KeyActorHandle {
 
 impl KeyActorHandle {
     /// Create a new KeyActor and return a handle to it
+    ///
+    /// Only the keys of this instance's shard are put in rotation
     pub async fn start() -> Result<Self, ractor::SpawnErr> {
-        let (actor_ref, _join_handle) =
-            Actor::spawn(None, KeyActor, CLEWDR_CONFIG.load().gemini_keys.clone()).await?;
+        let config = CLEWDR_CONFIG.load();
+        let keys = config
+            .gemini_keys
+            .iter()
+            .filter(|k| config.owns_key(&k.key))
+            .cloned()
+            .collect::<HashSet<_>>();
+        if let Some(shard) = config.key_shard {
+            check_shard(shard, keys.len(), config.gemini_keys.len());
+        }
+        let (actor_ref, _join_handle) = Actor::spawn(None, KeyActor, keys).await?;
         Ok(Self { actor_ref })
     }
 
     /// Request a key from the key actor
-    pub async fn request(&self) -> Result<KeyStatus, ClewdrError> {
-        ractor::call!(self.actor_ref, KeyActorMessage::Request).map_err(|e| {
-            ClewdrError::RactorError {
-                loc: Location::generate(),
-                msg: format!("Failed to communicate with KeyActor for request operation: {e}"),
-            }
+    pub async fn request(&self, req: KeyRequest) -> Result<KeyStatus, ClewdrError> {
+        ractor::call!(
+            self.actor_ref,
+            KeyActorMessage::Request,
+            req,
+            Span::current()
+        )
+        .map_err(|e| ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with KeyActor for request operation: {e}"),
         })?
     }
 
+    /// Request a key, waiting up to `key_wait_secs` for one to come off its
+    /// cooldown or be added when none is usable
+    pub async fn request_waiting(&self, req: KeyRequest) -> Result<KeyStatus, ClewdrError> {
+        let (timeout, max_depth) = {
+            let config = CLEWDR_CONFIG.load();
+            (config.key_wait_secs, config.queue_max_depth)
+        };
+        match self.request(req.to_owned()).await {
+            Err(ClewdrError::NoKeyAvailable) if timeout > 0 => {}
+            res => return res,
+        }
+        let _waiter = KeyWaiter::enter(max_depth)?;
+        debug!("No key available, waiting up to {}s", timeout);
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        loop {
+            let wait = KEY_RECHECK_INTERVAL.min(deadline.saturating_duration_since(Instant::now()));
+            tokio::time::sleep(wait).await;
+            match self.request(req.to_owned()).await {
+                Err(ClewdrError::NoKeyAvailable) if Instant::now() < deadline => continue,
+                Err(ClewdrError::NoKeyAvailable) => return Err(ClewdrError::KeyWaitTimeout),
+                res => return res,
+            }
+        }
+    }
+
     /// Return a key to the key actor
     pub async fn return_key(&self, key: KeyStatus) -> Result<(), ClewdrError> {
-        ractor::cast!(self.actor_ref, KeyActorMessage::Return(key)).map_err(|e| {
-            ClewdrError::RactorError {
-                loc: Location::generate(),
-                msg: format!("Failed to communicate with KeyActor for return operation: {e}"),
-            }
+        ractor::cast!(
+            self.actor_ref,
+            KeyActorMessage::Return(key, Span::current())
+        )
+        .map_err(|e| ClewdrError::RactorError {
+            loc: Location::generate(),
+            msg: format!("Failed to communicate with KeyActor for return operation: {e}"),
         })
     }
 
+    /// Count `tokens` consumed through `key` against its daily budget
+    pub fn consume(&self, key: GeminiKey, tokens: u64) {
+        if let Err(e) = ractor::cast!(self.actor_ref, KeyActorMessage::Consume(key, tokens)) {
+            error!("Failed to communicate with KeyActor for consume operation: {e}");
+        }
+    }
+
+    /// Apply the state shared by other instances
+    pub fn sync(&self, shared: Vec<SharedKeyState>) {
+        if let Err(e) = ractor::cast!(self.actor_ref, KeyActorMessage::Sync(shared)) {
+            error!("Failed to communicate with KeyActor for sync operation: {e}");
+        }
+    }
+
     /// Submit a new key to the key actor
     pub async fn submit(&self, key: KeyStatus) -> Result<(), ClewdrError> {
         ractor::cast!(self.actor_ref, KeyActorMessage::Submit(key)).map_err(|e| {
@@ -219,4 +692,52 @@ impl KeyActorHandle {
             }
         })?
     }
+
+    /// Lift the cooldowns of every key
+    ///
+    /// # Returns
+    /// Number of keys that were cooling down
+    pub async fn clear_cooldowns(&self) -> Result<usize, ClewdrError> {
+        ractor::call!(self.actor_ref, KeyActorMessage::ClearCooldowns).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!(
+                    "Failed to communicate with KeyActor for clear cooldowns operation: {e}"
+                ),
+            }
+        })
+    }
+
+    /// Suspend every key until `until`
+    ///
+    /// # Returns
+    /// Number of keys suspended
+    pub async fn cooldown_all(&self, until: i64) -> Result<usize, ClewdrError> {
+        ractor::call!(self.actor_ref, KeyActorMessage::CooldownAll, until).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with KeyActor for cooldown operation: {e}"),
+            }
+        })
+    }
+
+    /// Move `keys` to the front of the rotation
+    pub async fn reorder(&self, keys: Vec<GeminiKey>) -> Result<(), ClewdrError> {
+        ractor::call!(self.actor_ref, KeyActorMessage::Reorder, keys).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with KeyActor for reorder operation: {e}"),
+            }
+        })?
+    }
+
+    /// Dispatch `key` first for the next `requests` requests, 0 unpins it
+    pub async fn pin(&self, key: GeminiKey, requests: u32) -> Result<(), ClewdrError> {
+        ractor::call!(self.actor_ref, KeyActorMessage::Pin, key, requests).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with KeyActor for pin operation: {e}"),
+            }
+        })?
+    }
 }
@@ -0,0 +1,220 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex, MutexGuard},
+    time::Instant,
+};
+
+use async_stream::stream;
+use axum::{body::Body, response::Response};
+use futures::StreamExt;
+use http::header::CONTENT_TYPE;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Weight of the newest sample in the moving averages
+const ALPHA: f64 = 0.2;
+
+/// Bytes of a non-streaming body kept to read its usage
+const MAX_BODY: usize = 4 << 20;
+
+/// Latency of every cookie and key that has served a request
+pub static LATENCY_STATS: LazyLock<LatencyStats> = LazyLock::new(LatencyStats::default);
+
+#[derive(Debug)]
+struct CredentialLatency {
+    backend: &'static str,
+    /// Shortened credential, as in the logs
+    label: String,
+    first_byte_ms: f64,
+    tokens_per_sec: Option<f64>,
+    samples: u64,
+}
+
+/// Latency of a credential, for the admin API
+#[derive(Debug, Serialize)]
+pub struct LatencyStatus {
+    pub backend: &'static str,
+    pub credential: String,
+    /// Moving average of the time to the first byte of the response
+    pub first_byte_ms: u64,
+    /// Moving average of the output tokens per second, for responses
+    /// reporting their usage
+    pub tokens_per_sec: Option<f64>,
+    pub samples: u64,
+}
+
+/// Exponential moving averages of the time to first byte and the generation
+/// speed per credential
+///
+/// Keys of throttled projects can answer many times slower than others, the
+/// averages let the admin API show them and the key rotation pass them over.
+#[derive(Default)]
+pub struct LatencyStats {
+    credentials: Mutex<HashMap<String, CredentialLatency>>,
+}
+
+fn ewma(avg: f64, sample: f64) -> f64 {
+    avg + ALPHA * (sample - avg)
+}
+
+impl LatencyStats {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CredentialLatency>> {
+        self.credentials.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn record_first_byte(&self, backend: &'static str, id: &str, label: &str, ms: f64) {
+        let mut credentials = self.lock();
+        match credentials.get_mut(id) {
+            Some(c) => {
+                c.first_byte_ms = ewma(c.first_byte_ms, ms);
+                c.samples += 1;
+            }
+            None => {
+                credentials.insert(
+                    id.to_owned(),
+                    CredentialLatency {
+                        backend,
+                        label: label.to_owned(),
+                        first_byte_ms: ms,
+                        tokens_per_sec: None,
+                        samples: 1,
+                    },
+                );
+            }
+        }
+    }
+
+    fn record_throughput(&self, id: &str, tokens_per_sec: f64) {
+        if let Some(c) = self.lock().get_mut(id) {
+            c.tokens_per_sec = Some(
+                c.tokens_per_sec
+                    .map_or(tokens_per_sec, |avg| ewma(avg, tokens_per_sec)),
+            );
+        }
+    }
+
+    /// Average time to first byte of a credential
+    ///
+    /// # Arguments
+    /// * `id` - The full credential, e.g. a Gemini key
+    pub fn first_byte_ms(&self, id: &str) -> Option<f64> {
+        self.lock().get(id).map(|c| c.first_byte_ms)
+    }
+
+    /// Latency of the credentials that have served requests, fastest first
+    pub fn status(&self) -> Vec<LatencyStatus> {
+        let mut status = self
+            .lock()
+            .values()
+            .map(|c| LatencyStatus {
+                backend: c.backend,
+                credential: c.label.to_owned(),
+                first_byte_ms: c.first_byte_ms as u64,
+                tokens_per_sec: c.tokens_per_sec.map(|t| (t * 10.0).round() / 10.0),
+                samples: c.samples,
+            })
+            .collect::<Vec<_>>();
+        status.sort_unstable_by(|a, b| {
+            a.backend
+                .cmp(b.backend)
+                .then(a.first_byte_ms.cmp(&b.first_byte_ms))
+        });
+        status
+    }
+}
+
+/// Output tokens a completion, or a chunk of a stream, reports in any of the
+/// Claude, OpenAI and Gemini formats
+fn output_tokens(v: &Value) -> Option<u64> {
+    v["usage"]["output_tokens"]
+        .as_u64()
+        .or_else(|| v["usage"]["completion_tokens"].as_u64())
+        .or_else(|| v["usageMetadata"]["candidatesTokenCount"].as_u64())
+}
+
+/// Records the latency of a response as its body is sent
+///
+/// The time to first byte runs from `started` to the first body chunk. Speed
+/// is the output tokens the response reports over the time streams spent
+/// after their first chunk, or the whole time of non-streaming responses.
+///
+/// # Arguments
+/// * `backend` - Backend serving the response
+/// * `id` - Full credential of the attempt
+/// * `label` - Shortened credential shown by the admin API
+/// * `started` - When the request was sent upstream
+pub fn meter(
+    backend: &'static str,
+    id: String,
+    label: String,
+    started: Instant,
+    res: Response,
+) -> Response {
+    let streaming = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|c| c.starts_with("text/event-stream"));
+    let (parts, body) = res.into_parts();
+    let body = stream! {
+        let mut body = body.into_data_stream();
+        let mut first = None;
+        let mut tokens = None;
+        // the current line of a stream, or the whole body
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            if first.is_none() {
+                let now = Instant::now();
+                let ms = (now - started).as_secs_f64() * 1000.0;
+                LATENCY_STATS.record_first_byte(backend, &id, &label, ms);
+                first = Some(now);
+            }
+            if !streaming {
+                if buf.len() < MAX_BODY {
+                    buf.extend_from_slice(&chunk);
+                }
+                yield Ok(chunk);
+                continue;
+            }
+            buf.extend_from_slice(&chunk);
+            while let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+                let line = buf.drain(..=pos).collect::<Vec<_>>();
+                if let Some(data) = line.strip_prefix(b"data:")
+                    && let Some(n) = serde_json::from_slice::<Value>(data.trim_ascii())
+                        .ok()
+                        .as_ref()
+                        .and_then(output_tokens)
+                {
+                    tokens = Some(n);
+                }
+            }
+            yield Ok(chunk);
+        }
+        if !streaming {
+            tokens = serde_json::from_slice::<Value>(&buf)
+                .ok()
+                .as_ref()
+                .and_then(output_tokens);
+        }
+        let Some(tokens) = tokens.filter(|&n| n > 0) else {
+            return;
+        };
+        let since = if streaming {
+            first.unwrap_or(started)
+        } else {
+            started
+        };
+        let secs = since.elapsed().as_secs_f64();
+        if secs > 0.001 {
+            LATENCY_STATS.record_throughput(&id, tokens as f64 / secs);
+        }
+    };
+    Response::from_parts(parts, Body::from_stream(body))
+}
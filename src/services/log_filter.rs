@@ -0,0 +1,67 @@
+use std::{
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+use tracing::info;
+use tracing_subscriber::{EnvFilter, Registry, reload};
+
+/// Handle to the filter shared by every log layer
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+static HANDLE: OnceLock<FilterHandle> = OnceLock::new();
+/// Pending task restoring the filter after a temporary change
+static REVERT: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+/// Registers the reloadable filter built at startup
+pub fn install(handle: FilterHandle) {
+    let _ = HANDLE.set(handle);
+}
+
+fn handle() -> Result<&'static FilterHandle, String> {
+    HANDLE
+        .get()
+        .ok_or_else(|| "Log filter is not reloadable".to_string())
+}
+
+/// Current filter directives, e.g. `info,clewdr::gemini_state=debug`
+pub fn current() -> Result<String, String> {
+    handle()?
+        .with_current(|f| f.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Replaces the filter directives
+///
+/// # Arguments
+/// * `directives` - Directives in `RUST_LOG` syntax
+/// * `revert_after` - Restore the previous filter after this long, a later
+///   change cancels the pending restore
+///
+/// # Returns
+/// The directives that were active before the change
+pub fn set(directives: &str, revert_after: Option<Duration>) -> Result<String, String> {
+    let handle = handle()?;
+    let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+    let previous = current()?;
+    handle.reload(filter).map_err(|e| e.to_string())?;
+    info!("Log filter set to {}", directives);
+
+    let mut revert = REVERT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(task) = revert.take() {
+        task.abort();
+    }
+    if let Some(after) = revert_after {
+        let restore = previous.to_owned();
+        *revert = Some(tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            if let Ok(filter) = EnvFilter::try_new(&restore)
+                && handle.reload(filter).is_ok()
+            {
+                info!("Log filter restored to {}", restore);
+            }
+        }));
+    }
+    Ok(previous)
+}
@@ -0,0 +1,238 @@
+use std::{sync::LazyLock, time::Duration};
+
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use clap::Parser;
+use http::StatusCode;
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::{
+    Args,
+    config::CLEWDR_CONFIG,
+    error::{ClaudeErrorBody, ClewdrError},
+    gemini_state::GeminiApiFormat,
+    streaming::{
+        ResponseStream, StreamDialect,
+        anthropic::{AnthropicEncoder, Delta},
+        data,
+    },
+    types::claude::{StopReason, Usage},
+    utils::roll,
+};
+
+/// `--mock` on the command line, the config flag can be toggled at runtime
+static MOCK_FLAG: LazyLock<bool> = LazyLock::new(|| Args::parse().mock);
+
+/// Whether chat requests are answered by the mock instead of upstream
+pub fn enabled() -> bool {
+    *MOCK_FLAG || CLEWDR_CONFIG.load().mock.enabled
+}
+
+/// Plays the attempts of a real request, each one waits `latency_ms` and
+/// fails with `error_status` at `error_rate`, up to `max_retries` retries
+///
+/// # Returns
+/// The error of the last attempt if every attempt failed
+async fn attempts(error: impl Fn(StatusCode) -> ClewdrError) -> Result<(), ClewdrError> {
+    let config = CLEWDR_CONFIG.load();
+    let mock = &config.mock;
+    let status = StatusCode::from_u16(mock.error_status).unwrap_or(StatusCode::TOO_MANY_REQUESTS);
+    let mut last = None;
+    for i in 0..config.max_retries + 1 {
+        if i > 0 {
+            info!("[MOCK] retry attempt: {}", i);
+        }
+        tokio::time::sleep(Duration::from_millis(mock.latency_ms)).await;
+        if !roll(mock.error_rate) {
+            return Ok(());
+        }
+        warn!("[MOCK] injected {}", status);
+        last = Some(error(status));
+    }
+    Err(last.unwrap_or(ClewdrError::TooManyRetries))
+}
+
+fn text(model: &str) -> String {
+    CLEWDR_CONFIG.load().mock.response.replace("{model}", model)
+}
+
+/// Splits the text into word sized chunks, keeping the spaces
+fn chunks(text: &str) -> Vec<String> {
+    text.split_inclusive(' ').map(ToString::to_string).collect()
+}
+
+/// Streams pre-rendered SSE frames with `chunk_delay_ms` between them
+fn sse(dialect: StreamDialect, frames: Vec<String>) -> Response {
+    let delay = Duration::from_millis(CLEWDR_CONFIG.load().mock.chunk_delay_ms);
+    ResponseStream::from_events(dialect, frames, delay).into_response()
+}
+
+/// Mock response in the Anthropic Messages format, for Claude web and code
+///
+/// # Arguments
+/// * `model` - Requested model, echoed back
+/// * `stream` - Answer with SSE events
+/// * `input_tokens` - Reported input usage
+pub async fn claude(model: &str, stream: bool, input_tokens: u32) -> Result<Response, ClewdrError> {
+    attempts(|code| ClewdrError::ClaudeHttpError {
+        code,
+        inner: ClaudeErrorBody {
+            message: json!("Mock error injected by ClewdR"),
+            r#type: "mock_error".to_string(),
+            code: Some(code.as_u16()),
+        },
+    })
+    .await?;
+    let text = text(model);
+    let parts = chunks(&text);
+    let output_tokens = parts.len() as u32;
+    let id = format!("msg_mock_{}", uuid::Uuid::new_v4().simple());
+    if !stream {
+        return Ok(Json(json!({
+            "id": id,
+            "type": "message",
+            "role": "assistant",
+            "model": model,
+            "content": [{ "type": "text", "text": text }],
+            "stop_reason": "end_turn",
+            "stop_sequence": null,
+            "usage": { "input_tokens": input_tokens, "output_tokens": output_tokens },
+        }))
+        .into_response());
+    }
+    let usage = |output_tokens| Usage {
+        input_tokens,
+        output_tokens,
+        ..Default::default()
+    };
+    let mut encoder = AnthropicEncoder::new(id, model, usage(0));
+    let mut frames = parts
+        .into_iter()
+        .flat_map(|p| encoder.encode(Delta::Text(p)))
+        .collect::<Vec<_>>();
+    frames.extend(encoder.encode(Delta::Usage(usage(output_tokens))));
+    frames.extend(encoder.encode(Delta::Stop {
+        reason: Some(StopReason::EndTurn),
+        sequence: None,
+    }));
+    frames.extend(encoder.finish());
+    Ok(sse(StreamDialect::Claude, frames))
+}
+
+/// Mock response for Gemini routes, in the native or OpenAI format
+///
+/// # Arguments
+/// * `model` - Requested model, echoed back
+/// * `stream` - Answer with SSE events
+/// * `api_format` - Format the route answers in
+/// * `input_tokens` - Reported input usage
+pub async fn gemini(
+    model: &str,
+    stream: bool,
+    api_format: GeminiApiFormat,
+    input_tokens: u32,
+) -> Result<Response, ClewdrError> {
+    attempts(|code| ClewdrError::GeminiHttpError {
+        code,
+        inner: json!({
+            "error": {
+                "code": code.as_u16(),
+                "message": "Mock error injected by ClewdR",
+                "status": "MOCK_ERROR",
+            },
+        }),
+    })
+    .await?;
+    let text = text(model);
+    let parts = chunks(&text);
+    let output_tokens = parts.len() as u32;
+    let usage = json!({
+        "promptTokenCount": input_tokens,
+        "candidatesTokenCount": output_tokens,
+        "totalTokenCount": input_tokens + output_tokens,
+    });
+    let oai_usage = json!({
+        "prompt_tokens": input_tokens,
+        "completion_tokens": output_tokens,
+        "total_tokens": input_tokens + output_tokens,
+    });
+    let id = format!("chatcmpl-mock-{}", uuid::Uuid::new_v4().simple());
+    let created = chrono::Utc::now().timestamp();
+    match (api_format, stream) {
+        (GeminiApiFormat::Gemini, false) => Ok(Json(json!({
+            "candidates": [{
+                "content": { "role": "model", "parts": [{ "text": text }] },
+                "finishReason": "STOP",
+                "index": 0,
+            }],
+            "usageMetadata": usage,
+            "modelVersion": model,
+        }))
+        .into_response()),
+        (GeminiApiFormat::Gemini, true) => {
+            let last = parts.len().saturating_sub(1);
+            let frames = parts
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    let mut chunk = json!({
+                        "candidates": [{
+                            "content": { "role": "model", "parts": [{ "text": p }] },
+                            "index": 0,
+                        }],
+                        "modelVersion": model,
+                    });
+                    if i == last {
+                        chunk["candidates"][0]["finishReason"] = json!("STOP");
+                        chunk["usageMetadata"] = usage.to_owned();
+                    }
+                    data(chunk)
+                })
+                .collect();
+            Ok(sse(StreamDialect::Gemini, frames))
+        }
+        (GeminiApiFormat::OpenAI, false) => Ok(Json(json!({
+            "id": id,
+            "object": "chat.completion",
+            "created": created,
+            "model": model,
+            "choices": [{
+                "index": 0,
+                "message": { "role": "assistant", "content": text },
+                "finish_reason": "stop",
+            }],
+            "usage": oai_usage,
+        }))
+        .into_response()),
+        (GeminiApiFormat::OpenAI, true) => {
+            let chunk = |delta: Value, finish: Value| {
+                json!({
+                    "id": id,
+                    "object": "chat.completion.chunk",
+                    "created": created,
+                    "model": model,
+                    "choices": [{ "index": 0, "delta": delta, "finish_reason": finish }],
+                })
+            };
+            let mut frames = vec![data(chunk(json!({ "role": "assistant" }), Value::Null))];
+            frames.extend(
+                parts
+                    .iter()
+                    .map(|p| data(chunk(json!({ "content": p }), Value::Null))),
+            );
+            let mut last = chunk(json!({}), json!("stop"));
+            last["usage"] = oai_usage;
+            frames.push(data(last));
+            frames.push(data("[DONE]"));
+            Ok(sse(StreamDialect::OpenAI, frames))
+        }
+    }
+}
+
+/// Rough input token count of a request body, the mock does not tokenize
+pub fn estimate_tokens(body: &impl serde::Serialize) -> u32 {
+    serde_json::to_vec(body).map_or(0, |b| (b.len() / 4) as u32)
+}
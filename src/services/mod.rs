@@ -1,4 +1,35 @@
+pub mod adaptive_limit;
+pub mod audit;
+pub mod auth_guard;
+pub mod batch;
+pub mod chat_sweeper;
+pub mod connection_registry;
+pub mod continuation;
 pub mod cookie_actor;
+pub mod cookie_keeper;
+pub mod daemon;
+pub mod daily_report;
+pub mod doctor;
+pub mod export;
+pub mod gemini_endpoint;
+pub mod hooks;
+pub mod image_fetch;
+pub mod import;
+pub mod jwt;
 pub mod key_actor;
+pub mod latency_stats;
+pub mod log_filter;
+pub mod mock;
+pub mod openai_pool;
+pub mod proxy_pool;
+pub mod quality_gate;
+pub mod redaction;
+pub mod redis;
+pub mod remote_config;
+pub mod request_queue;
+pub mod routing;
+pub mod shared_state;
+pub mod token_actor;
 #[cfg(feature = "portable")]
 pub mod update;
+pub mod wasm_hook;
@@ -0,0 +1,65 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use tracing::warn;
+
+use crate::{config::OpenAIUpstream, error::ClewdrError};
+
+/// Keys of the OpenAI compatible upstreams
+pub static OPENAI_POOL: LazyLock<OpenAIPool> = LazyLock::new(OpenAIPool::default);
+
+/// Rotation state of the keys of one upstream
+#[derive(Default)]
+struct UpstreamKeys {
+    next: usize,
+    /// Time each cooling down key becomes usable again
+    cooldowns: HashMap<String, i64>,
+}
+
+/// Round-robin rotation over the keys of each upstream, with rate limited or
+/// rejected keys skipped until their cooldown ends
+///
+/// The keys themselves stay in the config, so editing an upstream takes
+/// effect on the next request.
+#[derive(Default)]
+pub struct OpenAIPool {
+    upstreams: Mutex<HashMap<String, UpstreamKeys>>,
+}
+
+impl OpenAIPool {
+    /// Next usable key of the upstream
+    ///
+    /// # Returns
+    /// `None` if the upstream needs no key
+    pub fn dispatch(&self, upstream: &OpenAIUpstream) -> Result<Option<String>, ClewdrError> {
+        if upstream.keys.is_empty() {
+            return Ok(None);
+        }
+        let now = chrono::Utc::now().timestamp();
+        let mut upstreams = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+        let state = upstreams.entry(upstream.name.to_owned()).or_default();
+        state.cooldowns.retain(|_, until| *until > now);
+        let len = upstream.keys.len();
+        for i in 0..len {
+            let key = &upstream.keys[(state.next + i) % len];
+            if !state.cooldowns.contains_key(key) {
+                state.next = (state.next + i + 1) % len;
+                return Ok(Some(key.to_owned()));
+            }
+        }
+        Err(ClewdrError::NoKeyAvailable)
+    }
+
+    /// Takes a key of the upstream out of rotation for `secs` seconds
+    pub fn cooldown(&self, upstream: &str, key: &str, secs: i64) {
+        warn!("Cooling down key of {} for {}s", upstream, secs);
+        let mut upstreams = self.upstreams.lock().unwrap_or_else(|e| e.into_inner());
+        upstreams
+            .entry(upstream.to_owned())
+            .or_default()
+            .cooldowns
+            .insert(key.to_owned(), chrono::Utc::now().timestamp() + secs);
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    sync::{
+        LazyLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use moka::sync::Cache;
+use tracing::{error, warn};
+use wreq::Proxy;
+
+use crate::config::CLEWDR_CONFIG;
+
+/// How long a failing proxy is skipped by the rotation
+const UNHEALTHY_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Global proxy pool
+pub static PROXY_POOL: LazyLock<ProxyPool> = LazyLock::new(ProxyPool::new);
+
+/// Round-robin rotation over `proxy_pool` in the config, skipping proxies
+/// that recently failed
+pub struct ProxyPool {
+    cursor: AtomicUsize,
+    unhealthy: Cache<String, ()>,
+}
+
+impl ProxyPool {
+    fn new() -> Self {
+        Self {
+            cursor: AtomicUsize::new(0),
+            unhealthy: Cache::builder().time_to_live(UNHEALTHY_COOLDOWN).build(),
+        }
+    }
+
+    /// Picks the proxy for a request
+    ///
+    /// A proxy assigned to the key or cookie always wins, then the pool is
+    /// rotated, and the global `proxy` setting is used as the last resort
+    ///
+    /// # Arguments
+    /// * `assigned` - Proxy pinned to the key or cookie, if any
+    pub fn resolve(&self, assigned: Option<&str>) -> Option<String> {
+        if let Some(assigned) = assigned.filter(|p| !p.trim().is_empty()) {
+            return Some(assigned.to_string());
+        }
+        self.next()
+            .or_else(|| CLEWDR_CONFIG.load().proxy.to_owned())
+    }
+
+    /// Next healthy proxy in the pool, if every proxy is unhealthy the rotation
+    /// continues anyway rather than failing the request
+    fn next(&self) -> Option<String> {
+        let config = CLEWDR_CONFIG.load();
+        let pool = &config.proxy_pool;
+        if pool.is_empty() {
+            return None;
+        }
+        let start = self.cursor.fetch_add(1, Ordering::Relaxed);
+        (0..pool.len())
+            .map(|i| &pool[(start + i) % pool.len()])
+            .find(|p| !self.unhealthy.contains_key(*p))
+            .or_else(|| pool.get(start % pool.len()))
+            .cloned()
+    }
+
+    /// Takes a proxy out of the rotation for a while
+    ///
+    /// # Returns
+    /// Whether the proxy belongs to the pool, i.e. a retry will pick another one
+    pub fn mark_unhealthy(&self, proxy: &str) -> bool {
+        if !CLEWDR_CONFIG.load().proxy_pool.iter().any(|p| p == proxy) {
+            return false;
+        }
+        warn!("Proxy marked unhealthy: {}", proxy);
+        self.unhealthy.insert(proxy.to_string(), ());
+        true
+    }
+}
+
+/// Parses a proxy URL into a wreq proxy, logging invalid ones
+pub fn to_wreq_proxy(proxy: &str) -> Option<Proxy> {
+    Proxy::all(proxy)
+        .inspect_err(|e| error!("Failed to parse proxy {}: {}", proxy, e))
+        .ok()
+}
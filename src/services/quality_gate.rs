@@ -0,0 +1,131 @@
+use axum::{body::Body, response::Response};
+use regex::RegexSet;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, QualityGate},
+    error::ClewdrError,
+    streaming::StreamDialect,
+};
+
+/// Text of a completion, `None` if it calls tools, which may rightly come
+/// without text
+fn completion_text(msg: &Value, dialect: StreamDialect) -> Option<String> {
+    match dialect {
+        StreamDialect::Claude => {
+            let blocks = msg["content"].as_array()?;
+            if blocks.iter().any(|b| b["type"] == "tool_use") {
+                return None;
+            }
+            Some(blocks.iter().filter_map(|b| b["text"].as_str()).collect())
+        }
+        StreamDialect::Gemini => {
+            let parts = msg["candidates"][0]["content"]["parts"].as_array()?;
+            if parts.iter().any(|p| p.get("functionCall").is_some()) {
+                return None;
+            }
+            Some(
+                parts
+                    .iter()
+                    .filter(|p| p["thought"] != true)
+                    .filter_map(|p| p["text"].as_str())
+                    .collect(),
+            )
+        }
+        StreamDialect::OpenAI => {
+            let message = &msg["choices"][0]["message"];
+            if message["tool_calls"]
+                .as_array()
+                .is_some_and(|c| !c.is_empty())
+            {
+                return None;
+            }
+            Some(message["content"].as_str().unwrap_or_default().to_string())
+        }
+    }
+}
+
+/// Why a completion fails the gate
+///
+/// # Returns
+/// `None` if it passes
+fn failure(gate: &QualityGate, msg: &Value, dialect: StreamDialect) -> Option<String> {
+    let text = completion_text(msg, dialect)?;
+    let text = text.trim();
+    let len = text.chars().count();
+    if len < gate.min_length {
+        return Some(format!("{len} characters, below {}", gate.min_length));
+    }
+    if gate.refusal_patterns.is_empty() {
+        return None;
+    }
+    match RegexSet::new(&gate.refusal_patterns) {
+        Ok(set) => set.is_match(text).then(|| "refusal".to_string()),
+        Err(e) => {
+            warn!("Invalid refusal pattern: {}", e);
+            None
+        }
+    }
+}
+
+/// Buffers a successful JSON response
+///
+/// # Returns
+/// The response, rebuilt, and its body if it is JSON
+async fn buffer(res: Response) -> Result<(Response, Option<Value>), ClewdrError> {
+    if !res.status().is_success() {
+        return Ok((res, None));
+    }
+    let (parts, body) = res.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await?;
+    let msg = serde_json::from_slice::<Value>(&bytes).ok();
+    Ok((Response::from_parts(parts, Body::from(bytes)), msg))
+}
+
+/// Retries a non-streaming completion that is too short or matches a refusal
+/// pattern, up to the gate's own `max_retries`
+///
+/// The last completion is returned as is when every retry fails the gate, or
+/// when a retry fails outright.
+///
+/// # Arguments
+/// * `res` - Response to the original request
+/// * `dialect` - Format of the response
+/// * `retry` - Sends the request again, with another cookie or key
+pub async fn enforce<F, Fut>(
+    res: Response,
+    dialect: StreamDialect,
+    mut retry: F,
+) -> Result<Response, ClewdrError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response, ClewdrError>>,
+{
+    let gate = CLEWDR_CONFIG.load().quality_gate.to_owned();
+    if !gate.enabled() {
+        return Ok(res);
+    }
+    let (mut res, mut msg) = buffer(res).await?;
+    for i in 1..=gate.max_retries {
+        let Some(reason) = msg.as_ref().and_then(|m| failure(&gate, m, dialect)) else {
+            return Ok(res);
+        };
+        info!(
+            "[QUALITY] completion failed the gate ({}), retry {}/{}",
+            reason, i, gate.max_retries
+        );
+        match retry().await {
+            Ok(next) if next.status().is_success() => (res, msg) = buffer(next).await?,
+            Ok(next) => {
+                warn!("Quality retry failed with {}", next.status());
+                break;
+            }
+            Err(e) => {
+                warn!("Quality retry failed: {}", e);
+                break;
+            }
+        }
+    }
+    Ok(res)
+}
@@ -0,0 +1,58 @@
+use std::{
+    borrow::Cow,
+    sync::{LazyLock, Mutex},
+};
+
+use regex::Regex;
+use tracing::warn;
+
+use crate::config::{CLEWDR_CONFIG, RedactionRule};
+
+/// Rules compiled for the configured `redaction`, rebuilt when it changes
+static COMPILED: LazyLock<Mutex<Compiled>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct Compiled {
+    rules: Vec<RedactionRule>,
+    regexes: Vec<(Regex, String)>,
+}
+
+impl Compiled {
+    fn update(&mut self, rules: &[RedactionRule]) {
+        if self.rules == rules {
+            return;
+        }
+        self.regexes = rules
+            .iter()
+            .filter_map(|r| match Regex::new(&r.pattern) {
+                Ok(re) => Some((re, r.replacement.to_owned())),
+                Err(e) => {
+                    warn!("Skipping invalid redaction pattern: {}", e);
+                    None
+                }
+            })
+            .collect();
+        self.rules = rules.to_vec();
+    }
+}
+
+/// Applies the redaction rules to text about to leave clewdr's memory, e.g.
+/// request dumps in the log directory
+///
+/// # Returns
+/// The text unchanged if no rule matches
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let config = CLEWDR_CONFIG.load();
+    if config.redaction.is_empty() {
+        return Cow::Borrowed(text);
+    }
+    let mut compiled = COMPILED.lock().unwrap_or_else(|e| e.into_inner());
+    compiled.update(&config.redaction);
+    let mut text = Cow::Borrowed(text);
+    for (re, replacement) in &compiled.regexes {
+        if let Cow::Owned(s) = re.replace_all(&text, replacement.as_str()) {
+            text = Cow::Owned(s);
+        }
+    }
+    text
+}
@@ -0,0 +1,158 @@
+use snafu::ResultExt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::Mutex,
+};
+use url::Url;
+
+use crate::error::{ClewdrError, UrlSnafu};
+
+/// Reply to a Redis command
+#[derive(Debug)]
+pub enum Reply {
+    Nil,
+    Int(i64),
+    Str(String),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    /// Flattens a `HGETALL` reply into field and value pairs
+    pub fn into_pairs(self) -> Vec<(String, String)> {
+        let Reply::Array(items) = self else {
+            return vec![];
+        };
+        let mut items = items.into_iter().filter_map(|r| match r {
+            Reply::Str(s) => Some(s),
+            Reply::Int(i) => Some(i.to_string()),
+            _ => None,
+        });
+        let mut pairs = vec![];
+        while let (Some(k), Some(v)) = (items.next(), items.next()) {
+            pairs.push((k, v));
+        }
+        pairs
+    }
+
+    pub fn into_string(self) -> Option<String> {
+        match self {
+            Reply::Str(s) => Some(s),
+            Reply::Int(i) => Some(i.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Minimal Redis client speaking RESP over a single connection
+///
+/// The connection is opened on first use and again after any error, commands
+/// are serialized through it.
+pub struct RedisClient {
+    addr: String,
+    password: Option<String>,
+    db: Option<u32>,
+    conn: Mutex<Option<BufStream<TcpStream>>>,
+}
+
+impl RedisClient {
+    /// Parses a `redis://[:password@]host[:port][/db]` URL
+    pub fn new(url: &str) -> Result<Self, ClewdrError> {
+        let url = Url::parse(url).context(UrlSnafu { url })?;
+        if url.scheme() != "redis" {
+            return Err(ClewdrError::RedisError {
+                msg: format!("unsupported scheme {}", url.scheme()),
+            });
+        }
+        let host = url.host_str().unwrap_or("127.0.0.1");
+        let port = url.port().unwrap_or(6379);
+        Ok(Self {
+            addr: format!("{host}:{port}"),
+            password: url.password().map(str::to_string),
+            db: url.path().trim_start_matches('/').parse().ok(),
+            conn: Mutex::new(None),
+        })
+    }
+
+    /// Runs a command, reconnecting first if the connection was lost
+    pub async fn cmd(&self, args: &[&str]) -> Result<Reply, ClewdrError> {
+        let mut conn = self.conn.lock().await;
+        if conn.is_none() {
+            let mut stream = BufStream::new(TcpStream::connect(&self.addr).await?);
+            if let Some(ref password) = self.password {
+                exec(&mut stream, &["AUTH", password]).await?;
+            }
+            if let Some(db) = self.db {
+                exec(&mut stream, &["SELECT", &db.to_string()]).await?;
+            }
+            *conn = Some(stream);
+        }
+        let Some(stream) = conn.as_mut() else {
+            unreachable!("connection was just opened");
+        };
+        let result = exec(stream, args).await;
+        if result.is_err() {
+            *conn = None;
+        }
+        result
+    }
+}
+
+async fn exec(stream: &mut BufStream<TcpStream>, args: &[&str]) -> Result<Reply, ClewdrError> {
+    let mut buf = format!("*{}\r\n", args.len());
+    for arg in args {
+        buf.push_str(&format!("${}\r\n{}\r\n", arg.len(), arg));
+    }
+    stream.write_all(buf.as_bytes()).await?;
+    stream.flush().await?;
+    read_reply(stream).await
+}
+
+async fn read_line(stream: &mut BufStream<TcpStream>) -> Result<String, ClewdrError> {
+    let mut line = String::new();
+    if stream.read_line(&mut line).await? == 0 {
+        return Err(ClewdrError::RedisError {
+            msg: "connection closed".into(),
+        });
+    }
+    Ok(line.trim_end_matches("\r\n").to_string())
+}
+
+async fn read_reply(stream: &mut BufStream<TcpStream>) -> Result<Reply, ClewdrError> {
+    let line = read_line(stream).await?;
+    let (kind, rest) = line.split_at_checked(1).unwrap_or(("", ""));
+    let parse = |s: &str| {
+        s.parse::<i64>().map_err(|_| ClewdrError::RedisError {
+            msg: format!("malformed reply: {line}"),
+        })
+    };
+    match kind {
+        "+" => Ok(Reply::Str(rest.to_string())),
+        "-" => Err(ClewdrError::RedisError {
+            msg: rest.to_string(),
+        }),
+        ":" => Ok(Reply::Int(parse(rest)?)),
+        "$" => {
+            let Ok(len) = usize::try_from(parse(rest)?) else {
+                return Ok(Reply::Nil);
+            };
+            let mut data = vec![0; len + 2];
+            stream.read_exact(&mut data).await?;
+            data.truncate(len);
+            Ok(Reply::Str(String::from_utf8_lossy(&data).into_owned()))
+        }
+        "*" => {
+            let Ok(len) = usize::try_from(parse(rest)?) else {
+                return Ok(Reply::Nil);
+            };
+            let mut items = Vec::with_capacity(len);
+            for _ in 0..len {
+                items.push(Box::pin(read_reply(stream)).await?);
+            }
+            Ok(Reply::Array(items))
+        }
+        _ => Err(ClewdrError::RedisError {
+            msg: format!("malformed reply: {line}"),
+        }),
+    }
+}
@@ -0,0 +1,229 @@
+use std::{fmt::Write, time::Duration};
+
+use chrono::Utc;
+use colored::Colorize;
+use ring::{digest, hmac};
+use tracing::{info, warn};
+use url::Url;
+use wreq::{Client, ClientBuilder, Proxy, RequestBuilder, StatusCode, header::ETAG};
+
+use crate::config::{CLEWDR_CONFIG, ClewdrConfig, RemoteConfig, Severity, format_issues};
+
+/// Timeout of a single fetch of the remote config
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Hex of the SHA-256 of an empty payload, signed for S3 GET requests
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Outcome of polling the remote config source
+enum Fetched {
+    /// Same ETag as the last fetch
+    Unchanged,
+    Changed {
+        body: String,
+        etag: Option<String>,
+    },
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut out, b| {
+        _ = write!(out, "{b:02x}");
+        out
+    })
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> hmac::Tag {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes())
+}
+
+/// Percent-encodes a path segment as SigV4 expects
+fn uri_encode(segment: &str) -> String {
+    segment.bytes().fold(String::new(), |mut out, b| {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            out.push(b as char);
+        } else {
+            _ = write!(out, "%{b:02X}");
+        }
+        out
+    })
+}
+
+/// URL of an object in a bucket, path-style on custom endpoints and
+/// virtual-hosted on AWS
+fn s3_url(remote: &RemoteConfig, bucket: &str, key: &str) -> Result<Url, String> {
+    let key = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let url = match remote.s3_endpoint {
+        Some(ref endpoint) => format!("{}/{bucket}/{key}", endpoint.trim_end_matches('/')),
+        None => format!(
+            "https://{bucket}.s3.{}.amazonaws.com/{key}",
+            remote.s3_region
+        ),
+    };
+    Url::parse(&url).map_err(|e| format!("invalid S3 URL `{url}`: {e}"))
+}
+
+/// Signs a GET of `url` with AWS Signature Version 4
+fn sign_s3(
+    request: RequestBuilder,
+    url: &Url,
+    remote: &RemoteConfig,
+) -> Result<RequestBuilder, String> {
+    let (Some(access_key), Some(secret_key)) = (&remote.s3_access_key, &remote.s3_secret_key)
+    else {
+        // public bucket
+        return Ok(request);
+    };
+    let host = match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!("{date}/{}/s3/aws4_request", remote.s3_region);
+
+    // If-None-Match is not signed, S3 accepts unsigned headers
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "GET\n{}\n\nhost:{host}\nx-amz-content-sha256:{EMPTY_SHA256}\nx-amz-date:{amz_date}\n\n{signed_headers}\n{EMPTY_SHA256}",
+        url.path()
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+        hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let key = hmac_sha256(format!("AWS4{secret_key}").as_bytes(), &date);
+    let key = hmac_sha256(key.as_ref(), &remote.s3_region);
+    let key = hmac_sha256(key.as_ref(), "s3");
+    let key = hmac_sha256(key.as_ref(), "aws4_request");
+    let signature = hex(hmac_sha256(key.as_ref(), &string_to_sign).as_ref());
+    Ok(request
+        .header("x-amz-date", amz_date)
+        .header("x-amz-content-sha256", EMPTY_SHA256)
+        .header(
+            "authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={access_key}/{scope}, SignedHeaders={signed_headers}, Signature={signature}"
+            ),
+        ))
+}
+
+fn client() -> Result<Client, String> {
+    let mut builder = ClientBuilder::new().timeout(FETCH_TIMEOUT);
+    if let Some(ref proxy) = CLEWDR_CONFIG.load().proxy {
+        builder = builder.proxy(Proxy::all(proxy).map_err(|e| format!("invalid proxy: {e}"))?);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// Fetches the config file, conditionally on the ETag of the last fetch
+async fn fetch(remote: &RemoteConfig, etag: Option<&str>) -> Result<Fetched, String> {
+    let client = client()?;
+    let mut request = match remote.url.strip_prefix("s3://") {
+        Some(path) => {
+            let (bucket, key) = path
+                .split_once('/')
+                .filter(|(b, k)| !b.is_empty() && !k.is_empty())
+                .ok_or_else(|| format!("`{}` is not s3://bucket/key", remote.url))?;
+            let url = s3_url(remote, bucket, key)?;
+            let request = client.get(url.to_owned());
+            sign_s3(request, &url, remote)?
+        }
+        None => {
+            let request = client.get(&remote.url);
+            match remote.bearer_token {
+                Some(ref token) => request.bearer_auth(token),
+                None => request,
+            }
+        }
+    };
+    if let Some(etag) = etag {
+        request = request.header("if-none-match", etag);
+    }
+    let res = request.send().await.map_err(|e| e.to_string())?;
+    match res.status() {
+        StatusCode::NOT_MODIFIED => Ok(Fetched::Unchanged),
+        status if status.is_success() => {
+            let new_etag = res
+                .headers()
+                .get(ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(ToOwned::to_owned);
+            if new_etag.is_some() && new_etag.as_deref() == etag {
+                return Ok(Fetched::Unchanged);
+            }
+            let body = res.text().await.map_err(|e| e.to_string())?;
+            Ok(Fetched::Changed {
+                body,
+                etag: new_etag,
+            })
+        }
+        status => Err(format!("unexpected status {status}")),
+    }
+}
+
+/// Parses and checks a fetched config, then swaps it in with the local
+/// credentials and remote source kept
+async fn apply(body: &str) -> Result<(), String> {
+    let remote: ClewdrConfig = toml::from_str(body).map_err(|e| e.to_string())?;
+    let issues = remote.check();
+    if issues.iter().any(|i| i.severity == Severity::Error) {
+        return Err(format!("the config has errors\n{}", format_issues(&issues)));
+    }
+    if !issues.is_empty() {
+        warn!("Remote config has warnings\n{}", format_issues(&issues));
+    }
+    let remote = remote.validate();
+    CLEWDR_CONFIG.rcu(|old_c| {
+        let mut new_c = ClewdrConfig::clone(&remote);
+        new_c.cookie_array = old_c.cookie_array.to_owned();
+        new_c.wasted_cookie = old_c.wasted_cookie.to_owned();
+        new_c.gemini_keys = old_c.gemini_keys.to_owned();
+        if new_c.vertex.credential.is_none() {
+            new_c.vertex.credential = old_c.vertex.credential.to_owned();
+        }
+        // the source and the programs run on this host are only ever set locally
+        new_c.remote_config = old_c.remote_config.to_owned();
+        new_c.hooks = old_c.hooks.to_owned();
+        new_c.routing_script = old_c.routing_script.to_owned();
+        new_c
+    });
+    ClewdrConfig::save()
+        .await
+        .map_err(|e| format!("applied but not saved: {e}"))
+}
+
+/// Spawns the task polling `remote_config` and swapping in the fetched
+/// config whenever its ETag changes
+///
+/// Settings read at startup, such as the listen address, still take effect
+/// only on restart.
+pub fn spawn() {
+    if CLEWDR_CONFIG.load().remote_config.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut etag = None::<String>;
+        loop {
+            let Some(remote) = CLEWDR_CONFIG.load().remote_config.to_owned() else {
+                return;
+            };
+            match fetch(&remote, etag.as_deref()).await {
+                Ok(Fetched::Unchanged) => {}
+                Ok(Fetched::Changed { body, etag: new }) => match apply(&body).await {
+                    Ok(()) => {
+                        info!("Applied remote config from {}", remote.url.green());
+                        etag = new;
+                    }
+                    Err(e) => {
+                        warn!("Rejected remote config from {}: {}", remote.url, e);
+                        // don't fetch the same bad config again
+                        etag = new;
+                    }
+                },
+                Err(e) => warn!("Failed to fetch remote config from {}: {}", remote.url, e),
+            }
+            tokio::time::sleep(Duration::from_secs(remote.poll_secs.max(1))).await;
+        }
+    });
+}
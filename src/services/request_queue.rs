@@ -0,0 +1,152 @@
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError};
+
+/// Header a client uses to mark a request as background work
+pub const PRIORITY_HEADER: &str = "x-clewdr-priority";
+
+/// How often waiters re-check whether the key pool grew
+const RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Global queue in front of key dispatch
+pub static REQUEST_QUEUE: LazyLock<RequestQueue> = LazyLock::new(RequestQueue::default);
+
+/// Priority class of a request, interactive requests are always served first
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Default, Display, EnumString, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case", ascii_case_insensitive)]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Batch,
+}
+
+#[derive(Default)]
+struct QueueState {
+    in_flight: usize,
+    interactive: VecDeque<oneshot::Sender<()>>,
+    batch: VecDeque<oneshot::Sender<()>>,
+}
+
+impl QueueState {
+    fn waiting(&self) -> usize {
+        self.interactive.len() + self.batch.len()
+    }
+
+    /// Hands free slots to waiters, interactive ones first
+    fn grant(&mut self, capacity: usize) {
+        while self.in_flight < capacity {
+            let Some(tx) = self
+                .interactive
+                .pop_front()
+                .or_else(|| self.batch.pop_front())
+            else {
+                return;
+            };
+            // a waiter that gave up has dropped its receiver
+            if tx.send(()).is_ok() {
+                self.in_flight += 1;
+            }
+        }
+    }
+}
+
+/// Limits concurrent requests per key, queueing the excess by priority
+///
+/// Disabled unless `key_concurrency` is set. The capacity is the number of
+/// keys times `key_concurrency`, so with no key at all requests wait until one
+/// is added or `queue_timeout` passes instead of failing right away.
+#[derive(Default)]
+pub struct RequestQueue {
+    state: Mutex<QueueState>,
+}
+
+/// Slot in the queue, released when dropped
+pub struct QueuePermit {
+    queue: &'static RequestQueue,
+}
+
+impl Drop for QueuePermit {
+    fn drop(&mut self) {
+        let mut state = self.queue.lock();
+        state.in_flight = state.in_flight.saturating_sub(1);
+        state.grant(capacity());
+    }
+}
+
+/// Current number of slots, keys times `key_concurrency`
+fn capacity() -> usize {
+    let config = CLEWDR_CONFIG.load();
+    config.gemini_keys.len() * config.key_concurrency
+}
+
+impl RequestQueue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits for a free slot
+    ///
+    /// # Returns
+    /// `None` if the queue is disabled, otherwise a permit to hold for the
+    /// lifetime of the request
+    pub async fn acquire(
+        &'static self,
+        priority: Priority,
+    ) -> Result<Option<QueuePermit>, ClewdrError> {
+        let (max_depth, timeout) = {
+            let config = CLEWDR_CONFIG.load();
+            if config.key_concurrency == 0 {
+                return Ok(None);
+            }
+            (config.queue_max_depth, config.queue_timeout)
+        };
+        let deadline = Instant::now() + Duration::from_secs(timeout);
+        let mut rx = {
+            let mut state = self.lock();
+            if state.in_flight < capacity() && state.waiting() == 0 {
+                state.in_flight += 1;
+                return Ok(Some(QueuePermit { queue: self }));
+            }
+            if state.waiting() >= max_depth {
+                return Err(ClewdrError::QueueFull);
+            }
+            let (tx, rx) = oneshot::channel();
+            match priority {
+                Priority::Interactive => state.interactive.push_back(tx),
+                Priority::Batch => state.batch.push_back(tx),
+            }
+            state.grant(capacity());
+            rx
+        };
+        debug!("Request queued with {} priority", priority);
+        loop {
+            let wait = RECHECK_INTERVAL.min(deadline.saturating_duration_since(Instant::now()));
+            match tokio::time::timeout(wait, &mut rx).await {
+                Ok(Ok(())) => return Ok(Some(QueuePermit { queue: self })),
+                Ok(Err(_)) => return Err(ClewdrError::QueueTimeout),
+                Err(_) if Instant::now() >= deadline => {
+                    rx.close();
+                    // a slot may have been granted right before giving up
+                    return match rx.try_recv() {
+                        Ok(()) => Ok(Some(QueuePermit { queue: self })),
+                        Err(_) => Err(ClewdrError::QueueTimeout),
+                    };
+                }
+                // keys may have been added in the meantime
+                Err(_) => self.lock().grant(capacity()),
+            }
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    cell::Cell,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use rhai::{AST, Dynamic, Engine, Scope};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+
+use crate::config::{CLEWDR_CONFIG, ClaudeBackend};
+
+thread_local! {
+    /// When the script running on this thread is terminated
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut engine = Engine::new();
+    // checking the clock every operation would slow every script down
+    engine.on_progress(|ops| {
+        (ops % 1024 == 0 && DEADLINE.get().is_some_and(|d| Instant::now() > d))
+            .then_some(Dynamic::UNIT)
+    });
+    engine.on_print(|s| info!("[ROUTE] {}", s));
+    engine.on_debug(|s, _, pos| info!("[ROUTE] {} {}", pos, s));
+    engine
+});
+
+/// Script compiled from a file modified at the time
+type Compiled = (SystemTime, Arc<AST>);
+
+/// Compiled script, recompiled when the file changes
+static SCRIPT: LazyLock<Mutex<Option<(PathBuf, Compiled)>>> = LazyLock::new(Default::default);
+
+/// What the routing script sees as `input`
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInput {
+    pub path: String,
+    pub model: Option<String>,
+    /// Estimated prompt tokens
+    pub tokens: usize,
+    pub stream: bool,
+    /// Address of the client, if it connected over TCP
+    pub client: Option<String>,
+    /// Local time, e.g. `23:05`
+    pub time: String,
+    /// Local hour, 0 to 23
+    pub hour: u32,
+    /// Local day of the week, e.g. `Mon`
+    pub weekday: String,
+}
+
+/// What the routing script evaluates to, every field is optional
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RouteDecision {
+    /// Claude backend serving `/v1/chat/completions`
+    pub backend: Option<ClaudeBackend>,
+    /// Gemini key tiers to draw keys from, as with the `x-clewdr-key-tier`
+    /// header
+    pub key_tier: Option<Vec<String>>,
+    /// Top level fields merged into the request body, `()` removes a field
+    pub overrides: Map<String, Value>,
+}
+
+/// Whether a routing script is configured
+pub fn enabled() -> bool {
+    CLEWDR_CONFIG.load().routing_script.is_some()
+}
+
+/// Compiles the Rhai script at `path`, reusing the last compilation while
+/// the file is unchanged
+pub fn compile(path: &Path) -> Result<Arc<AST>, String> {
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    if let Some((ref p, (at, ref ast))) = *SCRIPT.lock().expect("lock poisoned")
+        && p == path
+        && at == modified
+    {
+        return Ok(ast.to_owned());
+    }
+    let ast = Arc::new(
+        ENGINE
+            .compile_file(path.to_owned())
+            .map_err(|e| e.to_string())?,
+    );
+    *SCRIPT.lock().expect("lock poisoned") = Some((path.to_owned(), (modified, ast.to_owned())));
+    Ok(ast)
+}
+
+/// Evaluates a compiled script with `input` in scope, terminating it after
+/// `timeout`
+///
+/// # Returns
+/// `None` if the script evaluated to `()`
+pub fn eval(
+    ast: &AST,
+    input: &RouteInput,
+    timeout: Duration,
+) -> Result<Option<RouteDecision>, Box<rhai::EvalAltResult>> {
+    let mut scope = Scope::new();
+    scope.push("input", rhai::serde::to_dynamic(input)?);
+    DEADLINE.set(Some(Instant::now() + timeout));
+    let result = ENGINE.eval_ast_with_scope::<Dynamic>(&mut scope, ast);
+    DEADLINE.set(None);
+    let result = result?;
+    if result.is_unit() {
+        return Ok(None);
+    }
+    Ok(Some(rhai::serde::from_dynamic(&result)?))
+}
+
+/// Asks the routing script how to serve a request
+///
+/// The script is written in [Rhai](https://rhai.rs) and runs inside the
+/// process. It sees the [`RouteInput`] as `input` and evaluates to a
+/// [`RouteDecision`] map, e.g.
+/// `if input.tokens > 100000 { #{ backend: "code" } }`, or to `()` to route
+/// the request as configured. It is recompiled when the file changes, so
+/// edits take effect at once.
+///
+/// # Returns
+/// `None` to route the request as configured, also when the script fails
+pub async fn decide(input: &RouteInput) -> Option<RouteDecision> {
+    let script = CLEWDR_CONFIG.load().routing_script.to_owned()?;
+    let ast = compile(&script.path)
+        .inspect_err(|e| warn!("Failed to compile routing script: {}", e))
+        .ok()?;
+    let input = input.to_owned();
+    let timeout = Duration::from_secs(script.timeout_secs);
+    let result = tokio::task::spawn_blocking(move || eval(&ast, &input, timeout)).await;
+    match result {
+        Ok(Ok(decision)) => decision,
+        Ok(Err(e)) => {
+            warn!("Routing script failed: {}", e);
+            None
+        }
+        Err(e) => {
+            warn!("Routing script failed: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input() -> RouteInput {
+        RouteInput {
+            path: "/v1/chat/completions".to_string(),
+            model: Some("claude-sonnet-4".to_string()),
+            tokens: 120_000,
+            stream: true,
+            client: None,
+            time: "23:05".to_string(),
+            hour: 23,
+            weekday: "Mon".to_string(),
+        }
+    }
+
+    #[test]
+    fn evaluates_to_a_decision() {
+        let ast = ENGINE
+            .compile(
+                r#"if input.tokens > 100000 && input.hour >= 22 {
+                    #{ backend: "code", key_tier: ["paid"], overrides: #{ temperature: 0.5, top_k: () } }
+                }"#,
+            )
+            .unwrap();
+        let decision = eval(&ast, &input(), Duration::from_secs(1))
+            .unwrap()
+            .unwrap();
+        assert_eq!(decision.backend, Some(ClaudeBackend::Code));
+        assert_eq!(decision.key_tier, Some(vec!["paid".to_string()]));
+        assert_eq!(decision.overrides["temperature"], 0.5);
+        assert!(decision.overrides["top_k"].is_null());
+    }
+
+    #[test]
+    fn unit_routes_as_configured() {
+        let ast = ENGINE
+            .compile(r#"if input.model == "gpt" { #{ backend: "code" } }"#)
+            .unwrap();
+        assert!(
+            eval(&ast, &input(), Duration::from_secs(1))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn endless_script_is_terminated() {
+        let ast = ENGINE.compile("loop {}").unwrap();
+        assert!(eval(&ast, &input(), Duration::from_millis(50)).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::{sync::LazyLock, time::Duration};
+
+use ring::digest;
+use tracing::{error, info, warn};
+
+use crate::{
+    config::{CLEWDR_CONFIG, ClewdrCookie, GeminiKey, KeyStatus, pacific_day},
+    error::ClewdrError,
+    services::{cookie_actor::CookieActorHandle, key_actor::KeyActorHandle, redis::RedisClient},
+};
+
+/// Redis shared with the other instances, `redis_url` is read once at startup
+static REDIS: LazyLock<Option<RedisClient>> = LazyLock::new(|| {
+    let url = CLEWDR_CONFIG.load().redis_url.to_owned()?;
+    RedisClient::new(&url)
+        .inspect_err(|e| error!("Invalid Redis URL: {e}"))
+        .ok()
+});
+
+/// Field of a key's cooldown hash holding the cooldown of all models
+const ALL_MODELS: &str = "*";
+
+/// Usage counters outlive the Pacific Time day they count by this long
+const USAGE_TTL_SECS: u64 = 2 * 24 * 3600;
+
+/// Cooldowns and usage of a key as recorded by all instances
+#[derive(Debug, Clone)]
+pub struct SharedKeyState {
+    pub key: GeminiKey,
+    pub suspended_until: Option<i64>,
+    pub model_cooldowns: Vec<(String, i64)>,
+    /// Pacific Time day the usage counts
+    pub day: String,
+    pub requests: u64,
+    pub tokens: u64,
+}
+
+/// Identifier of a credential in Redis that does not reveal it
+pub fn id(secret: &str) -> String {
+    let hash = digest::digest(&digest::SHA256, secret.as_bytes());
+    hash.as_ref()[..12]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn key_cooldown_name(key: &GeminiKey) -> String {
+    format!("clewdr:key:{}:cooldown", id(key))
+}
+
+fn key_usage_name(key: &GeminiKey, day: &str) -> String {
+    format!("clewdr:key:{}:usage:{}", id(key), day)
+}
+
+fn cookie_cooldown_name(cookie: &ClewdrCookie) -> String {
+    format!("clewdr:cookie:{}:cooldown", id(&cookie.to_string()))
+}
+
+/// Runs commands in the background, logging failures
+fn spawn_commands(what: &'static str, commands: Vec<Vec<String>>) {
+    let Some(redis) = REDIS.as_ref() else {
+        return;
+    };
+    tokio::spawn(async move {
+        for command in commands {
+            let args = command.iter().map(String::as_str).collect::<Vec<_>>();
+            if let Err(e) = redis.cmd(&args).await {
+                warn!("Failed to share {what}: {e}");
+                return;
+            }
+        }
+    });
+}
+
+/// Shares the active cooldowns of a key with the other instances
+pub fn publish_key_cooldowns(key: &KeyStatus) {
+    let now = chrono::Utc::now().timestamp();
+    let entries = key
+        .suspended_until
+        .map(|until| (ALL_MODELS.to_string(), until))
+        .into_iter()
+        .chain(key.model_cooldowns.iter().map(|(m, &u)| (m.to_owned(), u)))
+        .filter(|(_, until)| *until > now)
+        .collect::<Vec<_>>();
+    let Some(expire) = entries.iter().map(|(_, until)| *until).max() else {
+        return;
+    };
+    let name = key_cooldown_name(&key.key);
+    let mut hset = vec!["HSET".to_string(), name.to_owned()];
+    for (scope, until) in entries {
+        hset.extend([scope, until.to_string()]);
+    }
+    let expire = vec!["EXPIREAT".to_string(), name, expire.to_string()];
+    spawn_commands("key cooldown", vec![hset, expire]);
+}
+
+/// Clears the shared cooldowns of a key, so other instances stop applying them
+pub fn clear_key_cooldowns(key: &GeminiKey) {
+    spawn_commands(
+        "key cooldown",
+        vec![vec!["DEL".to_string(), key_cooldown_name(key)]],
+    );
+}
+
+/// Adds requests and tokens consumed through a key to the shared counters
+pub fn add_key_usage(key: &GeminiKey, day: &str, requests: u64, tokens: u64) {
+    let name = key_usage_name(key, day);
+    let mut commands = vec![];
+    for (field, n) in [("requests", requests), ("tokens", tokens)] {
+        if n > 0 {
+            commands.push(vec![
+                "HINCRBY".to_string(),
+                name.to_owned(),
+                field.to_string(),
+                n.to_string(),
+            ]);
+        }
+    }
+    if commands.is_empty() {
+        return;
+    }
+    commands.push(vec!["EXPIRE".to_string(), name, USAGE_TTL_SECS.to_string()]);
+    spawn_commands("key usage", commands);
+}
+
+/// Shares the time a rate limited cookie resets at with the other instances
+pub fn publish_cookie_cooldown(cookie: &ClewdrCookie, until: i64) {
+    if until <= chrono::Utc::now().timestamp() {
+        return;
+    }
+    let command = vec![
+        "SET".to_string(),
+        cookie_cooldown_name(cookie),
+        until.to_string(),
+        "EXAT".to_string(),
+        until.to_string(),
+    ];
+    spawn_commands("cookie cooldown", vec![command]);
+}
+
+/// Reads the shared state of the keys
+async fn pull_keys(
+    redis: &RedisClient,
+    keys: Vec<GeminiKey>,
+) -> Result<Vec<SharedKeyState>, ClewdrError> {
+    let day = pacific_day();
+    let mut states = vec![];
+    for key in keys {
+        let mut state = SharedKeyState {
+            key: key.to_owned(),
+            suspended_until: None,
+            model_cooldowns: vec![],
+            day: day.to_owned(),
+            requests: 0,
+            tokens: 0,
+        };
+        let cooldowns = redis.cmd(&["HGETALL", &key_cooldown_name(&key)]).await?;
+        for (scope, until) in cooldowns.into_pairs() {
+            let Ok(until) = until.parse::<i64>() else {
+                continue;
+            };
+            if scope == ALL_MODELS {
+                state.suspended_until = Some(until);
+            } else {
+                state.model_cooldowns.push((scope, until));
+            }
+        }
+        let usage = redis.cmd(&["HGETALL", &key_usage_name(&key, &day)]).await?;
+        for (field, n) in usage.into_pairs() {
+            let n = n.parse().unwrap_or_default();
+            match field.as_str() {
+                "requests" => state.requests = n,
+                "tokens" => state.tokens = n,
+                _ => {}
+            }
+        }
+        states.push(state);
+    }
+    Ok(states)
+}
+
+/// Reads the shared cooldowns of the cookies, keeping the active ones
+async fn pull_cookies(
+    redis: &RedisClient,
+    cookies: Vec<ClewdrCookie>,
+) -> Result<Vec<(ClewdrCookie, i64)>, ClewdrError> {
+    let now = chrono::Utc::now().timestamp();
+    let mut cooldowns = vec![];
+    for cookie in cookies {
+        let until = redis
+            .cmd(&["GET", &cookie_cooldown_name(&cookie)])
+            .await?
+            .into_string()
+            .and_then(|s| s.parse::<i64>().ok());
+        if let Some(until) = until.filter(|u| *u > now) {
+            cooldowns.push((cookie, until));
+        }
+    }
+    Ok(cooldowns)
+}
+
+/// Spawns the task applying the cooldowns and usage shared by the other
+/// instances every `redis_sync_secs`, if a Redis backend is configured
+pub fn spawn_sync(keys: KeyActorHandle, cookies: CookieActorHandle) {
+    let Some(redis) = REDIS.as_ref() else {
+        return;
+    };
+    info!("Sharing cooldowns and usage through Redis");
+    tokio::spawn(async move {
+        loop {
+            let secs = CLEWDR_CONFIG.load().redis_sync_secs.max(1);
+            tokio::time::sleep(Duration::from_secs(secs)).await;
+            let (Ok(key_status), Ok(cookie_status)) =
+                (keys.get_status().await, cookies.get_status().await)
+            else {
+                // the actors are gone
+                break;
+            };
+            let key_list = key_status.valid.into_iter().map(|k| k.key).collect();
+            match pull_keys(redis, key_list).await {
+                Ok(states) => keys.sync(states),
+                Err(e) => {
+                    warn!("Failed to read shared key state: {e}");
+                    continue;
+                }
+            }
+            let cookie_list = cookie_status.valid.into_iter().map(|c| c.cookie).collect();
+            match pull_cookies(redis, cookie_list).await {
+                Ok(cooldowns) if !cooldowns.is_empty() => cookies.sync_cooldowns(cooldowns),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to read shared cookie cooldowns: {e}"),
+            }
+        }
+    });
+}
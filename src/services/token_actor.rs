@@ -0,0 +1,222 @@
+use std::collections::{HashMap, VecDeque};
+
+use ractor::{Actor, ActorProcessingErr, ActorRef, RpcReplyPort};
+use serde::Serialize;
+use snafu::{GenerateImplicitData, Location};
+use tracing::{info, warn};
+
+use crate::{
+    claude_code_state::ClaudeCodeState,
+    config::{CLEWDR_CONFIG, ClewdrCookie, CookieStatus},
+    error::ClewdrError,
+    services::{cookie_actor::CookieActorHandle, mock},
+};
+
+/// How often tokens close to expiry are looked for, in seconds
+const INTERVAL: u64 = 60;
+/// Refresh attempts kept per credential
+const HISTORY_LEN: usize = 10;
+
+/// One refresh attempt of a token
+#[derive(Debug, Serialize, Clone)]
+pub struct RefreshEvent {
+    pub at: i64,
+    /// Expiry of the new token, if the refresh succeeded
+    pub expires_at: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// OAuth token of a Claude code credential
+#[derive(Debug, Serialize, Clone)]
+pub struct TokenStatusInfo {
+    pub cookie: String,
+    pub organization: String,
+    pub expires_at: i64,
+    /// Background refreshes, oldest first
+    pub history: Vec<RefreshEvent>,
+}
+
+/// Messages that the TokenActor can handle
+#[derive(Debug)]
+enum TokenActorMessage {
+    /// Refresh the tokens close to expiry
+    Refresh,
+    /// Get the tokens and their refresh history
+    GetStatus(RpcReplyPort<Vec<TokenStatusInfo>>),
+}
+
+/// TokenActor state - the cookies holding the tokens and refresh history
+struct TokenActorState {
+    cookies: CookieActorHandle,
+    history: HashMap<ClewdrCookie, VecDeque<RefreshEvent>>,
+}
+
+/// Token actor that rotates OAuth refresh tokens ahead of expiry, so requests
+/// rarely wait for a refresh and unused credentials keep a live refresh token
+struct TokenActor;
+
+impl TokenActor {
+    /// Refreshes the tokens expiring within `token_refresh_ahead_secs`
+    ///
+    /// Rotated tokens are handed back to the cookie actor, which persists
+    /// them to the config.
+    async fn refresh(state: &mut TokenActorState) {
+        let ahead = CLEWDR_CONFIG.load().token_refresh_ahead_secs;
+        if ahead == 0 || mock::enabled() {
+            return;
+        }
+        let now = chrono::Utc::now().timestamp();
+        let cookies = match state.cookies.expiring_tokens(now + ahead as i64).await {
+            Ok(cookies) => cookies,
+            Err(e) => {
+                warn!("Failed to list expiring tokens: {}", e);
+                return;
+            }
+        };
+        for cookie in cookies {
+            let event = Self::rotate(&state.cookies, cookie.to_owned()).await;
+            let history = state.history.entry(cookie.cookie).or_default();
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(event);
+        }
+    }
+
+    /// Rotates the token of a single cookie
+    async fn rotate(cookies: &CookieActorHandle, cookie: CookieStatus) -> RefreshEvent {
+        let ellipse = cookie.cookie.ellipse();
+        let mut state = ClaudeCodeState::new(cookies.to_owned());
+        let res = match state.use_cookie(cookie) {
+            Ok(_) => state.rotate_token().await,
+            Err(e) => Err(e),
+        };
+        let at = chrono::Utc::now().timestamp();
+        match res {
+            Ok(()) => {
+                state.return_cookie(None).await;
+                let expires_at = state
+                    .cookie
+                    .as_ref()
+                    .and_then(|c| c.token.as_ref())
+                    .map(|t| t.expires_at.timestamp());
+                info!("[TOKEN] refreshed token of {}", ellipse);
+                RefreshEvent {
+                    at,
+                    expires_at,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                warn!("[TOKEN] failed to refresh token of {}: {}", ellipse, e);
+                RefreshEvent {
+                    at,
+                    expires_at: None,
+                    error: Some(e.to_string()),
+                }
+            }
+        }
+    }
+
+    /// Creates a report of all tokens
+    async fn report(state: &TokenActorState) -> Vec<TokenStatusInfo> {
+        let info = match state.cookies.get_status().await {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Failed to get cookie status: {}", e);
+                return vec![];
+            }
+        };
+        info.valid
+            .iter()
+            .chain(info.exhausted.iter())
+            .filter_map(|c| {
+                let token = c.token.as_ref()?;
+                Some(TokenStatusInfo {
+                    cookie: c.cookie.ellipse(),
+                    organization: token.organization.uuid.to_owned(),
+                    expires_at: token.expires_at.timestamp(),
+                    history: state
+                        .history
+                        .get(&c.cookie)
+                        .map(|h| h.iter().cloned().collect())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+impl Actor for TokenActor {
+    type Msg = TokenActorMessage;
+    type State = TokenActorState;
+    type Arguments = CookieActorHandle;
+
+    async fn pre_start(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        cookies: Self::Arguments,
+    ) -> Result<Self::State, ActorProcessingErr> {
+        Ok(TokenActorState {
+            cookies,
+            history: HashMap::new(),
+        })
+    }
+
+    async fn handle(
+        &self,
+        _myself: ActorRef<Self::Msg>,
+        message: Self::Msg,
+        state: &mut Self::State,
+    ) -> Result<(), ActorProcessingErr> {
+        match message {
+            TokenActorMessage::Refresh => {
+                Self::refresh(state).await;
+            }
+            TokenActorMessage::GetStatus(reply_port) => {
+                reply_port.send(Self::report(state).await)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle for interacting with the TokenActor
+#[derive(Clone)]
+pub struct TokenActorHandle {
+    actor_ref: ActorRef<TokenActorMessage>,
+}
+
+impl TokenActorHandle {
+    /// Create a new TokenActor refreshing the tokens held by `cookies`
+    pub async fn start(cookies: CookieActorHandle) -> Result<Self, ractor::SpawnErr> {
+        let (actor_ref, _join_handle) = Actor::spawn(None, TokenActor, cookies).await?;
+        let handle = Self { actor_ref };
+        handle.spawn_refresher();
+        Ok(handle)
+    }
+
+    /// Spawns the task triggering refreshes
+    fn spawn_refresher(&self) {
+        let actor_ref = self.actor_ref.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(INTERVAL));
+            loop {
+                interval.tick().await;
+                if ractor::cast!(actor_ref, TokenActorMessage::Refresh).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Get the tokens and their refresh history
+    pub async fn get_status(&self) -> Result<Vec<TokenStatusInfo>, ClewdrError> {
+        ractor::call!(self.actor_ref, TokenActorMessage::GetStatus).map_err(|e| {
+            ClewdrError::RactorError {
+                loc: Location::generate(),
+                msg: format!("Failed to communicate with TokenActor for get status operation: {e}"),
+            }
+        })
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use tracing::warn;
+use wasmi::{
+    Caller, Config, Engine, Extern, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+    core::TrapCode,
+};
+
+/// Fuel granted per second of a hook's timeout, roughly one unit per
+/// executed instruction
+const FUEL_PER_SECOND: u64 = 200_000_000;
+/// Linear memory a module may grow to
+const MEMORY_LIMIT: usize = 64 << 20;
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+});
+
+/// Module compiled from a file modified at the time
+type Compiled = (SystemTime, Arc<Module>);
+
+/// Compiled modules by path, recompiled when the file changes
+static MODULES: LazyLock<Mutex<HashMap<PathBuf, Compiled>>> = LazyLock::new(Default::default);
+
+/// What a module decided about the body it was given
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Pass the body on unchanged
+    Keep,
+    /// Replace the body with these bytes
+    Replace(Vec<u8>),
+    /// Reject the request with this message
+    Reject(String),
+}
+
+struct State {
+    limits: StoreLimits,
+    output: Option<Vec<u8>>,
+    reject: Option<String>,
+}
+
+fn module(path: &Path) -> Result<Arc<Module>, String> {
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| e.to_string())?;
+    if let Some((at, module)) = MODULES.lock().expect("lock poisoned").get(path)
+        && *at == modified
+    {
+        return Ok(module.to_owned());
+    }
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let module = Arc::new(Module::new(&ENGINE, &bytes[..]).map_err(|e| e.to_string())?);
+    MODULES
+        .lock()
+        .expect("lock poisoned")
+        .insert(path.to_owned(), (modified, module.to_owned()));
+    Ok(module)
+}
+
+/// Copies `len` bytes at `ptr` out of the calling module's memory
+fn read(caller: &Caller<'_, State>, ptr: i32, len: i32) -> Result<Vec<u8>, wasmi::Error> {
+    let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+        return Err(wasmi::Error::new("module exports no memory"));
+    };
+    let mut buf = vec![0; len as u32 as usize];
+    memory
+        .read(caller, ptr as u32 as usize, &mut buf)
+        .map_err(|_| wasmi::Error::new("read out of bounds"))?;
+    Ok(buf)
+}
+
+/// Runs `hook` of a compiled module on `input`
+///
+/// The module exports `memory`, `alloc(len) -> ptr` to reserve room for the
+/// input and `hook(ptr, len)`, and may import `clewdr.output(ptr, len)` to
+/// set the new body and `clewdr.reject(ptr, len)` to reject the request with
+/// a message. Each call gets a fresh instance, so no state leaks between
+/// requests.
+pub fn call(module: &Module, input: &[u8], fuel: u64) -> Result<Outcome, wasmi::Error> {
+    let mut store = Store::new(
+        &ENGINE,
+        State {
+            limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+            output: None,
+            reject: None,
+        },
+    );
+    store.limiter(|s| &mut s.limits);
+    store.set_fuel(fuel)?;
+    let mut linker = Linker::<State>::new(&ENGINE);
+    linker.func_wrap(
+        "clewdr",
+        "output",
+        |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+            let bytes = read(&caller, ptr, len)?;
+            caller.data_mut().output = Some(bytes);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "clewdr",
+        "reject",
+        |mut caller: Caller<'_, State>, ptr: i32, len: i32| {
+            let bytes = read(&caller, ptr, len)?;
+            caller.data_mut().reject = Some(String::from_utf8_lossy(&bytes).into_owned());
+            Ok(())
+        },
+    )?;
+    let instance = linker.instantiate(&mut store, module)?.start(&mut store)?;
+    let memory = instance
+        .get_memory(&store, "memory")
+        .ok_or_else(|| wasmi::Error::new("module exports no memory"))?;
+    let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+    let hook = instance.get_typed_func::<(i32, i32), ()>(&store, "hook")?;
+    let len = i32::try_from(input.len()).map_err(|_| wasmi::Error::new("input too large"))?;
+    let ptr = alloc.call(&mut store, len)?;
+    memory
+        .write(&mut store, ptr as u32 as usize, input)
+        .map_err(|_| wasmi::Error::new("write out of bounds"))?;
+    hook.call(&mut store, (ptr, len))?;
+    let state = store.into_data();
+    Ok(match (state.reject, state.output) {
+        (Some(msg), _) => Outcome::Reject(msg),
+        (None, Some(output)) => Outcome::Replace(output),
+        (None, None) => Outcome::Keep,
+    })
+}
+
+/// Runs the WASM module at `path` on `input` off the async runtime, with
+/// fuel for `timeout_secs` of work
+///
+/// # Returns
+/// `None` if the module could not be loaded, trapped or ran out of fuel,
+/// logged as a warning
+pub async fn exec(path: &Path, input: Vec<u8>, timeout_secs: u64) -> Option<Outcome> {
+    let module = match module(path) {
+        Ok(module) => module,
+        Err(e) => {
+            warn!("Failed to load {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let fuel = timeout_secs.saturating_mul(FUEL_PER_SECOND);
+    let result = tokio::task::spawn_blocking(move || call(&module, &input, fuel)).await;
+    match result {
+        Ok(Ok(outcome)) => Some(outcome),
+        Ok(Err(e)) if e.as_trap_code() == Some(TrapCode::OutOfFuel) => {
+            warn!("{} timed out after {}s", path.display(), timeout_secs);
+            None
+        }
+        Ok(Err(e)) => {
+            warn!("{} failed: {}", path.display(), e);
+            None
+        }
+        Err(e) => {
+            warn!("{} failed: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ECHO: &str = r#"(module
+      (import "clewdr" "output" (func $output (param i32 i32)))
+      (import "clewdr" "reject" (func $reject (param i32 i32)))
+      (memory (export "memory") 1)
+      (data (i32.const 0) "{\"rewritten\":true}")
+      (data (i32.const 32) "blocked")
+      (func (export "alloc") (param i32) (result i32) i32.const 1024)
+      (func (export "hook") (param $ptr i32) (param $len i32)
+        (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 120))
+          (then (call $reject (i32.const 32) (i32.const 7))
+                return))
+        (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 107))
+          (then return))
+        (call $output (i32.const 0) (i32.const 18))))"#;
+
+    fn compile(wat: &str) -> Module {
+        Module::new(&ENGINE, &wat::parse_str(wat).unwrap()[..]).unwrap()
+    }
+
+    #[test]
+    fn replaces_keeps_and_rejects() {
+        let module = compile(ECHO);
+        assert_eq!(
+            call(&module, b"{}", 10_000).unwrap(),
+            Outcome::Replace(br#"{"rewritten":true}"#.to_vec())
+        );
+        assert_eq!(call(&module, b"keep", 10_000).unwrap(), Outcome::Keep);
+        assert_eq!(
+            call(&module, b"x", 10_000).unwrap(),
+            Outcome::Reject("blocked".to_string())
+        );
+    }
+
+    #[test]
+    fn endless_loop_runs_out_of_fuel() {
+        let module = compile(
+            r#"(module
+              (memory (export "memory") 1)
+              (func (export "alloc") (param i32) (result i32) i32.const 0)
+              (func (export "hook") (param i32 i32) (loop br 0)))"#,
+        );
+        let err = call(&module, b"{}", 10_000).unwrap_err();
+        assert_eq!(err.as_trap_code(), Some(TrapCode::OutOfFuel));
+    }
+
+    #[test]
+    fn missing_exports_fail() {
+        let module = compile(r#"(module (memory (export "memory") 1))"#);
+        assert!(call(&module, b"{}", 10_000).is_err());
+    }
+}
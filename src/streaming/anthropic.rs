@@ -0,0 +1,569 @@
+use serde_json::{Value, json};
+
+use crate::{
+    streaming::event,
+    types::claude::{StopReason, Usage},
+};
+
+/// A piece of a generation, whatever format upstream streamed it in
+#[derive(Debug)]
+pub enum Delta {
+    Text(String),
+    Thinking(String),
+    /// Signature of the current thinking block
+    Signature(String),
+    /// Starts a tool call, its arguments follow as [`Delta::ToolInput`]
+    ToolCall {
+        id: String,
+        name: String,
+        /// Run by the provider, e.g. web search
+        server: bool,
+    },
+    /// Part of the JSON arguments of the current tool call
+    ToolInput(String),
+    /// Any other content block, sent whole
+    Block(Value),
+    /// Ends the open block, so the next delta starts a new one even if it is
+    /// of the same kind
+    EndBlock,
+    /// Usage so far, the last one is reported
+    Usage(Usage),
+    /// Why the generation ended
+    Stop {
+        reason: Option<StopReason>,
+        sequence: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+    Text,
+    Thinking,
+    Tool,
+    Other,
+}
+
+/// Writes the Anthropic Messages event sequence for a stream of [`Delta`]s
+///
+/// Opens and closes content blocks as the kind of delta changes, so clients
+/// always get `message_start`, balanced `content_block_start` and
+/// `content_block_stop` events, then `message_delta` and `message_stop`.
+pub struct AnthropicEncoder {
+    id: String,
+    model: String,
+    usage: Usage,
+    started: bool,
+    /// Index and kind of the open block
+    open: Option<(usize, BlockKind)>,
+    next: usize,
+    stop: Option<(Option<StopReason>, Option<String>)>,
+}
+
+impl AnthropicEncoder {
+    /// # Arguments
+    /// * `id` - Message ID
+    /// * `model` - Model reported to the client
+    /// * `usage` - Usage known when the message starts, e.g. input tokens
+    pub fn new(id: impl Into<String>, model: impl Into<String>, usage: Usage) -> Self {
+        Self {
+            id: id.into(),
+            model: model.into(),
+            usage,
+            started: false,
+            open: None,
+            next: 0,
+            stop: None,
+        }
+    }
+
+    fn start(&mut self, events: &mut Vec<String>) {
+        if self.started {
+            return;
+        }
+        self.started = true;
+        events.push(event(
+            "message_start",
+            json!({
+                "type": "message_start",
+                "message": {
+                    "id": self.id,
+                    "type": "message",
+                    "role": "assistant",
+                    "model": self.model,
+                    "content": [],
+                    "stop_reason": null,
+                    "stop_sequence": null,
+                    "usage": self.usage,
+                },
+            }),
+        ));
+    }
+
+    fn close(&mut self, events: &mut Vec<String>) {
+        if let Some((index, _)) = self.open.take() {
+            events.push(event(
+                "content_block_stop",
+                json!({ "type": "content_block_stop", "index": index }),
+            ));
+        }
+    }
+
+    /// Makes sure a block of `kind` is open, starting it with `block` if not
+    ///
+    /// # Returns
+    /// Index of the block
+    fn open(&mut self, kind: BlockKind, block: Value, events: &mut Vec<String>) -> usize {
+        if let Some((index, open)) = self.open
+            && open == kind
+            && kind != BlockKind::Tool
+        {
+            return index;
+        }
+        self.close(events);
+        let index = self.next;
+        self.next += 1;
+        events.push(event(
+            "content_block_start",
+            json!({ "type": "content_block_start", "index": index, "content_block": block }),
+        ));
+        self.open = Some((index, kind));
+        index
+    }
+
+    fn delta(index: usize, delta: Value) -> String {
+        event(
+            "content_block_delta",
+            json!({ "type": "content_block_delta", "index": index, "delta": delta }),
+        )
+    }
+
+    /// Events for the next delta
+    pub fn encode(&mut self, delta: Delta) -> Vec<String> {
+        let mut events = vec![];
+        self.start(&mut events);
+        match delta {
+            Delta::Text(text) => {
+                let index = self.open(
+                    BlockKind::Text,
+                    json!({ "type": "text", "text": "" }),
+                    &mut events,
+                );
+                events.push(Self::delta(
+                    index,
+                    json!({ "type": "text_delta", "text": text }),
+                ));
+            }
+            Delta::Thinking(thinking) => {
+                let block = json!({ "type": "thinking", "thinking": "", "signature": "" });
+                let index = self.open(BlockKind::Thinking, block, &mut events);
+                events.push(Self::delta(
+                    index,
+                    json!({ "type": "thinking_delta", "thinking": thinking }),
+                ));
+            }
+            Delta::Signature(signature) => {
+                let block = json!({ "type": "thinking", "thinking": "", "signature": "" });
+                let index = self.open(BlockKind::Thinking, block, &mut events);
+                events.push(Self::delta(
+                    index,
+                    json!({ "type": "signature_delta", "signature": signature }),
+                ));
+            }
+            Delta::ToolCall { id, name, server } => {
+                let kind = if server {
+                    "server_tool_use"
+                } else {
+                    "tool_use"
+                };
+                let block = json!({ "type": kind, "id": id, "name": name, "input": {} });
+                self.open(BlockKind::Tool, block, &mut events);
+            }
+            Delta::ToolInput(json) => {
+                if let Some((index, BlockKind::Tool)) = self.open {
+                    events.push(Self::delta(
+                        index,
+                        json!({ "type": "input_json_delta", "partial_json": json }),
+                    ));
+                }
+            }
+            Delta::Block(block) => {
+                self.open(BlockKind::Other, block, &mut events);
+                self.close(&mut events);
+            }
+            Delta::EndBlock => self.close(&mut events),
+            Delta::Usage(usage) => self.usage = usage,
+            Delta::Stop { reason, sequence } => {
+                self.close(&mut events);
+                self.stop = Some((reason, sequence));
+            }
+        }
+        events
+    }
+
+    /// Events ending the message, once upstream is done
+    pub fn finish(mut self) -> Vec<String> {
+        let mut events = vec![];
+        self.start(&mut events);
+        self.close(&mut events);
+        let (reason, sequence) = self
+            .stop
+            .take()
+            .unwrap_or((Some(StopReason::EndTurn), None));
+        events.push(event(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": { "stop_reason": reason, "stop_sequence": sequence },
+                "usage": self.usage,
+            }),
+        ));
+        events.push(event("message_stop", json!({ "type": "message_stop" })));
+        events
+    }
+}
+
+/// Deltas of a native Gemini stream chunk, first candidate only
+pub fn gemini_deltas(chunk: &Value) -> Vec<Delta> {
+    let candidate = &chunk["candidates"][0];
+    let mut deltas = vec![];
+    for part in candidate["content"]["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+    {
+        if let Some(text) = part["text"].as_str() {
+            if part["thought"].as_bool().unwrap_or_default() {
+                deltas.push(Delta::Thinking(text.to_string()));
+            } else {
+                deltas.push(Delta::Text(text.to_string()));
+            }
+        } else if let Some(call) = part.get("functionCall") {
+            deltas.push(Delta::ToolCall {
+                id: format!("toolu_{}", uuid::Uuid::new_v4().simple()),
+                name: call["name"].as_str().unwrap_or_default().to_string(),
+                server: false,
+            });
+            deltas.push(Delta::ToolInput(
+                call.get("args").unwrap_or(&json!({})).to_string(),
+            ));
+        }
+        if let Some(signature) = part["thoughtSignature"].as_str() {
+            deltas.push(Delta::Signature(signature.to_string()));
+        }
+    }
+    let meta = &chunk["usageMetadata"];
+    if meta.is_object() {
+        deltas.push(Delta::Usage(Usage {
+            input_tokens: meta["promptTokenCount"].as_u64().unwrap_or_default() as u32,
+            output_tokens: (meta["candidatesTokenCount"].as_u64().unwrap_or_default()
+                + meta["thoughtsTokenCount"].as_u64().unwrap_or_default())
+                as u32,
+            cache_read_input_tokens: meta["cachedContentTokenCount"].as_u64().map(|c| c as u32),
+            ..Default::default()
+        }));
+    }
+    if let Some(reason) = candidate["finishReason"].as_str() {
+        let called = deltas.iter().any(|d| matches!(d, Delta::ToolCall { .. }));
+        let reason = match reason {
+            "MAX_TOKENS" => StopReason::MaxTokens,
+            "STOP" if called => StopReason::ToolUse,
+            "STOP" => StopReason::EndTurn,
+            _ => StopReason::Refusal,
+        };
+        deltas.push(Delta::Stop {
+            reason: Some(reason),
+            sequence: None,
+        });
+    }
+    deltas
+}
+
+/// Deltas of an OpenAI `chat.completion.chunk`, first choice only
+pub fn openai_deltas(chunk: &Value) -> Vec<Delta> {
+    let choice = &chunk["choices"][0];
+    let delta = &choice["delta"];
+    let mut deltas = vec![];
+    if let Some(thinking) = delta["reasoning_content"]
+        .as_str()
+        .filter(|t| !t.is_empty())
+    {
+        deltas.push(Delta::Thinking(thinking.to_string()));
+    }
+    if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+        deltas.push(Delta::Text(text.to_string()));
+    }
+    for call in delta["tool_calls"].as_array().into_iter().flatten() {
+        if let Some(name) = call["function"]["name"].as_str() {
+            deltas.push(Delta::ToolCall {
+                id: call["id"].as_str().unwrap_or_default().to_string(),
+                name: name.to_string(),
+                server: false,
+            });
+        }
+        if let Some(args) = call["function"]["arguments"]
+            .as_str()
+            .filter(|a| !a.is_empty())
+        {
+            deltas.push(Delta::ToolInput(args.to_string()));
+        }
+    }
+    let usage = &chunk["usage"];
+    if usage.is_object() {
+        deltas.push(Delta::Usage(Usage {
+            input_tokens: usage["prompt_tokens"].as_u64().unwrap_or_default() as u32,
+            output_tokens: usage["completion_tokens"].as_u64().unwrap_or_default() as u32,
+            cache_read_input_tokens: usage["prompt_tokens_details"]["cached_tokens"]
+                .as_u64()
+                .map(|c| c as u32),
+            ..Default::default()
+        }));
+    }
+    if let Some(reason) = choice["finish_reason"].as_str() {
+        let reason = match reason {
+            "length" => StopReason::MaxTokens,
+            "tool_calls" | "function_call" => StopReason::ToolUse,
+            "content_filter" => StopReason::Refusal,
+            _ => StopReason::EndTurn,
+        };
+        deltas.push(Delta::Stop {
+            reason: Some(reason),
+            sequence: None,
+        });
+    }
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Name and data of each encoded event
+    fn parse(events: &[String]) -> Vec<(String, Value)> {
+        events
+            .iter()
+            .map(|e| {
+                let (name, data) = e
+                    .trim_end()
+                    .strip_prefix("event: ")
+                    .and_then(|e| e.split_once("\ndata: "))
+                    .unwrap();
+                (name.to_string(), serde_json::from_str(data).unwrap())
+            })
+            .collect()
+    }
+
+    fn encode(deltas: Vec<Delta>) -> Vec<(String, Value)> {
+        let usage = Usage {
+            input_tokens: 3,
+            ..Default::default()
+        };
+        let mut encoder = AnthropicEncoder::new("msg_1", "claude-test", usage);
+        let mut events = vec![];
+        for delta in deltas {
+            events.extend(encoder.encode(delta));
+        }
+        events.extend(encoder.finish());
+        parse(&events)
+    }
+
+    fn names(events: &[(String, Value)]) -> Vec<&str> {
+        events.iter().map(|(n, _)| n.as_str()).collect()
+    }
+
+    /// Every started block is stopped once, in order, with increasing indices
+    fn assert_balanced(events: &[(String, Value)]) {
+        let mut open = None;
+        let mut next = 0;
+        for (name, data) in events {
+            match name.as_str() {
+                "content_block_start" => {
+                    assert!(open.is_none(), "block started while another is open");
+                    assert_eq!(data["index"], next);
+                    open = Some(next);
+                    next += 1;
+                }
+                "content_block_delta" => assert_eq!(data["index"].as_u64(), open),
+                "content_block_stop" => {
+                    assert_eq!(data["index"].as_u64(), open);
+                    open = None;
+                }
+                _ => {}
+            }
+        }
+        assert!(open.is_none());
+    }
+
+    #[test]
+    fn test_text_message() {
+        let events = encode(vec![
+            Delta::Text("Hel".into()),
+            Delta::Text("lo".into()),
+            Delta::Usage(Usage {
+                input_tokens: 3,
+                output_tokens: 2,
+                ..Default::default()
+            }),
+        ]);
+        assert_eq!(
+            names(&events),
+            [
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop",
+            ]
+        );
+        assert_balanced(&events);
+        let start = &events[0].1["message"];
+        assert_eq!(start["id"], "msg_1");
+        assert_eq!(start["model"], "claude-test");
+        assert_eq!(start["usage"]["input_tokens"], 3);
+        assert_eq!(events[2].1["delta"]["text"], "Hel");
+        // no stop reason from upstream ends the turn
+        assert_eq!(events[5].1["delta"]["stop_reason"], "end_turn");
+        assert_eq!(events[5].1["usage"]["output_tokens"], 2);
+    }
+
+    #[test]
+    fn test_blocks_switch_kinds() {
+        let events = encode(vec![
+            Delta::Thinking("hmm".into()),
+            Delta::Signature("sig".into()),
+            Delta::Text("answer".into()),
+            Delta::ToolCall {
+                id: "toolu_1".into(),
+                name: "a".into(),
+                server: false,
+            },
+            Delta::ToolInput("{\"x\":".into()),
+            Delta::ToolInput("1}".into()),
+            Delta::ToolCall {
+                id: "toolu_2".into(),
+                name: "b".into(),
+                server: true,
+            },
+            Delta::Stop {
+                reason: Some(StopReason::ToolUse),
+                sequence: None,
+            },
+        ]);
+        assert_balanced(&events);
+        let starts = events
+            .iter()
+            .filter(|(n, _)| n == "content_block_start")
+            .map(|(_, d)| d["content_block"]["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        // consecutive tool calls get blocks of their own
+        assert_eq!(starts, ["thinking", "text", "tool_use", "server_tool_use"]);
+        let inputs = events
+            .iter()
+            .filter(|(_, d)| d["delta"]["type"] == "input_json_delta")
+            .map(|(_, d)| d["delta"]["partial_json"].as_str().unwrap())
+            .collect::<String>();
+        assert_eq!(inputs, "{\"x\":1}");
+        assert_eq!(
+            events[events.len() - 2].1["delta"]["stop_reason"],
+            "tool_use"
+        );
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let events = encode(vec![]);
+        assert_eq!(
+            names(&events),
+            ["message_start", "message_delta", "message_stop"]
+        );
+    }
+
+    #[test]
+    fn test_end_block_and_whole_blocks() {
+        let events = encode(vec![
+            Delta::Text("a".into()),
+            Delta::EndBlock,
+            Delta::Text("b".into()),
+            Delta::Block(json!({ "type": "web_search_tool_result", "content": [] })),
+            // input without an open tool call is dropped
+            Delta::ToolInput("{}".into()),
+        ]);
+        assert_balanced(&events);
+        let starts = events
+            .iter()
+            .filter(|(n, _)| n == "content_block_start")
+            .count();
+        assert_eq!(starts, 3);
+        assert!(
+            !events
+                .iter()
+                .any(|(_, d)| d["delta"]["type"] == "input_json_delta")
+        );
+    }
+
+    #[test]
+    fn test_gemini_deltas() {
+        let chunk = json!({
+            "candidates": [{
+                "content": { "parts": [
+                    { "text": "plan", "thought": true, "thoughtSignature": "sig" },
+                    { "text": "Hi" },
+                    { "functionCall": { "name": "f", "args": { "a": 1 } } },
+                ] },
+                "finishReason": "STOP",
+            }],
+            "usageMetadata": {
+                "promptTokenCount": 4,
+                "candidatesTokenCount": 5,
+                "thoughtsTokenCount": 1,
+            },
+        });
+        let events = encode(gemini_deltas(&chunk));
+        assert_balanced(&events);
+        let deltas = events
+            .iter()
+            .filter(|(n, _)| n == "content_block_delta")
+            .map(|(_, d)| d["delta"]["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deltas,
+            [
+                "thinking_delta",
+                "signature_delta",
+                "text_delta",
+                "input_json_delta"
+            ]
+        );
+        let end = &events[events.len() - 2].1;
+        assert_eq!(end["delta"]["stop_reason"], "tool_use");
+        assert_eq!(end["usage"]["input_tokens"], 4);
+        assert_eq!(end["usage"]["output_tokens"], 6);
+    }
+
+    #[test]
+    fn test_openai_deltas() {
+        let chunks = [
+            json!({ "choices": [{ "delta": { "role": "assistant", "reasoning_content": "r" } }] }),
+            json!({ "choices": [{ "delta": { "content": "Hi" } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{
+                "index": 0, "id": "call_1", "function": { "name": "f", "arguments": "" },
+            }] } }] }),
+            json!({ "choices": [{ "delta": { "tool_calls": [{
+                "index": 0, "function": { "arguments": "{}" },
+            }] }, "finish_reason": "length" }] }),
+            json!({ "choices": [], "usage": { "prompt_tokens": 2, "completion_tokens": 3 } }),
+        ];
+        let events = encode(chunks.iter().flat_map(openai_deltas).collect());
+        assert_balanced(&events);
+        let starts = events
+            .iter()
+            .filter(|(n, _)| n == "content_block_start")
+            .map(|(_, d)| d["content_block"]["type"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(starts, ["thinking", "text", "tool_use"]);
+        let end = &events[events.len() - 2].1;
+        assert_eq!(end["delta"]["stop_reason"], "max_tokens");
+        assert_eq!(end["usage"]["output_tokens"], 3);
+    }
+}
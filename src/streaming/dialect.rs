@@ -0,0 +1,128 @@
+use axum::body::Bytes;
+use serde_json::{Value, json};
+
+use crate::config::{KeepAliveStyle, StreamSalvage};
+
+/// Format of an event stream, decides how events the proxy adds are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamDialect {
+    /// Anthropic Messages events, also what Claude web streams look like
+    Claude,
+    OpenAI,
+    Gemini,
+}
+
+impl StreamDialect {
+    /// Error object of a timeout the proxy reports itself
+    pub fn error_object(self, message: &str) -> Value {
+        match self {
+            StreamDialect::Claude => json!({
+                "type": "error",
+                "error": { "type": "timeout_error", "message": message },
+            }),
+            StreamDialect::OpenAI => json!({
+                "error": { "message": message, "type": "timeout_error", "code": 504 },
+            }),
+            StreamDialect::Gemini => json!({
+                "error": { "code": 504, "message": message, "status": "DEADLINE_EXCEEDED" },
+            }),
+        }
+    }
+
+    /// Error event ending a stream the proxy aborts, e.g. a stalled one
+    pub fn error_event(self, message: &str) -> Bytes {
+        let error = self.error_object(message);
+        let event = match self {
+            StreamDialect::Claude => format!("event: error\ndata: {error}\n\n"),
+            StreamDialect::OpenAI | StreamDialect::Gemini => format!("data: {error}\n\n"),
+        };
+        Bytes::from(event)
+    }
+
+    /// Event sent while upstream is silent, `None` when keep-alives are off
+    pub fn keep_alive_event(self, style: KeepAliveStyle) -> Option<Bytes> {
+        let event = match (style, self) {
+            (KeepAliveStyle::Off, _) => return None,
+            (KeepAliveStyle::Comment, _) => ": keepalive\n\n".to_string(),
+            (KeepAliveStyle::EmptyDelta, StreamDialect::Claude) => {
+                format!("event: ping\ndata: {}\n\n", json!({ "type": "ping" }))
+            }
+            (KeepAliveStyle::EmptyDelta, StreamDialect::OpenAI) => format!(
+                "data: {}\n\n",
+                json!({
+                    "object": "chat.completion.chunk",
+                    "choices": [{ "index": 0, "delta": {}, "finish_reason": null }],
+                })
+            ),
+            (KeepAliveStyle::EmptyDelta, StreamDialect::Gemini) => format!(
+                "data: {}\n\n",
+                json!({
+                    "candidates": [{
+                        "content": { "role": "model", "parts": [{ "text": "" }] },
+                        "index": 0,
+                    }],
+                })
+            ),
+        };
+        Some(Bytes::from(event))
+    }
+
+    /// Events ending a salvaged stream as if upstream finished it
+    pub fn closing_events(self, salvage: StreamSalvage) -> String {
+        let length = salvage == StreamSalvage::Length;
+        match self {
+            StreamDialect::Claude if length => format!(
+                "event: message_delta\ndata: {}\n\nevent: message_stop\ndata: {}\n\n",
+                json!({
+                    "type": "message_delta",
+                    "delta": { "stop_reason": "max_tokens", "stop_sequence": null },
+                    "usage": { "output_tokens": 0 },
+                }),
+                json!({ "type": "message_stop" }),
+            ),
+            StreamDialect::Claude => format!(
+                "event: error\ndata: {}\n\n",
+                json!({
+                    "type": "error",
+                    "error": { "type": "api_error", "message": "Upstream stream interrupted" },
+                })
+            ),
+            StreamDialect::OpenAI => format!(
+                "data: {}\n\ndata: [DONE]\n\n",
+                json!({
+                    "object": "chat.completion.chunk",
+                    "choices": [{
+                        "index": 0,
+                        "delta": {},
+                        "finish_reason": if length { "length" } else { "error" },
+                    }],
+                })
+            ),
+            StreamDialect::Gemini => format!(
+                "data: {}\n\n",
+                json!({
+                    "candidates": [{
+                        "content": { "role": "model", "parts": [{ "text": "" }] },
+                        "finishReason": if length { "MAX_TOKENS" } else { "OTHER" },
+                        "index": 0,
+                    }],
+                })
+            ),
+        }
+    }
+}
+
+/// Whether a chunk of an event stream ends on an event boundary
+pub fn ends_event(chunk: &[u8]) -> bool {
+    chunk.ends_with(b"\n\n") || chunk.ends_with(b"\r\n\r\n")
+}
+
+/// A named SSE event
+pub fn event(name: &str, data: impl std::fmt::Display) -> String {
+    format!("event: {name}\ndata: {data}\n\n")
+}
+
+/// An unnamed SSE event
+pub fn data(data: impl std::fmt::Display) -> String {
+    format!("data: {data}\n\n")
+}
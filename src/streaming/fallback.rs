@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use axum::response::Response;
+use http::StatusCode;
+use serde_json::{Value, json};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    error::ClewdrError,
+    streaming::{
+        ResponseStream, StreamDialect,
+        anthropic::{AnthropicEncoder, Delta},
+        data,
+    },
+    types::claude::Usage,
+};
+
+/// Whether a streaming request that failed with `e` is retried without
+/// streaming
+///
+/// Only upstream failures qualify, errors caused by the request itself or by
+/// an empty pool would fail the same way again.
+pub fn applies(e: &ClewdrError) -> bool {
+    if !CLEWDR_CONFIG.load().stream_fallback {
+        return false;
+    }
+    match e {
+        ClewdrError::NoCookieAvailable
+        | ClewdrError::NoKeyAvailable
+        | ClewdrError::QueueFull
+        | ClewdrError::QueueTimeout
+        | ClewdrError::KeyWaitTimeout => false,
+        ClewdrError::StreamStalled { .. }
+        | ClewdrError::TooManyRetries
+        | ClewdrError::WreqError { .. } => true,
+        e => e.status().is_server_error() || e.status() == StatusCode::TOO_MANY_REQUESTS,
+    }
+}
+
+/// Anthropic Messages events replaying a complete message, one delta per
+/// content block
+fn claude_events(msg: &Value) -> Vec<String> {
+    let usage = serde_json::from_value::<Usage>(msg["usage"].to_owned()).unwrap_or_default();
+    let mut encoder = AnthropicEncoder::new(
+        msg["id"].as_str().unwrap_or_default(),
+        msg["model"].as_str().unwrap_or_default(),
+        usage,
+    );
+    let mut deltas = vec![];
+    let blocks = msg["content"].as_array().cloned().unwrap_or_default();
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => {
+                deltas.push(Delta::Text(
+                    block["text"].as_str().unwrap_or_default().into(),
+                ));
+            }
+            Some("thinking") => {
+                let thinking = block["thinking"].as_str().unwrap_or_default();
+                deltas.push(Delta::Thinking(thinking.into()));
+                if let Some(signature) = block["signature"].as_str() {
+                    deltas.push(Delta::Signature(signature.into()));
+                }
+            }
+            Some(kind @ ("tool_use" | "server_tool_use")) => {
+                deltas.push(Delta::ToolCall {
+                    id: block["id"].as_str().unwrap_or_default().into(),
+                    name: block["name"].as_str().unwrap_or_default().into(),
+                    server: kind == "server_tool_use",
+                });
+                deltas.push(Delta::ToolInput(block["input"].to_string()));
+            }
+            // blocks without a delta type arrive whole
+            _ => deltas.push(Delta::Block(block)),
+        }
+        deltas.push(Delta::EndBlock);
+    }
+    deltas.push(Delta::Stop {
+        reason: serde_json::from_value(msg["stop_reason"].to_owned()).ok(),
+        sequence: msg["stop_sequence"].as_str().map(ToString::to_string),
+    });
+    let mut events = deltas
+        .into_iter()
+        .flat_map(|d| encoder.encode(d))
+        .collect::<Vec<_>>();
+    events.extend(encoder.finish());
+    events
+}
+
+/// OpenAI chunks replaying a complete chat completion, one per choice
+fn openai_events(completion: &Value) -> Vec<String> {
+    let choices = completion["choices"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let mut events = choices
+        .into_iter()
+        .map(|choice| {
+            let mut delta = choice["message"].to_owned();
+            // streamed tool calls carry their position
+            if let Some(calls) = delta["tool_calls"].as_array_mut() {
+                for (i, call) in calls.iter_mut().enumerate() {
+                    call["index"] = json!(i);
+                }
+            }
+            let mut chunk = completion.to_owned();
+            chunk["object"] = json!("chat.completion.chunk");
+            chunk["choices"] = json!([{
+                "index": choice["index"],
+                "delta": delta,
+                "finish_reason": choice["finish_reason"],
+                "logprobs": choice["logprobs"],
+            }]);
+            if let Some(obj) = chunk.as_object_mut() {
+                obj.remove("usage");
+            }
+            data(chunk)
+        })
+        .collect::<Vec<_>>();
+    if !completion["usage"].is_null() {
+        let mut chunk = completion.to_owned();
+        chunk["object"] = json!("chat.completion.chunk");
+        chunk["choices"] = json!([]);
+        events.push(data(chunk));
+    }
+    events.push(data("[DONE]"));
+    events
+}
+
+/// Turns a non-streaming completion into an event stream in `dialect`
+///
+/// Errors are returned untouched, a client that asked for a stream handles
+/// an error status before reading events.
+pub async fn into_sse(res: Response, dialect: StreamDialect) -> Result<Response, ClewdrError> {
+    if !res.status().is_success() {
+        return Ok(res);
+    }
+    let (mut parts, body) = res.into_parts();
+    let bytes = axum::body::to_bytes(body, usize::MAX).await?;
+    let value = serde_json::from_slice::<Value>(&bytes)?;
+    let events = match dialect {
+        StreamDialect::Claude => claude_events(&value),
+        StreamDialect::OpenAI => openai_events(&value),
+        // a streamed chunk has the shape of the whole response
+        StreamDialect::Gemini => vec![data(&value)],
+    };
+    parts.headers.insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("text/event-stream"),
+    );
+    Ok(ResponseStream::from_events(dialect, events, Duration::ZERO)
+        .with_head(parts)
+        .into_response())
+}
@@ -0,0 +1,632 @@
+use axum::{BoxError, body::Body, response::Response};
+use bytes::{Buf, Bytes, BytesMut};
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, stream::BoxStream};
+use http::{HeaderValue, StatusCode, header::CONTENT_TYPE};
+use serde_json::{Value, json};
+use tracing::warn;
+
+use crate::{
+    error::ClewdrError,
+    types::{
+        gemini::response::UsageMetadata,
+        oai::{CompletionUsage, normalize_grounding},
+    },
+};
+
+/// Chunks in a row allowed without any content, before the stream counts as
+/// an empty candidate storm
+const EMPTY_CHUNK_LIMIT: usize = 16;
+
+/// Finish reasons of a candidate that was blocked
+const BLOCKED: [&str; 6] = [
+    "SAFETY",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+    "RECITATION",
+];
+
+/// How a native Gemini stream is framed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GeminiFraming {
+    /// `alt=sse`, one `data:` event per chunk
+    Sse,
+    /// The default, chunks are elements of a JSON array sent piece by piece
+    JsonArray,
+}
+
+impl GeminiFraming {
+    /// Framing a client asked for with its `alt` query parameter
+    pub fn from_alt(alt: Option<&str>) -> Self {
+        match alt {
+            Some(alt) if alt.eq_ignore_ascii_case("sse") => GeminiFraming::Sse,
+            _ => GeminiFraming::JsonArray,
+        }
+    }
+}
+
+/// Splits an upstream stream into its JSON chunks
+///
+/// # Arguments
+/// * `stream` - Upstream response body
+/// * `framing` - How upstream frames the chunks
+pub fn chunks<S, E>(
+    stream: S,
+    framing: GeminiFraming,
+) -> BoxStream<'static, Result<Value, BoxError>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match framing {
+        GeminiFraming::Sse => stream
+            .eventsource()
+            .filter_map(|event| async move {
+                match event {
+                    Ok(event) if event.data.trim().is_empty() => None,
+                    Ok(event) => Some(serde_json::from_str(&event.data).map_err(Into::into)),
+                    Err(e) => Some(Err(e.into())),
+                }
+            })
+            .boxed(),
+        GeminiFraming::JsonArray => async_stream::stream! {
+            let mut stream = Box::pin(stream);
+            let mut buf = BytesMut::new();
+            loop {
+                // drop separators between the elements
+                let skip = buf
+                    .iter()
+                    .take_while(|b| b.is_ascii_whitespace() || matches!(b, b'[' | b',' | b']'))
+                    .count();
+                buf.advance(skip);
+                if !buf.is_empty() {
+                    let mut values = serde_json::Deserializer::from_slice(&buf).into_iter::<Value>();
+                    match values.next() {
+                        Some(Ok(value)) => {
+                            let used = values.byte_offset();
+                            buf.advance(used);
+                            yield Ok(value);
+                            continue;
+                        }
+                        Some(Err(e)) if !e.is_eof() => {
+                            yield Err(e.into());
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                match stream.next().await {
+                    Some(Ok(bytes)) => buf.extend_from_slice(&bytes),
+                    Some(Err(e)) => {
+                        yield Err(e.into());
+                        break;
+                    }
+                    None => break,
+                }
+            }
+        }
+        .boxed(),
+    }
+}
+
+/// Whether a chunk carries any generated content
+fn has_content(chunk: &Value) -> bool {
+    chunk["candidates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|c| {
+            c["content"]["parts"]
+                .as_array()
+                .is_some_and(|parts| !parts.is_empty())
+        })
+}
+
+/// Why upstream blocked the prompt or a candidate, if it did
+fn block_reason(chunk: &Value) -> Option<&str> {
+    if let Some(reason) = chunk["promptFeedback"]["blockReason"].as_str() {
+        return Some(reason);
+    }
+    chunk["candidates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| c["finishReason"].as_str())
+        .find(|r| BLOCKED.contains(r))
+}
+
+/// Checks a stream of chunks as it arrives
+///
+/// Chunks are held back until one carries content, so a stream that ends or
+/// keeps sending empty candidates before that fails with
+/// [`ClewdrError::EmptyChoices`] and can be retried. Safety blocks are logged
+/// and forwarded, retrying would be blocked the same way. Once content was
+/// sent, an empty candidate storm ends the stream with an error chunk.
+pub async fn validate(
+    mut chunks: BoxStream<'static, Result<Value, BoxError>>,
+) -> Result<BoxStream<'static, Result<Value, BoxError>>, ClewdrError> {
+    let mut held = vec![];
+    loop {
+        let Some(chunk) = chunks.next().await else {
+            return Err(ClewdrError::EmptyChoices);
+        };
+        let done = match chunk {
+            Ok(ref c) if has_content(c) => true,
+            Ok(ref c) if !c["error"].is_null() => true,
+            Ok(ref c) => match block_reason(c) {
+                Some(reason) => {
+                    warn!("[GEMINI] stream blocked before any content: {}", reason);
+                    true
+                }
+                None => false,
+            },
+            Err(_) => true,
+        };
+        held.push(chunk);
+        if done {
+            break;
+        }
+        if held.len() > EMPTY_CHUNK_LIMIT {
+            warn!("[GEMINI] {} chunks without content, retrying", held.len());
+            return Err(ClewdrError::EmptyChoices);
+        }
+    }
+    let rest = async_stream::stream! {
+        for chunk in held {
+            yield chunk;
+        }
+        let mut empty = 0;
+        while let Some(chunk) = chunks.next().await {
+            if let Ok(ref c) = chunk {
+                if let Some(reason) = block_reason(c) {
+                    warn!("[GEMINI] stream blocked mid-response: {}", reason);
+                }
+                if has_content(c) || !c["usageMetadata"].is_null() {
+                    empty = 0;
+                } else {
+                    empty += 1;
+                }
+            }
+            if empty > EMPTY_CHUNK_LIMIT {
+                warn!("[GEMINI] {} chunks in a row without content, ending the stream", empty);
+                yield Ok(json!({
+                    "error": {
+                        "code": 502,
+                        "message": "Upstream kept sending empty candidates",
+                        "status": "UNAVAILABLE",
+                    },
+                }));
+                break;
+            }
+            yield chunk;
+        }
+    };
+    Ok(rest.boxed())
+}
+
+/// Sends chunks to the client in the framing it asked for
+pub fn into_response(
+    status: StatusCode,
+    chunks: BoxStream<'static, Result<Value, BoxError>>,
+    framing: GeminiFraming,
+) -> Response {
+    let body = async_stream::stream! {
+        let mut chunks = chunks;
+        let mut first = true;
+        while let Some(chunk) = chunks.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            let frame = match framing {
+                GeminiFraming::Sse => format!("data: {chunk}\r\n\r\n"),
+                GeminiFraming::JsonArray if first => format!("[{chunk}"),
+                GeminiFraming::JsonArray => format!("\r\n,{chunk}"),
+            };
+            first = false;
+            yield Ok(Bytes::from(frame));
+        }
+        if framing == GeminiFraming::JsonArray {
+            yield Ok(Bytes::from_static(if first { b"[]" } else { b"]" }));
+        }
+    };
+    let content_type = match framing {
+        GeminiFraming::Sse => "text/event-stream",
+        GeminiFraming::JsonArray => "application/json",
+    };
+    let mut res = Response::new(Body::from_stream(body));
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    res
+}
+
+/// Replays a non-streaming response as a single chunk stream in `framing`
+///
+/// Errors are returned untouched.
+pub async fn replay(res: Response, framing: GeminiFraming) -> Result<Response, ClewdrError> {
+    if !res.status().is_success() {
+        return Ok(res);
+    }
+    let status = res.status();
+    let bytes = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+    let value = serde_json::from_slice::<Value>(&bytes)?;
+    let chunks = futures::stream::once(async { Ok(value) }).boxed();
+    Ok(into_response(status, chunks, framing))
+}
+
+/// OpenAI finish reason of a Gemini one
+fn finish_reason(reason: &str, called_tools: bool) -> &'static str {
+    match reason {
+        "MAX_TOKENS" => "length",
+        r if BLOCKED.contains(&r) => "content_filter",
+        _ if called_tools => "tool_calls",
+        _ => "stop",
+    }
+}
+
+/// Translates native chunks into OpenAI `chat.completion.chunk`s as they
+/// arrive
+///
+/// Text goes into `content`, thoughts into `reasoning_content` when
+/// `expose_thoughts` is set, function calls into `tool_calls`. The usage of
+/// the last chunk reporting one is sent in a final chunk without choices.
+///
+/// # Arguments
+/// * `chunks` - Native chunks, see [`validate`]
+/// * `model` - Requested model, echoed back
+/// * `expose` - `gemini_thinking.expose_thoughts` of the config
+pub fn to_openai(
+    mut chunks: BoxStream<'static, Result<Value, BoxError>>,
+    model: String,
+    expose: bool,
+) -> BoxStream<'static, Result<Value, BoxError>> {
+    let id = format!("chatcmpl-{}", uuid::Uuid::new_v4().simple());
+    let created = chrono::Utc::now().timestamp();
+    async_stream::stream! {
+        // per candidate: whether the role was sent, tool calls so far
+        let mut started: Vec<(bool, usize)> = vec![];
+        let mut usage = None;
+        while let Some(chunk) = chunks.next().await {
+            let mut chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            };
+            if !chunk["error"].is_null() {
+                yield Ok(json!({ "error": chunk["error"].take() }));
+                continue;
+            }
+            if let Ok(meta) = serde_json::from_value::<UsageMetadata>(chunk["usageMetadata"].take()) {
+                usage = Some(meta);
+            }
+            let candidates = chunk["candidates"].as_array().cloned().unwrap_or_default();
+            let mut choices = vec![];
+            for (i, candidate) in candidates.into_iter().enumerate() {
+                let index = candidate["index"].as_u64().map_or(i, |i| i as usize);
+                if started.len() <= index {
+                    started.resize(index + 1, (false, 0));
+                }
+                let (role_sent, calls) = &mut started[index];
+                let mut delta = json!({});
+                if !*role_sent {
+                    delta["role"] = json!("assistant");
+                    *role_sent = true;
+                }
+                let (mut content, mut reasoning, mut tool_calls) = (String::new(), String::new(), vec![]);
+                for part in candidate["content"]["parts"].as_array().into_iter().flatten() {
+                    if let Some(text) = part["text"].as_str() {
+                        if part["thought"].as_bool().unwrap_or_default() {
+                            reasoning.push_str(text);
+                        } else {
+                            content.push_str(text);
+                        }
+                    } else if let Some(call) = part.get("functionCall") {
+                        tool_calls.push(json!({
+                            "index": *calls,
+                            "id": format!("call_{}", uuid::Uuid::new_v4().simple()),
+                            "type": "function",
+                            "function": {
+                                "name": call["name"],
+                                "arguments": call.get("args").unwrap_or(&json!({})).to_string(),
+                            },
+                        }));
+                        *calls += 1;
+                    }
+                }
+                if !content.is_empty() {
+                    delta["content"] = json!(content);
+                }
+                if expose && !reasoning.is_empty() {
+                    delta["reasoning_content"] = json!(reasoning);
+                }
+                if !tool_calls.is_empty() {
+                    delta["tool_calls"] = json!(tool_calls);
+                }
+                let finish = candidate["finishReason"]
+                    .as_str()
+                    .map(|r| finish_reason(r, *calls > 0));
+                let mut choice = json!({
+                    "index": index,
+                    "delta": delta,
+                    "finish_reason": finish,
+                });
+                if let Some(meta) = candidate.get("groundingMetadata") {
+                    choice["groundingMetadata"] = meta.to_owned();
+                }
+                choices.push(choice);
+            }
+            if choices.is_empty() {
+                continue;
+            }
+            let mut chunk = json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": model,
+                "choices": choices,
+            });
+            normalize_grounding(&mut chunk);
+            yield Ok(chunk);
+        }
+        if let Some(ref meta) = usage {
+            yield Ok(json!({
+                "id": id,
+                "object": "chat.completion.chunk",
+                "created": created,
+                "model": model,
+                "choices": [],
+                "usage": CompletionUsage::from(meta),
+            }));
+        }
+    }
+    .boxed()
+}
+
+/// Sends OpenAI chunks as an event stream, ended with `[DONE]`
+pub fn into_openai_response(
+    status: StatusCode,
+    mut chunks: BoxStream<'static, Result<Value, BoxError>>,
+) -> Response {
+    let body = async_stream::stream! {
+        while let Some(chunk) = chunks.next().await {
+            match chunk {
+                Ok(chunk) => yield Ok(Bytes::from(format!("data: {chunk}\n\n"))),
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        yield Ok(Bytes::from_static(b"data: [DONE]\n\n"));
+    };
+    let mut res = Response::new(Body::from_stream(body));
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+
+    use super::*;
+
+    /// Upstream body delivered in the given pieces
+    fn body(pieces: &[&str]) -> impl Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static {
+        let pieces = pieces
+            .iter()
+            .map(|p| Ok::<_, std::io::Error>(Bytes::copy_from_slice(p.as_bytes())))
+            .collect::<Vec<_>>();
+        stream::iter(pieces)
+    }
+
+    fn values(chunks: Vec<Value>) -> BoxStream<'static, Result<Value, BoxError>> {
+        stream::iter(chunks.into_iter().map(Ok)).boxed()
+    }
+
+    async fn collect(chunks: BoxStream<'static, Result<Value, BoxError>>) -> Vec<Value> {
+        chunks.map(|c| c.unwrap()).collect().await
+    }
+
+    fn text(text: &str) -> Value {
+        json!({ "candidates": [{ "content": { "parts": [{ "text": text }] } }] })
+    }
+
+    fn empty() -> Value {
+        json!({ "candidates": [{ "content": { "parts": [] } }] })
+    }
+
+    #[tokio::test]
+    async fn test_json_array_split_anywhere() {
+        let raw = "[{\"a\":1}\r\n,{\"b\":\"x],[\"}\r\n]";
+        // every split point, down to single bytes
+        for size in 1..raw.len() {
+            let pieces = raw
+                .as_bytes()
+                .chunks(size)
+                .map(|c| std::str::from_utf8(c).unwrap())
+                .collect::<Vec<_>>();
+            let chunks = collect(chunks(body(&pieces), GeminiFraming::JsonArray)).await;
+            assert_eq!(chunks, vec![json!({ "a": 1 }), json!({ "b": "x],[" })]);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_array_malformed() {
+        let mut chunks = chunks(body(&["[{\"a\":1},{oops}]"]), GeminiFraming::JsonArray);
+        assert_eq!(chunks.next().await.unwrap().unwrap(), json!({ "a": 1 }));
+        assert!(chunks.next().await.unwrap().is_err());
+        assert!(chunks.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_sse_chunks() {
+        let pieces = [
+            "data: {\"a\"",
+            ":1}\r\n\r\n",
+            "data: \r\n\r\ndata: {\"b\":2}\r\n\r\n",
+        ];
+        let chunks = collect(chunks(body(&pieces), GeminiFraming::Sse)).await;
+        assert_eq!(chunks, vec![json!({ "a": 1 }), json!({ "b": 2 })]);
+    }
+
+    #[tokio::test]
+    async fn test_validate_holds_until_content() {
+        let chunks = validate(values(vec![empty(), empty(), text("hi")]))
+            .await
+            .unwrap();
+        assert_eq!(collect(chunks).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_validate_retries_without_content() {
+        let result = validate(values(vec![empty(), empty()])).await;
+        assert!(matches!(result, Err(ClewdrError::EmptyChoices)));
+        let storm = vec![empty(); EMPTY_CHUNK_LIMIT + 2];
+        let result = validate(values(storm)).await;
+        assert!(matches!(result, Err(ClewdrError::EmptyChoices)));
+    }
+
+    #[tokio::test]
+    async fn test_validate_forwards_blocks() {
+        let blocked = json!({ "promptFeedback": { "blockReason": "SAFETY" } });
+        let chunks = validate(values(vec![blocked.to_owned()])).await.unwrap();
+        assert_eq!(collect(chunks).await, vec![blocked]);
+    }
+
+    #[tokio::test]
+    async fn test_validate_ends_storm_after_content() {
+        let mut storm = vec![text("hi")];
+        storm.extend(vec![empty(); EMPTY_CHUNK_LIMIT + 5]);
+        let chunks = collect(validate(values(storm)).await.unwrap()).await;
+        assert_eq!(chunks.len(), EMPTY_CHUNK_LIMIT + 2);
+        assert_eq!(chunks.last().unwrap()["error"]["code"], 502);
+    }
+
+    #[tokio::test]
+    async fn test_into_response_framing() {
+        let res = into_response(
+            StatusCode::OK,
+            values(vec![text("a"), text("b")]),
+            GeminiFraming::JsonArray,
+        );
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let array = serde_json::from_slice::<Value>(&bytes).unwrap();
+        assert_eq!(array, json!([text("a"), text("b")]));
+
+        let res = into_response(StatusCode::OK, values(vec![]), GeminiFraming::JsonArray);
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"[]");
+
+        let res = into_response(StatusCode::OK, values(vec![text("a")]), GeminiFraming::Sse);
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let round_trip = collect(chunks(
+            stream::once(async move { Ok::<_, std::io::Error>(bytes) }),
+            GeminiFraming::Sse,
+        ))
+        .await;
+        assert_eq!(round_trip, vec![text("a")]);
+    }
+
+    #[tokio::test]
+    async fn test_to_openai() {
+        let upstream = vec![
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": "pondering", "thought": true },
+                { "text": "Hello" },
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [
+                { "functionCall": { "name": "lookup", "args": { "q": "x" } } },
+            ] }, "finishReason": "STOP" }],
+              "usageMetadata": {
+                "promptTokenCount": 5,
+                "candidatesTokenCount": 7,
+                "thoughtsTokenCount": 2,
+                "totalTokenCount": 14,
+            } }),
+        ];
+        let chunks = collect(to_openai(values(upstream), "gemini-2.5-pro".into(), false)).await;
+        assert_eq!(chunks.len(), 3);
+        for chunk in &chunks {
+            assert_eq!(chunk["object"], "chat.completion.chunk");
+            assert_eq!(chunk["model"], "gemini-2.5-pro");
+            assert_eq!(chunk["id"], chunks[0]["id"]);
+        }
+        let first = &chunks[0]["choices"][0];
+        assert_eq!(first["delta"]["role"], "assistant");
+        assert_eq!(first["delta"]["content"], "Hello");
+        // thoughts stay hidden unless exposed
+        assert!(first["delta"]["reasoning_content"].is_null());
+        assert!(first["finish_reason"].is_null());
+        let second = &chunks[1]["choices"][0];
+        assert!(second["delta"]["role"].is_null());
+        let call = &second["delta"]["tool_calls"][0];
+        assert_eq!(call["index"], 0);
+        assert_eq!(call["function"]["name"], "lookup");
+        assert_eq!(
+            serde_json::from_str::<Value>(call["function"]["arguments"].as_str().unwrap()).unwrap(),
+            json!({ "q": "x" })
+        );
+        assert_eq!(second["finish_reason"], "tool_calls");
+        // usage comes last, without choices
+        assert_eq!(chunks[2]["choices"], json!([]));
+        assert_eq!(chunks[2]["usage"]["prompt_tokens"], 5);
+        assert_eq!(chunks[2]["usage"]["completion_tokens"], 9);
+        assert_eq!(chunks[2]["usage"]["total_tokens"], 14);
+    }
+
+    #[tokio::test]
+    async fn test_to_openai_thoughts_and_reasons() {
+        let upstream = vec![
+            json!({ "candidates": [{ "content": { "parts": [
+                { "text": "pondering", "thought": true },
+            ] } }] }),
+            json!({ "candidates": [{ "content": { "parts": [{ "text": "cut" }] },
+                "finishReason": "MAX_TOKENS" }] }),
+            json!({ "candidates": [{ "index": 1, "finishReason": "SAFETY" }] }),
+        ];
+        let chunks = collect(to_openai(values(upstream), "m".into(), true)).await;
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks[0]["choices"][0]["delta"]["reasoning_content"],
+            "pondering"
+        );
+        assert_eq!(chunks[1]["choices"][0]["finish_reason"], "length");
+        let blocked = &chunks[2]["choices"][0];
+        assert_eq!(blocked["index"], 1);
+        // the first chunk of each candidate carries the role
+        assert_eq!(blocked["delta"]["role"], "assistant");
+        assert_eq!(blocked["finish_reason"], "content_filter");
+    }
+
+    #[tokio::test]
+    async fn test_to_openai_errors() {
+        let error = json!({ "error": { "code": 502, "message": "boom" } });
+        let chunks = collect(to_openai(values(vec![error]), "m".into(), false)).await;
+        assert_eq!(
+            chunks,
+            vec![json!({ "error": { "code": 502, "message": "boom" } })]
+        );
+
+        let res = into_openai_response(StatusCode::OK, values(vec![json!({ "a": 1 })]));
+        let bytes = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&bytes[..], b"data: {\"a\":1}\n\ndata: [DONE]\n\n");
+    }
+}
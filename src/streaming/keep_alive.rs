@@ -0,0 +1,88 @@
+use std::{future::Future, time::Duration};
+
+use async_stream::stream;
+use axum::{
+    body::{Body, Bytes},
+    response::{IntoResponse, Response},
+};
+use futures::{FutureExt, StreamExt, pin_mut};
+use http::{HeaderValue, header::CONTENT_TYPE};
+use tokio::select;
+
+use super::StreamDialect;
+use crate::{config::CLEWDR_CONFIG, middleware::to_dialect_error};
+
+/// Time a padded JSON response waits past the request timeout, so the error
+/// of the upstream timeout normally arrives first
+const TIMEOUT_GRACE: Duration = Duration::from_secs(30);
+
+/// Marks a response padded by [`json_keep_alive`], its body is streamed
+/// while the completion runs and must not be buffered by later layers
+#[derive(Debug, Clone, Copy)]
+pub struct PaddedJson;
+
+/// Answers a non-streaming request right away and pads the JSON body with
+/// newlines until the completion is ready
+///
+/// JSON bodies can only be padded with whitespace. The status is sent before
+/// the completion is known, so errors arrive as a JSON body with a 200,
+/// rendered in `dialect`. If nothing arrived once `timeout`, the request
+/// timeout of the route, has passed, a timeout error object ends the body.
+pub fn json_keep_alive<F>(
+    completion: F,
+    dialect: StreamDialect,
+    timeout: Option<Duration>,
+) -> Response
+where
+    F: Future<Output = Response> + Send + 'static,
+{
+    let period = CLEWDR_CONFIG.load().keep_alive.interval_secs.max(1);
+    let mut interval = tokio::time::interval(Duration::from_secs(period));
+    let deadline = timeout.map(|t| t + TIMEOUT_GRACE);
+    let body = stream! {
+        let stream = completion
+            .then(move |res| to_dialect_error(res, dialect))
+            .map(|res| res.into_body().into_data_stream())
+            .into_stream()
+            .flatten();
+        pin_mut!(stream);
+        let start = std::time::Instant::now();
+        let mut started = false;
+        loop {
+            select! {
+                biased;
+                data = stream.next() => {
+                    match data {
+                        Some(Ok(d)) => {
+                            started = true;
+                            yield Ok(d);
+                        }
+                        Some(Err(e)) => {
+                            yield Err(e);
+                            break;
+                        }
+                        None => break
+                    }
+                }
+                _ = interval.tick() => {
+                    if started {
+                        // the body is on its way, bounded by the request timeout
+                        continue;
+                    }
+                    if deadline.is_some_and(|d| start.elapsed() > d) {
+                        let error = dialect.error_object("Timed out waiting for upstream");
+                        yield Ok(Bytes::from(error.to_string()));
+                        break;
+                    }
+                    yield Ok(Bytes::from("\n"));
+                }
+                else => break
+            }
+        }
+    };
+    let mut res = Body::from_stream(body).into_response();
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    res.extensions_mut().insert(PaddedJson);
+    res
+}
@@ -0,0 +1,25 @@
+pub mod anthropic;
+/// Streamed responses, shared by every backend
+///
+/// Upstream event streams are passed through [`ResponseStream`], which adds
+/// keep-alives and cancellation in the dialect of the route, so the backends
+/// do not each grow their own variant:
+///
+/// - Dialect: Events the proxy writes itself, in each client format
+/// - Anthropic: Write spec conforming Messages streams from any upstream
+/// - Watchdog: Abort upstream streams that stall
+/// - Fallback: Replay non-streaming completions as event streams
+/// - Gemini: Parse and check native Gemini streams, in either framing
+/// - Resume: Buffer streams so clients can reconnect with `Last-Event-ID`
+/// - Keep-alive: Keep connections busy while waiting on upstream
+mod dialect;
+pub mod fallback;
+pub mod gemini;
+mod keep_alive;
+mod response_stream;
+pub mod resume;
+pub mod watchdog;
+
+pub use dialect::{StreamDialect, data, ends_event, event};
+pub use keep_alive::{PaddedJson, json_keep_alive};
+pub use response_stream::ResponseStream;
@@ -0,0 +1,193 @@
+use std::{future::Future, time::Duration};
+
+use axum::{
+    BoxError,
+    body::{Body, Bytes},
+    response::Response,
+};
+use futures::{
+    FutureExt, Stream, StreamExt, TryStreamExt,
+    future::BoxFuture,
+    stream::{self, BoxStream},
+};
+use http::{
+    HeaderValue,
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    response::Parts,
+};
+
+use crate::{
+    config::CLEWDR_CONFIG,
+    services::connection_registry::CONNECTION_REGISTRY,
+    streaming::{StreamDialect, ends_event},
+};
+
+/// An event stream on its way to the client
+///
+/// Made of a chunk source and the dialect of its events. Keep-alives and
+/// cancellation are added on top, writing their events in that dialect.
+pub struct ResponseStream {
+    head: Option<Parts>,
+    /// Whether the body is an event stream, other bodies get no keep-alives
+    /// or error events, e.g. a Gemini stream framed as a JSON array
+    events: bool,
+    dialect: StreamDialect,
+    source: BoxStream<'static, Result<Bytes, axum::Error>>,
+    keep_alive: Option<(Bytes, Duration)>,
+    cancel: Option<BoxFuture<'static, String>>,
+}
+
+impl ResponseStream {
+    /// Wraps a stream of SSE bytes
+    pub fn new<S, E>(dialect: StreamDialect, source: S) -> Self
+    where
+        S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+        E: Into<BoxError> + 'static,
+    {
+        Self {
+            head: None,
+            events: true,
+            dialect,
+            source: source.map_err(axum::Error::new).boxed(),
+            keep_alive: None,
+            cancel: None,
+        }
+    }
+
+    /// Takes over the body of a streamed response, keeping its head
+    pub fn from_response(dialect: StreamDialect, res: Response) -> Self {
+        let (parts, body) = res.into_parts();
+        Self::new(dialect, body.into_data_stream()).with_head(parts)
+    }
+
+    /// Streams pre-rendered events, `delay` apart
+    pub fn from_events(dialect: StreamDialect, events: Vec<String>, delay: Duration) -> Self {
+        let source =
+            stream::iter(events.into_iter().enumerate()).then(move |(i, event)| async move {
+                if i > 0 && !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                Ok::<_, axum::Error>(Bytes::from(event))
+            });
+        Self::new(dialect, source)
+    }
+
+    /// Sends the stream with the status and headers of `parts`
+    pub fn with_head(mut self, mut parts: Parts) -> Self {
+        parts.headers.remove(CONTENT_LENGTH);
+        self.events = parts
+            .headers
+            .get(CONTENT_TYPE)
+            .is_none_or(|v| v.as_bytes().starts_with(b"text/event-stream"));
+        self.head = Some(parts);
+        self
+    }
+
+    /// Sends keep-alives in the style configured for the dialect whenever the
+    /// source is silent for `keep_alive.interval_secs`
+    pub fn with_keep_alive(mut self) -> Self {
+        if !self.events {
+            return self;
+        }
+        let config = CLEWDR_CONFIG.load();
+        let style = match self.dialect {
+            StreamDialect::Gemini => config.keep_alive.gemini,
+            StreamDialect::OpenAI => config.keep_alive.openai,
+            StreamDialect::Claude => config.keep_alive.claude,
+        };
+        let period = Duration::from_secs(config.keep_alive.interval_secs.max(1));
+        self.keep_alive = self.dialect.keep_alive_event(style).map(|e| (e, period));
+        self
+    }
+
+    /// Ends the stream with an error event once `cancelled` resolves with the
+    /// reason
+    pub fn with_cancel(mut self, cancelled: impl Future<Output = String> + Send + 'static) -> Self {
+        self.cancel = Some(cancelled.boxed());
+        self
+    }
+
+    /// Ends the stream when the server shuts down, like realtime connections
+    ///
+    /// # Arguments
+    /// * `label` - What the stream is for, used in logs
+    pub fn cancel_on_shutdown(self, label: impl Into<String>) -> Self {
+        let mut handle = CONNECTION_REGISTRY.register(label);
+        self.with_cancel(async move {
+            handle.cancelled().await;
+            "Server is shutting down".to_string()
+        })
+    }
+
+    /// Body of the response
+    ///
+    /// Keep-alives and the cancellation event only go out between events,
+    /// never inside a partially sent one.
+    pub fn into_body(self) -> Body {
+        let Self {
+            head: _,
+            events,
+            dialect,
+            mut source,
+            keep_alive,
+            cancel,
+        } = self;
+        let mut cancel = cancel.unwrap_or_else(|| std::future::pending().boxed());
+        let body = async_stream::stream! {
+            let mut clean = true;
+            loop {
+                let next = async {
+                    match keep_alive {
+                        Some((_, period)) => tokio::time::timeout(period, source.next()).await.ok(),
+                        None => Some(source.next().await),
+                    }
+                };
+                tokio::select! {
+                    next = next => match next {
+                        Some(Some(chunk)) => {
+                            if let Ok(ref c) = chunk
+                                && !c.is_empty()
+                            {
+                                clean = ends_event(c);
+                            }
+                            yield chunk;
+                        }
+                        Some(None) => break,
+                        // silent for a whole period
+                        None => {
+                            if clean && let Some((ref event, _)) = keep_alive {
+                                yield Ok(event.to_owned());
+                            }
+                        }
+                    },
+                    reason = &mut cancel => {
+                        if !events {
+                            break;
+                        }
+                        let prefix = if clean { "" } else { "\n\n" };
+                        let event = dialect.error_event(&reason);
+                        yield Ok(Bytes::from([prefix.as_bytes(), &event].concat()));
+                        break;
+                    }
+                }
+            }
+        };
+        Body::from_stream(body)
+    }
+
+    /// Response carrying the stream
+    ///
+    /// Keeps the head of the response the stream was taken from, if any,
+    /// otherwise a plain 200 event stream.
+    pub fn into_response(mut self) -> Response {
+        match self.head.take() {
+            Some(parts) => Response::from_parts(parts, self.into_body()),
+            None => {
+                let mut res = Response::new(self.into_body());
+                res.headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+                res
+            }
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use axum::body::{Body, Bytes};
+use futures::{Stream, StreamExt};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::services::connection_registry::CONNECTION_REGISTRY;
+
+/// Events of one streamed response, kept so a client that lost the
+/// connection can pick up where it left off
+pub struct StreamBuffer {
+    request_id: String,
+    /// Digest of the credentials the stream was requested with
+    credential: Vec<u8>,
+    capacity: usize,
+    events: Mutex<VecDeque<Bytes>>,
+    /// Number of events pushed so far, and whether upstream is done
+    produced: watch::Sender<(u64, bool)>,
+    /// Reader currently attached, as its generation and next event
+    consumed: watch::Sender<Option<(u64, u64)>>,
+    generation: AtomicU64,
+}
+
+impl StreamBuffer {
+    fn new(request_id: String, credential: Vec<u8>, capacity: usize) -> Self {
+        Self {
+            request_id,
+            credential,
+            capacity: capacity.max(1),
+            events: Mutex::new(VecDeque::new()),
+            produced: watch::Sender::new((0, false)),
+            consumed: watch::Sender::new(None),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<Bytes>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a client presenting `credential` may read this stream
+    pub fn owned_by(&self, credential: &[u8]) -> bool {
+        self.credential == credential
+    }
+
+    /// Whether events from `from` on are still buffered
+    pub fn can_resume_from(&self, from: u64) -> bool {
+        let (total, _) = *self.produced.borrow();
+        let first = total - self.lock().len() as u64;
+        from >= first && from <= total
+    }
+
+    /// Pushes an event, waiting for the attached reader when the buffer is
+    /// full and it has not read the oldest event yet
+    async fn push(&self, event: Bytes, window: Duration) {
+        let mut consumed = self.consumed.subscribe();
+        loop {
+            let (total, _) = *self.produced.borrow();
+            let first = total - self.lock().len() as u64;
+            let full = self.lock().len() >= self.capacity;
+            let blocked = full && consumed.borrow().is_some_and(|(_, pos)| pos <= first);
+            if !blocked {
+                break;
+            }
+            // a reader stuck for a whole window is given up on
+            let wait = consumed.wait_for(|c| c.is_none_or(|(_, pos)| pos > first));
+            if tokio::time::timeout(window, wait).await.is_err() {
+                warn!(
+                    "[RESUME] reader of {} stalled, dropping buffered events",
+                    self.request_id
+                );
+                break;
+            }
+        }
+        let mut events = self.lock();
+        if events.len() >= self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event);
+        drop(events);
+        self.produced.send_modify(|(total, _)| *total += 1);
+    }
+
+    fn finish(&self) {
+        self.produced.send_modify(|(_, done)| *done = true);
+    }
+
+    /// Resolves once no reader has been attached for `window`
+    async fn abandoned(&self, window: Duration) {
+        let mut consumed = self.consumed.subscribe();
+        loop {
+            let _ = consumed.wait_for(Option::is_none).await;
+            let attached = consumed.wait_for(Option::is_some);
+            if tokio::time::timeout(window, attached).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Streams events from `from` on, tagged with `id:` lines so the client
+    /// can reconnect with `Last-Event-ID`
+    ///
+    /// Attaching a reader detaches the previous one, which ends once it is
+    /// polled again.
+    pub fn reader(self: Arc<Self>, from: u64) -> impl Stream<Item = Result<Bytes, axum::Error>> {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        self.consumed.send_replace(Some((generation, from)));
+        let guard = ReaderGuard {
+            buffer: self.to_owned(),
+            generation,
+        };
+        async_stream::stream! {
+            let _guard = guard;
+            let buffer = self;
+            let mut produced = buffer.produced.subscribe();
+            let mut pos = from;
+            loop {
+                let (total, done) = match produced.wait_for(|(total, done)| *total > pos || *done).await {
+                    Ok(state) => *state,
+                    Err(_) => break,
+                };
+                if buffer.consumed.borrow().is_none_or(|(g, _)| g != generation) {
+                    debug!("[RESUME] reader of {} replaced", buffer.request_id);
+                    break;
+                }
+                if pos >= total && done {
+                    break;
+                }
+                let event = {
+                    let events = buffer.lock();
+                    let first = total - events.len() as u64;
+                    if pos < first {
+                        None
+                    } else {
+                        events.get((pos - first) as usize).cloned()
+                    }
+                };
+                let Some(event) = event else {
+                    warn!("[RESUME] reader of {} fell behind the buffer", buffer.request_id);
+                    yield Err(axum::Error::new(std::io::Error::other("stream buffer overrun")));
+                    break;
+                };
+                yield Ok(tag(&buffer.request_id, pos, &event));
+                pos += 1;
+                buffer.consumed.send_if_modified(|c| match c {
+                    Some((g, p)) if *g == generation => {
+                        *p = pos;
+                        true
+                    }
+                    _ => false,
+                });
+            }
+        }
+    }
+}
+
+/// Detaches the reader when the client goes away
+struct ReaderGuard {
+    buffer: Arc<StreamBuffer>,
+    generation: u64,
+}
+
+impl Drop for ReaderGuard {
+    fn drop(&mut self) {
+        self.buffer.consumed.send_if_modified(|c| {
+            if c.is_some_and(|(g, _)| g == self.generation) {
+                *c = None;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+/// Prefixes an event with its `id:` line
+fn tag(request_id: &str, seq: u64, event: &[u8]) -> Bytes {
+    let mut out = format!("id: {request_id}:{seq}\n").into_bytes();
+    out.extend_from_slice(event);
+    out.extend_from_slice(b"\n\n");
+    Bytes::from(out)
+}
+
+/// Splits the first complete SSE event off `buf`, without its terminator
+fn split_event(buf: &mut Vec<u8>) -> Option<Bytes> {
+    let lf = buf.windows(2).position(|w| w == b"\n\n").map(|i| (i, 2));
+    let crlf = buf
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|i| (i, 4));
+    let (end, sep) = match (lf, crlf) {
+        (Some(a), Some(b)) => a.min(b),
+        (a, b) => a.or(b)?,
+    };
+    let event = Bytes::copy_from_slice(&buf[..end]);
+    buf.drain(..end + sep);
+    Some(event)
+}
+
+/// Parses a `Last-Event-ID` written by [`StreamBuffer::reader`]
+///
+/// # Returns
+/// The request ID and the next event to send
+pub fn parse_last_event_id(value: &str) -> Option<(&str, u64)> {
+    let (request_id, seq) = value.trim().rsplit_once(':')?;
+    Some((request_id, seq.parse::<u64>().ok()?.checked_add(1)?))
+}
+
+/// Moves a streamed response body into a buffer drained by a background task,
+/// so the upstream stream outlives the client connection
+///
+/// The buffer is registered under the request ID for `window` after the
+/// stream ends. Upstream is dropped once no client has been attached for
+/// `window`.
+///
+/// # Arguments
+/// * `request_id` - Request ID of the streamed request
+/// * `credential` - Digest of the credentials a reconnect must present
+/// * `capacity` - Events kept at most
+/// * `window` - How long a client has to reconnect
+pub fn buffer(
+    request_id: String,
+    credential: Vec<u8>,
+    body: Body,
+    capacity: usize,
+    window: Duration,
+) -> Arc<StreamBuffer> {
+    let buffer = Arc::new(StreamBuffer::new(
+        request_id.to_owned(),
+        credential,
+        capacity,
+    ));
+    CONNECTION_REGISTRY.track_stream(&request_id, buffer.to_owned());
+    let pump = buffer.to_owned();
+    let mut handle = CONNECTION_REGISTRY.register(format!("resumable stream {request_id}"));
+    tokio::spawn(async move {
+        let mut stream = body.into_data_stream();
+        let mut pending = Vec::new();
+        let abandoned = pump.abandoned(window);
+        tokio::pin!(abandoned);
+        loop {
+            let chunk = tokio::select! {
+                chunk = stream.next() => chunk,
+                _ = &mut abandoned => {
+                    debug!("[RESUME] stream {} abandoned", request_id);
+                    break;
+                }
+                _ = handle.cancelled() => break,
+            };
+            match chunk {
+                Some(Ok(chunk)) => {
+                    pending.extend_from_slice(&chunk);
+                    while let Some(event) = split_event(&mut pending) {
+                        pump.push(event, window).await;
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("[RESUME] upstream stream {} failed: {}", request_id, e);
+                    break;
+                }
+                None => break,
+            }
+        }
+        if !pending.is_empty() {
+            pump.push(Bytes::from(pending), window).await;
+        }
+        pump.finish();
+        drop(handle);
+        tokio::time::sleep(window).await;
+        CONNECTION_REGISTRY.forget_stream(&request_id, &pump);
+    });
+    buffer
+}
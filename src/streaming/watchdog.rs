@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt, stream::BoxStream};
+use tracing::warn;
+
+use crate::{config::CLEWDR_CONFIG, error::ClewdrError, streaming::StreamDialect};
+
+/// Longest gap allowed between two chunks of an upstream stream, `None` when
+/// the watchdog is disabled
+pub fn idle_timeout() -> Option<Duration> {
+    match CLEWDR_CONFIG.load().stream_idle_timeout_secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Guards an upstream byte stream against stalls
+///
+/// Waits for the first chunk before returning, so a stream that never starts
+/// fails with [`ClewdrError::StreamStalled`] and can be retried with another
+/// credential. A stream that stalls later is ended with an error event in
+/// `dialect`, after calling `on_stall`.
+///
+/// # Arguments
+/// * `stream` - Upstream response body
+/// * `dialect` - Format of the events the client receives
+/// * `on_stall` - Called when the stream stalls after it started, e.g. to
+///   report the credential
+pub async fn guard<S, E>(
+    stream: S,
+    dialect: StreamDialect,
+    on_stall: impl FnOnce() + Send + 'static,
+) -> Result<BoxStream<'static, Result<Bytes, E>>, ClewdrError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let mut stream = Box::pin(stream);
+    let Some(timeout) = idle_timeout() else {
+        return Ok(stream);
+    };
+    let secs = timeout.as_secs();
+    let Ok(first) = tokio::time::timeout(timeout, stream.next()).await else {
+        warn!("Upstream sent nothing for {}s, aborting", secs);
+        return Err(ClewdrError::StreamStalled { secs });
+    };
+    let Some(first) = first else {
+        return Ok(futures::stream::empty().boxed());
+    };
+    let rest = async_stream::stream! {
+        yield first;
+        let mut on_stall = Some(on_stall);
+        loop {
+            match tokio::time::timeout(timeout, stream.next()).await {
+                Ok(Some(chunk)) => yield chunk,
+                Ok(None) => break,
+                Err(_) => {
+                    warn!("Upstream stream stalled for {}s, aborting", secs);
+                    if let Some(f) = on_stall.take() {
+                        f();
+                    }
+                    let msg = format!("Upstream sent no data for {secs}s");
+                    yield Ok(dialect.error_event(&msg));
+                    break;
+                }
+            }
+        }
+    };
+    Ok(rest.boxed())
+}
@@ -77,7 +77,7 @@ impl CreateMessageParams {
                 MessageContent::Blocks { ref content } => content
                     .iter()
                     .map(|block| match block {
-                        ContentBlock::Text { text } => text,
+                        ContentBlock::Text { text, .. } => text,
                         _ => "",
                     })
                     .collect::<String>(),
@@ -87,22 +87,86 @@ impl CreateMessageParams {
         bpe.encode_with_special_tokens(&systems).len() as u32
             + bpe.encode_with_special_tokens(&messages).len() as u32
     }
+
+    /// Number of prompt caching breakpoints set by the client
+    pub fn cache_breakpoints(&self) -> usize {
+        let systems = match self.system {
+            Some(Value::Array(ref arr)) => arr
+                .iter()
+                .filter(|v| v.get("cache_control").is_some_and(|c| !c.is_null()))
+                .count(),
+            _ => 0,
+        };
+        let tools = self
+            .tools
+            .iter()
+            .flatten()
+            .filter(|t| t.cache_control.is_some())
+            .count();
+        let messages = self
+            .messages
+            .iter()
+            .filter_map(|msg| match msg.content {
+                MessageContent::Blocks { ref content } => Some(content),
+                MessageContent::Text { .. } => None,
+            })
+            .flatten()
+            .filter(|block| block.cache_control().is_some())
+            .count();
+        systems + tools + messages
+    }
 }
 
+/// Smallest thinking budget Anthropic accepts
+pub const MIN_THINKING_BUDGET: u64 = 1024;
+
 /// Thinking mode in Claude API Request
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Thinking {
-    pub budget_tokens: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_tokens: Option<u64>,
     r#type: String,
 }
 
 impl Thinking {
     pub fn new(budget_tokens: u64) -> Self {
         Self {
-            budget_tokens,
+            budget_tokens: Some(budget_tokens),
             r#type: String::from("enabled"),
         }
     }
+
+    pub fn is_enabled(&self) -> bool {
+        self.r#type == "enabled"
+    }
+}
+
+impl CreateMessageParams {
+    /// Brings an enabled thinking budget within what Anthropic accepts
+    ///
+    /// The budget is raised to the minimum and kept below `max_tokens`,
+    /// thinking is dropped when `max_tokens` leaves no room for it. Sampling
+    /// parameters thinking does not support are cleared.
+    pub fn fit_thinking(&mut self) {
+        let Some(ref mut thinking) = self.thinking else {
+            return;
+        };
+        if !thinking.is_enabled() {
+            return;
+        }
+        if self.max_tokens <= MIN_THINKING_BUDGET as u32 {
+            self.thinking = None;
+            return;
+        }
+        let budget = thinking
+            .budget_tokens
+            .unwrap_or(MIN_THINKING_BUDGET)
+            .clamp(MIN_THINKING_BUDGET, self.max_tokens as u64 - 1);
+        thinking.budget_tokens = Some(budget);
+        self.temperature = None;
+        self.top_k = None;
+        self.top_p = self.top_p.map(|p| p.clamp(0.95, 1.0));
+    }
 }
 
 impl From<RequiredMessageParams> for CreateMessageParams {
@@ -183,6 +247,7 @@ pub struct Message {
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Role {
+    #[serde(alias = "developer")]
     System,
     User,
     #[default]
@@ -199,16 +264,45 @@ pub enum MessageContent {
     Blocks { content: Vec<ContentBlock> },
 }
 
+/// Prompt caching breakpoint
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct CacheControl {
+    /// Type of the cache, always "ephemeral"
+    #[serde(rename = "type")]
+    pub type_: String,
+    /// Lifetime of the cache entry, e.g. "5m" or "1h"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<String>,
+}
+
+impl CacheControl {
+    /// Create an ephemeral breakpoint with the default lifetime
+    pub fn ephemeral() -> Self {
+        Self {
+            type_: "ephemeral".into(),
+            ttl: None,
+        }
+    }
+}
+
 /// Content block in a message
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 #[serde(tag = "type")]
 pub enum ContentBlock {
     /// Text content
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     /// Image content
     #[serde(rename = "image")]
-    Image { source: ImageSource },
+    Image {
+        source: ImageSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "image_url")]
     ImageUrl { image_url: ImageUrl },
     /// Tool use content
@@ -217,13 +311,27 @@ pub enum ContentBlock {
         id: String,
         name: String,
         input: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
     },
     /// Tool result content
     #[serde(rename = "tool_result")]
     ToolResult {
         tool_use_id: String,
         content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    /// Extended thinking content, the signature must be sent back unchanged
+    #[serde(rename = "thinking")]
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
     },
+    /// Thinking content encrypted by the safety system
+    #[serde(rename = "redacted_thinking")]
+    RedactedThinking { data: String },
 }
 
 /// Source of an image
@@ -254,6 +362,9 @@ pub struct Tool {
     pub description: Option<String>,
     /// JSON schema for tool input
     pub input_schema: serde_json::Value,
+    /// Prompt caching breakpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<CacheControl>,
 }
 
 /// Tool choice configuration
@@ -262,13 +373,26 @@ pub struct Tool {
 pub enum ToolChoice {
     /// Let model choose whether to use tools
     #[serde(rename = "auto")]
-    Auto,
+    Auto {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
     /// Model must use one of the provided tools
     #[serde(rename = "any")]
-    Any,
+    Any {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
     /// Model must use a specific tool
     #[serde(rename = "tool")]
-    Tool { name: String },
+    Tool {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        disable_parallel_tool_use: Option<bool>,
+    },
+    /// Model must not use tools
+    #[serde(rename = "none")]
+    None,
 }
 
 /// Message metadata
@@ -308,8 +432,9 @@ impl CreateMessageResponse {
             .content
             .iter()
             .map(|block| match block {
-                ContentBlock::Text { text } => text,
-                ContentBlock::Image { source } => &source.data,
+                ContentBlock::Text { text, .. } => text,
+                ContentBlock::Image { source, .. } => &source.data,
+                ContentBlock::Thinking { thinking, .. } => thinking,
                 _ => "",
             })
             .collect::<Vec<_>>()
@@ -352,6 +477,12 @@ pub struct Usage {
     pub input_tokens: u32,
     /// Output tokens used
     pub output_tokens: u32,
+    /// Input tokens written to the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Input tokens read from the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Default)]
@@ -361,6 +492,12 @@ pub struct StreamUsage {
     pub input_tokens: u32,
     /// Output tokens used
     pub output_tokens: u32,
+    /// Input tokens written to the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Input tokens read from the prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 impl Message {
@@ -385,9 +522,23 @@ impl Message {
 
 // Helper methods for content blocks
 impl ContentBlock {
+    /// Prompt caching breakpoint of the block, if any
+    pub fn cache_control(&self) -> Option<&CacheControl> {
+        match self {
+            Self::Text { cache_control, .. }
+            | Self::Image { cache_control, .. }
+            | Self::ToolUse { cache_control, .. }
+            | Self::ToolResult { cache_control, .. } => cache_control.as_ref(),
+            Self::ImageUrl { .. } | Self::Thinking { .. } | Self::RedactedThinking { .. } => None,
+        }
+    }
+
     /// Create a new text block
     pub fn text(text: impl Into<String>) -> Self {
-        Self::Text { text: text.into() }
+        Self::Text {
+            text: text.into(),
+            cache_control: None,
+        }
     }
 
     /// Create a new image block
@@ -402,6 +553,7 @@ impl ContentBlock {
                 media_type: media_type.into(),
                 data: data.into(),
             },
+            cache_control: None,
         }
     }
 }
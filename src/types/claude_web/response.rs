@@ -7,8 +7,9 @@ use serde::Deserialize;
 use crate::{
     claude_web_state::ClaudeWebState,
     error::ClewdrError,
+    streaming::StreamDialect,
     types::claude::{ContentBlock, CreateMessageResponse, Message, Role},
-    utils::{forward_response, print_out_text},
+    utils::{forward_guarded, print_out_text},
 };
 
 /// Merges server-sent events (SSE) from a stream into a single string
@@ -48,10 +49,7 @@ where
     /// # Returns
     /// * `Message` - A message with assistant role and text content
     fn from(str: S) -> Self {
-        Message::new_blocks(
-            Role::Assistant,
-            vec![ContentBlock::Text { text: str.into() }],
-        )
+        Message::new_blocks(Role::Assistant, vec![ContentBlock::text(str)])
     }
 }
 
@@ -72,7 +70,7 @@ impl ClaudeWebState {
         wreq_res: wreq::Response,
     ) -> Result<axum::response::Response, ClewdrError> {
         if self.stream {
-            return forward_response(wreq_res);
+            return forward_guarded(wreq_res, StreamDialect::Claude, || ()).await;
         }
 
         let stream = wreq_res.bytes_stream();
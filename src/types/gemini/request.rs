@@ -1,7 +1,12 @@
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, Default)]
+use crate::{
+    config::{CLEWDR_CONFIG, GeminiThinkingConfig},
+    error::ClewdrError,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, Default, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum Role {
     #[default]
@@ -122,7 +127,7 @@ impl SystemInstruction {
 #[derive(Serialize, Deserialize, Hash, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct GeminiRequestBody {
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "system_instruction", skip_serializing_if = "Option::is_none")]
     pub system_instruction: Option<SystemInstruction>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tools: Option<Vec<Tool>>,
@@ -133,7 +138,103 @@ pub struct GeminiRequestBody {
     pub safety_settings: Option<Value>,
 }
 
+/// Body of a native Gemini request
+///
+/// `generateContent` bodies are typed so safety settings can be applied, other
+/// methods such as Imagen's `predict` are forwarded as is
+#[derive(Serialize, Clone)]
+#[serde(untagged)]
+pub enum GeminiBody {
+    Generate(GeminiRequestBody),
+    Raw(Value),
+}
+
+/// Text of the turns inserted to keep a conversation valid
+pub const PLACEHOLDER_TURN: &str = "...";
+
 impl GeminiRequestBody {
+    /// Moves the system instruction into a preamble of the first user turn,
+    /// for models that reject `systemInstruction`
+    pub fn system_to_user(&mut self) {
+        let Some(system) = self.system_instruction.take() else {
+            return;
+        };
+        match self.contents.first_mut() {
+            Some(first) if matches!(first.role, Role::user) => {
+                first.parts.splice(0..0, system.parts);
+            }
+            _ => self.contents.insert(
+                0,
+                Chat {
+                    role: Role::user,
+                    parts: system.parts,
+                },
+            ),
+        }
+    }
+
+    /// Merges consecutive turns of the same role, drops empty ones and starts
+    /// the conversation with a user turn
+    pub fn merge_turns(&mut self) {
+        let mut contents: Vec<Chat> = Vec::with_capacity(self.contents.len());
+        for chat in self.contents.drain(..).filter(|c| !c.parts.is_empty()) {
+            match contents.last_mut() {
+                Some(last) if last.role == chat.role => last.parts.extend(chat.parts),
+                _ => contents.push(chat),
+            }
+        }
+        if contents.first().is_none_or(|c| c.role != Role::user) {
+            contents.insert(
+                0,
+                Chat {
+                    role: Role::user,
+                    parts: vec![Part::Text {
+                        text: PLACEHOLDER_TURN.into(),
+                        thought: None,
+                    }],
+                },
+            );
+        }
+        self.contents = contents;
+    }
+
+    /// Enforces the configured output token ceiling of the model
+    pub fn limit_output(&mut self, model: &str) -> Result<(), ClewdrError> {
+        let Some(config) = self.generation_config.as_mut() else {
+            return Ok(());
+        };
+        let limits = &CLEWDR_CONFIG.load().output_limits;
+        for key in ["maxOutputTokens", "max_output_tokens"] {
+            if let Some(requested) = config.get(key).and_then(Value::as_u64) {
+                let requested = u32::try_from(requested).unwrap_or(u32::MAX);
+                config[key] = json!(limits.apply(model, requested)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the configured thinking defaults unless the client set its own
+    pub fn apply_thinking(&mut self, model: &str) {
+        let config = &CLEWDR_CONFIG.load().gemini_thinking;
+        if !GeminiThinkingConfig::supports(model)
+            || (config.budget.is_none() && !config.include_thoughts)
+        {
+            return;
+        }
+        let generation_config = self.generation_config.get_or_insert_with(|| json!({}));
+        if !generation_config.is_object() || generation_config.get("thinkingConfig").is_some() {
+            return;
+        }
+        let mut thinking = json!({});
+        if let Some(budget) = config.budget {
+            thinking["thinkingBudget"] = json!(budget);
+        }
+        if config.include_thoughts {
+            thinking["includeThoughts"] = json!(true);
+        }
+        generation_config["thinkingConfig"] = thinking;
+    }
+
     pub fn safety_off(&mut self) {
         self.safety_settings = Some(json!([
           { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
@@ -152,10 +253,16 @@ impl GeminiRequestBody {
 #[allow(non_camel_case_types)]
 pub enum Tool {
     /// Generally it can be `Tool::google_search(json!({}))`
+    #[serde(alias = "googleSearch")]
     google_search(Value),
+    /// Legacy search grounding with a dynamic retrieval threshold, used by
+    /// Gemini 1.5 models
+    #[serde(alias = "googleSearchRetrieval")]
+    google_search_retrieval(Value),
     /// It is of form `Tool::function_calling(`[functionDeclaration](https://ai.google.dev/gemini-api/docs/function-calling?example=meeting)`)`
     functionDeclarations(Vec<Value>),
     /// Generally it can be `Tool::code_execution(json!({}))`,
+    #[serde(alias = "codeExecution")]
     code_execution(Value),
     #[serde(untagged)]
     Unknown(Value),
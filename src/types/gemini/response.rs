@@ -1,8 +1,6 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use super::request::*;
-
 #[derive(Serialize, Deserialize, PartialEq, Eq)]
 #[allow(non_camel_case_types)]
 pub enum FinishReason {
@@ -35,15 +33,31 @@ pub enum FinishReason {
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct Candidate {
-    content: Chat,
+    /// Missing when the candidate is blocked, image parts may carry extra fields
+    #[serde(default)]
+    content: Option<Value>,
     pub finishReason: Option<FinishReason>,
 }
 
+/// Token accounting returned by Gemini
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct UsageMetadata {
+    pub prompt_token_count: u32,
+    pub candidates_token_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thoughts_token_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_content_token_count: Option<u32>,
+    pub total_token_count: u32,
+}
+
 #[derive(Serialize, Deserialize)]
 #[allow(non_snake_case)]
 pub struct GeminiResponse {
     pub candidates: Vec<Candidate>,
-    pub usageMetadata: Value,
+    #[serde(default)]
+    pub usageMetadata: Option<UsageMetadata>,
     pub modelVersion: String,
     pub promptFeedback: Option<Value>,
 }
@@ -2,3 +2,4 @@ pub mod claude;
 pub mod claude_web;
 pub mod gemini;
 pub mod oai;
+pub mod realtime;
@@ -3,7 +3,68 @@ use serde_json::{Value, json};
 use tiktoken_rs::o200k_base;
 
 use super::claude::{CreateMessageParams as ClaudeCreateMessageParams, *};
-use crate::{config::CLEWDR_CONFIG, types::claude::Message};
+use crate::{
+    config::{CLEWDR_CONFIG, GeminiThinkingConfig},
+    error::ClewdrError,
+    types::{
+        claude::Message,
+        gemini::{request::PLACEHOLDER_TURN, response::UsageMetadata},
+    },
+};
+
+/// Token usage statistics in OpenAI format
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct CompletionUsage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens_details: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens_details: Option<Value>,
+}
+
+impl From<&Usage> for CompletionUsage {
+    fn from(usage: &Usage) -> Self {
+        // Claude reports cached tokens apart from the input tokens
+        let cache_read = usage.cache_read_input_tokens.unwrap_or_default();
+        let prompt_tokens =
+            usage.input_tokens + cache_read + usage.cache_creation_input_tokens.unwrap_or_default();
+        Self {
+            prompt_tokens,
+            completion_tokens: usage.output_tokens,
+            total_tokens: prompt_tokens + usage.output_tokens,
+            prompt_tokens_details: usage
+                .cache_read_input_tokens
+                .map(|c| json!({ "cached_tokens": c })),
+            completion_tokens_details: None,
+        }
+    }
+}
+
+impl From<&UsageMetadata> for CompletionUsage {
+    fn from(usage: &UsageMetadata) -> Self {
+        // thinking tokens are billed as output tokens
+        let completion_tokens =
+            usage.candidates_token_count + usage.thoughts_token_count.unwrap_or_default();
+        let total_tokens = if usage.total_token_count > 0 {
+            usage.total_token_count
+        } else {
+            usage.prompt_token_count + completion_tokens
+        };
+        Self {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens,
+            total_tokens,
+            prompt_tokens_details: usage
+                .cached_content_token_count
+                .map(|c| json!({ "cached_tokens": c })),
+            completion_tokens_details: usage
+                .thoughts_token_count
+                .map(|t| json!({ "reasoning_tokens": t })),
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -24,7 +85,7 @@ impl From<CreateMessageParams> for ClaudeCreateMessageParams {
             .into_iter()
             .map(|m| m.content)
             .flat_map(|c| match c {
-                MessageContent::Text { content } => vec![ContentBlock::Text { text: content }],
+                MessageContent::Text { content } => vec![ContentBlock::text(content)],
                 MessageContent::Blocks { content } => content,
             })
             .filter(|b| matches!(b, ContentBlock::Text { .. }))
@@ -41,7 +102,8 @@ impl From<CreateMessageParams> for ClaudeCreateMessageParams {
             thinking: params
                 .thinking
                 .or_else(|| params.reasoning_effort.map(|e| Thinking::new(e as u64))),
-            temperature: params.temperature,
+            // Anthropic accepts temperatures up to 1
+            temperature: params.temperature.map(|t| t.min(1.0)),
             stream: params.stream,
             top_k: params.top_k,
             top_p: params.top_p,
@@ -53,6 +115,128 @@ impl From<CreateMessageParams> for ClaudeCreateMessageParams {
     }
 }
 
+/// Rewrites the OpenAI tool definitions, tool choice and tool call messages of
+/// a chat completion request into their Claude equivalents
+///
+/// Parts already in the Claude shape are left as they are. `parallel_tool_calls`
+/// becomes `disable_parallel_tool_use` on the tool choice.
+pub fn normalize_tool_calls(body: &mut Value) {
+    if let Some(tools) = body.get_mut("tools").and_then(Value::as_array_mut) {
+        for tool in tools.iter_mut().filter(|t| t["type"] == "function") {
+            let function = tool["function"].take();
+            let schema = match function["parameters"] {
+                Value::Null => json!({ "type": "object", "properties": {} }),
+                ref p => p.to_owned(),
+            };
+            *tool = json!({
+                "name": function["name"],
+                "description": function["description"],
+                "input_schema": schema,
+            });
+        }
+    }
+    match body["tool_choice"] {
+        Value::String(ref choice) => {
+            let kind = match choice.as_str() {
+                "required" => "any",
+                "none" => "none",
+                _ => "auto",
+            };
+            body["tool_choice"] = json!({ "type": kind });
+        }
+        ref choice if choice["type"] == "function" => {
+            body["tool_choice"] = json!({ "type": "tool", "name": choice["function"]["name"] });
+        }
+        _ => {}
+    }
+    if body["parallel_tool_calls"] == false && body["tools"].is_array() {
+        if body["tool_choice"].is_null() {
+            body["tool_choice"] = json!({ "type": "auto" });
+        }
+        if body["tool_choice"]["type"] != "none" {
+            body["tool_choice"]["disable_parallel_tool_use"] = json!(true);
+        }
+    }
+    let Some(messages) = body.get_mut("messages").and_then(Value::as_array_mut) else {
+        return;
+    };
+    let mut normalized: Vec<Value> = Vec::with_capacity(messages.len());
+    for mut msg in messages.drain(..) {
+        match msg["role"].as_str() {
+            Some("tool") => {
+                let content = match msg["content"].take() {
+                    Value::String(s) => s,
+                    Value::Array(parts) => parts
+                        .iter()
+                        .filter_map(|p| p["text"].as_str())
+                        .collect::<String>(),
+                    _ => String::new(),
+                };
+                let result = json!({
+                    "type": "tool_result",
+                    "tool_use_id": msg["tool_call_id"],
+                    "content": content,
+                });
+                // results of parallel calls belong in the same user turn
+                if let Some(last) = normalized.last_mut()
+                    && last["role"] == "user"
+                    && let Some(blocks) = last["content"].as_array_mut()
+                    && blocks.iter().all(|b| b["type"] == "tool_result")
+                {
+                    blocks.push(result);
+                } else {
+                    normalized.push(json!({ "role": "user", "content": [result] }));
+                }
+            }
+            Some("assistant") if msg["tool_calls"].is_array() => {
+                let mut blocks = match msg["content"].take() {
+                    Value::String(s) if !s.is_empty() => vec![json!({ "type": "text", "text": s })],
+                    Value::Array(parts) => parts,
+                    _ => vec![],
+                };
+                for call in msg["tool_calls"].as_array().into_iter().flatten() {
+                    let input = call["function"]["arguments"]
+                        .as_str()
+                        .and_then(|a| serde_json::from_str::<Value>(a).ok())
+                        .unwrap_or_else(|| json!({}));
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call["id"],
+                        "name": call["function"]["name"],
+                        "input": input,
+                    }));
+                }
+                normalized.push(json!({ "role": "assistant", "content": blocks }));
+            }
+            _ => {
+                if msg["content"].is_null() {
+                    msg["content"] = json!("");
+                }
+                normalized.push(msg);
+            }
+        }
+    }
+    *messages = normalized;
+}
+
+/// Reads `stop` as either a single sequence or a list
+fn deserialize_stop<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stop {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match Option::<Stop>::deserialize(deserializer)? {
+        Some(Stop::One(s)) => Some(vec![s]),
+        Some(Stop::Many(v)) => Some(v),
+        None => None,
+    })
+}
+
 #[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct CreateMessageParams {
     /// Maximum number of tokens to generate
@@ -73,9 +257,19 @@ pub struct CreateMessageParams {
     /// Temperature for response generation
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
-    /// Custom stop sequences
-    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Custom stop sequences, a single string is accepted as well
+    #[serde(
+        default,
+        deserialize_with = "deserialize_stop",
+        skip_serializing_if = "Option::is_none"
+    )]
     pub stop: Option<Vec<String>>,
+    /// Presence penalty for response generation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// Seed for deterministic sampling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
     /// Whether to stream the response
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
@@ -103,9 +297,24 @@ pub struct CreateMessageParams {
     /// extra body for Gemini
     #[serde(skip_serializing_if = "Option::is_none")]
     pub extra_body: Option<serde_json::Value>,
-    /// Number of completions to generate
+    /// Number of completions to generate, Gemini maps it to `candidateCount`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub n: Option<u32>,
+    /// Options for streaming responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<Value>,
+    /// Web search options, enables grounding with Google Search on Gemini
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_search_options: Option<Value>,
+    /// Structured output format
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Return token log probabilities, Gemini maps it to `responseLogprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+    /// Number of alternatives per token, Gemini maps it to `logprobs`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u32>,
 }
 
 impl CreateMessageParams {
@@ -119,7 +328,7 @@ impl CreateMessageParams {
                 MessageContent::Blocks { ref content } => content
                     .iter()
                     .map(|block| match block {
-                        ContentBlock::Text { text } => text,
+                        ContentBlock::Text { text, .. } => text,
                         _ => "",
                     })
                     .collect::<String>(),
@@ -130,7 +339,7 @@ impl CreateMessageParams {
     }
 
     fn optimize_for_gemini(&mut self) {
-        let mut extra_body = json!({});
+        let extra_body = self.extra_body.get_or_insert_with(|| json!({}));
         extra_body["google"]["safety_settings"] = json!([
           { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
           { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
@@ -141,8 +350,317 @@ impl CreateMessageParams {
             "threshold": "OFF"
           }
         ]);
-        self.extra_body = Some(extra_body);
         self.frequency_penalty = None;
+        self.presence_penalty = None;
+    }
+
+    /// Translates `reasoning_effort` or a Claude style `thinking` budget into
+    /// the Gemini thinking config, falling back to the configured defaults
+    ///
+    /// Gemini rejects requests setting both `reasoning_effort` and a thinking
+    /// config, so the OpenAI fields are removed.
+    pub fn map_thinking_for_gemini(&mut self) {
+        let requested = self
+            .thinking
+            .take()
+            .map(|t| match t.budget_tokens {
+                _ if !t.is_enabled() => 0,
+                Some(budget) => budget as i32,
+                None => -1,
+            })
+            .or_else(|| self.reasoning_effort.take().map(|e| e as i32));
+        if !GeminiThinkingConfig::supports(&self.model) {
+            return;
+        }
+        let config = &CLEWDR_CONFIG.load().gemini_thinking;
+        let budget = requested.or(config.budget);
+        if budget.is_none() && !config.include_thoughts {
+            return;
+        }
+        let extra_body = self.extra_body.get_or_insert_with(|| json!({}));
+        let thinking = &mut extra_body["google"]["thinking_config"];
+        if thinking.is_object() {
+            // set by the client
+            return;
+        }
+        *thinking = json!({});
+        if let Some(budget) = budget {
+            thinking["thinking_budget"] = json!(budget);
+        }
+        if config.include_thoughts {
+            thinking["include_thoughts"] = json!(true);
+        }
+    }
+
+    /// Merges all system messages into a single leading one
+    ///
+    /// # Arguments
+    /// * `as_user` - Send the merged prompt as a user turn instead, for models
+    ///   that reject system instructions
+    pub fn merge_system_messages(&mut self, as_user: bool) {
+        let (systems, messages): (Vec<Message>, Vec<Message>) = std::mem::take(&mut self.messages)
+            .into_iter()
+            .partition(|m| m.role == Role::System);
+        self.messages = messages;
+        let system = systems
+            .into_iter()
+            .flat_map(|m| match m.content {
+                MessageContent::Text { content } => vec![content],
+                MessageContent::Blocks { content } => content
+                    .into_iter()
+                    .filter_map(|b| match b {
+                        ContentBlock::Text { text, .. } => Some(text),
+                        _ => None,
+                    })
+                    .collect(),
+            })
+            .filter(|s| !s.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if system.is_empty() {
+            return;
+        }
+        self.messages.insert(
+            0,
+            Message {
+                role: if as_user { Role::User } else { Role::System },
+                content: MessageContent::Text { content: system },
+            },
+        );
+    }
+
+    /// Merges consecutive user or assistant messages and makes the first one
+    /// after the system prompt a user message, Gemini rejects the request
+    /// otherwise
+    pub fn merge_turns(&mut self) {
+        let mut messages: Vec<Message> = Vec::with_capacity(self.messages.len());
+        for msg in std::mem::take(&mut self.messages) {
+            match messages.last_mut() {
+                Some(last) if last.role == msg.role && msg.role != Role::System => {
+                    let mut blocks = match std::mem::replace(
+                        &mut last.content,
+                        MessageContent::Blocks { content: vec![] },
+                    ) {
+                        MessageContent::Text { content } => vec![ContentBlock::text(content)],
+                        MessageContent::Blocks { content } => content,
+                    };
+                    match msg.content {
+                        MessageContent::Text { content } => {
+                            blocks.push(ContentBlock::text(content))
+                        }
+                        MessageContent::Blocks { content } => blocks.extend(content),
+                    }
+                    last.content = MessageContent::Blocks { content: blocks };
+                }
+                _ => messages.push(msg),
+            }
+        }
+        let first = messages
+            .iter()
+            .position(|m| m.role != Role::System)
+            .unwrap_or(messages.len());
+        if messages.get(first).is_none_or(|m| m.role != Role::User) {
+            messages.insert(first, Message::new_text(Role::User, PLACEHOLDER_TURN));
+        }
+        self.messages = messages;
+    }
+
+    /// Replaces OpenAI `web_search_options` with the Gemini search tool
+    pub fn enable_search_grounding(&mut self) {
+        if self.web_search_options.take().is_none() {
+            return;
+        }
+        let extra_body = self.extra_body.get_or_insert_with(|| json!({}));
+        let tools = &mut extra_body["google"]["tools"];
+        if !tools.is_array() {
+            *tools = json!([]);
+        }
+        if let Some(tools) = tools.as_array_mut()
+            && !tools
+                .iter()
+                .any(|t| t.get("google_search").is_some() || t.get("googleSearch").is_some())
+        {
+            tools.push(json!({ "google_search": {} }));
+        }
+    }
+
+    /// Enforces the configured output token ceiling of the model
+    pub fn limit_output(&mut self) -> Result<(), ClewdrError> {
+        let limits = &CLEWDR_CONFIG.load().output_limits;
+        for field in [&mut self.max_tokens, &mut self.max_completion_tokens] {
+            if let Some(requested) = *field {
+                *field = Some(limits.apply(&self.model, requested)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Asks the upstream to send a final chunk carrying token usage when streaming
+    pub fn request_stream_usage(&mut self) {
+        if !self.stream.unwrap_or_default() {
+            return;
+        }
+        let options = self.stream_options.get_or_insert_with(|| json!({}));
+        if let Some(options) = options.as_object_mut() {
+            options.entry("include_usage").or_insert(json!(true));
+        }
+    }
+
+    /// Builds the native `streamGenerateContent` body for the request
+    ///
+    /// Expects the preprocessing of the OpenAI route, Gemini specific options
+    /// are read from `extra_body.google`.
+    pub fn to_gemini(&self) -> Value {
+        // tool results only carry the ID of the call
+        let mut tool_names = std::collections::HashMap::new();
+        let mut system = None;
+        let mut contents = vec![];
+        for msg in &self.messages {
+            let blocks = match msg.content {
+                MessageContent::Text { ref content } => vec![ContentBlock::text(content)],
+                MessageContent::Blocks { ref content } => content.to_owned(),
+            };
+            let parts = blocks
+                .into_iter()
+                .filter_map(|block| match block {
+                    ContentBlock::Text { text, .. } => Some(json!({ "text": text })),
+                    ContentBlock::Image { source, .. } => Some(json!({
+                        "inlineData": { "mimeType": source.media_type, "data": source.data },
+                    })),
+                    ContentBlock::ImageUrl { image_url } => {
+                        match image_url
+                            .url
+                            .strip_prefix("data:")
+                            .and_then(|u| u.split_once(";base64,"))
+                        {
+                            Some((mime, data)) => Some(json!({
+                                "inlineData": { "mimeType": mime, "data": data },
+                            })),
+                            None => Some(json!({ "fileData": { "fileUri": image_url.url } })),
+                        }
+                    }
+                    ContentBlock::ToolUse {
+                        id, name, input, ..
+                    } => {
+                        tool_names.insert(id, name.to_owned());
+                        Some(json!({ "functionCall": { "name": name, "args": input } }))
+                    }
+                    ContentBlock::Thinking { .. } | ContentBlock::RedactedThinking { .. } => None,
+                    ContentBlock::ToolResult {
+                        tool_use_id,
+                        content,
+                        ..
+                    } => tool_names.get(&tool_use_id).map(|name| {
+                        json!({
+                            "functionResponse": {
+                                "name": name,
+                                "response": { "content": content },
+                            },
+                        })
+                    }),
+                })
+                .collect::<Vec<_>>();
+            match msg.role {
+                Role::System => system = Some(json!({ "parts": parts })),
+                Role::User => contents.push(json!({ "role": "user", "parts": parts })),
+                Role::Assistant => contents.push(json!({ "role": "model", "parts": parts })),
+            }
+        }
+
+        let mut generation_config = json!({});
+        let google = self
+            .extra_body
+            .as_ref()
+            .map(|b| b["google"].to_owned())
+            .unwrap_or_default();
+        if let Some(max) = self.max_completion_tokens.or(self.max_tokens) {
+            generation_config["maxOutputTokens"] = json!(max);
+        }
+        for (key, value) in [
+            ("temperature", self.temperature.map(|v| json!(v))),
+            ("topP", self.top_p.map(|v| json!(v))),
+            ("topK", self.top_k.map(|v| json!(v))),
+            ("seed", self.seed.map(|v| json!(v))),
+            ("stopSequences", self.stop.as_ref().map(|v| json!(v))),
+            ("presencePenalty", self.presence_penalty.map(|v| json!(v))),
+            ("frequencyPenalty", self.frequency_penalty.map(|v| json!(v))),
+            (
+                "candidateCount",
+                self.n.filter(|n| *n > 1).map(|v| json!(v)),
+            ),
+            ("responseLogprobs", self.logprobs.map(|v| json!(v))),
+            ("logprobs", self.top_logprobs.map(|v| json!(v))),
+        ] {
+            if let Some(value) = value {
+                generation_config[key] = value;
+            }
+        }
+        match self.response_format {
+            Some(ResponseFormat::JsonObject) => {
+                generation_config["responseMimeType"] = json!("application/json");
+            }
+            Some(ResponseFormat::JsonSchema { ref json_schema }) => {
+                generation_config["responseMimeType"] = json!("application/json");
+                if let Some(ref schema) = json_schema.schema {
+                    generation_config["responseSchema"] = schema.to_owned();
+                }
+            }
+            Some(ResponseFormat::Text) | None => {}
+        }
+        if let Some(thinking) = google["thinking_config"].as_object() {
+            let mut config = json!({});
+            if let Some(budget) = thinking.get("thinking_budget") {
+                config["thinkingBudget"] = budget.to_owned();
+            }
+            if let Some(include) = thinking.get("include_thoughts") {
+                config["includeThoughts"] = include.to_owned();
+            }
+            generation_config["thinkingConfig"] = config;
+        }
+
+        let mut tools = google["tools"].as_array().cloned().unwrap_or_default();
+        if let Some(ref functions) = self.tools {
+            let declarations = functions
+                .iter()
+                .map(|t| {
+                    json!({
+                        "name": t.name,
+                        "description": t.description.as_deref().unwrap_or_default(),
+                        "parameters": t.input_schema,
+                    })
+                })
+                .collect::<Vec<_>>();
+            tools.push(json!({ "functionDeclarations": declarations }));
+        }
+
+        let mut body = json!({
+            "contents": contents,
+            "generationConfig": generation_config,
+            "safetySettings": [
+                { "category": "HARM_CATEGORY_HARASSMENT", "threshold": "OFF" },
+                { "category": "HARM_CATEGORY_HATE_SPEECH", "threshold": "OFF" },
+                { "category": "HARM_CATEGORY_SEXUALLY_EXPLICIT", "threshold": "OFF" },
+                { "category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "OFF" },
+                { "category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "OFF" },
+            ],
+        });
+        if let Some(system) = system {
+            body["systemInstruction"] = system;
+        }
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        if let Some(ref choice) = self.tool_choice {
+            body["toolConfig"]["functionCallingConfig"] = match choice {
+                ToolChoice::Auto { .. } => json!({ "mode": "AUTO" }),
+                ToolChoice::Any { .. } => json!({ "mode": "ANY" }),
+                ToolChoice::None => json!({ "mode": "NONE" }),
+                ToolChoice::Tool { name, .. } => {
+                    json!({ "mode": "ANY", "allowedFunctionNames": [name] })
+                }
+            };
+        }
+        body
     }
 
     pub fn preprocess_vertex(&mut self) {
@@ -154,3 +672,623 @@ impl CreateMessageParams {
         self.model = format!("google/{}", self.model);
     }
 }
+
+/// Keys under which Gemini endpoints report grounding metadata
+const GROUNDING_KEYS: [&str; 2] = ["groundingMetadata", "grounding_metadata"];
+
+fn take_grounding(value: &mut Value) -> Option<Value> {
+    let obj = value.as_object_mut()?;
+    GROUNDING_KEYS.iter().find_map(|k| obj.remove(*k))
+}
+
+/// Web sources of the grounding metadata as `(url, title)`, deduplicated
+fn grounding_sources(meta: &Value) -> Vec<(String, String)> {
+    let mut sources: Vec<(String, String)> = vec![];
+    for chunk in meta["groundingChunks"].as_array().into_iter().flatten() {
+        let Some(url) = chunk["web"]["uri"].as_str() else {
+            continue;
+        };
+        if sources.iter().any(|(u, _)| u == url) {
+            continue;
+        }
+        let title = chunk["web"]["title"].as_str().unwrap_or(url);
+        sources.push((url.to_string(), title.to_string()));
+    }
+    sources
+}
+
+/// Turns Gemini grounding metadata into OpenAI `url_citation` annotations
+///
+/// Works on completions and stream chunks alike. The sources are also
+/// appended to the content as footnotes, since most clients ignore
+/// annotations.
+///
+/// # Returns
+/// Whether the response was modified
+pub fn normalize_grounding(res: &mut Value) -> bool {
+    let mut root = take_grounding(res);
+    let mut modified = root.is_some();
+    let Some(choices) = res["choices"].as_array_mut() else {
+        return modified;
+    };
+    for (i, choice) in choices.iter_mut().enumerate() {
+        let Some(meta) = take_grounding(choice).or_else(|| root.take().filter(|_| i == 0)) else {
+            continue;
+        };
+        modified = true;
+        let sources = grounding_sources(&meta);
+        if sources.is_empty() {
+            continue;
+        }
+        let key = if choice["message"].is_object() {
+            "message"
+        } else {
+            "delta"
+        };
+        let message = &mut choice[key];
+        let mut content = message["content"].as_str().unwrap_or_default().to_string();
+        content.push_str("\n\nSources:");
+        let mut annotations = vec![];
+        for (n, (url, title)) in sources.iter().enumerate() {
+            content.push('\n');
+            let start = content.chars().count();
+            content.push_str(&format!("[{}] [{title}]({url})", n + 1));
+            annotations.push(json!({
+                "type": "url_citation",
+                "url_citation": {
+                    "url": url,
+                    "title": title,
+                    "start_index": start,
+                    "end_index": content.chars().count(),
+                }
+            }));
+        }
+        message["content"] = json!(content);
+        message["annotations"] = json!(annotations);
+    }
+    modified
+}
+
+/// Keys under which Gemini endpoints report token log probabilities
+const LOGPROBS_KEYS: [&str; 2] = ["logprobsResult", "logprobs_result"];
+
+/// Converts a Gemini token candidate into an OpenAI log probability entry
+fn token_logprob(candidate: &Value) -> Value {
+    let token = candidate["token"].as_str().unwrap_or_default();
+    json!({
+        "token": token,
+        "logprob": candidate["logProbability"].as_f64().unwrap_or_default(),
+        "bytes": token.as_bytes(),
+    })
+}
+
+/// Turns Gemini `logprobsResult` into OpenAI `logprobs`
+///
+/// Works on completions and stream chunks alike.
+///
+/// # Returns
+/// Whether the response was modified
+pub fn normalize_logprobs(res: &mut Value) -> bool {
+    let take = |v: &mut Value| {
+        let obj = v.as_object_mut()?;
+        LOGPROBS_KEYS.iter().find_map(|k| obj.remove(*k))
+    };
+    let mut root = take(res);
+    let mut modified = root.is_some();
+    let Some(choices) = res["choices"].as_array_mut() else {
+        return modified;
+    };
+    for (i, choice) in choices.iter_mut().enumerate() {
+        let Some(result) = take(choice).or_else(|| root.take().filter(|_| i == 0)) else {
+            continue;
+        };
+        modified = true;
+        let top = result["topCandidates"].as_array();
+        let content = result["chosenCandidates"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(n, chosen)| {
+                let mut entry = token_logprob(chosen);
+                entry["top_logprobs"] = top
+                    .and_then(|t| t.get(n))
+                    .and_then(|t| t["candidates"].as_array())
+                    .map(|c| c.iter().map(token_logprob).collect::<Vec<_>>())
+                    .map_or_else(|| json!([]), |c| json!(c));
+                entry
+            })
+            .collect::<Vec<_>>();
+        choice["logprobs"] = json!({ "content": content });
+    }
+    modified
+}
+
+/// Structured output format of an OpenAI request
+///
+/// Gemini maps it to `responseMimeType` and `responseSchema`, which only
+/// accept a subset of JSON schema, see [`ResponseFormat::sanitize_for_gemini`]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    Text,
+    JsonObject,
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// JSON schema keywords rejected by Gemini's `responseSchema`
+const UNSUPPORTED_SCHEMA_KEYS: [&str; 7] = [
+    "$schema",
+    "$id",
+    "additionalProperties",
+    "unevaluatedProperties",
+    "patternProperties",
+    "default",
+    "examples",
+];
+
+/// Removes unsupported keywords from a schema and its subschemas
+fn sanitize_schema(schema: &mut Value) {
+    match schema {
+        Value::Object(obj) => {
+            for key in UNSUPPORTED_SCHEMA_KEYS {
+                obj.remove(key);
+            }
+            for (key, value) in obj.iter_mut() {
+                match key.as_str() {
+                    // maps of property names, which may shadow keywords
+                    "properties" | "$defs" | "definitions" => {
+                        if let Some(props) = value.as_object_mut() {
+                            props.values_mut().for_each(sanitize_schema);
+                        }
+                    }
+                    "enum" | "const" | "required" => {}
+                    _ => sanitize_schema(value),
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(sanitize_schema),
+        _ => {}
+    }
+}
+
+/// Checks a value against the common subset of JSON schema
+fn check_schema(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+    if let Some(types) = schema.get("type") {
+        let matches = |t: &Value| match t.as_str() {
+            Some("object") => value.is_object(),
+            Some("array") => value.is_array(),
+            Some("string") => value.is_string(),
+            Some("boolean") => value.is_boolean(),
+            Some("null") => value.is_null(),
+            Some("number") => value.is_number(),
+            Some("integer") => {
+                value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|f| f.fract() == 0.0)
+            }
+            _ => true,
+        };
+        let ok = match types {
+            Value::Array(types) => types.iter().any(matches),
+            t => matches(t),
+        };
+        if !ok {
+            return Err(format!("{path}: expected type {types}"));
+        }
+    }
+    if schema.get("nullable").and_then(Value::as_bool) == Some(true) && value.is_null() {
+        return Ok(());
+    }
+    if let Some(variants) = schema.get("enum").and_then(Value::as_array)
+        && !variants.contains(value)
+    {
+        return Err(format!("{path}: value not in enum"));
+    }
+    if let Some(any_of) = schema.get("anyOf").and_then(Value::as_array)
+        && !any_of.iter().any(|s| check_schema(s, value, path).is_ok())
+    {
+        return Err(format!("{path}: no anyOf variant matches"));
+    }
+    if let Some(obj) = value.as_object() {
+        for key in schema["required"].as_array().into_iter().flatten() {
+            if let Some(key) = key.as_str()
+                && !obj.contains_key(key)
+            {
+                return Err(format!("{path}: missing required property `{key}`"));
+            }
+        }
+        if let Some(props) = schema.get("properties").and_then(Value::as_object) {
+            for (key, value) in obj {
+                if let Some(prop) = props.get(key) {
+                    check_schema(prop, value, &format!("{path}.{key}"))?;
+                }
+            }
+        }
+    }
+    if let Some(items) = value.as_array()
+        && let Some(item_schema) = schema.get("items")
+    {
+        for (i, item) in items.iter().enumerate() {
+            check_schema(item_schema, item, &format!("{path}[{i}]"))?;
+        }
+    }
+    Ok(())
+}
+
+impl ResponseFormat {
+    /// Strips schema keywords Gemini rejects
+    pub fn sanitize_for_gemini(&mut self) {
+        if let ResponseFormat::JsonSchema { json_schema } = self
+            && let Some(ref mut schema) = json_schema.schema
+        {
+            sanitize_schema(schema);
+        }
+    }
+
+    /// Checks that the content of a completion matches the format
+    ///
+    /// # Returns
+    /// A description of the first mismatch
+    pub fn validate(&self, content: &str) -> Result<(), String> {
+        let schema = match self {
+            ResponseFormat::Text => return Ok(()),
+            ResponseFormat::JsonObject => None,
+            ResponseFormat::JsonSchema { json_schema } => json_schema.schema.as_ref(),
+        };
+        let value = serde_json::from_str::<Value>(content.trim())
+            .map_err(|e| format!("invalid JSON: {e}"))?;
+        match schema {
+            Some(schema) => check_schema(schema, &value, "$"),
+            None if value.is_object() => Ok(()),
+            None => Err("expected a JSON object".to_string()),
+        }
+    }
+}
+
+const THOUGHT_OPEN: &str = "<thought>";
+const THOUGHT_CLOSE: &str = "</thought>";
+
+/// Separates `<thought>` tagged summaries from the content of OpenAI format
+/// Gemini responses
+///
+/// Thoughts may span several stream chunks, so the splitter keeps whether each
+/// choice is inside a thought between calls.
+#[derive(Default)]
+pub struct ThoughtSplitter {
+    in_thought: Vec<bool>,
+}
+
+impl ThoughtSplitter {
+    /// Splits text into content and reasoning
+    fn split(&mut self, index: usize, mut text: &str) -> (String, String) {
+        if self.in_thought.len() <= index {
+            self.in_thought.resize(index + 1, false);
+        }
+        let in_thought = &mut self.in_thought[index];
+        let (mut content, mut reasoning) = (String::new(), String::new());
+        while !text.is_empty() {
+            let (tag, out) = if *in_thought {
+                (THOUGHT_CLOSE, &mut reasoning)
+            } else {
+                (THOUGHT_OPEN, &mut content)
+            };
+            match text.split_once(tag) {
+                Some((before, after)) => {
+                    out.push_str(before);
+                    *in_thought = !*in_thought;
+                    text = after;
+                }
+                None => {
+                    out.push_str(text);
+                    break;
+                }
+            }
+        }
+        (content, reasoning)
+    }
+
+    /// Moves thoughts of a completion or stream chunk into `reasoning_content`,
+    /// or drops them
+    ///
+    /// # Returns
+    /// Whether the response was modified
+    pub fn apply(&mut self, res: &mut Value, expose: bool) -> bool {
+        let Some(choices) = res["choices"].as_array_mut() else {
+            return false;
+        };
+        let mut modified = false;
+        for (i, choice) in choices.iter_mut().enumerate() {
+            // with `n > 1` stream chunks carry the choices by index
+            let i = choice["index"].as_u64().map_or(i, |i| i as usize);
+            let key = if choice["message"].is_object() {
+                "message"
+            } else {
+                "delta"
+            };
+            let message = &mut choice[key];
+            let Some(text) = message["content"].as_str() else {
+                continue;
+            };
+            let in_thought = self.in_thought.get(i).copied().unwrap_or_default();
+            if !in_thought && !text.contains(THOUGHT_OPEN) {
+                continue;
+            }
+            let (content, reasoning) = self.split(i, text);
+            message["content"] = json!(content.trim_start());
+            if expose && !reasoning.is_empty() {
+                message["reasoning_content"] = json!(reasoning);
+            }
+            modified = true;
+        }
+        modified
+    }
+}
+
+/// Image generation request in OpenAI format
+#[derive(Debug, Deserialize, Clone)]
+pub struct ImageGenerationParams {
+    pub model: String,
+    pub prompt: String,
+    /// Number of images, sent as `sampleCount` to Imagen and `candidateCount`
+    /// to Gemini models
+    #[serde(default)]
+    pub n: Option<u32>,
+    /// Size as `WIDTHxHEIGHT`, mapped to the closest supported aspect ratio
+    #[serde(default)]
+    pub size: Option<String>,
+    /// `b64_json` or `url`, URLs are returned as data URLs
+    #[serde(default)]
+    pub response_format: Option<String>,
+}
+
+impl ImageGenerationParams {
+    /// Model name without the `models/` prefix
+    pub fn model(&self) -> &str {
+        self.model.trim_start_matches("models/")
+    }
+
+    /// Whether the model is served by Imagen's `predict` method
+    pub fn is_imagen(&self) -> bool {
+        self.model().starts_with("imagen")
+    }
+
+    /// Aspect ratio supported by Gemini closest to the requested size
+    fn aspect_ratio(&self) -> Option<&'static str> {
+        const RATIOS: [(&str, f64); 5] = [
+            ("1:1", 1.0),
+            ("3:4", 0.75),
+            ("4:3", 4.0 / 3.0),
+            ("9:16", 9.0 / 16.0),
+            ("16:9", 16.0 / 9.0),
+        ];
+        let (w, h) = self.size.as_deref()?.split_once('x')?;
+        let ratio = w.trim().parse::<f64>().ok()? / h.trim().parse::<f64>().ok()?;
+        RATIOS
+            .iter()
+            .min_by(|a, b| (a.1 - ratio).abs().total_cmp(&(b.1 - ratio).abs()))
+            .map(|r| r.0)
+    }
+
+    /// Builds the Gemini request body, `predict` for Imagen models and
+    /// `generateContent` with image output otherwise
+    pub fn to_gemini(&self) -> Value {
+        let aspect_ratio = self.aspect_ratio();
+        if self.is_imagen() {
+            let mut parameters = json!({ "sampleCount": self.n.unwrap_or(1) });
+            if let Some(ratio) = aspect_ratio {
+                parameters["aspectRatio"] = json!(ratio);
+            }
+            return json!({
+                "instances": [{ "prompt": self.prompt }],
+                "parameters": parameters,
+            });
+        }
+        let mut generation_config = json!({ "responseModalities": ["TEXT", "IMAGE"] });
+        if let Some(ratio) = aspect_ratio {
+            generation_config["imageConfig"] = json!({ "aspectRatio": ratio });
+        }
+        if let Some(n) = self.n.filter(|n| *n > 1) {
+            generation_config["candidateCount"] = json!(n);
+        }
+        json!({
+            "contents": [{ "role": "user", "parts": [{ "text": self.prompt }] }],
+            "generationConfig": generation_config,
+        })
+    }
+
+    /// Converts a Gemini response into an OpenAI images response
+    pub fn images_response(&self, res: &Value) -> Value {
+        // (mime type, base64 data)
+        let images = if self.is_imagen() {
+            res["predictions"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|p| {
+                    let data = p["bytesBase64Encoded"].as_str()?;
+                    let mime = p["mimeType"].as_str().unwrap_or("image/png");
+                    Some((mime, data))
+                })
+                .collect::<Vec<_>>()
+        } else {
+            res["candidates"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|c| c["content"]["parts"].as_array())
+                .flatten()
+                .filter_map(|p| {
+                    let inline = p.get("inlineData").or_else(|| p.get("inline_data"))?;
+                    let data = inline["data"].as_str()?;
+                    let mime = inline["mimeType"]
+                        .as_str()
+                        .or(inline["mime_type"].as_str())
+                        .unwrap_or("image/png");
+                    Some((mime, data))
+                })
+                .collect::<Vec<_>>()
+        };
+        let as_url = self.response_format.as_deref() == Some("url");
+        let data = images
+            .into_iter()
+            .map(|(mime, data)| {
+                if as_url {
+                    json!({ "url": format!("data:{mime};base64,{data}") })
+                } else {
+                    json!({ "b64_json": data })
+                }
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "created": chrono::Utc::now().timestamp(),
+            "data": data,
+        })
+    }
+}
+
+/// Default Gemini model for OpenAI speech models
+const DEFAULT_TTS_MODEL: &str = "gemini-2.5-flash-preview-tts";
+/// Default Gemini model for OpenAI transcription models
+const DEFAULT_TRANSCRIPTION_MODEL: &str = "gemini-2.5-flash";
+
+/// Speech request in OpenAI format
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpeechParams {
+    pub model: String,
+    pub input: String,
+    #[serde(default)]
+    pub voice: Option<String>,
+    /// Style instructions, prepended to the input
+    #[serde(default)]
+    pub instructions: Option<String>,
+    /// Only `pcm` and `wav` can be produced, other formats get `wav`
+    #[serde(default)]
+    pub response_format: Option<String>,
+}
+
+impl SpeechParams {
+    /// Gemini model, OpenAI model names map to the default TTS model
+    pub fn model(&self) -> &str {
+        let model = self.model.trim_start_matches("models/");
+        if model.starts_with("tts-") || model.starts_with("gpt-") {
+            DEFAULT_TTS_MODEL
+        } else {
+            model
+        }
+    }
+
+    /// Whether raw PCM is requested instead of WAV
+    pub fn is_pcm(&self) -> bool {
+        self.response_format.as_deref() == Some("pcm")
+    }
+
+    /// Gemini prebuilt voice, OpenAI voices map to a similar one
+    fn voice(&self) -> String {
+        let voice = self.voice.as_deref().unwrap_or_default();
+        let mapped = match voice.to_ascii_lowercase().as_str() {
+            "" | "alloy" => "Kore",
+            "echo" => "Puck",
+            "fable" => "Fenrir",
+            "onyx" => "Charon",
+            "nova" => "Aoede",
+            "shimmer" => "Leda",
+            "ash" | "ballad" => "Orus",
+            "coral" | "sage" | "verse" => "Zephyr",
+            _ => voice,
+        };
+        mapped.to_string()
+    }
+
+    /// Builds the Gemini request body with audio output
+    pub fn to_gemini(&self) -> Value {
+        let text = match self.instructions.as_deref().filter(|i| !i.is_empty()) {
+            Some(instructions) => format!("{instructions}: {}", self.input),
+            None => self.input.to_owned(),
+        };
+        json!({
+            "contents": [{ "role": "user", "parts": [{ "text": text }] }],
+            "generationConfig": {
+                "responseModalities": ["AUDIO"],
+                "speechConfig": {
+                    "voiceConfig": { "prebuiltVoiceConfig": { "voiceName": self.voice() } }
+                }
+            },
+        })
+    }
+}
+
+/// Transcription request in OpenAI format, parsed from a multipart form
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptionParams {
+    pub model: String,
+    pub mime_type: String,
+    /// Base64 encoded audio
+    pub audio: String,
+    pub prompt: Option<String>,
+    pub language: Option<String>,
+    pub response_format: Option<String>,
+}
+
+impl TranscriptionParams {
+    /// Gemini model, OpenAI model names map to the default transcription model
+    pub fn model(&self) -> &str {
+        let model = self.model.trim_start_matches("models/");
+        if model.is_empty() || model.starts_with("whisper") || model.starts_with("gpt-") {
+            DEFAULT_TRANSCRIPTION_MODEL
+        } else {
+            model
+        }
+    }
+
+    /// Builds the Gemini request body with the audio inlined
+    pub fn to_gemini(&self) -> Value {
+        let mut instruction = String::from(
+            "Generate a verbatim transcript of the speech. Reply with the transcript only.",
+        );
+        if let Some(ref language) = self.language {
+            instruction.push_str(&format!(" The speech is in {language}."));
+        }
+        if let Some(ref prompt) = self.prompt {
+            instruction.push_str(&format!(" Context: {prompt}"));
+        }
+        json!({
+            "contents": [{
+                "role": "user",
+                "parts": [
+                    { "text": instruction },
+                    { "inlineData": { "mimeType": self.mime_type, "data": self.audio } }
+                ]
+            }],
+        })
+    }
+
+    /// Converts a Gemini response into an OpenAI transcription
+    ///
+    /// # Returns
+    /// The body and whether it is JSON rather than plain text
+    pub fn transcription_response(&self, res: &Value) -> (String, bool) {
+        let text = res["candidates"][0]["content"]["parts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|p| !p["thought"].as_bool().unwrap_or_default())
+            .filter_map(|p| p["text"].as_str())
+            .collect::<String>()
+            .trim()
+            .to_string();
+        match self.response_format.as_deref() {
+            Some("text") | Some("srt") | Some("vtt") => (text, false),
+            _ => (json!({ "text": text }).to_string(), true),
+        }
+    }
+}
@@ -0,0 +1,359 @@
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// Model used when the client asks for an OpenAI realtime model
+const DEFAULT_REALTIME_MODEL: &str = "gemini-2.5-flash";
+
+fn new_id(prefix: &str) -> String {
+    format!("{prefix}_{}", uuid::Uuid::new_v4().simple())
+}
+
+/// Maps the `model` query parameter to a Gemini model
+pub fn realtime_model(model: Option<&str>) -> String {
+    match model.map(|m| m.trim_start_matches("models/")) {
+        Some(m) if !m.is_empty() && !m.starts_with("gpt-") => m.to_string(),
+        _ => DEFAULT_REALTIME_MODEL.to_string(),
+    }
+}
+
+/// Events sent by the client
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+pub enum ClientEvent {
+    #[serde(rename = "session.update")]
+    SessionUpdate { session: ResponseParams },
+    #[serde(rename = "conversation.item.create")]
+    ItemCreate { item: Value },
+    #[serde(rename = "conversation.item.delete")]
+    ItemDelete { item_id: String },
+    #[serde(rename = "response.create")]
+    ResponseCreate {
+        #[serde(default)]
+        response: ResponseParams,
+    },
+    #[serde(rename = "response.cancel")]
+    ResponseCancel,
+}
+
+/// Settings of the session, also accepted per response
+#[derive(Deserialize, Default, Clone)]
+pub struct ResponseParams {
+    #[serde(default)]
+    pub instructions: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// A number or `"inf"`
+    #[serde(default)]
+    pub max_response_output_tokens: Option<Value>,
+}
+
+impl ResponseParams {
+    fn max_output_tokens(&self) -> Option<u64> {
+        self.max_response_output_tokens.as_ref()?.as_u64()
+    }
+}
+
+/// `error` event sent to the client
+pub fn error_event(code: &str, message: impl Into<String>) -> Value {
+    json!({
+        "type": "error",
+        "error": {
+            "type": "invalid_request_error",
+            "code": code,
+            "message": message.into(),
+        },
+    })
+}
+
+/// Text of a conversation item, `None` for items that carry audio or
+/// function calls
+fn item_text(item: &Value) -> Option<String> {
+    if item["type"] != "message" {
+        return None;
+    }
+    let parts = item["content"].as_array()?;
+    parts
+        .iter()
+        .map(|p| match p["type"].as_str()? {
+            "input_text" | "text" => p["text"].as_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|t| t.join("\n"))
+}
+
+/// Conversation held by a realtime connection
+///
+/// Only text conversations are supported, items carrying audio or function
+/// calls are rejected with an `error` event.
+pub struct RealtimeSession {
+    id: String,
+    pub model: String,
+    params: ResponseParams,
+    items: Vec<Value>,
+}
+
+impl RealtimeSession {
+    pub fn new(model: String) -> Self {
+        Self {
+            id: new_id("sess"),
+            model,
+            params: ResponseParams::default(),
+            items: vec![],
+        }
+    }
+
+    fn session_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "object": "realtime.session",
+            "model": self.model,
+            "modalities": ["text"],
+            "instructions": self.params.instructions.as_deref().unwrap_or_default(),
+            "temperature": self.params.temperature,
+            "max_response_output_tokens": self
+                .params
+                .max_response_output_tokens
+                .to_owned()
+                .unwrap_or(json!("inf")),
+        })
+    }
+
+    /// `session.created` event sent on connect
+    pub fn created_event(&self) -> Value {
+        json!({ "type": "session.created", "session": self.session_json() })
+    }
+
+    /// Applies `session.update`, fields left out keep their value
+    pub fn update(&mut self, params: ResponseParams) -> Value {
+        if params.instructions.is_some() {
+            self.params.instructions = params.instructions;
+        }
+        if params.temperature.is_some() {
+            self.params.temperature = params.temperature;
+        }
+        if params.max_response_output_tokens.is_some() {
+            self.params.max_response_output_tokens = params.max_response_output_tokens;
+        }
+        json!({ "type": "session.updated", "session": self.session_json() })
+    }
+
+    /// Appends an item created by the client
+    ///
+    /// # Returns
+    /// The `conversation.item.created` event, or an `error` event for items
+    /// that cannot be sent to Gemini
+    pub fn add_item(&mut self, mut item: Value) -> Value {
+        if item_text(&item).is_none() {
+            return error_event("unsupported_item", "Only text message items are supported");
+        }
+        if !item["id"].is_string() {
+            item["id"] = json!(new_id("item"));
+        }
+        item["object"] = json!("realtime.item");
+        item["status"] = json!("completed");
+        let previous = self.items.last().map(|i| i["id"].to_owned());
+        self.items.push(item.to_owned());
+        json!({
+            "type": "conversation.item.created",
+            "previous_item_id": previous,
+            "item": item,
+        })
+    }
+
+    /// Records the assistant message of a finished response
+    pub fn push_output(&mut self, item: Value) {
+        self.items.push(item);
+    }
+
+    /// Removes an item from the conversation
+    pub fn delete_item(&mut self, id: &str) -> Value {
+        let before = self.items.len();
+        self.items.retain(|i| i["id"] != id);
+        if self.items.len() == before {
+            return error_event("item_not_found", format!("Item {id} does not exist"));
+        }
+        json!({ "type": "conversation.item.deleted", "item_id": id })
+    }
+
+    /// Builds the streaming Gemini request for the conversation so far,
+    /// settings of the response override the session ones
+    pub fn to_gemini(&self, params: &ResponseParams) -> Value {
+        let mut system = vec![];
+        if let Some(instructions) = params
+            .instructions
+            .as_deref()
+            .or(self.params.instructions.as_deref())
+            .filter(|i| !i.is_empty())
+        {
+            system.push(instructions.to_string());
+        }
+        let mut contents = vec![];
+        for item in &self.items {
+            let Some(text) = item_text(item) else {
+                continue;
+            };
+            let role = match item["role"].as_str() {
+                Some("system") => {
+                    system.push(text);
+                    continue;
+                }
+                Some("assistant") => "model",
+                _ => "user",
+            };
+            contents.push(json!({ "role": role, "parts": [{ "text": text }] }));
+        }
+        let mut body = json!({ "contents": contents });
+        if !system.is_empty() {
+            body["systemInstruction"] = json!({ "parts": [{ "text": system.join("\n\n") }] });
+        }
+        let mut config = json!({});
+        if let Some(t) = params.temperature.or(self.params.temperature) {
+            config["temperature"] = json!(t);
+        }
+        if let Some(max) = params
+            .max_output_tokens()
+            .or_else(|| self.params.max_output_tokens())
+        {
+            config["maxOutputTokens"] = json!(max);
+        }
+        body["generationConfig"] = config;
+        body
+    }
+}
+
+/// Builds the server events of a single response
+#[derive(Clone)]
+pub struct ResponseEvents {
+    response_id: String,
+    item_id: String,
+}
+
+impl Default for ResponseEvents {
+    fn default() -> Self {
+        Self {
+            response_id: new_id("resp"),
+            item_id: new_id("item"),
+        }
+    }
+}
+
+impl ResponseEvents {
+    fn item(&self, text: Option<&str>) -> Value {
+        let content = match text {
+            Some(text) => json!([{ "type": "text", "text": text }]),
+            None => json!([]),
+        };
+        json!({
+            "id": self.item_id,
+            "object": "realtime.item",
+            "type": "message",
+            "status": if text.is_some() { "completed" } else { "in_progress" },
+            "role": "assistant",
+            "content": content,
+        })
+    }
+
+    fn response(&self, status: &str, output: Vec<Value>) -> Value {
+        json!({
+            "id": self.response_id,
+            "object": "realtime.response",
+            "status": status,
+            "output": output,
+        })
+    }
+
+    /// Events announcing the response and its single text part
+    pub fn started(&self) -> Vec<Value> {
+        vec![
+            json!({
+                "type": "response.created",
+                "response": self.response("in_progress", vec![]),
+            }),
+            json!({
+                "type": "response.output_item.added",
+                "response_id": self.response_id,
+                "output_index": 0,
+                "item": self.item(None),
+            }),
+            json!({
+                "type": "response.content_part.added",
+                "response_id": self.response_id,
+                "item_id": self.item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "part": { "type": "text", "text": "" },
+            }),
+        ]
+    }
+
+    pub fn delta(&self, delta: &str) -> Value {
+        json!({
+            "type": "response.text.delta",
+            "response_id": self.response_id,
+            "item_id": self.item_id,
+            "output_index": 0,
+            "content_index": 0,
+            "delta": delta,
+        })
+    }
+
+    /// Events closing a completed response, the last one is `response.done`
+    ///
+    /// # Arguments
+    /// * `text` - Full text of the response
+    /// * `usage` - Gemini `usageMetadata`, if reported
+    pub fn completed(&self, text: &str, usage: Option<&Value>) -> Vec<Value> {
+        let item = self.item(Some(text));
+        let mut response = self.response("completed", vec![item.to_owned()]);
+        if let Some(usage) = usage {
+            response["usage"] = json!({
+                "input_tokens": usage["promptTokenCount"],
+                "output_tokens": usage["candidatesTokenCount"],
+                "total_tokens": usage["totalTokenCount"],
+            });
+        }
+        vec![
+            json!({
+                "type": "response.text.done",
+                "response_id": self.response_id,
+                "item_id": self.item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "text": text,
+            }),
+            json!({
+                "type": "response.content_part.done",
+                "response_id": self.response_id,
+                "item_id": self.item_id,
+                "output_index": 0,
+                "content_index": 0,
+                "part": { "type": "text", "text": text },
+            }),
+            json!({
+                "type": "response.output_item.done",
+                "response_id": self.response_id,
+                "output_index": 0,
+                "item": item,
+            }),
+            json!({ "type": "response.done", "response": response }),
+        ]
+    }
+
+    /// `response.done` for a response that ended early
+    ///
+    /// # Arguments
+    /// * `status` - `cancelled` or `failed`
+    /// * `error` - Error message of a failed response
+    pub fn ended(&self, status: &str, error: Option<String>) -> Value {
+        let mut response = self.response(status, vec![]);
+        response["status_details"] = match error {
+            Some(message) => json!({
+                "type": status,
+                "error": { "type": "server_error", "message": message },
+            }),
+            None => json!({ "type": status, "reason": "client_cancelled" }),
+        };
+        json!({ "type": "response.done", "response": response })
+    }
+}
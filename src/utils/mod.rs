@@ -1,11 +1,14 @@
 use axum::body::Body;
 use colored::{ColoredString, Colorize};
+use ring::rand::{SecureRandom, SystemRandom};
 use tokio::{io::AsyncWriteExt, spawn};
 use tracing::error;
 
 use crate::{
     config::{CLEWDR_CONFIG, LOG_DIR},
     error::ClewdrError,
+    services::redaction,
+    streaming::{StreamDialect, watchdog},
 };
 
 /// Helper function to format a boolean value as "Enabled" or "Disabled"
@@ -30,7 +33,8 @@ pub fn print_out_json(json: impl serde::ser::Serialize, file_name: &str) {
     print_out_text(text, file_name);
 }
 
-/// Helper function to print out text to a file in the log directory
+/// Helper function to print out text to a file in the log directory, with
+/// the `redaction` rules applied
 ///
 /// # Arguments
 /// * `text` - The text content to write
@@ -40,6 +44,7 @@ pub fn print_out_text(text: String, file_name: &str) {
         return;
     }
     let file_name = LOG_DIR.join(file_name);
+    let text = redaction::redact(&text).into_owned();
     spawn(async move {
         let Ok(mut file) = tokio::fs::File::options()
             .write(true)
@@ -57,6 +62,25 @@ pub fn print_out_text(text: String, file_name: &str) {
     });
 }
 
+/// Random draw that is true with probability `rate`, for fault injection
+pub fn roll(rate: f64) -> bool {
+    if rate <= 0.0 {
+        return false;
+    }
+    let mut buf = [0u8; 4];
+    if SystemRandom::new().fill(&mut buf).is_err() {
+        return false;
+    }
+    (u32::from_le_bytes(buf) as f64 / u32::MAX as f64) < rate
+}
+
+/// Random index below `len`, `len` must not be 0
+pub fn random_index(len: usize) -> usize {
+    let mut buf = [0u8; 8];
+    let _ = SystemRandom::new().fill(&mut buf);
+    (u64::from_le_bytes(buf) % len as u64) as usize
+}
+
 /// Timezone for the API
 pub const TIME_ZONE: &str = "America/New_York";
 
@@ -64,6 +88,36 @@ pub fn forward_response(in_: wreq::Response) -> Result<http::Response<Body>, Cle
     let status = in_.status();
     let header = in_.headers().to_owned();
     let stream = in_.bytes_stream();
+    forward_parts(status, header, stream)
+}
+
+/// Forwards a streamed upstream response, guarded by the idle stream watchdog
+///
+/// # Arguments
+/// * `in_` - Upstream response
+/// * `dialect` - Format of the events, for the error event ending a stall
+/// * `on_stall` - Called when the stream stalls after it started
+pub async fn forward_guarded(
+    in_: wreq::Response,
+    dialect: StreamDialect,
+    on_stall: impl FnOnce() + Send + 'static,
+) -> Result<http::Response<Body>, ClewdrError> {
+    let status = in_.status();
+    let header = in_.headers().to_owned();
+    let stream = watchdog::guard(in_.bytes_stream(), dialect, on_stall).await?;
+    forward_parts(status, header, stream)
+}
+
+fn forward_parts<S>(
+    status: http::StatusCode,
+    header: http::HeaderMap,
+    stream: S,
+) -> Result<http::Response<Body>, ClewdrError>
+where
+    S: futures::TryStream + Send + 'static,
+    S::Ok: Into<axum::body::Bytes>,
+    S::Error: Into<axum::BoxError>,
+{
     let mut res = http::Response::builder().status(status);
 
     let headers = res.headers_mut().unwrap();